@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, Utc};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::json;
+use transit_kindle::{
+    api_client::{Cached, Client, Snapshot},
+    config::ConfigFile,
+    layout::data_to_layout,
+    output::{self, OutputFormat, OutputParams},
+    render::SharedRenderData,
+};
+
+fn config() -> ConfigFile {
+    serde_yaml::from_str(include_str!("fixtures/stops.yml")).unwrap()
+}
+
+/// Journeys for every agency in the fixture config, departing over the next
+/// hour so that none of them are dropped as already gone
+fn snapshot(config_file: &ConfigFile) -> Snapshot {
+    let now = Utc::now();
+    let mut snapshot = Snapshot::default();
+
+    for (agency_idx, stop_config) in config_file.stops.iter().enumerate() {
+        let mut journeys = Vec::new();
+
+        for (stop_idx, stop) in stop_config.stops.iter().enumerate() {
+            for line in 0..6 {
+                for (direction, destination) in [("IB", "Downtown"), ("OB", "Ocean Beach")]
+                    .into_iter()
+                    .chain([("N", "San Francisco"), ("S", "San Jose")])
+                {
+                    for departure in 0..6 {
+                        let minutes =
+                            2 + departure * 9 + line + stop_idx as i64 + agency_idx as i64;
+
+                        journeys.push(json!({
+                            "LineRef": format!("{}{line}", stop_config.agency),
+                            "DirectionRef": direction,
+                            "DestinationName": destination,
+                            "MonitoredCall": {
+                                "ExpectedArrivalTime": (now + Duration::minutes(minutes)).to_rfc3339(),
+                                "StopPointRef": stop,
+                                "DestinationDisplay": destination,
+                            },
+                        }));
+                    }
+                }
+            }
+        }
+
+        let cached: Cached = serde_json::from_value(json!({
+            "journeys": journeys,
+            "live_time": now,
+        }))
+        .unwrap();
+
+        snapshot.insert(stop_config.agency.clone(), cached);
+    }
+
+    snapshot
+}
+
+fn benchmarks(c: &mut Criterion) {
+    let config_file = config();
+    let snapshot = snapshot(&config_file);
+    let client = Client::new(String::new(), HashMap::new(), 0, usize::MAX, None);
+
+    c.bench_function("transform_results", |b| {
+        b.iter(|| client.stop_data(&config_file, &snapshot).unwrap())
+    });
+
+    c.bench_function("data_to_layout", |b| {
+        b.iter_batched(
+            || client.stop_data(&config_file, &snapshot).unwrap(),
+            |stop_data| data_to_layout(stop_data, &config_file),
+            BatchSize::SmallInput,
+        )
+    });
+
+    let shared = SharedRenderData::new(&config_file).unwrap();
+    let layout = data_to_layout(
+        client.stop_data(&config_file, &snapshot).unwrap(),
+        &config_file,
+    );
+
+    c.bench_function("render_png", |b| {
+        b.iter(|| {
+            output::render(
+                shared.clone(),
+                &layout,
+                OutputFormat::Png,
+                OutputParams::default(),
+            )
+            .unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,304 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use eyre::{eyre, Context, Result};
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+/// A journey which stopped appearing in the feed this long before its last
+/// prediction was probably cancelled or dropped, rather than departed
+const DROPPED_THRESHOLD: Duration = Duration::minutes(5);
+
+/// Records every prediction made for a departure along with when the vehicle
+/// actually left, so that `departure_lead_minutes` can be set from how early
+/// vehicles turn out to leave
+pub struct AccuracyLog {
+    db: Mutex<Connection>,
+    /// Journeys in the most recent fetch of each agency, keyed by agency
+    tracked: Mutex<HashMap<String, HashMap<JourneyKey, Tracked>>>,
+    /// How long departures are kept after they leave
+    keep: Duration,
+    /// Zone whose midnights divide one day's departures from the next
+    timezone: Tz,
+}
+
+/// A departure in a fetched feed
+pub struct Observation {
+    /// Identifies the vehicle's trip, unique for the day
+    pub journey: String,
+    pub stop: String,
+    pub line: String,
+    pub destination: String,
+    pub predicted: DateTime<Utc>,
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct JourneyKey {
+    journey: String,
+    stop: String,
+}
+
+struct Tracked {
+    line: String,
+    destination: String,
+    /// When each prediction was fetched, and the departure time it predicted
+    predictions: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+}
+
+/// How far predictions for a line were from when its vehicles left
+pub struct LineAccuracy {
+    pub agency: String,
+    pub line: String,
+    pub destination: String,
+    /// Number of predictions made around the lead time
+    pub samples: usize,
+    /// Average minutes that vehicles left after the predicted time. Negative
+    /// when they tend to leave early.
+    pub mean_minutes: f64,
+    /// Nine in ten vehicles left no earlier than this many minutes before
+    /// the predicted time
+    pub early_p90_minutes: f64,
+    /// Earliest any vehicle left, in minutes before the predicted time
+    pub earliest_minutes: f64,
+}
+
+/// One prediction of a departure, as exported to CSV
+#[derive(Serialize)]
+pub struct PredictionRecord {
+    pub agency: String,
+    pub line: String,
+    pub destination: String,
+    pub stop: String,
+    /// When the prediction was fetched
+    pub predicted_at: DateTime<Utc>,
+    /// When the vehicle was predicted to leave
+    pub predicted: DateTime<Utc>,
+    /// When the vehicle was observed to have left
+    pub departed: DateTime<Utc>,
+}
+
+impl AccuracyLog {
+    pub fn open(path: &Path, keep_days: i64, timezone: Tz) -> Result<Self> {
+        let db = Connection::open(path)
+            .wrap_err_with(|| format!("opening accuracy database {}", path.display()))?;
+
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS predictions (
+                agency TEXT NOT NULL,
+                line TEXT NOT NULL,
+                destination TEXT NOT NULL,
+                stop TEXT NOT NULL,
+                predicted_at TEXT NOT NULL,
+                predicted TEXT NOT NULL,
+                departed TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS predictions_line
+                ON predictions (agency, line, destination);
+            CREATE INDEX IF NOT EXISTS predictions_departed
+                ON predictions (departed);",
+        )
+        .wrap_err("creating accuracy tables")?;
+
+        Ok(Self {
+            db: Mutex::new(db),
+            tracked: Mutex::new(HashMap::new()),
+            keep: Duration::days(keep_days),
+            timezone,
+        })
+    }
+
+    /// The date it is now where departures are counted
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.timezone).date_naive()
+    }
+
+    /// Record the departures in a fresh fetch of an agency. Journeys that were
+    /// in the previous fetch but not this one have departed, and their
+    /// predictions are written to the database. Departures older than the
+    /// configured number of days are forgotten.
+    pub fn observe(
+        &self,
+        agency: &str,
+        observations: Vec<Observation>,
+        fetched_at: DateTime<Utc>,
+    ) -> Result<()> {
+        let mut tracked = self.tracked.lock().unwrap();
+        let tracked = tracked.entry(agency.to_owned()).or_default();
+
+        let mut seen = HashSet::new();
+
+        for observation in observations {
+            let key = JourneyKey {
+                journey: observation.journey,
+                stop: observation.stop,
+            };
+
+            tracked
+                .entry(key.clone())
+                .or_insert_with(|| Tracked {
+                    line: observation.line,
+                    destination: observation.destination,
+                    predictions: Vec::new(),
+                })
+                .predictions
+                .push((fetched_at, observation.predicted));
+
+            seen.insert(key);
+        }
+
+        let departed = tracked
+            .keys()
+            .filter(|key| !seen.contains(*key))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction()?;
+
+        for key in departed {
+            let Some(journey) = tracked.remove(&key) else {
+                continue;
+            };
+            let Some(&(last_seen, last_predicted)) = journey.predictions.last() else {
+                continue;
+            };
+
+            if last_predicted - fetched_at > DROPPED_THRESHOLD {
+                continue;
+            }
+
+            // the vehicle left some time between the last fetch it was in and
+            // this one, and the final prediction is the best guess of when
+            let departed = last_predicted.clamp(last_seen, fetched_at);
+
+            for (predicted_at, predicted) in &journey.predictions {
+                tx.execute(
+                    "INSERT INTO predictions
+                        (agency, line, destination, stop, predicted_at, predicted, departed)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        agency,
+                        journey.line,
+                        journey.destination,
+                        key.stop,
+                        predicted_at,
+                        predicted,
+                        departed,
+                    ],
+                )?;
+            }
+        }
+
+        tx.execute(
+            "DELETE FROM predictions WHERE departed < ?1",
+            params![fetched_at - self.keep],
+        )?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Accuracy of the predictions made about `lead_minutes` before each
+    /// departure, the time it takes to walk to the stop
+    pub fn report(&self, lead_minutes: i64) -> Result<Vec<LineAccuracy>> {
+        // predictions are fetched every few minutes, so take those made in
+        // the few minutes before the lead time
+        let lead = Duration::minutes(lead_minutes);
+        let window = Duration::minutes(5);
+
+        let db = self.db.lock().unwrap();
+
+        let mut statement = db.prepare(
+            "SELECT agency, line, destination, predicted, departed
+                FROM predictions
+                WHERE (julianday(departed) - julianday(predicted_at)) * 86400 >= ?1
+                    AND (julianday(departed) - julianday(predicted_at)) * 86400 < ?2",
+        )?;
+
+        let rows = statement.query_map(
+            params![lead.num_seconds(), (lead + window).num_seconds()],
+            |row| {
+                Ok((
+                    (
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    ),
+                    row.get::<_, DateTime<Utc>>(3)?,
+                    row.get::<_, DateTime<Utc>>(4)?,
+                ))
+            },
+        )?;
+
+        let mut errors = BTreeMap::<_, Vec<f64>>::new();
+        for row in rows {
+            let (line, predicted, departed) = row?;
+
+            errors
+                .entry(line)
+                .or_default()
+                .push((departed - predicted).num_seconds() as f64 / 60.0);
+        }
+
+        Ok(errors
+            .into_iter()
+            .map(|((agency, line, destination), mut errors)| {
+                errors.sort_by(f64::total_cmp);
+
+                LineAccuracy {
+                    agency,
+                    line,
+                    destination,
+                    samples: errors.len(),
+                    mean_minutes: errors.iter().sum::<f64>() / errors.len() as f64,
+                    early_p90_minutes: 0.0 - errors[errors.len() / 10],
+                    earliest_minutes: 0.0 - errors[0],
+                }
+            })
+            .collect())
+    }
+
+    /// Every prediction of the vehicles which left on `date`, in the board's
+    /// time zone, in the order they left
+    pub fn departures_on(&self, date: NaiveDate) -> Result<Vec<PredictionRecord>> {
+        let midnight = |date: NaiveDate| {
+            date.and_hms_opt(0, 0, 0)
+                .and_then(|time| self.timezone.from_local_datetime(&time).earliest())
+                .map(|time| time.with_timezone(&Utc))
+        };
+        let (Some(start), Some(end)) = (midnight(date), date.succ_opt().and_then(midnight)) else {
+            return Err(eyre!("date {date} is out of range"));
+        };
+
+        let db = self.db.lock().unwrap();
+
+        let mut statement = db.prepare(
+            "SELECT agency, line, destination, stop, predicted_at, predicted, departed
+                FROM predictions
+                WHERE departed >= ?1 AND departed < ?2
+                ORDER BY departed, predicted_at",
+        )?;
+
+        let records = statement
+            .query_map(params![start, end], |row| {
+                Ok(PredictionRecord {
+                    agency: row.get(0)?,
+                    line: row.get(1)?,
+                    destination: row.get(2)?,
+                    stop: row.get(3)?,
+                    predicted_at: row.get(4)?,
+                    predicted: row.get(5)?,
+                    departed: row.get(6)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+
+        Ok(records)
+    }
+}
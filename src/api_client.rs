@@ -1,15 +1,43 @@
 use std::{
-    collections::{BTreeMap, HashMap},
-    sync::Arc,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fmt,
+    hash::{BuildHasher, Hasher, RandomState},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use chrono::{DateTime, Utc};
-use eyre::{Context, Result};
-use serde::{Deserialize, Serialize};
-use tokio::task::JoinSet;
-use tracing::{debug, warn};
-
-use crate::config::{ConfigFile, StopConfig};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use eyre::{eyre, Context, Result};
+use itertools::Itertools;
+use reqwest::{header, StatusCode};
+use serde::{
+    de::{self, value::MapAccessDeserializer, IntoDeserializer, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use tokio::{
+    sync::watch,
+    task::{AbortHandle, JoinSet},
+};
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use crate::{
+    accuracy::{AccuracyLog, Observation},
+    bearing::BearingSource,
+    calendar::{ServiceCalendar, ServiceDay},
+    config::{
+        BudgetConfig, ConfigFile, DestinationNormalization, FeedFormat, Provider, RemoteConfig,
+        SourceConfig, SourceQuirks, StopConfig, FIVE_ONE_ONE,
+    },
+    conformance, digitransit,
+    error::{FetchError, ParseError},
+    five_one_one,
+    gtfs::GtfsSchedule,
+    mbta, normalize, oba, remote, tfl, transitous,
+};
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -17,18 +45,279 @@ struct StopMonitoringResponse {
     service_delivery: ServiceDelivery,
 }
 
+/// A response wrapped in the `Siri` object that the SIRI standard puts
+/// around `ServiceDelivery`, which 511.org leaves out
+#[derive(Deserialize)]
+struct SiriJson {
+    #[serde(rename = "Siri")]
+    siri: StopMonitoringResponse,
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct ServiceDelivery {
-    stop_monitoring_delivery: StopMonitoringDelivery,
+    #[serde(deserialize_with = "one_or_many")]
+    stop_monitoring_delivery: Vec<StopMonitoringDelivery>,
 }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct StopMonitoringDelivery {
+    /// Left out entirely by some feeds when nothing is scheduled
+    #[serde(default, deserialize_with = "one_or_many")]
     monitored_stop_visit: Vec<MonitoredStopVisit>,
 }
 
+/// Accept either a single value or an array of them, since 511 switches
+/// between the two for some SIRI elements. `null` is read as no values.
+/// Responses from sources without the `one_or_many` quirk are read as
+/// [`StrictSiri`] instead.
+fn one_or_many<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    struct OneOrMany<T>(PhantomData<T>);
+
+    impl<'de, T: Deserialize<'de>> Visitor<'de> for OneOrMany<T> {
+        type Value = Vec<T>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a value or an array of values")
+        }
+
+        fn visit_unit<E: de::Error>(self) -> std::result::Result<Vec<T>, E> {
+            Ok(Vec::new())
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> std::result::Result<Vec<T>, E> {
+            T::deserialize(value.into_deserializer()).map(|value| vec![value])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> std::result::Result<Vec<T>, A::Error> {
+            let mut values = Vec::new();
+            while let Some(value) = seq.next_element()? {
+                values.push(value);
+            }
+
+            Ok(values)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> std::result::Result<Vec<T>, A::Error> {
+            T::deserialize(MapAccessDeserializer::new(map)).map(|value| vec![value])
+        }
+    }
+
+    deserializer.deserialize_any(OneOrMany(PhantomData))
+}
+
+/// The same response with every list given as one, as XML always has them,
+/// where the root `Siri` element holds `ServiceDelivery`. JSON from sources
+/// without the `one_or_many` quirk is read this way too, so that a single
+/// value where SIRI has a list is a malformed response.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StrictSiri {
+    service_delivery: StrictServiceDelivery,
+}
+
+/// [`StrictSiri`] in the `Siri` object the SIRI standard puts around it
+#[derive(Deserialize)]
+struct StrictSiriJson {
+    #[serde(rename = "Siri")]
+    siri: StrictSiri,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StrictServiceDelivery {
+    #[serde(default)]
+    stop_monitoring_delivery: Vec<StrictStopMonitoringDelivery>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StrictStopMonitoringDelivery {
+    #[serde(default)]
+    monitored_stop_visit: Vec<StrictMonitoredStopVisit>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StrictMonitoredStopVisit {
+    monitored_vehicle_journey: StrictMonitoredVehicleJourney,
+}
+
+/// Journey with its lists read strictly, and as XML has them, where repeated
+/// elements like `VehicleFeatureRef` can only be collected by a plain list
+/// field
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct StrictMonitoredVehicleJourney {
+    line_ref: Option<String>,
+    direction_ref: Option<String>,
+    destination_name: Option<String>,
+    monitored_call: MonitoredCall,
+    #[serde(default)]
+    vehicle_feature_ref: Vec<String>,
+    framed_vehicle_journey_ref: Option<FramedVehicleJourneyRef>,
+}
+
+impl From<StrictMonitoredStopVisit> for MonitoredStopVisit {
+    fn from(visit: StrictMonitoredStopVisit) -> Self {
+        let journey = visit.monitored_vehicle_journey;
+
+        MonitoredStopVisit {
+            monitored_vehicle_journey: MonitoredVehicleJourney {
+                line_ref: journey.line_ref,
+                direction_ref: journey.direction_ref,
+                destination_name: journey.destination_name,
+                monitored_call: journey.monitored_call,
+                vehicle_feature_ref: journey.vehicle_feature_ref,
+                framed_vehicle_journey_ref: journey.framed_vehicle_journey_ref,
+            },
+        }
+    }
+}
+
+impl From<tfl::Arrival> for MonitoredVehicleJourney {
+    fn from(arrival: tfl::Arrival) -> Self {
+        MonitoredVehicleJourney {
+            line_ref: arrival.line_name.clone().or(arrival.line_id.clone()),
+            direction_ref: arrival.direction(),
+            destination_name: arrival.destination(),
+            monitored_call: MonitoredCall {
+                expected_arrival_time: arrival.expected_arrival,
+                aimed_arrival_time: None,
+                stop_point_ref: arrival.naptan_id,
+                destination_display: None,
+                departure_platform_name: None,
+                departure_status: None,
+            },
+            vehicle_feature_ref: Vec::new(),
+            framed_vehicle_journey_ref: arrival.vehicle_id.map(|vehicle| FramedVehicleJourneyRef {
+                data_frame_ref: None,
+                dated_vehicle_journey_ref: Some(vehicle),
+            }),
+        }
+    }
+}
+
+impl From<mbta::Departure> for MonitoredVehicleJourney {
+    fn from(departure: mbta::Departure) -> Self {
+        MonitoredVehicleJourney {
+            line_ref: Some(departure.line),
+            direction_ref: departure.direction,
+            destination_name: departure.destination,
+            monitored_call: MonitoredCall {
+                expected_arrival_time: departure.expected,
+                aimed_arrival_time: departure.scheduled,
+                stop_point_ref: departure.stop_id,
+                destination_display: None,
+                departure_platform_name: departure.track,
+                departure_status: departure.cancelled.then(|| String::from("cancelled")),
+            },
+            vehicle_feature_ref: Vec::new(),
+            framed_vehicle_journey_ref: departure.trip_id.map(|trip| FramedVehicleJourneyRef {
+                data_frame_ref: None,
+                dated_vehicle_journey_ref: Some(trip),
+            }),
+        }
+    }
+}
+
+impl From<digitransit::Departure> for MonitoredVehicleJourney {
+    fn from(departure: digitransit::Departure) -> Self {
+        MonitoredVehicleJourney {
+            line_ref: Some(departure.line),
+            direction_ref: departure.direction,
+            destination_name: departure.headsign,
+            monitored_call: MonitoredCall {
+                expected_arrival_time: departure.expected,
+                aimed_arrival_time: departure.scheduled,
+                stop_point_ref: departure.stop_id,
+                destination_display: None,
+                departure_platform_name: departure.track,
+                departure_status: departure.cancelled.then(|| String::from("cancelled")),
+            },
+            vehicle_feature_ref: Vec::new(),
+            framed_vehicle_journey_ref: departure.trip_id.map(|trip| FramedVehicleJourneyRef {
+                data_frame_ref: None,
+                dated_vehicle_journey_ref: Some(trip),
+            }),
+        }
+    }
+}
+
+impl From<transitous::Departure> for MonitoredVehicleJourney {
+    fn from(departure: transitous::Departure) -> Self {
+        MonitoredVehicleJourney {
+            line_ref: departure.line,
+            // there's no direction to go by, but the headsign tells the two
+            // ways of a line apart
+            direction_ref: departure.headsign.clone(),
+            destination_name: departure.headsign,
+            monitored_call: MonitoredCall {
+                expected_arrival_time: departure.expected,
+                aimed_arrival_time: departure.scheduled,
+                stop_point_ref: departure.stop,
+                destination_display: None,
+                departure_platform_name: departure.track,
+                departure_status: departure.cancelled.then(|| String::from("cancelled")),
+            },
+            vehicle_feature_ref: Vec::new(),
+            framed_vehicle_journey_ref: departure.trip_id.map(|trip| FramedVehicleJourneyRef {
+                data_frame_ref: None,
+                dated_vehicle_journey_ref: Some(trip),
+            }),
+        }
+    }
+}
+
+impl From<oba::Departure> for MonitoredVehicleJourney {
+    fn from(departure: oba::Departure) -> Self {
+        MonitoredVehicleJourney {
+            line_ref: departure.line,
+            direction_ref: departure.direction,
+            destination_name: departure.headsign,
+            monitored_call: MonitoredCall {
+                expected_arrival_time: departure.expected,
+                aimed_arrival_time: departure.scheduled,
+                stop_point_ref: departure.stop_id,
+                destination_display: None,
+                departure_platform_name: None,
+                departure_status: None,
+            },
+            vehicle_feature_ref: Vec::new(),
+            framed_vehicle_journey_ref: Some(FramedVehicleJourneyRef {
+                data_frame_ref: None,
+                dated_vehicle_journey_ref: Some(departure.trip_id),
+            }),
+        }
+    }
+}
+
+impl From<StrictSiri> for StopMonitoringResponse {
+    fn from(siri: StrictSiri) -> Self {
+        StopMonitoringResponse {
+            service_delivery: ServiceDelivery {
+                stop_monitoring_delivery: siri
+                    .service_delivery
+                    .stop_monitoring_delivery
+                    .into_iter()
+                    .map(|delivery| StopMonitoringDelivery {
+                        monitored_stop_visit: delivery
+                            .monitored_stop_visit
+                            .into_iter()
+                            .map(MonitoredStopVisit::from)
+                            .collect(),
+                    })
+                    .collect(),
+            },
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct MonitoredStopVisit {
@@ -42,28 +331,136 @@ struct MonitoredVehicleJourney {
     direction_ref: Option<String>,
     destination_name: Option<String>,
     monitored_call: MonitoredCall,
+    /// Features of the vehicle, such as `lowFloor` or `wheelchairAccessible`
+    #[serde(default, deserialize_with = "one_or_many")]
+    vehicle_feature_ref: Vec<String>,
+    framed_vehicle_journey_ref: Option<FramedVehicleJourneyRef>,
+}
+
+/// Identifies a vehicle's trip on a particular operating day
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct FramedVehicleJourneyRef {
+    data_frame_ref: Option<String>,
+    dated_vehicle_journey_ref: Option<String>,
+}
+
+impl MonitoredVehicleJourney {
+    /// Apply the quirks of the source the journey came from, so that every
+    /// source's journeys read the same
+    fn normalize(&mut self, quirks: &SourceQuirks) {
+        let call = &mut self.monitored_call;
+        for time in [
+            &mut call.expected_arrival_time,
+            &mut call.aimed_arrival_time,
+        ] {
+            if let Some(normalized) = time
+                .as_deref()
+                .and_then(|text| normalize::time(text, quirks.timezone))
+            {
+                *time = Some(normalized);
+            }
+        }
+
+        for name in [&mut self.destination_name, &mut call.destination_display]
+            .into_iter()
+            .flatten()
+        {
+            *name = normalize::destination(name, quirks.destination_case);
+        }
+    }
+
+    /// The expected time, line, direction, and destination, which every
+    /// journey on the board needs, or the first of them that's missing. A
+    /// time which can't be read counts as missing.
+    fn required(&self) -> Result<(DateTime<Utc>, &String, &String, &String), SkipReason> {
+        Ok((
+            self.expected_time().ok_or(SkipReason::NoExpectedTime)?,
+            self.line_ref.as_ref().ok_or(SkipReason::NoLine)?,
+            self.direction_ref.as_ref().ok_or(SkipReason::NoDirection)?,
+            self.monitored_call
+                .destination_display
+                .as_ref()
+                .or(self.destination_name.as_ref())
+                .ok_or(SkipReason::NoDestination)?,
+        ))
+    }
+
+    fn expected_time(&self) -> Option<DateTime<Utc>> {
+        self.monitored_call
+            .expected_arrival_time
+            .as_ref()?
+            .parse()
+            .ok()
+    }
+
+    /// Whether the vehicle can be boarded in a wheelchair, if the feed says
+    fn wheelchair_accessible(&self) -> Option<bool> {
+        let features = self
+            .vehicle_feature_ref
+            .iter()
+            .map(|feature| feature.to_lowercase())
+            .collect::<Vec<_>>();
+
+        if features
+            .iter()
+            .any(|feature| feature.starts_with("not") || feature.contains("inaccessible"))
+        {
+            Some(false)
+        } else if features
+            .iter()
+            .any(|feature| feature.contains("wheelchair") || feature.contains("lowfloor"))
+        {
+            Some(true)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 #[serde(rename_all = "PascalCase")]
 struct MonitoredCall {
     expected_arrival_time: Option<String>,
+    /// Timetabled arrival, which the expected time is late or early against
+    aimed_arrival_time: Option<String>,
     stop_point_ref: String,
     destination_display: Option<String>,
+    /// Track or platform the vehicle leaves from
+    #[serde(default)]
+    departure_platform_name: Option<String>,
+    /// `cancelled` for a departure that won't happen, among others
+    #[serde(default)]
+    departure_status: Option<String>,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// A predicted departure
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
 pub struct Upcoming {
     time: DateTime<Utc>,
+    accessible: Option<bool>,
+    #[serde(default)]
+    aimed: Option<DateTime<Utc>>,
+    #[serde(default)]
+    cancelled: bool,
+    #[serde(default)]
+    track: Option<String>,
 }
 
 struct UpcomingResponse {
     agency: String,
+    /// Journeys left out for missing a field
+    incomplete: usize,
     upcoming: BTreeMap<Line, Vec<Upcoming>>,
+    bearings: HashMap<Line, f32>,
+    line_colors: HashMap<Line, [u8; 3]>,
+    last_departures: HashMap<Line, DateTime<Utc>>,
+    /// Stops each direction's departures leave from
+    stops: BTreeMap<String, BTreeSet<Stop>>,
     live_time: DateTime<Utc>,
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
 pub struct Line {
     pub line: String,
     pub agency: String,
@@ -72,77 +469,802 @@ pub struct Line {
 }
 
 pub struct Client {
+    http: reqwest::Client,
     api_key: Arc<str>,
+    /// Endpoints other than 511.org's, by name
+    sources: Arc<HashMap<String, SourceConfig>>,
     destination_subs: Arc<HashMap<String, String>>,
+    status: Mutex<BTreeMap<String, AgencyStatus>>,
+    /// Paths in upstream responses that aren't modelled, each logged once
+    ignored_fields: Mutex<HashSet<String>>,
+    /// Cache validators from the last full response for each request URL
+    validators: Mutex<HashMap<String, Validators>>,
+    /// Agencies with at most this many stops are requested one stop at a time
+    per_stop_request_limit: usize,
+    /// Most journeys kept from a fetch of one agency
+    max_cached_journeys: usize,
+    /// Directory that each agency's cache file is kept in
+    cache_dir: PathBuf,
+    snapshot: watch::Sender<Snapshot>,
+    /// Where the direction of travel of each agency's lines comes from
+    schedules: Mutex<HashMap<String, Arc<GtfsSchedule>>>,
+    calendars: Mutex<HashMap<String, Arc<ServiceCalendar>>>,
+    accuracy: Option<Arc<AccuracyLog>>,
+    /// Departures pulled from other instances, keyed by local agency name
+    remote: Mutex<HashMap<String, Arc<AgencyDirections>>>,
+    /// Transitous stop ids, by the stop name they were looked up from
+    stop_ids: Mutex<HashMap<String, String>>,
+    /// Names of the stops of agencies fetched from 511.org without a GTFS
+    /// feed, by agency and then stop code
+    stop_names: Mutex<HashMap<String, HashMap<String, String>>>,
+    /// Log the journeys left off the board for missing a field
+    strict: bool,
+    /// When each agency last had a journey logged for each reason
+    skip_warnings: Mutex<HashMap<(String, SkipReason), Instant>>,
+}
+
+/// `ETag` and `Last-Modified` of an upstream response, sent back on the next
+/// request so that an unchanged feed doesn't have to be downloaded again
+#[derive(Clone, Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+/// One request for some of an agency's departures
+struct Request<'a> {
+    url: String,
+    /// JSON to POST, for GraphQL endpoints, which can't be asked whether a
+    /// response has changed
+    query: Option<serde_json::Value>,
+    /// The stop asked for, when stops are requested one at a time
+    stop: Option<&'a String>,
+}
+
+impl<'a> Request<'a> {
+    fn get(url: impl Into<String>, stop: Option<&'a String>) -> Self {
+        Request {
+            url: url.into(),
+            query: None,
+            stop,
+        }
+    }
+}
+
+/// Content type, text, and validators of an upstream response
+struct Body {
+    content_type: String,
+    text: String,
+    validators: Validators,
+}
+
+/// Outcome of the most recent fetches for an agency, shown on the status page
+#[derive(Clone, Default)]
+pub struct AgencyStatus {
+    /// When the agency was last fetched, successfully or not
+    pub last_fetch: Option<DateTime<Utc>>,
+
+    /// Time and message of the most recent failed fetch
+    pub last_error: Option<(DateTime<Utc>, String)>,
+
+    /// Path within the response at which the last fetch failed to parse
+    pub schema_error: Option<String>,
+
+    /// How long the last successful upstream request took
+    pub latency: Option<Duration>,
+
+    /// Bytes of the responses to the last successful fetch, not counting
+    /// those upstream said hadn't changed
+    pub response_bytes: usize,
+
+    /// Fetches which have gone over each of the agency's budgets
+    pub budget_violations: BTreeMap<Budget, usize>,
+
+    /// Budgets that the latest fetches have all gone over, and how many
+    /// fetches in a row that is
+    pub budget_streak: Option<(BTreeSet<Budget>, usize)>,
+
+    /// Number of journeys at the configured stops in the last successful fetch
+    pub journeys_retained: usize,
+
+    /// Journeys at the configured stops left out of the last successful
+    /// fetch, because they had already departed or were over the cap.
+    /// Those without a readable time are kept, and counted in
+    /// `journeys_skipped`.
+    pub journeys_dropped: usize,
+
+    /// Stop visits at any stop in the last successful fetch. Responses which
+    /// upstream said hadn't changed count the visits kept from before.
+    pub visits_returned: usize,
+
+    /// Of `visits_returned`, those at the configured stops
+    pub visits_at_stops: usize,
+
+    /// Cached journeys left off the board when it was last drawn, by reason
+    pub journeys_skipped: BTreeMap<SkipReason, usize>,
+
+    /// Size of the agency's cache file when it was last written
+    pub cache_bytes: Option<u64>,
+
+    /// When the cached data last read for rendering was fetched
+    pub cache_time: Option<DateTime<Utc>>,
+
+    pub breaker: Breaker,
 }
 
+/// Fetches in a row which must go over a budget before it shows on the
+/// status page, so that one slow response isn't reported
+pub const SUSTAINED_BUDGET_VIOLATIONS: usize = 3;
+
+/// What a fetch can go over the agency's budget for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Budget {
+    ResponseBytes,
+    Latency,
+}
+
+impl Budget {
+    pub fn name(self) -> &'static str {
+        match self {
+            Budget::ResponseBytes => "response_bytes",
+            Budget::Latency => "latency",
+        }
+    }
+}
+
+/// Why a cached journey was left off the board
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SkipReason {
+    NoExpectedTime,
+    NoLine,
+    NoDirection,
+    NoDestination,
+    /// Its expected time had passed by the time the board was drawn
+    Departed,
+}
+
+impl SkipReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            SkipReason::NoExpectedTime => "no_expected_time",
+            SkipReason::NoLine => "no_line",
+            SkipReason::NoDirection => "no_direction",
+            SkipReason::NoDestination => "no_destination",
+            SkipReason::Departed => "departed",
+        }
+    }
+}
+
+/// Shortest time between logging journeys left off the board for the same
+/// agency and reason, with `strict` on
+const SKIP_WARNING_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+/// Longest wait for an agency's stop names from 511.org
+const STOP_NAMES_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Consecutive failed fetches after which an agency stops being requested
+const BREAKER_FAILURE_THRESHOLD: u32 = 3;
+
+/// How long an agency is left alone once its breaker has opened
+const BREAKER_COOL_DOWN: Duration = Duration::from_secs(60 * 15);
+
+/// Circuit breaker which stops fetching an agency whose endpoint keeps failing,
+/// trying it again once the cool-down has passed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Breaker {
+    /// Fetching normally, counting consecutive failures
+    Closed { failures: u32 },
+
+    /// Not fetching until the given time
+    Open { until: Instant },
+
+    /// Cool-down has passed, the next fetch decides whether to close again
+    HalfOpen,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker::Closed { failures: 0 }
+    }
+}
+
+impl Breaker {
+    /// Whether a fetch should be attempted now
+    fn allow(&mut self) -> bool {
+        match *self {
+            Breaker::Closed { .. } | Breaker::HalfOpen => true,
+            Breaker::Open { until } if Instant::now() >= until => {
+                *self = Breaker::HalfOpen;
+                true
+            }
+            Breaker::Open { .. } => false,
+        }
+    }
+
+    fn record_success(&mut self) {
+        *self = Breaker::Closed { failures: 0 };
+    }
+
+    fn record_failure(&mut self) {
+        let failures = match *self {
+            Breaker::Closed { failures } => failures + 1,
+            Breaker::Open { .. } | Breaker::HalfOpen => BREAKER_FAILURE_THRESHOLD,
+        };
+
+        *self = if failures >= BREAKER_FAILURE_THRESHOLD {
+            Breaker::Open {
+                until: Instant::now() + BREAKER_COOL_DOWN,
+            }
+        } else {
+            Breaker::Closed { failures }
+        };
+    }
+
+    /// Whether the agency's data should be shown as degraded
+    pub fn is_degraded(&self) -> bool {
+        !matches!(self, Breaker::Closed { .. })
+    }
+}
+
+/// Journeys fetched for an agency, as stored in its cache file
 #[derive(Serialize, Deserialize)]
-struct Cached {
+pub struct Cached {
     journeys: Vec<MonitoredVehicleJourney>,
+    /// When the journeys were fetched, which decides whether the agency is
+    /// fetched again straight away after a restart
     live_time: DateTime<Utc>,
 }
 
+/// Journeys most recently fetched for each agency, handed from the background
+/// refresh to whatever renders the board
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    agencies: HashMap<String, Arc<Cached>>,
+}
+
+impl Snapshot {
+    pub fn insert(&mut self, agency: String, cached: Cached) {
+        self.agencies.insert(agency, Arc::new(cached));
+    }
+}
+
 #[derive(Default)]
 pub struct StopData {
     pub agencies: HashMap<String, AgencyDirections>,
+
+    /// Compass bearing each line travels in from the configured stops, where known
+    pub bearings: HashMap<Line, f32>,
+
+    /// Colour each line is known by, for providers which publish one
+    pub line_colors: HashMap<Line, [u8; 3]>,
+
+    /// Last scheduled departure of the service day for each line, where known
+    /// from the agency's GTFS feed
+    pub last_departures: HashMap<Line, DateTime<Utc>>,
+
+    /// When each agency with a configured `service_end` next stops running
+    pub service_end: HashMap<String, DateTime<Utc>>,
+
+    /// Agencies which are not being fetched because their breaker is open
+    pub degraded: BTreeSet<String>,
+
+    /// Journeys left out for missing a line, direction, destination, or
+    /// expected time
+    pub incomplete_journeys: usize,
+
+    /// Whether each agency with a GTFS calendar runs today
+    pub service: HashMap<String, ServiceDay>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AgencyDirections {
     pub live_time: DateTime<Utc>,
     pub directions: HashMap<String, AgencyDirectionLines>,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize, ToSchema)]
 pub struct AgencyDirectionLines {
+    /// Pairs of a line and its upcoming departures, soonest first
+    #[schema(value_type = Vec<Vec<Object>>)]
     pub lines: Vec<(Line, Vec<Upcoming>)>,
+    /// Stops the departures leave from
+    #[serde(default)]
+    pub stops: BTreeSet<Stop>,
+}
+
+/// A configured stop, with its name if known
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub struct Stop {
+    /// Id or code the stop is configured by
+    pub code: String,
+    pub name: Option<String>,
 }
 
+/// How often fresh data is fetched from the upstream API
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(60 * 3);
+
+/// Longest an agency which keeps failing is left between fetches, unless
+/// its circuit breaker has opened
+const MAX_BACKOFF: Duration = Duration::from_secs(60 * 15);
+
+/// Largest share of its interval by which an agency's fetch is moved at
+/// random, so that agencies on the same upstream aren't all fetched at once
+const JITTER: f64 = 0.05;
+
 pub struct DataAccess {
     client: Arc<Client>,
+    snapshot: watch::Receiver<Snapshot>,
+    supervisor: FetchSupervisor,
+}
+
+/// Background tasks which keep the departures fresh, one per agency, each
+/// fetching on its own interval and backing off from its own failures. Every
+/// task is stopped once this is dropped, such as when the config is reloaded.
+pub struct FetchSupervisor {
+    /// Each agency's task, keyed by agency name
+    tasks: BTreeMap<String, AbortHandle>,
+    /// Loads the GTFS schedules and stop names, which every task waits for
+    /// before its first fetch
+    setup: AbortHandle,
+    last_refreshed: Arc<Mutex<Option<DateTime<Utc>>>>,
+    last_attempt: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+/// What one of the supervisor's tasks fetches
+#[derive(Clone)]
+enum AgencyFetch {
+    /// An agency fetched from its upstream
+    Upstream(StopConfig),
+    /// An agency fetched from another board
+    Remote(RemoteConfig),
+}
+
+impl AgencyFetch {
+    fn all(config_file: &ConfigFile) -> Vec<Self> {
+        let upstream = config_file.stops.iter().cloned().map(AgencyFetch::Upstream);
+        let remote = config_file.remotes.iter().cloned().map(AgencyFetch::Remote);
+
+        upstream.chain(remote).collect()
+    }
+
+    fn agency(&self) -> &str {
+        match self {
+            AgencyFetch::Upstream(stop_config) => &stop_config.agency,
+            AgencyFetch::Remote(remote_config) => remote_config.local_agency(),
+        }
+    }
+}
+
+/// When an agency is next fetched
+struct Schedule {
+    interval: Duration,
+    /// Fetches which have failed in a row
+    failures: u32,
+}
+
+impl Schedule {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            failures: 0,
+        }
+    }
+
+    fn record(&mut self, succeeded: bool) {
+        self.failures = if succeeded { 0 } else { self.failures + 1 };
+    }
+
+    /// How long until an agency last fetched at `fetched` is due again, if it
+    /// was fetched less than an interval ago
+    fn due_in(&self, fetched: DateTime<Utc>) -> Option<Duration> {
+        let age = (Utc::now() - fetched).to_std().ok()?;
+        self.interval.checked_sub(age)
+    }
+
+    /// How long to wait before the next fetch: the interval, doubled for each
+    /// failure in a row up to [`MAX_BACKOFF`], and moved by up to [`JITTER`]
+    fn delay(&self) -> Duration {
+        let backoff = self
+            .interval
+            .saturating_mul(1 << self.failures.min(8))
+            .min(MAX_BACKOFF.max(self.interval));
+
+        // any source of randomness will do, as long as each fetch differs
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(1.0 + JITTER * (2.0 * random - 1.0))
+    }
+}
+
+impl FetchSupervisor {
+    fn spawn(client: Arc<Client>, config_file: ConfigFile) -> Self {
+        let last_refreshed = Arc::new(Mutex::new(None));
+        let last_attempt = Arc::new(Mutex::new(None));
+
+        let tasks = AgencyFetch::all(&config_file)
+            .into_iter()
+            .map(|fetch| {
+                let agency = fetch.agency().to_owned();
+                let task = tokio::spawn(Self::supervise(
+                    client.clone(),
+                    fetch,
+                    Schedule::new(REFRESH_INTERVAL),
+                    last_refreshed.clone(),
+                    last_attempt.clone(),
+                ));
+
+                (agency, task.abort_handle())
+            })
+            .collect();
+
+        // stop names and schedules are only looked up when the board is
+        // laid out, so fetching doesn't wait for them
+        let setup = tokio::spawn(async move {
+            client.load_gtfs(&config_file.stops).await;
+            client.load_stop_names(&config_file).await;
+        });
+
+        Self {
+            tasks,
+            setup: setup.abort_handle(),
+            last_refreshed,
+            last_attempt,
+        }
+    }
+
+    /// Keep fetching one agency for as long as the supervisor lives
+    async fn supervise(
+        client: Arc<Client>,
+        fetch: AgencyFetch,
+        mut schedule: Schedule,
+        last_refreshed: Arc<Mutex<Option<DateTime<Utc>>>>,
+        last_attempt: Arc<Mutex<Option<DateTime<Utc>>>>,
+    ) {
+        // data fetched within the interval, such as by the run before a
+        // restart, is shown until it's due rather than fetched again
+        if let Some(fetched) = client.fetched_at(fetch.agency()) {
+            if let Some(due_in) = schedule.due_in(fetched) {
+                info!(
+                    agency = fetch.agency(),
+                    ?due_in,
+                    "cached data is still fresh, waiting to fetch"
+                );
+
+                for time in [&last_refreshed, &last_attempt] {
+                    let mut time = time.lock().unwrap();
+                    *time = (*time).max(Some(fetched));
+                }
+                tokio::time::sleep(due_in).await;
+            }
+        }
+
+        loop {
+            if let Some(result) = client.fetch_agency(&fetch, schedule.interval).await {
+                match &result {
+                    Ok(()) => *last_refreshed.lock().unwrap() = Some(Utc::now()),
+                    Err(e) => warn!(agency = fetch.agency(), ?e, "failed to load stop data"),
+                }
+                schedule.record(result.is_ok());
+                *last_attempt.lock().unwrap() = Some(Utc::now());
+            }
+
+            tokio::time::sleep(schedule.delay()).await;
+        }
+    }
+
+    /// Names of the agencies being fetched
+    pub fn agencies(&self) -> impl Iterator<Item = &str> {
+        self.tasks.keys().map(String::as_str)
+    }
+}
+
+impl Drop for FetchSupervisor {
+    fn drop(&mut self) {
+        self.setup.abort();
+        for task in self.tasks.values() {
+            task.abort();
+        }
+    }
 }
 
 impl DataAccess {
     pub fn new(config_file: ConfigFile) -> Arc<Self> {
-        let access = Self {
-            client: Arc::new(Client::new(
-                config_file.api_key.clone(),
-                config_file.destination_subs.clone(),
-            )),
-        };
+        let client = Arc::new(Client::from_config(&config_file));
 
-        let client = access.client.clone();
-        tokio::spawn(async move {
-            loop {
-                if let Err(e) = client.load_stop_data(config_file.clone()).await {
-                    warn!(?e, "failed to load stop data")
-                }
-                tokio::time::sleep(std::time::Duration::from_secs(60 * 3)).await;
+        // show whatever the last run fetched until the first refresh finishes
+        client.load_disk_cache(&config_file.stops);
+
+        let snapshot = client.subscribe();
+        let supervisor = FetchSupervisor::spawn(client.clone(), config_file);
+
+        Arc::new(Self {
+            client,
+            snapshot,
+            supervisor,
+        })
+    }
+
+    /// Fetch every agency once and write their cache files, for running from
+    /// cron without the server. Fails if any agency couldn't be fetched.
+    pub async fn fetch_once(config_file: ConfigFile) -> Result<()> {
+        Arc::new(Client::from_config(&config_file))
+            .refresh(config_file)
+            .await
+    }
+
+    /// The stop data in the cache files left by the server or `fetch`, along
+    /// with the GTFS schedules, without fetching anything. Fails if any agency
+    /// has no usable cache file.
+    pub async fn cached_stop_data(config_file: &ConfigFile) -> Result<StopData> {
+        let client = Client::from_config(config_file);
+        client.load_disk_cache(&config_file.stops);
+        client.load_gtfs(&config_file.stops).await;
+
+        let snapshot = client.subscribe().borrow().clone();
+        client.stop_data(config_file, &snapshot)
+    }
+
+    /// Fetch every agency taken from the source named `source` once, `511`
+    /// for those without one, and print how well what came back conforms to
+    /// what the board expects of every provider. Nothing is cached. Fails if
+    /// any departure doesn't conform.
+    pub async fn check_provider(config_file: ConfigFile, source: &str) -> Result<()> {
+        let client = Client::from_config(&config_file);
+
+        let mut checked = 0;
+        let mut violations = 0;
+        for stop_config in &config_file.stops {
+            if stop_config.source.as_deref().unwrap_or(FIVE_ONE_ONE) != source {
+                continue;
             }
-        });
 
-        Arc::new(access)
+            let report = client
+                .check_source(
+                    &stop_config.agency,
+                    &client.source(stop_config.source.as_deref())?,
+                    &stop_config.stops,
+                    stop_config.format,
+                    Utc::now(),
+                )
+                .await
+                .wrap_err_with(|| format!("checking agency {}", stop_config.agency))?;
+            println!("{report}");
+
+            checked += 1;
+            violations += report.violations.len();
+        }
+
+        if checked == 0 {
+            return Err(eyre!("no agency is fetched from source {source}"));
+        }
+        if violations > 0 {
+            return Err(eyre!("{violations} departures from {source} don't conform"));
+        }
+
+        Ok(())
     }
 
-    pub async fn load_stop_data(&self, config_file: ConfigFile) -> Result<StopData> {
-        let mut joinset = JoinSet::new();
+    /// Where predicted and actual departures are recorded, if enabled
+    pub fn accuracy(&self) -> Option<Arc<AccuracyLog>> {
+        self.client.accuracy.clone()
+    }
 
-        for agency in config_file.stops {
-            let client = self.client.clone();
-            joinset.spawn(async move {
-                client
-                    .load_upcoming_from_cache(agency.clone())
-                    .await
-                    .wrap_err_with(|| format!("loading data for agency {}", agency.agency))
-            });
+    /// Per-agency fetch status, keyed by agency name
+    pub fn agency_status(&self) -> BTreeMap<String, AgencyStatus> {
+        self.client.status.lock().unwrap().clone()
+    }
+
+    /// The background tasks fetching each agency
+    pub fn supervisor(&self) -> &FetchSupervisor {
+        &self.supervisor
+    }
+
+    /// Time any agency was last fetched successfully in the background
+    pub fn last_refreshed(&self) -> Option<DateTime<Utc>> {
+        *self.supervisor.last_refreshed.lock().unwrap()
+    }
+
+    /// Time the last background fetch of any agency finished, whether or not
+    /// it succeeded. Agencies paused by their circuit breaker are not fetched.
+    pub fn last_attempt(&self) -> Option<DateTime<Utc>> {
+        *self.supervisor.last_attempt.lock().unwrap()
+    }
+
+    /// Upcoming departures for the configured stops, from the journeys most
+    /// recently fetched in the background
+    pub fn stop_data(&self, config_file: &ConfigFile) -> Result<StopData> {
+        let snapshot = self.snapshot.borrow().clone();
+
+        self.client.stop_data(config_file, &snapshot)
+    }
+
+    /// Directions of the journeys last fetched for an agency, keyed by the
+    /// stop they call at and then by their `DirectionRef`, for working out
+    /// what to put in a section's `direction`. `None` if the agency hasn't
+    /// been fetched.
+    pub fn directions(&self, agency: &str) -> Option<BTreeMap<String, BTreeMap<String, Heading>>> {
+        let cached = self.snapshot.borrow().agencies.get(agency).cloned()?;
+
+        let mut stops = BTreeMap::<_, BTreeMap<_, Heading>>::new();
+        for journey in &cached.journeys {
+            let direction = journey.direction_ref.clone().unwrap_or_default();
+            let heading = stops
+                .entry(journey.monitored_call.stop_point_ref.clone())
+                .or_default()
+                .entry(direction)
+                .or_default();
+
+            heading.journeys += 1;
+            heading.lines.extend(journey.line_ref.clone());
+            heading.destinations.extend(
+                journey
+                    .monitored_call
+                    .destination_display
+                    .as_ref()
+                    .or(journey.destination_name.as_ref())
+                    .cloned(),
+            );
+        }
+
+        Some(stops)
+    }
+}
+
+/// What's travelling in one direction from a stop
+#[derive(Default)]
+pub struct Heading {
+    pub journeys: usize,
+    pub lines: BTreeSet<String>,
+    /// Destinations as sent by the agency, before `destination_subs`
+    pub destinations: BTreeSet<String>,
+}
+
+impl Client {
+    fn from_config(config_file: &ConfigFile) -> Self {
+        let accuracy = config_file.accuracy_db.as_ref().and_then(|path| {
+            AccuracyLog::open(
+                Path::new(path),
+                config_file.accuracy_keep_days,
+                config_file.timezone,
+            )
+            .inspect_err(|e| warn!(error = ?e, "prediction accuracy will not be recorded"))
+            .ok()
+            .map(Arc::new)
+        });
+
+        Self {
+            cache_dir: config_file.cache_dir.clone().unwrap_or_default().into(),
+            sources: Arc::new(config_file.sources.clone()),
+            strict: config_file.strict,
+            ..Self::new(
+                config_file.api_key.clone(),
+                config_file.destination_subs.clone(),
+                config_file.per_stop_request_limit,
+                config_file.max_cached_journeys,
+                accuracy,
+            )
         }
+    }
 
+    pub fn new(
+        api_key: String,
+        destination_subs: HashMap<String, String>,
+        per_stop_request_limit: usize,
+        max_cached_journeys: usize,
+        accuracy: Option<Arc<AccuracyLog>>,
+    ) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            api_key: Arc::from(api_key),
+            sources: Arc::default(),
+            destination_subs: Arc::new(destination_subs),
+            status: Mutex::new(BTreeMap::new()),
+            ignored_fields: Mutex::new(HashSet::new()),
+            validators: Mutex::new(HashMap::new()),
+            per_stop_request_limit,
+            max_cached_journeys,
+            cache_dir: PathBuf::new(),
+            snapshot: watch::channel(Snapshot::default()).0,
+            schedules: Mutex::new(HashMap::new()),
+            calendars: Mutex::new(HashMap::new()),
+            accuracy,
+            remote: Mutex::new(HashMap::new()),
+            stop_ids: Mutex::new(HashMap::new()),
+            stop_names: Mutex::new(HashMap::new()),
+            strict: false,
+            skip_warnings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Receive every new snapshot published by [`Client::refresh`]
+    pub fn subscribe(&self) -> watch::Receiver<Snapshot> {
+        self.snapshot.subscribe()
+    }
+
+    /// Upcoming departures for the configured stops from the journeys in a
+    /// snapshot
+    pub fn stop_data(&self, config_file: &ConfigFile, snapshot: &Snapshot) -> Result<StopData> {
         let mut data = StopData {
             agencies: HashMap::new(),
+            bearings: HashMap::new(),
+            line_colors: HashMap::new(),
+            last_departures: HashMap::new(),
+            service_end: HashMap::new(),
+            degraded: self
+                .status
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(_, status)| status.breaker.is_degraded())
+                .map(|(agency, _)| agency.clone())
+                .collect(),
+            service: HashMap::new(),
+            incomplete_journeys: 0,
         };
 
-        while let Some(result) = joinset.join_next().await {
-            let response = result??;
+        let now = Utc::now().with_timezone(&config_file.timezone);
+        for (agency, calendar) in self.calendars.lock().unwrap().iter() {
+            data.service
+                .insert(agency.clone(), calendar.service_day(now.date_naive()));
+        }
+
+        for stop_config in &config_file.stops {
+            let Some(service_end) = stop_config.service_end else {
+                continue;
+            };
+
+            // the next time the clock reads `service_end`, which is usually
+            // just after midnight
+            let end = [
+                now.date_naive(),
+                now.date_naive() + chrono::Duration::days(1),
+            ]
+            .into_iter()
+            .filter_map(|date| {
+                config_file
+                    .timezone
+                    .from_local_datetime(&date.and_time(service_end))
+                    .earliest()
+            })
+            .find(|end| *end > now);
+
+            if let Some(end) = end {
+                data.service_end
+                    .insert(stop_config.agency.clone(), end.with_timezone(&Utc));
+            }
+        }
+
+        for stop_config in &config_file.stops {
+            let cached = snapshot
+                .agencies
+                .get(&stop_config.agency)
+                .ok_or_else(|| FetchError::NotFetched {
+                    agency: stop_config.agency.clone(),
+                })
+                .wrap_err_with(|| format!("loading data for agency {}", stop_config.agency))?;
+
+            self.update_status(&stop_config.agency, |status| {
+                status.cache_time = Some(cached.live_time);
+            });
+
+            let response = self
+                .transform_results(stop_config, cached, config_file.timezone)
+                .wrap_err_with(|| format!("loading data for agency {}", stop_config.agency))?;
+
+            data.incomplete_journeys += response.incomplete;
+            data.bearings.extend(response.bearings);
+            data.line_colors.extend(response.line_colors);
+            data.last_departures.extend(response.last_departures);
+
+            for (direction, stops) in response.stops {
+                data.agencies
+                    .entry(response.agency.clone())
+                    .or_default()
+                    .directions
+                    .entry(direction)
+                    .or_default()
+                    .stops
+                    .extend(stops);
+            }
 
             for (line, upcoming) in response.upcoming {
                 let agency_directions = data.agencies.entry(response.agency.clone()).or_default();
@@ -158,107 +1280,954 @@ impl DataAccess {
             }
         }
 
+        // a remote that hasn't been fetched yet fails its sections, rather
+        // than the whole board
+        let remote = self.remote.lock().unwrap();
+        for remote_config in &config_file.remotes {
+            if let Some(agency) = remote.get(remote_config.local_agency()) {
+                data.agencies
+                    .insert(remote_config.local_agency().to_owned(), (**agency).clone());
+            }
+        }
+
         Ok(data)
     }
-}
 
-impl Client {
-    pub fn new(api_key: String, destination_subs: HashMap<String, String>) -> Self {
-        Self {
-            api_key: Arc::from(api_key),
-            destination_subs: Arc::new(destination_subs),
+    /// Read the GTFS feeds configured for direction arrows and service
+    /// calendars. This can take a while for large agencies, so arrows appear
+    /// once it's done.
+    async fn load_gtfs(&self, stops: &[StopConfig]) {
+        for stop_config in stops {
+            let Some(gtfs) = stop_config.gtfs.clone() else {
+                continue;
+            };
+
+            let configured_stops = stop_config.stops.clone();
+            let loaded = tokio::task::spawn_blocking(move || {
+                let dir = Path::new(&gtfs);
+                (
+                    GtfsSchedule::load(dir, &configured_stops),
+                    ServiceCalendar::load(dir),
+                )
+            })
+            .await;
+
+            let (schedule, calendar) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    warn!(error = ?e, agency = stop_config.agency, "GTFS loading task failed");
+                    continue;
+                }
+            };
+
+            match schedule {
+                Ok(schedule) => {
+                    self.schedules
+                        .lock()
+                        .unwrap()
+                        .insert(stop_config.agency.clone(), Arc::new(schedule));
+                }
+                Err(e) => {
+                    warn!(error = ?e, agency = stop_config.agency, "failed to load GTFS stop times")
+                }
+            }
+
+            match calendar {
+                Ok(calendar) => {
+                    self.calendars
+                        .lock()
+                        .unwrap()
+                        .insert(stop_config.agency.clone(), Arc::new(calendar));
+                }
+                Err(e) => {
+                    warn!(error = ?e, agency = stop_config.agency, "failed to load GTFS calendar")
+                }
+            }
+        }
+    }
+
+    /// Look up the names of the stops of agencies fetched from 511.org, for
+    /// sections which show them, where there's no GTFS feed to find them in
+    async fn load_stop_names(&self, config_file: &ConfigFile) {
+        for stop_config in &config_file.stops {
+            if stop_config.gtfs.is_some()
+                || stop_config.source.is_some()
+                || !config_file.layout.shows_stop(&stop_config.agency)
+            {
+                continue;
+            }
+
+            let agency = &stop_config.agency;
+            let url = five_one_one::stops_url(&self.api_key, agency);
+            let names = async {
+                let text = self
+                    .http
+                    .get(url)
+                    .timeout(STOP_NAMES_TIMEOUT)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .map_err(FetchError::from)?
+                    .text()
+                    .await
+                    .map_err(FetchError::from)?;
+
+                serde_json::from_str::<five_one_one::StopsResponse>(normalize::strip_bom(&text))
+                    .wrap_err("parsing 511.org stops")
+            };
+
+            match names.await {
+                Ok(response) => {
+                    let names = response.names(&stop_config.stops);
+                    debug!(agency, stops = names.len(), "found stop names");
+                    self.stop_names
+                        .lock()
+                        .unwrap()
+                        .insert(agency.clone(), names);
+                }
+                Err(e) => warn!(error = ?e, agency, "failed to look up stop names"),
+            }
+        }
+    }
+
+    /// Name of one of an agency's configured stops, from its GTFS feed or
+    /// 511.org's stop list. Transitous stops are configured by name.
+    fn stop_name(
+        &self,
+        stop_config: &StopConfig,
+        provider: Provider,
+        schedule: Option<&GtfsSchedule>,
+        stop: &str,
+    ) -> Option<String> {
+        if provider == Provider::Transitous {
+            return Some(stop.to_owned());
         }
+
+        schedule
+            .and_then(|schedule| schedule.stop_name(stop))
+            .map(str::to_owned)
+            .or_else(|| {
+                self.stop_names
+                    .lock()
+                    .unwrap()
+                    .get(&stop_config.agency)?
+                    .get(stop)
+                    .cloned()
+            })
+    }
+
+    /// Seed the snapshot with the journeys cached on disk by a previous run
+    fn load_disk_cache(&self, stops: &[StopConfig]) {
+        for StopConfig { agency, .. } in stops {
+            self.migrate_legacy_cache(agency);
+
+            match Self::load_cached(&self.cache_path(agency)) {
+                Ok(cached) => self
+                    .snapshot
+                    .send_modify(|snapshot| snapshot.insert(agency.clone(), cached)),
+                Err(e) => debug!(error = ?e, agency, "no usable cached data"),
+            }
+        }
+    }
+
+    /// When an agency's data was last fetched, by this run or, from its cache
+    /// file, one before it
+    fn fetched_at(&self, agency: &str) -> Option<DateTime<Utc>> {
+        self.snapshot
+            .borrow()
+            .agencies
+            .get(agency)
+            .map(|cached| cached.live_time)
+    }
+
+    fn update_status(&self, agency: &str, update: impl FnOnce(&mut AgencyStatus)) {
+        update(
+            self.status
+                .lock()
+                .unwrap()
+                .entry(agency.to_owned())
+                .or_default(),
+        );
     }
 
-    async fn load_stop_data(self: &Arc<Self>, config_file: ConfigFile) -> Result<()> {
+    /// Fetch every agency once, all at the same time, and publish the
+    /// results. Fails if any agency couldn't be fetched.
+    async fn refresh(self: &Arc<Self>, config_file: ConfigFile) -> Result<()> {
         let mut joinset = JoinSet::new();
 
-        for StopConfig { agency, stops, .. } in config_file.stops {
+        for fetch in AgencyFetch::all(&config_file) {
             let client = self.clone();
-            joinset.spawn(async move {
-                client
-                    .request_and_cache(&agency, &stops)
-                    .await
-                    .wrap_err_with(|| format!("loading data for agency {}", agency))
-            });
+            joinset.spawn(async move { client.fetch_agency(&fetch, REFRESH_INTERVAL).await });
         }
 
         while let Some(result) = joinset.join_next().await {
-            result??;
+            result?.unwrap_or(Ok(()))?;
         }
 
         Ok(())
     }
 
-    fn load_cached(path: &str) -> Result<Cached> {
-        debug!(path, "trying to load cached file");
+    /// Fetch one agency and publish the results, both in the in-memory
+    /// snapshot and the cache files on disk. A fetch still running after
+    /// `interval` is cancelled and counted as failed, so that a slow upstream
+    /// can't hold up the agency's next fetch. `None` if the agency's circuit
+    /// breaker is open and it wasn't fetched at all.
+    async fn fetch_agency(
+        self: &Arc<Self>,
+        fetch: &AgencyFetch,
+        interval: Duration,
+    ) -> Option<Result<()>> {
+        let agency = fetch.agency();
+
+        let mut allowed = true;
+        self.update_status(agency, |status| allowed = status.breaker.allow());
+        if !allowed {
+            debug!(agency, "circuit breaker open, skipping fetch");
+            return None;
+        }
+
+        let started = Instant::now();
+        let result = match tokio::time::timeout(interval, self.fetch_unbounded(fetch)).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    agency,
+                    ?interval,
+                    "fetch still running when the next is due, cancelling it"
+                );
+
+                let error = eyre!("cancelled after {interval:?}, when the next fetch was due");
+                self.record_fetch(agency, started, Err(&error));
+                Err(error)
+            }
+        };
+
+        Some(result)
+    }
+
+    /// Fetch one agency, however long it takes
+    async fn fetch_unbounded(&self, fetch: &AgencyFetch) -> Result<()> {
+        let started = Instant::now();
+
+        match fetch {
+            AgencyFetch::Upstream(StopConfig {
+                agency,
+                stops,
+                format,
+                budget,
+                source,
+                normalize_destinations,
+                ..
+            }) => {
+                let result = self
+                    .request_and_cache(
+                        agency,
+                        source.as_deref(),
+                        stops,
+                        *format,
+                        normalize_destinations,
+                    )
+                    .await;
+
+                self.record_fetch(agency, started, result.as_ref().map(Vec::len));
+                if result.is_ok() {
+                    self.check_budget(agency, *budget);
+                }
+
+                result
+                    .map(|_| ())
+                    .wrap_err_with(|| format!("loading data for agency {}", agency))
+            }
+            AgencyFetch::Remote(remote_config) => {
+                let agency = remote_config.local_agency();
+                let result = remote::fetch(&self.http, remote_config)
+                    .await
+                    .map_err(eyre::Report::new);
+
+                self.record_fetch(
+                    agency,
+                    started,
+                    result.as_ref().map(|directions| {
+                        directions
+                            .directions
+                            .values()
+                            .map(|lines| lines.lines.len())
+                            .sum()
+                    }),
+                );
+
+                let directions = result
+                    .wrap_err_with(|| format!("loading data for remote agency {}", agency))?;
+                self.remote
+                    .lock()
+                    .unwrap()
+                    .insert(agency.to_owned(), Arc::new(directions));
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Update an agency's status with the outcome of fetching it, which
+    /// retained `journeys` journeys if it succeeded
+    fn record_fetch(&self, agency: &str, started: Instant, result: Result<usize, &eyre::Report>) {
+        self.update_status(agency, |status| {
+            let now = Utc::now();
+            status.last_fetch = Some(now);
+
+            match result {
+                Ok(journeys) => {
+                    status.latency = Some(started.elapsed());
+                    status.journeys_retained = journeys;
+                    status.schema_error = None;
+                    status.breaker.record_success();
+                }
+                Err(e) => {
+                    status.last_error = Some((now, format!("{e:#}")));
+                    status.schema_error = e
+                        .downcast_ref::<ParseError>()
+                        .and_then(ParseError::schema_path);
+                    status.breaker.record_failure();
+
+                    if let Breaker::Open { .. } = status.breaker {
+                        warn!(
+                            agency,
+                            cool_down = ?BREAKER_COOL_DOWN,
+                            "agency keeps failing, pausing fetches"
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    /// Compare an agency's last successful fetch with its budget, warning of
+    /// and counting each budget it went over
+    fn check_budget(&self, agency: &str, budget: BudgetConfig) {
+        self.update_status(agency, |status| {
+            let mut over = BTreeSet::new();
+
+            if let Some(max) = budget.max_response_bytes {
+                if status.response_bytes > max {
+                    warn!(
+                        agency,
+                        bytes = status.response_bytes,
+                        max,
+                        "response larger than budget"
+                    );
+                    over.insert(Budget::ResponseBytes);
+                }
+            }
+
+            if let (Some(max_ms), Some(latency)) = (budget.max_latency_ms, status.latency) {
+                if latency > Duration::from_millis(max_ms) {
+                    warn!(
+                        agency,
+                        latency_ms = latency.as_millis() as u64,
+                        max_ms,
+                        "fetch slower than budget"
+                    );
+                    over.insert(Budget::Latency);
+                }
+            }
+
+            for budget in &over {
+                *status.budget_violations.entry(*budget).or_default() += 1;
+            }
+
+            status.budget_streak = match status.budget_streak.take() {
+                _ if over.is_empty() => None,
+                Some((still_over, fetches)) => {
+                    let still_over: BTreeSet<_> = still_over.intersection(&over).copied().collect();
+                    if still_over.is_empty() {
+                        Some((over, 1))
+                    } else {
+                        Some((still_over, fetches + 1))
+                    }
+                }
+                None => Some((over, 1)),
+            };
+        });
+    }
+
+    /// Move an agency's cache file from the working directory, where it was
+    /// kept before `cache_dir` was set, into `cache_dir`, so that the board
+    /// isn't blank until the first refresh. A cache file already in
+    /// `cache_dir` is newer, so the old one is only removed.
+    fn migrate_legacy_cache(&self, agency: &str) {
+        let legacy = PathBuf::from(format!(".cache-{agency}.json"));
+        let path = self.cache_path(agency);
+
+        let moved_elsewhere = match (legacy.canonicalize(), self.cache_dir.canonicalize()) {
+            (Ok(legacy), Ok(cache_dir)) => legacy.parent() != Some(cache_dir.as_path()),
+            _ => false,
+        };
+        if !moved_elsewhere {
+            return;
+        }
+
+        let migrated = if path.exists() {
+            std::fs::remove_file(&legacy)
+        } else {
+            // renaming fails across filesystems, such as into a mounted volume
+            std::fs::rename(&legacy, &path).or_else(|_| {
+                std::fs::copy(&legacy, &path)?;
+                std::fs::remove_file(&legacy)
+            })
+        };
+
+        match migrated {
+            Ok(()) => info!(
+                agency,
+                from = %legacy.display(),
+                to = %path.display(),
+                "moved cache file into cache_dir"
+            ),
+            Err(e) => warn!(
+                error = ?e,
+                agency,
+                path = %legacy.display(),
+                "failed to move cache file into cache_dir"
+            ),
+        }
+    }
+
+    fn load_cached(path: &Path) -> Result<Cached> {
+        debug!(path = %path.display(), "trying to load cached file");
         let file = std::fs::File::open(path)?;
         let cached: Cached = serde_json::from_reader(file)?;
 
         let age = Utc::now() - cached.live_time;
-        debug!(path, ?age, "using cached data");
+        debug!(path = %path.display(), ?age, "using cached data");
 
         Ok(cached)
     }
 
-    fn store_cache(path: String, journeys: Vec<MonitoredVehicleJourney>) -> Result<()> {
-        let cached = Cached {
-            journeys,
-            live_time: Utc::now(),
-        };
-
-        debug!(path, "storing cache");
+    /// Write an agency's cache file, returning its size
+    fn store_cache(path: PathBuf, cached: &Cached) -> Result<u64> {
+        debug!(path = %path.display(), "storing cache");
 
         let file = std::fs::File::create(&path)?;
 
-        serde_json::to_writer(file, &cached)?;
+        serde_json::to_writer(&file, cached)?;
+        let bytes = file.metadata()?.len();
 
-        debug!(path, "cache ok");
+        debug!(path = %path.display(), bytes, "cache ok");
 
-        Ok(())
+        Ok(bytes)
+    }
+
+    fn cache_path(&self, agency: &str) -> PathBuf {
+        self.cache_dir.join(format!(".cache-{agency}.json"))
+    }
+
+    /// The endpoint an agency with this `source` is fetched from
+    fn source(&self, name: Option<&str>) -> Result<SourceConfig> {
+        match name {
+            None => Ok(SourceConfig::five_one_one(&self.api_key)),
+            Some(name) => self
+                .sources
+                .get(name)
+                .cloned()
+                .ok_or_else(|| eyre!("no source named {name}")),
+        }
+    }
+
+    /// The Transitous id of the stop with this name, looked up the first
+    /// time the stop is fetched and kept for as long as the server runs
+    async fn transitous_stop_id(&self, source: &SourceConfig, name: &str) -> Result<String> {
+        if let Some(id) = self.stop_ids.lock().unwrap().get(name) {
+            return Ok(id.clone());
+        }
+
+        let url = transitous::geocode_url(&source.url, name)?;
+        let matches = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(FetchError::from)?
+            .json()
+            .await
+            .map_err(FetchError::from)?;
+
+        let id = transitous::best_stop(matches).ok_or_else(|| FetchError::UnknownStop {
+            name: name.to_owned(),
+        })?;
+        info!(name, id, "found Transitous stop");
+
+        self.stop_ids
+            .lock()
+            .unwrap()
+            .insert(name.to_owned(), id.clone());
+
+        Ok(id)
     }
 
-    fn cache_path(agency: &str) -> String {
-        format!(".cache-{agency}.json")
+    /// The StopMonitoring requests for an agency, and the stop each is for
+    /// if it asks for only one
+    fn siri_requests<'a>(
+        &self,
+        source: &SourceConfig,
+        agency: &str,
+        stops: &'a [String],
+        format: FeedFormat,
+    ) -> Vec<Request<'a>> {
+        let SourceConfig {
+            url,
+            api_key,
+            quirks,
+            ..
+        } = source;
+
+        let mut url = format!(
+            "{url}?{agency_param}={agency}&format={format}",
+            agency_param = quirks.agency_param,
+            format = match format {
+                FeedFormat::Xml => "xml",
+                FeedFormat::Auto | FeedFormat::Json => "json",
+            },
+        );
+        if let Some(api_key) = api_key {
+            url.push_str(&format!("&{}={api_key}", quirks.key_param));
+        }
+
+        // with only a few stops, asking for each one is much smaller than the
+        // feed for the whole agency
+        if !stops.is_empty() && stops.len() <= self.per_stop_request_limit {
+            stops
+                .iter()
+                .map(|stop| Request::get(format!("{url}&{}={stop}", quirks.stop_param), Some(stop)))
+                .collect()
+        } else {
+            vec![Request::get(url, None)]
+        }
     }
 
-    async fn load_upcoming_from_cache(&self, stop_config: StopConfig) -> Result<UpcomingResponse> {
-        let cache_path = Self::cache_path(&stop_config.agency);
+    /// Fetch an agency's departures from `source` once, bypassing the cache
+    /// and validators, and check them against the [`conformance`] invariants
+    pub(crate) async fn check_source(
+        &self,
+        agency: &str,
+        source: &SourceConfig,
+        stops: &[String],
+        format: FeedFormat,
+        fetched_at: DateTime<Utc>,
+    ) -> Result<conformance::Report> {
+        let mut journeys = Vec::new();
+        for request in self.requests(agency, source, stops, format).await? {
+            let body = self.fetch(&request).await?.ok_or(FetchError::NotModified)?;
+            let (at_stops, _) =
+                self.parse_body(agency, &body, format, source, stops, request.stop)?;
+            journeys.extend(at_stops);
+        }
+
+        let departures = journeys.iter().map(|journey| conformance::Departure {
+            stop: &journey.monitored_call.stop_point_ref,
+            line: journey.line_ref.as_deref(),
+            expected: journey.monitored_call.expected_arrival_time.as_deref(),
+            aimed: journey.monitored_call.aimed_arrival_time.as_deref(),
+        });
 
-        let journeys =
-            tokio::task::spawn_blocking(move || Self::load_cached(&cache_path)).await??;
+        Ok(conformance::Report {
+            agency: agency.to_owned(),
+            departures: journeys.len(),
+            violations: conformance::check(departures, stops, fetched_at),
+        })
+    }
 
-        let upcoming = self.transform_results(&stop_config, journeys)?;
+    /// The requests for an agency's departures at `stops`
+    async fn requests<'a>(
+        &self,
+        agency: &str,
+        source: &SourceConfig,
+        stops: &'a [String],
+        format: FeedFormat,
+    ) -> Result<Vec<Request<'a>>> {
+        let api_key = source.api_key.as_deref();
+
+        let requests = match source.provider {
+            Provider::Siri => self.siri_requests(source, agency, stops, format),
+            // the Unified API takes every stop at once
+            Provider::Tfl => vec![Request::get(
+                tfl::arrivals_url(&source.url, stops, api_key),
+                None,
+            )],
+            Provider::Oba => stops
+                .iter()
+                .map(|stop| Request::get(oba::arrivals_url(&source.url, stop, api_key), Some(stop)))
+                .collect(),
+            Provider::Mbta => vec![Request::get(
+                mbta::predictions_url(&source.url, stops, api_key),
+                None,
+            )],
+            // GraphQL has no way of asking for several stops' departures in
+            // one list
+            Provider::Digitransit => stops
+                .iter()
+                .map(|stop| Request {
+                    url: digitransit::graphql_url(&source.url, api_key),
+                    query: Some(digitransit::stoptimes_query(stop)),
+                    stop: Some(stop),
+                })
+                .collect(),
+            Provider::Transitous => {
+                let mut requests = Vec::new();
+                for stop in stops {
+                    let id = self.transitous_stop_id(source, stop).await?;
+                    requests.push(Request::get(
+                        transitous::stoptimes_url(&source.url, &id)?,
+                        Some(stop),
+                    ));
+                }
+                requests
+            }
+        };
 
-        Ok(upcoming)
+        Ok(requests)
     }
 
     async fn request_and_cache(
         &self,
         agency: &str,
+        source: Option<&str>,
         stops: &[String],
+        format: FeedFormat,
+        normalization: &DestinationNormalization,
     ) -> Result<Vec<MonitoredVehicleJourney>> {
-        let url = format!(
-            "https://api.511.org/transit/StopMonitoring?api_key={api_key}&agency={agency}&format=json",
-            api_key=self.api_key,
+        let source = self.source(source)?;
+        let requests = self.requests(agency, &source, stops, format).await?;
+
+        let cached = self.snapshot.borrow().agencies.get(agency).cloned();
+
+        let mut journeys = Vec::new();
+        let mut validators = Vec::new();
+        let mut visits = 0;
+        let mut response_bytes = 0;
+
+        for request in requests {
+            let stop = request.stop;
+            let body = match self.fetch(&request).await? {
+                Some(body) => body,
+                None => {
+                    if let Some(cached) = &cached {
+                        debug!(agency, stop, "upstream data not modified");
+                        let before = journeys.len();
+                        journeys.extend(
+                            cached
+                                .journeys
+                                .iter()
+                                .filter(|journey| {
+                                    stop.is_none_or(|stop| {
+                                        &journey.monitored_call.stop_point_ref == stop
+                                    })
+                                })
+                                .cloned(),
+                        );
+                        visits += journeys.len() - before;
+                        continue;
+                    }
+
+                    warn!(
+                        agency,
+                        "upstream data not modified but nothing has been fetched, fetching again"
+                    );
+                    self.validators.lock().unwrap().remove(&request.url);
+                    self.fetch(&request).await?.ok_or(FetchError::NotModified)?
+                }
+            };
+
+            response_bytes += body.text.len();
+            let (at_stops, returned) =
+                self.parse_body(agency, &body, format, &source, stops, stop)?;
+            journeys.extend(at_stops);
+            visits += returned;
+            if request.query.is_none() {
+                validators.push((request.url, body.validators));
+            }
+        }
+
+        let visits_at_stops = journeys.len();
+
+        let fetched_at = Utc::now();
+
+        // every journey is observed, so that those still in the feed after
+        // their predicted time aren't counted as having left
+        let observations = self
+            .accuracy
+            .is_some()
+            .then(|| self.observations(&journeys, normalization));
+
+        let dropped = self.retain_upcoming(&mut journeys, fetched_at);
+        if dropped > 0 {
+            debug!(
+                agency,
+                dropped, "left departed and excess journeys out of the cache"
+            );
+        }
+        self.update_status(agency, |status| {
+            status.journeys_dropped = dropped;
+            status.visits_returned = visits;
+            status.visits_at_stops = visits_at_stops;
+            status.response_bytes = response_bytes;
+        });
+
+        let cached = Arc::new(Cached {
+            journeys: journeys.clone(),
+            live_time: fetched_at,
+        });
+
+        self.snapshot.send_modify(|snapshot| {
+            snapshot.agencies.insert(agency.to_owned(), cached.clone());
+        });
+
+        if let (Some(accuracy), Some(observations)) = (self.accuracy.clone(), observations) {
+            let agency_name = agency.to_owned();
+
+            if let Err(e) = tokio::task::spawn_blocking(move || {
+                accuracy.observe(&agency_name, observations, fetched_at)
+            })
+            .await?
+            {
+                warn!(error = ?e, agency, "failed to record prediction accuracy");
+            }
+        }
+
+        let cache_path = self.cache_path(agency);
+
+        match tokio::task::spawn_blocking(move || Self::store_cache(cache_path, &cached)).await? {
+            Ok(bytes) => {
+                self.update_status(agency, |status| status.cache_bytes = Some(bytes));
+
+                // only once the cache holds these responses can a later "not
+                // modified" be answered from it
+                self.validators.lock().unwrap().extend(validators);
+            }
+            Err(e) => warn!(
+                error = ?e,
+                path = %self.cache_path(agency).display(),
+                "failed to cache data"
+            ),
+        }
+
+        Ok(journeys)
+    }
+
+    /// Drop journeys which have already departed, and then all but the
+    /// soonest `max_cached_journeys`. Journeys whose departure time can't be
+    /// read are kept after the rest, within the same cap, for the board to
+    /// count as having no expected time. Returns how many were dropped.
+    fn retain_upcoming(
+        &self,
+        journeys: &mut Vec<MonitoredVehicleJourney>,
+        now: DateTime<Utc>,
+    ) -> usize {
+        let before = journeys.len();
+
+        let (mut upcoming, unreadable): (Vec<_>, Vec<_>) = std::mem::take(journeys)
+            .into_iter()
+            .filter(|journey| journey.expected_time().is_none_or(|time| time >= now))
+            .partition(|journey| journey.expected_time().is_some());
+
+        upcoming.sort_by_cached_key(MonitoredVehicleJourney::expected_time);
+
+        journeys.extend(
+            upcoming
+                .into_iter()
+                .chain(unreadable)
+                .take(self.max_cached_journeys),
         );
 
-        let response = reqwest::get(url).await?.error_for_status()?;
+        before - journeys.len()
+    }
+
+    /// A destination as the board shows it: tidied as the agency asks, then
+    /// substituted by `destination_subs`
+    fn displayed_destination(
+        &self,
+        destination: &str,
+        normalization: &DestinationNormalization,
+    ) -> String {
+        let tidied = normalize::tidy(destination, normalization);
+
+        self.destination_subs
+            .get(&tidied)
+            .cloned()
+            .unwrap_or(tidied)
+    }
 
-        let text = response.text().await?;
+    /// The departures in a fetch which can be followed from one fetch to the
+    /// next, for measuring prediction accuracy
+    fn observations(
+        &self,
+        journeys: &[MonitoredVehicleJourney],
+        normalization: &DestinationNormalization,
+    ) -> Vec<Observation> {
+        journeys
+            .iter()
+            .filter_map(|journey| {
+                let journey_ref = journey.framed_vehicle_journey_ref.as_ref()?;
+                let destination = journey
+                    .monitored_call
+                    .destination_display
+                    .as_ref()
+                    .or(journey.destination_name.as_ref())?;
+
+                Some(Observation {
+                    journey: format!(
+                        "{}/{}",
+                        journey_ref.data_frame_ref.as_deref().unwrap_or_default(),
+                        journey_ref.dated_vehicle_journey_ref.as_ref()?,
+                    ),
+                    stop: journey.monitored_call.stop_point_ref.clone(),
+                    line: journey.line_ref.clone()?,
+                    destination: self.displayed_destination(destination, normalization),
+                    predicted: journey.expected_time()?,
+                })
+            })
+            .collect()
+    }
+
+    /// The journeys at `stops` in a response, normalized by the source's
+    /// quirks, and how many journeys there were in all
+    fn parse_body(
+        &self,
+        agency: &str,
+        body: &Body,
+        format: FeedFormat,
+        source: &SourceConfig,
+        stops: &[String],
+        requested_stop: Option<&String>,
+    ) -> Result<(Vec<MonitoredVehicleJourney>, usize), ParseError> {
+        let (mut journeys, visits) =
+            self.read_body(agency, body, format, source, stops, requested_stop)?;
+
+        for journey in &mut journeys {
+            journey.normalize(&source.quirks);
+        }
+
+        Ok((journeys, visits))
+    }
+
+    /// The journeys in a response, as each provider sends them
+    fn read_body(
+        &self,
+        agency: &str,
+        body: &Body,
+        format: FeedFormat,
+        source: &SourceConfig,
+        stops: &[String],
+        requested_stop: Option<&String>,
+    ) -> Result<(Vec<MonitoredVehicleJourney>, usize), ParseError> {
+        let stripped_response = if source.quirks.strip_bom {
+            normalize::strip_bom(&body.text)
+        } else {
+            &body.text
+        };
+
+        match source.provider {
+            Provider::Siri => {}
+            Provider::Tfl => {
+                let arrivals =
+                    self.parse_response::<Vec<tfl::Arrival>>(agency, stripped_response)?;
+                let visits = arrivals.len();
+                let journeys = arrivals
+                    .into_iter()
+                    .filter(|arrival| stops.contains(&arrival.naptan_id))
+                    .map(MonitoredVehicleJourney::from)
+                    .collect();
+
+                return Ok((journeys, visits));
+            }
+            Provider::Transitous => {
+                let departures = self
+                    .parse_response::<transitous::Response>(agency, stripped_response)?
+                    .departures(requested_stop.map_or("", String::as_str));
+                let visits = departures.len();
+                let journeys = departures
+                    .into_iter()
+                    .map(MonitoredVehicleJourney::from)
+                    .collect();
+
+                return Ok((journeys, visits));
+            }
+            Provider::Digitransit => {
+                let departures = self
+                    .parse_response::<digitransit::Response>(agency, stripped_response)?
+                    .departures(requested_stop.map_or("", String::as_str))?;
+                let visits = departures.len();
+                let journeys = departures
+                    .into_iter()
+                    .map(MonitoredVehicleJourney::from)
+                    .collect();
+
+                return Ok((journeys, visits));
+            }
+            Provider::Mbta => {
+                let (departures, visits) = self
+                    .parse_response::<mbta::Response>(agency, stripped_response)?
+                    .departures(stops);
+                let journeys = departures
+                    .into_iter()
+                    .map(MonitoredVehicleJourney::from)
+                    .collect();
+
+                return Ok((journeys, visits));
+            }
+            Provider::Oba => {
+                let departures = self
+                    .parse_response::<oba::Response>(agency, stripped_response)?
+                    .departures();
+                let visits = departures.len();
+                let journeys = departures
+                    .into_iter()
+                    .filter(|departure| stops.contains(&departure.stop_id))
+                    .map(MonitoredVehicleJourney::from)
+                    .collect();
+
+                return Ok((journeys, visits));
+            }
+        }
 
-        let bom = unicode_bom::Bom::from(text.as_bytes());
+        let is_xml = match format {
+            FeedFormat::Json => false,
+            FeedFormat::Xml => true,
+            FeedFormat::Auto => {
+                body.content_type.contains("xml") || stripped_response.trim_start().starts_with('<')
+            }
+        };
 
-        let stripped_response = &text[bom.len()..];
+        let response = if is_xml {
+            quick_xml::de::from_str::<StrictSiri>(stripped_response)?.into()
+        } else if !source.quirks.one_or_many {
+            if source.quirks.siri_root {
+                self.parse_response::<StrictSiriJson>(agency, stripped_response)?
+                    .siri
+                    .into()
+            } else {
+                self.parse_response::<StrictSiri>(agency, stripped_response)?
+                    .into()
+            }
+        } else if source.quirks.siri_root {
+            self.parse_response::<SiriJson>(agency, stripped_response)?
+                .siri
+        } else {
+            self.parse_response(agency, stripped_response)?
+        };
 
-        let jd = &mut serde_json::Deserializer::from_str(stripped_response);
-        let json: StopMonitoringResponse = serde_path_to_error::deserialize(jd)?;
+        let visits = response
+            .service_delivery
+            .stop_monitoring_delivery
+            .iter()
+            .map(|delivery| delivery.monitored_stop_visit.len())
+            .sum();
 
-        let journeys = json
+        let journeys = response
             .service_delivery
             .stop_monitoring_delivery
-            .monitored_stop_visit
             .into_iter()
+            .flat_map(|delivery| delivery.monitored_stop_visit)
             .filter_map(|visit| {
                 if stops.contains(
                     &visit
@@ -273,46 +2242,200 @@ impl Client {
             })
             .collect::<Vec<_>>();
 
-        let journeys2 = journeys.clone();
+        Ok((journeys, visits))
+    }
+
+    /// Request the feed, sending the validators from the last response.
+    /// Returns `None` if upstream reports that nothing has changed since.
+    /// GraphQL queries are POSTed, and always answered in full.
+    async fn fetch(&self, request: &Request<'_>) -> Result<Option<Body>, FetchError> {
+        if let Some(query) = &request.query {
+            let response = self
+                .http
+                .post(&request.url)
+                .json(query)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            return Ok(Some(Body {
+                content_type: String::from("application/json"),
+                text: response.text().await?,
+                validators: Validators::default(),
+            }));
+        }
 
-        let cache_path = Self::cache_path(agency);
+        let url = &request.url;
+        let validators = self
+            .validators
+            .lock()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .unwrap_or_default();
+
+        let mut request = self.http.get(url);
+        if let Some(etag) = &validators.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
 
-        if let Err(e) =
-            tokio::task::spawn_blocking(move || Self::store_cache(cache_path, journeys2)).await?
-        {
-            warn!(error = ?e, path=Self::cache_path(agency), "failed to cache data");
+        let response = request.send().await?.error_for_status()?;
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
         }
 
-        Ok(journeys)
+        let header_string = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+
+        let content_type = header_string(header::CONTENT_TYPE).unwrap_or_default();
+        let validators = Validators {
+            etag: header_string(header::ETAG),
+            last_modified: header_string(header::LAST_MODIFIED),
+        };
+
+        let text = response.text().await?;
+
+        Ok(Some(Body {
+            content_type,
+            text,
+            validators,
+        }))
+    }
+
+    fn parse_response<T: de::DeserializeOwned>(
+        &self,
+        agency: &str,
+        text: &str,
+    ) -> Result<T, ParseError> {
+        let mut ignored = Vec::new();
+
+        let jd = &mut serde_json::Deserializer::from_str(text);
+        let mut record_ignored = |path: serde_ignored::Path| ignored.push(path.to_string());
+        let jd = serde_ignored::Deserializer::new(jd, &mut record_ignored);
+        let json = serde_path_to_error::deserialize(jd)?;
+
+        let mut seen = self.ignored_fields.lock().unwrap();
+        for path in ignored {
+            // array indices would make every element of a list look new
+            let path = path
+                .split('.')
+                .map(|segment| match segment.parse::<usize>() {
+                    Ok(_) => "[]",
+                    Err(_) => segment,
+                })
+                .join(".");
+
+            if seen.insert(path.clone()) {
+                debug!(agency, path, "ignoring unknown field in upstream response");
+            }
+        }
+
+        Ok(json)
+    }
+
+    /// Log a journey left off the board for missing a field, as it was sent,
+    /// at most once per agency and reason every [`SKIP_WARNING_INTERVAL`]
+    fn warn_skipped(&self, agency: &str, reason: SkipReason, journey: &MonitoredVehicleJourney) {
+        let now = Instant::now();
+
+        let mut warned = self.skip_warnings.lock().unwrap();
+        let key = (agency.to_owned(), reason);
+        if warned
+            .get(&key)
+            .is_some_and(|last| now.duration_since(*last) < SKIP_WARNING_INTERVAL)
+        {
+            return;
+        }
+        warned.insert(key, now);
+
+        let journey = serde_json::to_string(journey).unwrap_or_default();
+        warn!(
+            agency,
+            reason = reason.name(),
+            journey,
+            "left a journey off the board"
+        );
     }
 
     fn transform_results(
         &self,
         stop_config: &StopConfig,
-        cached: Cached,
+        cached: &Cached,
+        timezone: Tz,
     ) -> Result<UpcomingResponse> {
         let mut upcoming = BTreeMap::<_, Vec<_>>::new();
+        let mut bearings = HashMap::new();
+        let mut line_colors = HashMap::new();
+        let mut last_departures = HashMap::new();
+        let mut stops = BTreeMap::<_, BTreeSet<_>>::new();
+        let provider = stop_config
+            .source
+            .as_ref()
+            .and_then(|source| self.sources.get(source))
+            .map_or(Provider::Siri, |source| source.provider);
+        let schedule = self
+            .schedules
+            .lock()
+            .unwrap()
+            .get(&stop_config.agency)
+            .cloned();
+        let calendar = self
+            .calendars
+            .lock()
+            .unwrap()
+            .get(&stop_config.agency)
+            .cloned();
+        let now = Utc::now();
+        let mut skipped = BTreeMap::new();
+
+        for journey in &cached.journeys {
+            let (time, line, direction, destination) = match journey.required() {
+                Ok(x) => x,
+                Err(reason) => {
+                    *skipped.entry(reason).or_default() += 1;
+                    if self.strict {
+                        self.warn_skipped(&stop_config.agency, reason, journey);
+                    }
+                    continue;
+                }
+            };
 
-        for journey in cached.journeys {
-            let expected_arrival_time = opt_cont!(&journey.monitored_call.expected_arrival_time);
-            let line = opt_cont!(&journey.line_ref);
-            let direction = opt_cont!(&journey.direction_ref);
-            let destination = opt_cont!(journey
-                .monitored_call
-                .destination_display
-                .or(journey.destination_name));
-
-            let time = expected_arrival_time.parse::<DateTime<Utc>>()?;
-
-            if time < Utc::now() {
+            if time < now {
+                *skipped.entry(SkipReason::Departed).or_default() += 1;
                 continue;
             }
 
-            let destination = self
-                .destination_subs
-                .get(&destination)
-                .unwrap_or(&destination)
-                .clone();
+            let bearing = schedule
+                .as_ref()
+                .and_then(|schedule| schedule.bearing(line, destination));
+            let last_departure =
+                schedule
+                    .as_ref()
+                    .zip(calendar.as_ref())
+                    .and_then(|(schedule, calendar)| {
+                        schedule.last_departure(line, destination, calendar, now, timezone)
+                    });
+
+            let color = match provider {
+                Provider::Tfl => tfl::line_color(line),
+                Provider::Siri
+                | Provider::Oba
+                | Provider::Mbta
+                | Provider::Transitous
+                | Provider::Digitransit => None,
+            };
+
+            let destination =
+                self.displayed_destination(destination, &stop_config.normalize_destinations);
 
             let mut line = line.clone();
             for (prefix, replacement) in &stop_config.line_prefix_subs {
@@ -322,34 +2445,109 @@ impl Client {
                 }
             }
 
-            upcoming
-                .entry(Line {
-                    line,
-                    destination,
-                    agency: stop_config.agency.clone(),
-                    direction: direction.clone(),
-                })
-                .or_default()
-                .push(Upcoming { time })
+            let line = Line {
+                line,
+                destination,
+                agency: stop_config.agency.clone(),
+                direction: direction.clone(),
+            };
+
+            let stop = &journey.monitored_call.stop_point_ref;
+            let direction_stops = stops.entry(line.direction.clone()).or_default();
+            if !direction_stops
+                .iter()
+                .any(|known: &Stop| &known.code == stop)
+            {
+                direction_stops.insert(Stop {
+                    code: stop.clone(),
+                    name: self.stop_name(stop_config, provider, schedule.as_deref(), stop),
+                });
+            }
+
+            if let Some(bearing) = bearing {
+                bearings.entry(line.clone()).or_insert(bearing);
+            }
+            if let Some(color) = color {
+                line_colors.entry(line.clone()).or_insert(color);
+            }
+            if let Some(last_departure) = last_departure {
+                last_departures
+                    .entry(line.clone())
+                    .or_insert(last_departure);
+            }
+
+            upcoming.entry(line).or_default().push(Upcoming {
+                time,
+                accessible: journey.wheelchair_accessible(),
+                aimed: journey
+                    .monitored_call
+                    .aimed_arrival_time
+                    .as_ref()
+                    .and_then(|aimed| aimed.parse().ok()),
+                cancelled: journey
+                    .monitored_call
+                    .departure_status
+                    .as_ref()
+                    .is_some_and(|status| status.eq_ignore_ascii_case("cancelled")),
+                track: journey.monitored_call.departure_platform_name.clone(),
+            })
         }
 
         for times in upcoming.values_mut() {
             times.sort();
-            if times.len() > 4 {
-                for _ in times.drain(4..) {}
-            }
         }
 
+        let incomplete = skipped
+            .iter()
+            .filter(|(reason, _)| **reason != SkipReason::Departed)
+            .map(|(_, count)| count)
+            .sum();
+
+        self.update_status(&stop_config.agency, |status| {
+            status.journeys_skipped = skipped;
+        });
+
         Ok(UpcomingResponse {
             agency: stop_config.agency.clone(),
+            incomplete,
             upcoming,
+            bearings,
+            line_colors,
+            last_departures,
+            stops,
             live_time: cached.live_time,
         })
     }
 }
 
 impl Upcoming {
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+
+    /// Whether the vehicle can be boarded in a wheelchair, if the feed says
+    pub fn accessible(&self) -> Option<bool> {
+        self.accessible
+    }
+
     pub fn minutes(&self) -> i64 {
         (self.time - Utc::now()).num_minutes()
     }
+
+    /// Minutes behind the timetable, negative when running early, if the
+    /// feed gives a timetabled time
+    pub fn delay_minutes(&self) -> Option<i64> {
+        self.aimed.map(|aimed| (self.time - aimed).num_minutes())
+    }
+
+    /// Whether the departure won't happen, for feeds which keep cancelled
+    /// trips in
+    pub fn cancelled(&self) -> bool {
+        self.cancelled
+    }
+
+    /// Track or platform the vehicle leaves from, if the feed says
+    pub fn track(&self) -> Option<&str> {
+        self.track.as_deref()
+    }
 }
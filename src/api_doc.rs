@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use utoipa::{
+    openapi::{OpenApi as Document, Server},
+    IntoParams, OpenApi, ToSchema,
+};
+
+use crate::{
+    api_client::{AgencyDirectionLines, AgencyDirections, Line, Upcoming},
+    calibration,
+    config::ConfigFile,
+    history, output, overrides, pattern, remote, server, status, summary, trmnl,
+};
+
+/// Path the screen routes are described under, before being copied to each
+/// configured screen
+const SCREEN_PATH: &str = "/stops.png";
+
+/// Path the mirror route is described under, before being copied to each
+/// configured mirror
+const MIRROR_PATH: &str = "/mirrors/{name}.png";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "transit-kindle",
+        description = "Upcoming departures at nearby stops, drawn for e-ink screens"
+    ),
+    paths(
+        screen,
+        server::stops_html,
+        server::stops_txt,
+        summary::summary_json,
+        server::stops_svg,
+        server::stops_image,
+        server::mirror_png,
+        remote::stops_json,
+        status::status_page,
+        status::metrics,
+        status::accuracy_page,
+        status::export_csv,
+        status::directions_page,
+        history::history_page,
+        pattern::pattern_image,
+        calibration::calibration_png,
+        trmnl::setup,
+        trmnl::display,
+    ),
+    components(schemas(
+        remote::FederatedStops,
+        summary::Summary,
+        summary::SectionSummary,
+        AgencyDirections,
+        AgencyDirectionLines,
+        Line,
+        Upcoming,
+        trmnl::SetupResponse,
+        trmnl::DisplayResponse,
+        overrides::Side,
+        output::OutputFormat,
+        pattern::Frame,
+        Target,
+    ))
+)]
+struct ApiDoc;
+
+/// Who a screen route draws the board for
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+#[allow(dead_code)]
+enum Target {
+    /// Rotated to fit the Kindle's portrait framebuffer if the screen is
+    /// landscape
+    Kindle,
+    /// Drawn upright
+    Browser,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[allow(dead_code)]
+struct ScreenParams {
+    /// Picked from the screen's device, or else the client's User-Agent
+    target: Option<Target>,
+    /// Width in pixels, the device's by default
+    width: Option<u32>,
+    /// Height in pixels, the device's by default
+    height: Option<u32>,
+}
+
+/// The board as a PNG, for a configured screen
+#[utoipa::path(
+    get,
+    path = "/stops.png",
+    params(ScreenParams, overrides::LayoutOverrides),
+    responses(
+        (status = 200, description = "The board, or what went wrong drawing it", content_type = "image/png"),
+        (status = 429, description = "This client has asked for the board too often"),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached, or too many boards are being drawn"),
+    )
+)]
+#[allow(dead_code)]
+fn screen() {}
+
+/// The OpenAPI description of the routes this configuration serves, with one
+/// entry for each screen and without the optional routes left unconfigured
+pub fn document(config_file: &ConfigFile) -> Document {
+    let mut document = ApiDoc::openapi();
+    document.servers = Some(vec![Server::new(config_file.server.public_url())]);
+
+    let paths = &mut document.paths.paths;
+
+    if let Some(screen) = paths.remove(SCREEN_PATH) {
+        for configured in &config_file.screens {
+            paths.insert(configured.path.clone(), screen.clone());
+        }
+    }
+
+    if let Some(mirror) = paths.remove(MIRROR_PATH) {
+        for name in config_file.mirrors.keys() {
+            paths.insert(server::mirror_path(name), mirror.clone());
+        }
+    }
+
+    if config_file.history.is_none() {
+        paths.remove("/history");
+    }
+
+    if config_file.trmnl.is_none() {
+        paths.remove("/api/setup");
+        paths.remove("/api/display");
+    }
+
+    document
+}
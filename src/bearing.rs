@@ -0,0 +1,16 @@
+/// Looks up the compass bearing, in degrees clockwise from north, that a line
+/// travels in as it leaves the configured stops
+pub trait BearingSource: Send + Sync {
+    fn bearing(&self, line: &str, destination: &str) -> Option<f32>;
+}
+
+/// Compass bearing of the great circle from one `(lat, lon)` to another
+pub(crate) fn initial_bearing((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f32 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let delta_lon = (lon2 - lon1).to_radians();
+
+    let y = delta_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos();
+
+    (y.atan2(x).to_degrees().rem_euclid(360.0)) as f32
+}
@@ -0,0 +1,200 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use eyre::{Context, Result};
+use serde::{Deserialize, Deserializer};
+
+/// Whether an agency runs on a given day, according to its GTFS calendar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServiceDay {
+    Running,
+    /// Nothing is scheduled, e.g. a weekday-only agency on a Sunday
+    NoService,
+    /// Service which normally runs on this day of the week has been removed,
+    /// as happens on public holidays and school breaks
+    Holiday,
+}
+
+/// Days that each service id runs on, from an agency's `calendar.txt` and
+/// `calendar_dates.txt`
+pub struct ServiceCalendar {
+    weekly: Vec<WeeklyService>,
+    added: HashMap<NaiveDate, HashSet<String>>,
+    removed: HashMap<NaiveDate, HashSet<String>>,
+}
+
+struct WeeklyService {
+    service_id: String,
+    days: [bool; 7],
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+#[derive(Deserialize)]
+struct GtfsCalendar {
+    service_id: String,
+    #[serde(deserialize_with = "gtfs_bool")]
+    monday: bool,
+    #[serde(deserialize_with = "gtfs_bool")]
+    tuesday: bool,
+    #[serde(deserialize_with = "gtfs_bool")]
+    wednesday: bool,
+    #[serde(deserialize_with = "gtfs_bool")]
+    thursday: bool,
+    #[serde(deserialize_with = "gtfs_bool")]
+    friday: bool,
+    #[serde(deserialize_with = "gtfs_bool")]
+    saturday: bool,
+    #[serde(deserialize_with = "gtfs_bool")]
+    sunday: bool,
+    #[serde(deserialize_with = "gtfs_date")]
+    start_date: NaiveDate,
+    #[serde(deserialize_with = "gtfs_date")]
+    end_date: NaiveDate,
+}
+
+#[derive(Deserialize)]
+struct GtfsCalendarDate {
+    service_id: String,
+    #[serde(deserialize_with = "gtfs_date")]
+    date: NaiveDate,
+    /// 1 if service was added on the date, 2 if it was removed
+    exception_type: u8,
+}
+
+impl ServiceCalendar {
+    /// Read `calendar.txt` and `calendar_dates.txt` from an unzipped GTFS
+    /// feed. Either file may be missing, as the spec allows feeds to describe
+    /// their service with only one of them.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let weekly = read_optional::<GtfsCalendar>(&dir.join("calendar.txt"))?
+            .into_iter()
+            .map(|calendar| WeeklyService {
+                service_id: calendar.service_id,
+                days: [
+                    calendar.monday,
+                    calendar.tuesday,
+                    calendar.wednesday,
+                    calendar.thursday,
+                    calendar.friday,
+                    calendar.saturday,
+                    calendar.sunday,
+                ],
+                start: calendar.start_date,
+                end: calendar.end_date,
+            })
+            .collect();
+
+        let mut added = HashMap::<_, HashSet<_>>::new();
+        let mut removed = HashMap::<_, HashSet<_>>::new();
+
+        for exception in read_optional::<GtfsCalendarDate>(&dir.join("calendar_dates.txt"))? {
+            let dates = match exception.exception_type {
+                1 => &mut added,
+                2 => &mut removed,
+                _ => continue,
+            };
+
+            dates
+                .entry(exception.date)
+                .or_default()
+                .insert(exception.service_id);
+        }
+
+        Ok(Self {
+            weekly,
+            added,
+            removed,
+        })
+    }
+
+    /// Whether any service runs on a date, and if not, whether that's because
+    /// the regular service was removed for the day
+    pub fn service_day(&self, date: NaiveDate) -> ServiceDay {
+        let removed = self.removed.get(&date);
+        let is_removed = |service_id: &str| removed.is_some_and(|ids| ids.contains(service_id));
+
+        let regular = self
+            .weekly
+            .iter()
+            .filter(|service| service.runs_on(date))
+            .collect::<Vec<_>>();
+
+        let running = regular
+            .iter()
+            .any(|service| !is_removed(&service.service_id))
+            || self.added.get(&date).is_some_and(|ids| !ids.is_empty());
+
+        if running {
+            ServiceDay::Running
+        } else if !regular.is_empty() {
+            ServiceDay::Holiday
+        } else {
+            ServiceDay::NoService
+        }
+    }
+
+    /// Whether the trips of a service id run on a date
+    pub fn runs(&self, service_id: &str, date: NaiveDate) -> bool {
+        let listed = |dates: &HashMap<NaiveDate, HashSet<String>>| {
+            dates.get(&date).is_some_and(|ids| ids.contains(service_id))
+        };
+
+        if listed(&self.added) {
+            return true;
+        }
+        if listed(&self.removed) {
+            return false;
+        }
+
+        self.weekly
+            .iter()
+            .any(|service| service.service_id == service_id && service.runs_on(date))
+    }
+}
+
+impl WeeklyService {
+    fn runs_on(&self, date: NaiveDate) -> bool {
+        let day = match date.weekday() {
+            Weekday::Mon => 0,
+            Weekday::Tue => 1,
+            Weekday::Wed => 2,
+            Weekday::Thu => 3,
+            Weekday::Fri => 4,
+            Weekday::Sat => 5,
+            Weekday::Sun => 6,
+        };
+
+        self.days[day] && self.start <= date && date <= self.end
+    }
+}
+
+fn read_optional<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Vec<T>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    csv::Reader::from_path(path)
+        .and_then(|mut reader| reader.deserialize().collect())
+        .wrap_err_with(|| format!("reading {}", path.display()))
+}
+
+fn gtfs_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match u8::deserialize(deserializer)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        other => Err(serde::de::Error::custom(format!(
+            "expected 0 or 1, found {other}"
+        ))),
+    }
+}
+
+/// GTFS dates are written as `YYYYMMDD`
+fn gtfs_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+    let date = String::deserialize(deserializer)?;
+
+    NaiveDate::parse_from_str(&date, "%Y%m%d").map_err(serde::de::Error::custom)
+}
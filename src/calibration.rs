@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{
+    config::{ConfigFile, ScreenConfig},
+    device,
+    output::{self, OutputParams},
+    pattern,
+    render::SharedRenderData,
+};
+
+/// Largest side of a calibration image that will be drawn, so that a typo in
+/// the query string can't allocate gigabytes
+const MAX_SIDE: u32 = 4096;
+
+/// Bits per pixel of the swatches when no screen has a device or bit depth,
+/// which is the 16 greys a Kindle shows
+const DEFAULT_BIT_DEPTH: u8 = 4;
+
+#[derive(Clone)]
+pub struct CalibrationState {
+    shared: Arc<SharedRenderData>,
+    /// Bits per pixel of the first screen that's quantized
+    bit_depth: u8,
+    /// Size of the first screen with a device
+    size: (u32, u32),
+}
+
+impl CalibrationState {
+    pub fn new(shared: Arc<SharedRenderData>, config_file: &ConfigFile) -> Self {
+        let bit_depth = config_file
+            .screens
+            .iter()
+            .find_map(ScreenConfig::quantize)
+            .map_or(DEFAULT_BIT_DEPTH, |(bit_depth, _)| bit_depth);
+
+        Self {
+            shared,
+            bit_depth,
+            size: pattern::screen_size(config_file),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CalibrationParams {
+    /// Bits per pixel the swatches step through, the screen's by default
+    #[serde(default, deserialize_with = "crate::config::bit_depth")]
+    #[param(value_type = Option<u8>)]
+    bit_depth: Option<u8>,
+    /// Width in pixels, the screen's by default
+    width: Option<u32>,
+    /// Height in pixels, the screen's by default
+    height: Option<u32>,
+}
+
+/// Serve a labelled swatch of each grey the screen shows, drawn through
+/// `gray_ramp`, for finding the shades which tell the greys apart on a
+/// particular panel
+#[utoipa::path(
+    get,
+    path = "/calibration.png",
+    params(CalibrationParams),
+    responses(
+        (status = 200, description = "The swatches as a PNG with the screen's bits per pixel", content_type = "image/png"),
+        (status = 400, description = "The size or bit depth is out of range"),
+        (status = 500, description = "The swatches couldn't be drawn"),
+    )
+)]
+pub async fn calibration_png(
+    State(state): State<CalibrationState>,
+    Query(params): Query<CalibrationParams>,
+) -> Response {
+    let bit_depth = params.bit_depth.unwrap_or(state.bit_depth);
+    let width = params.width.unwrap_or(state.size.0);
+    let height = params.height.unwrap_or(state.size.1);
+
+    if !(1..=MAX_SIDE).contains(&width) || !(1..=MAX_SIDE).contains(&height) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("width and height must be between 1 and {MAX_SIDE}"),
+        )
+            .into_response();
+    }
+
+    let params = OutputParams {
+        width: width as i32,
+        height: height as i32,
+        ..OutputParams::default()
+    };
+
+    let drawn = output::blocking(move || {
+        let data = output::render_calibration_png(state.shared, bit_depth, params)?;
+
+        if bit_depth < 8 {
+            // undithered, so that each swatch is one of the panel's greys
+            return device::quantize(&data, bit_depth, false);
+        }
+
+        Ok(data)
+    })
+    .await;
+
+    match drawn {
+        Ok(data) => ([(header::CONTENT_TYPE, "image/png")], data).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to draw calibration swatches");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -1,23 +1,989 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
 
-use serde::Deserialize;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, NaiveTime, Utc,
+};
+use chrono_tz::{Tz, US::Pacific};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    device::Device, error::ConfigError, layout_engine::Metrics, listen::Listen, output,
+    rate_limit::Network, summary,
+};
 
 #[derive(Deserialize, Clone)]
 pub struct ConfigFile {
     pub stops: Vec<StopConfig>,
     #[serde(default)]
     pub destination_subs: HashMap<String, String>,
+    /// Second name for a destination, e.g. in another language, drawn in
+    /// smaller text below it. Keyed by the destination as displayed, after
+    /// `destination_subs` have been applied.
+    #[serde(default)]
+    pub secondary_destinations: HashMap<String, String>,
     pub layout: LayoutConfig,
+    /// 511.org API key, which `api_key_file` can give instead
+    #[serde(default)]
     pub api_key: String,
+    /// File holding the 511.org API key, such as a mounted Docker secret
+    #[serde(default)]
+    pub api_key_file: Option<PathBuf>,
+    /// SIRI StopMonitoring endpoints other than 511.org's, such as 511NY's,
+    /// by the name that stops give as their `source`
+    #[serde(default)]
+    pub sources: HashMap<String, SourceConfig>,
+    #[serde(default)]
+    pub profile: Profile,
+    /// Times of day the board is drawn with a different profile, e.g. large
+    /// print for reading at a glance on the way out of the door
+    #[serde(default)]
+    pub glance: Option<GlanceConfig>,
+    /// Zone the board's clock times, glance hours and service days are in,
+    /// such as `America/New_York`
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// Paths to font files used, in order, for glyphs missing from the bundled font
+    #[serde(default)]
+    pub fallback_fonts: Vec<String>,
+    #[serde(default)]
+    pub theme: Theme,
+    /// Font file the split-flap theme draws its characters in, a system
+    /// monospace font by default
+    #[serde(default)]
+    pub monospace_font: Option<String>,
+    #[serde(default)]
+    pub footer: FooterConfig,
+    #[serde(default)]
+    pub optimize_png: PngOptimizationConfig,
+    #[serde(default)]
+    pub text_rendering: TextRenderingConfig,
+    /// Shades the board's greys are drawn as, for panels which show some of
+    /// them darker or lighter than others
+    #[serde(default)]
+    pub gray_ramp: GrayRamp,
+    #[serde(default)]
+    pub bubble_palette: BubblePaletteConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    /// Agencies with at most this many configured stops are fetched with one
+    /// `stopCode` request per stop rather than the feed for the whole agency
+    #[serde(default)]
+    pub per_stop_request_limit: usize,
+    /// Most journeys kept from each fetch of an agency, keeping those
+    /// departing soonest, so that a large agency's feed can't bloat memory or
+    /// its cache file
+    #[serde(default = "default_max_cached_journeys")]
+    pub max_cached_journeys: usize,
+    /// Log each kind of journey left off the board for missing a line,
+    /// direction, destination, or expected time, with the journey as the feed
+    /// sent it
+    #[serde(default)]
+    pub strict: bool,
+    /// Directory that each agency's cache file is written to. Defaults to
+    /// the working directory, or systemd's `$STATE_DIRECTORY` with `--service`.
+    #[serde(default)]
+    pub cache_dir: Option<String>,
+    /// SQLite database that predicted and actual departure times are
+    /// recorded in, for the `/accuracy` report
+    #[serde(default)]
+    pub accuracy_db: Option<String>,
+    /// Days of departures kept in `accuracy_db`
+    #[serde(default = "default_accuracy_keep_days")]
+    pub accuracy_keep_days: i64,
+    /// Keep the board drawn after each refresh, for `/history`
+    #[serde(default)]
+    pub history: Option<HistoryConfig>,
+    /// Agencies pulled from other transit-kindle instances
+    #[serde(default)]
+    pub remotes: Vec<RemoteConfig>,
+    /// Kindle image routes, each for a screen mounted a particular way up
+    #[serde(default = "default_screens")]
+    pub screens: Vec<ScreenConfig>,
+    /// Parts of the User-Agent, ignoring case, of devices which are sent
+    /// images for the Kindle when a screen route isn't given a `target`
+    #[serde(default = "default_kindle_user_agents")]
+    pub kindle_user_agents: Vec<String>,
+    /// Directory of templates which replace the built-in ones for
+    /// `/stops.html`. Templates in it are read on every request.
+    #[serde(default)]
+    pub templates_dir: Option<String>,
+    #[serde(default)]
+    pub render_limit: RenderLimitConfig,
+    /// How often each client may ask for the board, unlimited when unset
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// What draws the board for the image routes other than the Kindle ones
+    #[serde(default)]
+    pub painter: Painter,
+    /// Serve the polling protocol of ESP32 panels running TRMNL's firmware
+    #[serde(default)]
+    pub trmnl: Option<TrmnlConfig>,
+    /// Copies of the board with parts left off, for sharing in public, each
+    /// served as `/mirrors/<name>.png`
+    #[serde(default)]
+    pub mirrors: HashMap<String, MirrorConfig>,
+}
+
+impl ConfigFile {
+    /// Profile the board is drawn with at `now`, which is the glance profile
+    /// during glance hours
+    pub fn profile_at(&self, now: DateTime<Utc>) -> Profile {
+        let time = now.with_timezone(&self.timezone).time();
+
+        match &self.glance {
+            Some(glance) if glance.hours.iter().any(|hours| hours.contains(time)) => glance.profile,
+            _ => self.profile,
+        }
+    }
+
+    /// Read the config from `path`, with any `TRANSIT_KINDLE_*` environment
+    /// variables layered over it. When the environment gives the whole
+    /// config, the file needn't exist.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let overrides = env_overrides(std::env::vars());
+        let invalid = |source| ConfigError::Invalid {
+            path: path.to_owned(),
+            source,
+        };
+
+        let file = match std::fs::File::open(path) {
+            Ok(file) => Some(file),
+            Err(source) if source.kind() == ErrorKind::NotFound && !overrides.is_empty() => None,
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_owned(),
+                    source,
+                })
+            }
+        };
+
+        let mut config_file: Self = match file {
+            // Deserialized straight from the file, errors give its line
+            Some(file) if overrides.is_empty() => {
+                serde_path_to_error::deserialize(serde_yaml::Deserializer::from_reader(file))
+                    .map_err(invalid)?
+            }
+            file => {
+                let mut config = match file {
+                    Some(file) => serde_path_to_error::deserialize(
+                        serde_yaml::Deserializer::from_reader(file),
+                    )
+                    .map_err(invalid)?,
+                    None => serde_yaml::Value::Mapping(Default::default()),
+                };
+
+                for (keys, value) in overrides {
+                    set_path(&mut config, &keys, value);
+                }
+
+                serde_path_to_error::deserialize(config).map_err(invalid)?
+            }
+        };
+
+        config_file.read_secrets()?;
+        config_file.check_layout()?;
+        config_file.check_summaries()?;
+        config_file.check_time_format()?;
+        config_file.check_trmnl()?;
+
+        Ok(config_file)
+    }
+
+    /// Refuse a layout with more sections in a column than any screen could
+    /// show, which would otherwise only come to light as a board with
+    /// sections missing
+    fn check_layout(&self) -> Result<(), ConfigError> {
+        let profiles = [self.profile]
+            .into_iter()
+            .chain(self.glance.as_ref().map(|glance| glance.profile));
+
+        for screen in &self.screens {
+            let (_, height) = screen.size();
+
+            for profile in profiles.clone() {
+                let fit = Metrics::for_profile(profile).sections_fitting(height as f32);
+                let columns = [
+                    ("left", &self.layout.left.sections, fit),
+                    ("right", &self.layout.right.sections, fit),
+                    // split between both columns
+                    ("sections", &self.layout.sections, fit * 2),
+                ];
+
+                for (column, sections, fit) in columns {
+                    if sections.len() > fit {
+                        return Err(ConfigError::SectionsDontFit {
+                            screen: screen.path.clone(),
+                            column,
+                            sections: sections.len(),
+                            fit,
+                            height,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refuse section summary templates which don't parse, rather than
+    /// failing every request to `/summary.json`
+    fn check_summaries(&self) -> Result<(), ConfigError> {
+        for section in self.layout.agency_sections() {
+            if let Some(template) = &section.summary {
+                summary::check_template(template).map_err(|source| {
+                    ConfigError::SummaryTemplate {
+                        agency: section.agency.clone(),
+                        source,
+                    }
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refuse a footer clock format with a specifier chrono doesn't know,
+    /// which would otherwise make every render panic
+    fn check_time_format(&self) -> Result<(), ConfigError> {
+        let format = &self.footer.time_format;
+
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            return Err(ConfigError::TimeFormat {
+                format: format.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Refuse a TRMNL screen which isn't one of the screen routes, which
+    /// panels would otherwise be sent to and get a 404 from
+    fn check_trmnl(&self) -> Result<(), ConfigError> {
+        let Some(trmnl) = &self.trmnl else {
+            return Ok(());
+        };
+
+        if !self
+            .screens
+            .iter()
+            .any(|screen| screen.path == trmnl.screen)
+        {
+            return Err(ConfigError::TrmnlScreen {
+                screen: trmnl.screen.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Fill in the credentials given as `*_file` paths from those files
+    fn read_secrets(&mut self) -> Result<(), ConfigError> {
+        let mut api_key = Some(std::mem::take(&mut self.api_key)).filter(|key| !key.is_empty());
+        read_secret("api_key", &mut api_key, self.api_key_file.as_deref())?;
+        // only needed by agencies fetched from 511.org
+        if self.stops.iter().any(|stop| stop.source.is_none()) {
+            self.api_key = api_key.ok_or(ConfigError::MissingSecret { field: "api_key" })?;
+        } else {
+            self.api_key = api_key.unwrap_or_default();
+        }
+
+        for source in self.sources.values_mut() {
+            read_secret(
+                "sources.api_key",
+                &mut source.api_key,
+                source.api_key_file.as_deref(),
+            )?;
+        }
+
+        for stop in &self.stops {
+            if let Some(source) = &stop.source {
+                if !self.sources.contains_key(source) {
+                    return Err(ConfigError::UnknownSource {
+                        agency: stop.agency.clone(),
+                        name: source.clone(),
+                    });
+                }
+            }
+        }
+
+        if let Some(trmnl) = &mut self.trmnl {
+            read_secret(
+                "trmnl.access_token",
+                &mut trmnl.access_token,
+                trmnl.access_token_file.as_deref(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Environment variables that configure the command line rather than the
+/// config file, despite sharing its prefix
+const COMMAND_LINE_VARS: &[&str] = &["TRANSIT_KINDLE_RELOAD_TOKEN"];
+
+/// The config values given by `TRANSIT_KINDLE_*` variables, each as the path
+/// of keys to it and its value. Nested keys are separated by a double
+/// underscore, so `TRANSIT_KINDLE_SERVER__BASE_URL` sets `server.base_url`,
+/// and values are read as YAML, so lists and maps such as `stops` can be
+/// given as a JSON blob.
+fn env_overrides(
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Vec<(Vec<String>, serde_yaml::Value)> {
+    let mut overrides: Vec<_> = vars
+        .into_iter()
+        .filter(|(name, _)| !COMMAND_LINE_VARS.contains(&name.as_str()))
+        .filter_map(|(name, value)| {
+            let keys = name
+                .strip_prefix("TRANSIT_KINDLE_")?
+                .split("__")
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>();
+            if keys.iter().any(String::is_empty) {
+                return None;
+            }
+
+            let value = serde_yaml::from_str(&value).unwrap_or(serde_yaml::Value::String(value));
+            Some((keys, value))
+        })
+        .collect();
+
+    // Set parents before their children, so that `SERVER` doesn't replace
+    // what `SERVER__PUBLIC_URL` set
+    overrides.sort_by_key(|(keys, _)| keys.len());
+    overrides
+}
+
+/// Set the value at a path of keys, making maps along the way for any that
+/// are missing
+fn set_path(config: &mut serde_yaml::Value, keys: &[String], value: serde_yaml::Value) {
+    let Some((key, rest)) = keys.split_first() else {
+        *config = value;
+        return;
+    };
+
+    if !config.is_mapping() {
+        *config = serde_yaml::Value::Mapping(Default::default());
+    }
+    let serde_yaml::Value::Mapping(map) = config else {
+        unreachable!("replaced with a mapping above");
+    };
+
+    let child = map
+        .entry(serde_yaml::Value::String(key.clone()))
+        .or_insert(serde_yaml::Value::Null);
+    set_path(child, rest, value);
+}
+
+/// Replace `value` with the contents of `file`, without the trailing newline
+/// most editors and `echo` leave, if a file is given
+fn read_secret(
+    field: &'static str,
+    value: &mut Option<String>,
+    file: Option<&Path>,
+) -> Result<(), ConfigError> {
+    let Some(file) = file else {
+        return Ok(());
+    };
+
+    if value.is_some() {
+        return Err(ConfigError::SecretConflict { field });
+    }
+
+    let secret = std::fs::read_to_string(file).map_err(|source| ConfigError::Secret {
+        field,
+        path: file.to_owned(),
+        source,
+    })?;
+    *value = Some(secret.trim_end().to_owned());
+
+    Ok(())
+}
+
+/// A kindling image route for one mounted screen
+#[derive(Deserialize, Clone)]
+pub struct ScreenConfig {
+    /// Route the image is served on, e.g. `/stops.png`
+    pub path: String,
+    #[serde(default)]
+    pub orientation: Orientation,
+    /// E-reader the route is for, which sets the image's default size,
+    /// rotation, grey levels, and dithering
+    #[serde(default)]
+    pub device: Option<Device>,
+    /// Bits per pixel of grey to send, overriding the device's
+    #[serde(default, deserialize_with = "bit_depth")]
+    pub bit_depth: Option<u8>,
+    /// Whether to dither down to `bit_depth`, overriding the device's
+    #[serde(default)]
+    pub dither: Option<bool>,
+}
+
+pub(crate) fn bit_depth<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u8>, D::Error> {
+    match u8::deserialize(deserializer)? {
+        bit_depth @ (1 | 2 | 4 | 8) => Ok(Some(bit_depth)),
+        other => Err(serde::de::Error::custom(format!(
+            "expected a bit depth of 1, 2, 4, or 8, found {other}"
+        ))),
+    }
+}
+
+impl ScreenConfig {
+    /// Width and height the board is drawn at by default, which is the
+    /// device's screen, or else the same size as `/stops.image`, turned to
+    /// the way the screen is mounted
+    pub fn size(&self) -> (u32, u32) {
+        let (width, height) = match self.device {
+            Some(device) => (device.profile().width, device.profile().height),
+            None => (output::DEFAULT_WIDTH as u32, output::DEFAULT_HEIGHT as u32),
+        };
+        let (long, short) = (width.max(height), width.min(height));
+
+        match self.orientation {
+            Orientation::Landscape => (long, short),
+            Orientation::Portrait => (short, long),
+        }
+    }
+
+    /// Bits per pixel and whether to dither, if the route's PNGs are reduced
+    /// to fewer greys than they're drawn with
+    pub fn quantize(&self) -> Option<(u8, bool)> {
+        let profile = self.device.map(Device::profile);
+        let bit_depth = self
+            .bit_depth
+            .or(profile.map(|profile| profile.bit_depth))
+            .filter(|bit_depth| *bit_depth < 8)?;
+        let dither = self
+            .dither
+            .or(profile.map(|profile| profile.dither))
+            .unwrap_or(false);
+
+        Some((bit_depth, dither))
+    }
+}
+
+/// What a mirror of the board leaves off, such as the line which gives away
+/// where its owner lives. Everything else is laid out from the same data as
+/// the board.
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct MirrorConfig {
+    /// Sections to leave off, named as for the `sections` query parameter: a
+    /// text section's title, or an agency, along with the sections beneath it
+    pub hide_sections: Vec<String>,
+    /// Ids of lines to leave out of every agency section
+    pub hide_lines: Vec<String>,
+    /// Leave off the stop names of sections with `show_stop`
+    pub hide_stops: bool,
+}
+
+/// Which way up a screen is mounted. Landscape images are rotated for the
+/// Kindle, whose framebuffer is portrait.
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    #[default]
+    Landscape,
+    Portrait,
+}
+
+fn default_max_cached_journeys() -> usize {
+    2000
+}
+
+fn default_timezone() -> Tz {
+    Pacific
+}
+
+fn default_kindle_user_agents() -> Vec<String> {
+    ["Kindle", "Kobo", "reMarkable"].map(String::from).to_vec()
+}
+
+fn default_screens() -> Vec<ScreenConfig> {
+    vec![ScreenConfig {
+        path: String::from("/stops.png"),
+        orientation: Orientation::Landscape,
+        device: None,
+        bit_depth: None,
+        dither: None,
+    }]
+}
+
+/// ESP32 e-ink panels polling `/api/display` for the image to show
+#[derive(Deserialize, Clone)]
+pub struct TrmnlConfig {
+    /// Screen route whose image the panels are sent to, `/stops.png` by
+    /// default
+    #[serde(default = "default_trmnl_screen")]
+    pub screen: String,
+    /// Key panels must send in their `Access-Token` header, to `/api/setup`
+    /// as well as `/api/display`, so it's entered on each panel by hand. Any
+    /// panel may poll when unset.
+    #[serde(default)]
+    pub access_token: Option<String>,
+    /// File holding the `access_token`
+    #[serde(default)]
+    pub access_token_file: Option<PathBuf>,
+}
+
+fn default_trmnl_screen() -> String {
+    String::from("/stops.png")
+}
+
+/// Where boards are kept for `/history`, and for how long
+#[derive(Deserialize, Clone)]
+pub struct HistoryConfig {
+    /// SQLite database the boards are written to
+    pub db: String,
+    #[serde(default = "default_history_keep_days")]
+    pub keep_days: i64,
+}
+
+fn default_history_keep_days() -> i64 {
+    7
+}
+
+fn default_accuracy_keep_days() -> i64 {
+    90
+}
+
+/// An agency shown on another transit-kindle instance, pulled from its
+/// `/stops.json`
+#[derive(Deserialize, Clone)]
+pub struct RemoteConfig {
+    /// Base URL of the other instance, including any path prefix
+    pub url: String,
+    /// Agency name in the other instance's config
+    pub agency: String,
+    /// Name that sections use for the agency here, if it differs from
+    /// `agency`, e.g. to tell it apart from the same agency fetched locally
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl RemoteConfig {
+    pub fn local_agency(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.agency)
+    }
+}
+
+/// How the app is reached from the outside, for running behind a reverse proxy
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Scheme and host that clients use to reach the server, without the path
+    /// prefix
+    pub base_url: String,
+    /// Path that every route is mounted under, e.g. `/transit`
+    pub path_prefix: String,
+    /// Log the client address from `X-Forwarded-For`, and accept requests
+    /// whose prefix was stripped by the proxy and sent in `X-Forwarded-Prefix`.
+    /// Only enable this when the server is reachable exclusively through a
+    /// proxy that sets them.
+    pub trust_forwarded_headers: bool,
+    /// Addresses to serve on, all at once, when `--listen` isn't given
+    pub listen: Vec<Listen>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            base_url: String::from("http://transit.lilys.hair"),
+            path_prefix: String::new(),
+            trust_forwarded_headers: false,
+            listen: Vec::new(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The path prefix with a single leading slash and no trailing slash, or
+    /// an empty string when routes are mounted at the root
+    pub fn normalized_prefix(&self) -> String {
+        let trimmed = self.path_prefix.trim_matches('/');
+
+        if trimmed.is_empty() {
+            String::new()
+        } else {
+            format!("/{trimmed}")
+        }
+    }
+
+    /// Base URL that kindling uses for links to the board, including the
+    /// path prefix
+    pub fn public_url(&self) -> String {
+        format!(
+            "{}{}",
+            self.base_url.trim_end_matches('/'),
+            self.normalized_prefix()
+        )
+    }
+}
+
+/// How many boards are drawn at once. Requests beyond that wait their turn,
+/// and are turned away once too many are waiting or they've waited too long.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct RenderLimitConfig {
+    /// Boards drawn at the same time
+    pub permits: usize,
+    /// Requests allowed to wait for a permit
+    pub queue: usize,
+    /// Seconds a request waits for a permit before being turned away
+    pub timeout_secs: u64,
+}
+
+impl Default for RenderLimitConfig {
+    fn default() -> Self {
+        Self {
+            permits: 2,
+            queue: 8,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// How often each client may ask for the board before being answered with
+/// `429 Too Many Requests`
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Requests allowed per minute, on average
+    pub per_minute: u32,
+    /// Requests allowed in a burst, after a quiet spell
+    pub burst: u32,
+    /// Addresses and networks, such as `192.168.1.0/24`, which aren't limited
+    pub allow: Vec<Network>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_minute: 6,
+            burst: 10,
+            allow: Vec::new(),
+        }
+    }
+}
+
+/// Whether PNG responses are re-encoded as 16-level greyscale, per render
+/// target
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct PngOptimizationConfig {
+    pub kindle: bool,
+    pub browser: bool,
+}
+
+/// How text is drawn, per render target
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct TextRenderingConfig {
+    pub kindle: TextRendering,
+    pub browser: TextRendering,
+}
+
+impl TextRenderingConfig {
+    pub fn get(&self, target: RenderTarget) -> TextRendering {
+        match target {
+            RenderTarget::Kindle => self.kindle,
+            RenderTarget::Browser => self.browser,
+        }
+    }
+}
+
+/// Who the board is drawn for, as given by a request's `target`
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum RenderTarget {
+    Kindle,
+    #[default]
+    Browser,
+}
+
+/// How the edges of the board's text are drawn
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(default)]
+pub struct TextRendering {
+    pub anti_alias: AntiAlias,
+    /// How far glyph outlines are moved to line up with the pixels
+    pub hinting: Hinting,
+    /// Thicken small text by outlining it as well as filling it, making up
+    /// for the contrast e-ink screens lose on thin strokes
+    pub fake_bold: Option<FakeBold>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct FakeBold {
+    /// Text set smaller than this many pixels is thickened
+    pub below: f32,
+    /// Width of the outline drawn around each glyph, in pixels
+    #[serde(default = "default_fake_bold_stroke")]
+    pub stroke: f32,
+}
+
+fn default_fake_bold_stroke() -> f32 {
+    0.6
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum AntiAlias {
+    /// Hard-edged text, which e-ink screens can show crisper than shaded
+    /// edges reduced to their few greys
+    Off,
+    /// Edges shaded in grey
+    #[default]
+    Grayscale,
+}
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Hinting {
+    None,
+    Slight,
+    #[default]
+    Normal,
+    Full,
+}
+
+/// How the bubbles behind line ids are shaded
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BubblePaletteConfig {
+    /// Colour of each line's bubble by its id, e.g. `"#005b95"`, in place of
+    /// the colour its feed gives it, if any
+    #[serde(deserialize_with = "line_colors")]
+    pub colors: HashMap<String, [u8; 3]>,
+    /// Least difference in shade, from 0 to 1, between the bubble of a line
+    /// without a colour and the bubble above it
+    pub min_contrast: f32,
+}
+
+impl Default for BubblePaletteConfig {
+    fn default() -> Self {
+        Self {
+            colors: HashMap::new(),
+            min_contrast: 0.1,
+        }
+    }
+}
+
+fn line_colors<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, [u8; 3]>, D::Error> {
+    HashMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(line, color)| {
+            let hex = color.trim_start_matches('#');
+            let channel = |i: usize| {
+                hex.get(i..i + 2)
+                    .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            };
+
+            match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(r), Some(g), Some(b)) => Ok((line, [r, g, b])),
+                _ => Err(serde::de::Error::custom(format!(
+                    "expected a colour such as \"#005b95\" for line {line}, found {color:?}"
+                ))),
+            }
+        })
+        .collect()
+}
+
+/// The shade each grey is drawn as, from black to white. A ramp of `n`
+/// shades gives the greys `0`, `1/(n-1)`, ... `1`, which are drawn as the
+/// shades given, with the greys between them blended from their neighbours.
+/// The default of `[0, 1]` draws every grey as it is.
+#[derive(Clone, PartialEq, Debug)]
+pub struct GrayRamp(Vec<f32>);
+
+impl Default for GrayRamp {
+    fn default() -> Self {
+        Self(vec![0.0, 1.0])
+    }
+}
+
+impl<'de> Deserialize<'de> for GrayRamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let shades = Vec::<f32>::deserialize(deserializer)?;
+
+        if shades.len() < 2 || shades.iter().any(|shade| !(0.0..=1.0).contains(shade)) {
+            return Err(serde::de::Error::custom(
+                "expected at least two shades, each from 0 to 1",
+            ));
+        }
+
+        Ok(Self(shades))
+    }
+}
+
+impl GrayRamp {
+    /// The shade `shade` is drawn as
+    pub fn map(&self, shade: f32) -> f32 {
+        let steps = self.0.len() - 1;
+        let position = shade.clamp(0.0, 1.0) * steps as f32;
+        let below = (position as usize).min(steps - 1);
+        let blend = position - below as f32;
+
+        self.0[below] + (self.0[below + 1] - self.0[below]) * blend
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct FooterConfig {
+    /// Age in minutes after which an agency's data is shown as stale
+    pub warning_minutes: i64,
+
+    /// Age in minutes after which an agency's data is shown as critically stale
+    pub critical_minutes: i64,
+
+    /// Text shown after each agency's status icon. `{mins}` is replaced with
+    /// the age of the agency's data in minutes
+    pub fresh_format: String,
+    pub warning_format: String,
+    pub critical_format: String,
+
+    /// strftime-style format of the current time shown in the footer
+    pub time_format: String,
+}
+
+impl Default for FooterConfig {
+    fn default() -> Self {
+        Self {
+            warning_minutes: 5,
+            critical_minutes: 15,
+            fresh_format: String::new(),
+            warning_format: String::from("{mins} mins"),
+            critical_format: String::from("{mins} mins"),
+            time_format: String::from("%a %b %d - %H:%M"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Profile {
+    #[default]
+    Standard,
+
+    /// Bigger text, higher contrast, and a single line per agency row
+    LargePrint,
+}
+
+/// Hours of the day, in local time, that the board switches profile
+#[derive(Deserialize, Clone)]
+pub struct GlanceConfig {
+    pub hours: Vec<HourRange>,
+    /// Profile drawn during those hours, large print unless set
+    #[serde(default = "default_glance_profile")]
+    pub profile: Profile,
+}
+
+fn default_glance_profile() -> Profile {
+    Profile::LargePrint
+}
+
+/// From `start` until `end`, wrapping past midnight if `end` is earlier
+#[derive(Deserialize, Clone, Copy)]
+pub struct HourRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl HourRange {
+    pub fn contains(self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+
+/// How departure times are drawn
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Theme {
+    #[default]
+    Standard,
+
+    /// Each character in a cell of its own, like the flaps of a split-flap
+    /// departure board
+    SplitFlap,
+}
+
+/// Backend that draws the laid out board
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Painter {
+    #[default]
+    Skia,
+
+    /// A pure Rust painter, only available when built with the `tiny-skia`
+    /// feature. Draws PNGs only, and doesn't support font fallback or
+    /// right-to-left text.
+    TinySkia,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct LayoutConfig {
+    #[serde(default)]
     pub left: SideConfig,
+    #[serde(default)]
     pub right: SideConfig,
+    /// Sections split between the two columns as they're drawn, in order, so
+    /// that the columns come out about the same height. When set, `left` and
+    /// `right` are ignored.
+    #[serde(default)]
+    pub sections: Vec<SectionConfig>,
+    /// Most lines shown in each agency section, keeping those departing soonest
+    #[serde(default)]
+    pub max_lines: Option<usize>,
+    /// Minutes taken off every departure time, hiding departures which would
+    /// then be in the past. Set to about how often the display refreshes, so
+    /// that it doesn't show departures that have already left.
+    #[serde(default)]
+    pub departure_lead_minutes: i64,
+    /// Leave out departures which the feed says aren't wheelchair accessible
+    #[serde(default)]
+    pub hide_inaccessible: bool,
+    /// Show a line's last departure of the night once it's this many minutes
+    /// away or less
+    #[serde(default)]
+    pub last_departure_minutes: Option<i64>,
 }
 
-#[derive(Deserialize, Clone)]
+impl LayoutConfig {
+    /// Whether any section of the agency's names its stop
+    pub fn shows_stop(&self, agency: &str) -> bool {
+        self.agency_sections()
+            .any(|section| section.agency == agency && section.show_stop)
+    }
+
+    /// Every agency section, in either column or split between them
+    pub fn agency_sections(&self) -> impl Iterator<Item = &AgencySectionConfig> {
+        self.left
+            .sections
+            .iter()
+            .chain(&self.right.sections)
+            .chain(&self.sections)
+            .filter_map(|section| match section {
+                SectionConfig::AgencySection(section) => Some(section),
+                SectionConfig::TextSection(_) => None,
+            })
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
 pub struct SideConfig {
     pub sections: Vec<SectionConfig>,
 }
@@ -32,12 +998,119 @@ pub enum SectionConfig {
 #[derive(Deserialize, Clone)]
 pub struct TextSectionConfig {
     pub text: String,
+    #[serde(default)]
+    pub style: SectionStyle,
 }
 
 #[derive(Deserialize, Clone)]
 pub struct AgencySectionConfig {
     pub agency: String,
     pub direction: String,
+    #[serde(default)]
+    pub style: SectionStyle,
+    /// Show how many departures each line has within this many minutes and
+    /// the soonest one, instead of listing the individual times
+    #[serde(default)]
+    pub window_minutes: Option<i64>,
+    /// Draw the section as a grid of each line's next departures, one column
+    /// per departure with its clock time, like a station departure board
+    #[serde(default)]
+    pub timetable_columns: Option<usize>,
+    /// Most destinations shown for any one line, soonest first, for lines
+    /// with many branches. The rest are merged into a single row, such as
+    /// "…and 3 more branches".
+    #[serde(default)]
+    pub max_branches: Option<usize>,
+    /// Name the stop the section's departures leave from at its top, such as
+    /// "at Church St & Duboce Ave", from the agency's GTFS feed, or 511.org's
+    /// list of stops
+    #[serde(default)]
+    pub show_stop: bool,
+    /// Settings for particular lines in the section
+    #[serde(default)]
+    pub lines: Vec<LineFilter>,
+    /// Template of the sentence `/summary.json` reads the section out as, in
+    /// place of e.g. "Next N toward Ocean Beach in 4 and 12 minutes."
+    #[serde(default)]
+    pub summary: Option<String>,
+}
+
+/// Settings for the lines in a section with the id `line`
+#[derive(Deserialize, Clone)]
+pub struct LineFilter {
+    pub line: String,
+    /// Always draw the line first in its section, in larger text, and keep
+    /// it when `max_lines` leaves others out
+    #[serde(default)]
+    pub pin: bool,
+    /// The section's `max_branches`, for this line alone
+    #[serde(default)]
+    pub max_branches: Option<usize>,
+    /// Leave the line out of the section
+    #[serde(default)]
+    pub hide: bool,
+}
+
+impl AgencySectionConfig {
+    /// Whether the line with this id is pinned to the top of the section
+    pub fn pinned(&self, line: &str) -> bool {
+        self.lines
+            .iter()
+            .any(|filter| filter.pin && filter.line == line)
+    }
+
+    /// Whether the line with this id is left out of the section
+    pub fn hidden(&self, line: &str) -> bool {
+        self.lines
+            .iter()
+            .any(|filter| filter.hide && filter.line == line)
+    }
+
+    /// Most destinations shown for the line with this id, if limited
+    pub fn max_branches(&self, line: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .find(|filter| filter.line == line)
+            .and_then(|filter| filter.max_branches)
+            .or(self.max_branches)
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+pub struct SectionStyle {
+    /// Grey level of the section background, from 0.0 (black) to 1.0 (white)
+    pub background: Option<f32>,
+
+    /// Stroke width of the separator drawn above the section
+    pub border_weight: Option<f32>,
+
+    #[serde(default)]
+    pub title_style: TitleStyle,
+
+    /// Which way a text section's title reads
+    #[serde(default)]
+    pub title_direction: TitleDirection,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleDirection {
+    #[default]
+    Horizontal,
+    /// Turned to read upward, in a strip beside the sections below it, for
+    /// columns too narrow for the title
+    Vertical,
+    /// Vertical when the title is wider than its column
+    Auto,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TitleStyle {
+    #[default]
+    Normal,
+    Bold,
+    Inverse,
 }
 
 #[derive(Deserialize, Clone)]
@@ -46,4 +1119,179 @@ pub struct StopConfig {
     #[serde(default)]
     pub line_prefix_subs: HashMap<String, String>,
     pub stops: Vec<String>,
+    #[serde(default)]
+    pub format: FeedFormat,
+    /// Directory holding the agency's unzipped GTFS feed, used to draw an
+    /// arrow showing which way each line travels from the configured stops,
+    /// and to tell a holiday apart from a broken feed
+    #[serde(default)]
+    pub gtfs: Option<String>,
+    /// Time of day that the agency stops running, e.g. `01:00`, used to find
+    /// each line's last departure when there's no GTFS feed
+    #[serde(default)]
+    pub service_end: Option<NaiveTime>,
+    #[serde(default)]
+    pub budget: BudgetConfig,
+    /// Name of the entry in `sources` the agency is fetched from, 511.org
+    /// when unset
+    #[serde(default)]
+    pub source: Option<String>,
+    /// Clean-up of the agency's destination names, done before
+    /// `destination_subs`, which are then keyed by the cleaned-up names
+    #[serde(default)]
+    pub normalize_destinations: DestinationNormalization,
+}
+
+/// Ways of tidying destination names, each off unless set
+#[derive(Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct DestinationNormalization {
+    /// Names in capitals, such as `BALBOA PARK`, made `Balboa Park`
+    pub titlecase: bool,
+    /// Runs of whitespace made a single space, and any at either end trimmed
+    pub collapse_whitespace: bool,
+    /// Words taken off the end of names, such as `Station` or `Transit
+    /// Center`, whatever their case. A name that is nothing but one is left
+    /// alone.
+    pub strip_suffixes: Vec<String>,
+}
+
+/// Name that agencies without a `source` are fetched from, 511.org
+pub const FIVE_ONE_ONE: &str = "511";
+
+/// A SIRI StopMonitoring endpoint run by another region, or another kind of
+/// API, with its own key
+#[derive(Deserialize, Clone)]
+pub struct SourceConfig {
+    #[serde(default)]
+    pub provider: Provider,
+    /// The endpoint's URL, without a query string. For TfL, the base URL of
+    /// the Unified API.
+    pub url: String,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// File holding the `api_key`
+    #[serde(default)]
+    pub api_key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub quirks: SourceQuirks,
+}
+
+impl SourceConfig {
+    /// 511.org, which agencies without a `source` are fetched from
+    pub fn five_one_one(api_key: &str) -> Self {
+        Self {
+            provider: Provider::Siri,
+            url: String::from("https://api.511.org/transit/StopMonitoring"),
+            api_key: Some(api_key.to_owned()),
+            api_key_file: None,
+            quirks: SourceQuirks::default(),
+        }
+    }
+}
+
+/// The kind of API a source serves
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    /// SIRI StopMonitoring, as 511.org and most regional hubs serve it
+    #[default]
+    Siri,
+    /// Transport for London's Unified API, whose stops are NaPTAN codes and
+    /// whose directions are platform names, e.g. `Westbound - Platform 1`
+    Tfl,
+    /// A OneBusAway server's `arrivals-and-departures-for-stop`, whose stops
+    /// are OneBusAway stop ids, e.g. `1_75403`, and whose directions are each
+    /// trip's GTFS `direction_id`, `0` or `1`
+    Oba,
+    /// The MBTA's V3 API, whose stops are MBTA stop ids, either platforms or
+    /// whole stations such as `place-sstat`, and whose directions are each
+    /// trip's `direction_id`, `0` or `1`
+    Mbta,
+    /// Transitous, the community MOTIS instance covering much of Europe from
+    /// GTFS feeds, whose stops are given by name and looked up once, and
+    /// whose directions are each trip's headsign
+    Transitous,
+    /// A Digitransit GraphQL API, such as Helsinki's, or another
+    /// OpenTripPlanner deployment's, whose stops are GTFS ids such as
+    /// `HSL:1040129`, each asked for in its own query, and whose directions
+    /// are the trip's `direction_id`
+    Digitransit,
+}
+
+/// How an endpoint differs from 511.org's in what it's asked for and how it
+/// answers
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct SourceQuirks {
+    /// Query parameter the key is sent in
+    pub key_param: String,
+    /// Query parameter the agency is sent in
+    pub agency_param: String,
+    /// Query parameter a single stop is asked for with
+    pub stop_param: String,
+    /// JSON responses are wrapped in a `Siri` object, as the SIRI standard
+    /// has them, rather than starting at `ServiceDelivery`
+    pub siri_root: bool,
+    /// Strip a byte order mark from the start of responses, as 511 sends
+    pub strip_bom: bool,
+    /// Accept a single object, or a string, where SIRI has a list, as 511
+    /// sends when there's only one. Otherwise that's a malformed response.
+    pub one_or_many: bool,
+    /// Zone that times without a UTC offset are in, such as
+    /// `Europe/Brussels`. Without one, departures at such times are left off
+    /// the board.
+    pub timezone: Option<Tz>,
+    /// How destination names are cased
+    pub destination_case: DestinationCase,
+}
+
+/// Casing of destination names
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DestinationCase {
+    /// As the source sends them
+    #[default]
+    AsSent,
+    /// Names sent in capitals, such as `BALBOA PARK`, made `Balboa Park`
+    Title,
+}
+
+impl Default for SourceQuirks {
+    fn default() -> Self {
+        Self {
+            key_param: String::from("api_key"),
+            agency_param: String::from("agency"),
+            stop_param: String::from("stopCode"),
+            siri_root: false,
+            strip_bom: true,
+            one_or_many: true,
+            timezone: None,
+            destination_case: DestinationCase::AsSent,
+        }
+    }
+}
+
+/// Limits on what fetching an agency should take, past which a warning is
+/// logged and counted, such as when 511 starts sending the whole region's
+/// feed rather than one agency's
+#[derive(Deserialize, Clone, Copy, Default)]
+pub struct BudgetConfig {
+    /// Most bytes of responses to one fetch, across its per-stop requests
+    #[serde(default)]
+    pub max_response_bytes: Option<usize>,
+    /// Longest a fetch should take, in milliseconds
+    #[serde(default)]
+    pub max_latency_ms: Option<u64>,
+}
+
+/// Encoding requested from the StopMonitoring endpoint
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedFormat {
+    /// Ask for JSON, but parse XML if that's what comes back
+    #[default]
+    Auto,
+    Json,
+    Xml,
 }
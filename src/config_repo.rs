@@ -0,0 +1,272 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use eyre::{Context, Result};
+use serde::Deserialize;
+use tokio::{
+    process::Command,
+    sync::{watch, Mutex},
+};
+use tracing::{info, warn};
+
+use crate::{
+    api_client::DataAccess,
+    config::ConfigFile,
+    error::{ConfigError, Failure},
+    render::SharedRenderData,
+    server, service,
+};
+
+/// A Git repository holding `stops.yml`, kept checked out locally so that the
+/// board's config can be managed like the rest of a declarative setup
+pub struct ConfigRepo {
+    pub url: String,
+    pub branch: String,
+    /// Path of the config file within the repository
+    pub file: PathBuf,
+    /// Directory the repository is checked out in
+    pub checkout: PathBuf,
+}
+
+impl ConfigRepo {
+    /// Clone the repository, or reset the checkout to the tip of the branch,
+    /// returning the commit now checked out
+    pub async fn pull(&self) -> Result<String> {
+        if self.checkout.join(".git").exists() {
+            run(
+                "fetch",
+                git(&self.checkout).args(["fetch", "--depth", "1", &self.url, &self.branch]),
+            )
+            .await?;
+            run(
+                "reset",
+                git(&self.checkout).args(["reset", "--hard", "FETCH_HEAD"]),
+            )
+            .await?;
+        } else {
+            run(
+                "clone",
+                Command::new("git")
+                    .args(["clone", "--depth", "1", "--branch", &self.branch, &self.url])
+                    .arg(&self.checkout),
+            )
+            .await?;
+        }
+
+        run("rev-parse", git(&self.checkout).args(["rev-parse", "HEAD"])).await
+    }
+
+    /// Pull the repository and load the config from it. If the repository
+    /// can't be reached, the config last checked out is used instead, so
+    /// that the board still comes up while the network is down.
+    pub async fn load(&self) -> Result<(ConfigFile, String)> {
+        let commit = match self.pull().await {
+            Ok(x) => x,
+            Err(error) if self.checkout.join(".git").exists() => {
+                warn!(
+                    ?error,
+                    "failed to pull config repository, using last checkout"
+                );
+                run("rev-parse", git(&self.checkout).args(["rev-parse", "HEAD"])).await?
+            }
+            Err(error) => return Err(error),
+        };
+
+        let config_file = ConfigFile::load(&self.checkout.join(&self.file))?;
+
+        Ok((config_file, commit))
+    }
+}
+
+fn git(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.current_dir(dir);
+    command
+}
+
+/// Run a git command, returning what it printed
+async fn run(name: &'static str, command: &mut Command) -> Result<String> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .wrap_err("running git")?;
+
+    if !output.status.success() {
+        return Err(ConfigError::Git {
+            command: name,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        }
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Whether a reload changed anything
+pub enum Reloaded {
+    Unchanged(String),
+    Applied(String),
+}
+
+/// Serves the board from the config in a [`ConfigRepo`], swapping in a freshly
+/// built app whenever a new commit is pulled. Requests already being served
+/// finish with the old config, and the old fetch loop stops once they have.
+pub struct ConfigReload {
+    repo: ConfigRepo,
+    /// Token webhooks must send, if any
+    token: Option<String>,
+    /// Whether running as a service, which keeps caches in its state directory
+    service: bool,
+    /// Commit the running config came from, locked for the whole of a reload
+    /// so that only one runs at a time
+    commit: Mutex<String>,
+    /// Routes of the running config, behind a mutex rather than a read-write
+    /// lock since `Router` is only `Sync` from axum 0.7.4. It's only held long
+    /// enough to clone.
+    app: std::sync::Mutex<Router>,
+    data_access: watch::Sender<Arc<DataAccess>>,
+}
+
+impl ConfigReload {
+    /// Start serving `config_file`, which was loaded from `commit` of the
+    /// repository
+    pub fn new(
+        repo: ConfigRepo,
+        token: Option<String>,
+        service: bool,
+        config_file: ConfigFile,
+        commit: String,
+    ) -> Result<Arc<Self>> {
+        let shared_render_data = SharedRenderData::new(&config_file)?;
+        let data_access = DataAccess::new(config_file.clone());
+
+        let reload = Arc::new(Self {
+            repo,
+            token,
+            service,
+            commit: Mutex::new(commit),
+            app: std::sync::Mutex::new(Router::new()),
+            data_access: watch::channel(data_access.clone()).0,
+        });
+
+        *reload.app.lock().unwrap() = server::app(
+            data_access,
+            shared_render_data,
+            config_file,
+            Some(reload.clone()),
+        );
+
+        Ok(reload)
+    }
+
+    /// Routes which always pass requests on to the current app
+    pub fn router(self: &Arc<Self>) -> Router {
+        let reload = self.clone();
+
+        Router::new().fallback_service(tower::service_fn(move |request| {
+            let app = reload.app.lock().unwrap().clone();
+            tower::ServiceExt::oneshot(app, request)
+        }))
+    }
+
+    /// The fetch loop of the current config
+    pub fn data_access(&self) -> watch::Receiver<Arc<DataAccess>> {
+        self.data_access.subscribe()
+    }
+
+    /// Pull the repository, and switch to its config if it's on a new commit.
+    /// A config which can't be loaded leaves the running one in place.
+    pub async fn reload(self: &Arc<Self>) -> Result<Reloaded> {
+        let mut commit = self.commit.lock().await;
+
+        let pulled = self.repo.pull().await?;
+        if pulled == *commit {
+            return Ok(Reloaded::Unchanged(pulled));
+        }
+
+        let mut config_file = ConfigFile::load(&self.repo.checkout.join(&self.repo.file))?;
+        if self.service {
+            service::use_state_directory(&mut config_file);
+        }
+
+        let shared_render_data = SharedRenderData::new(&config_file)?;
+        let data_access = DataAccess::new(config_file.clone());
+
+        let app = server::app(
+            data_access.clone(),
+            shared_render_data,
+            config_file,
+            Some(self.clone()),
+        );
+
+        *self.app.lock().unwrap() = app;
+        self.data_access.send_replace(data_access);
+
+        info!(%pulled, previous = %commit, "applied config from repository");
+        *commit = pulled.clone();
+
+        Ok(Reloaded::Applied(pulled))
+    }
+
+    /// Reload every `interval`, for repositories which can't send webhooks
+    pub async fn poll(self: Arc<Self>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            if let Err(error) = self.reload().await {
+                warn!(?error, "failed to reload config from repository");
+            }
+        }
+    }
+
+    fn authorized(&self, headers: &HeaderMap, params: &ReloadParams) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+
+        let bearer = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        bearer == Some(token.as_str()) || params.token.as_ref() == Some(token)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReloadParams {
+    /// The reload token, for forges which can't send an Authorization header
+    token: Option<String>,
+}
+
+/// Pull the config repository and apply any changes, for a push webhook
+pub async fn reload_webhook(
+    State(reload): State<Arc<ConfigReload>>,
+    headers: HeaderMap,
+    Query(params): Query<ReloadParams>,
+) -> Response {
+    if !reload.authorized(&headers, &params) {
+        return (StatusCode::UNAUTHORIZED, "invalid reload token").into_response();
+    }
+
+    match reload.reload().await {
+        Ok(Reloaded::Unchanged(commit)) => format!("already at {commit}\n").into_response(),
+        Ok(Reloaded::Applied(commit)) => format!("applied {commit}\n").into_response(),
+        Err(error) => {
+            warn!(?error, "failed to reload config from repository");
+
+            Failure::of(&error).response(&error)
+        }
+    }
+}
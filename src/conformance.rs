@@ -0,0 +1,315 @@
+use std::fmt;
+
+use chrono::{DateTime, Duration, Utc};
+
+/// How long before a fetch a departure can still be in the response, as
+/// feeds keep vehicles that are about to leave, or only just have
+const PAST_GRACE: Duration = Duration::minutes(10);
+
+/// How far after a fetch departures can be. Any later is taken to be a time
+/// read in the wrong unit or zone, rather than a departure worth showing.
+const HORIZON: Duration = Duration::days(1);
+
+/// What a departure from any provider comes down to once it's been read, for
+/// checking against the invariants every provider must hold
+pub struct Departure<'a> {
+    pub stop: &'a str,
+    pub line: Option<&'a str>,
+    pub expected: Option<&'a str>,
+    pub aimed: Option<&'a str>,
+}
+
+/// A departure which breaks one of the invariants
+#[derive(Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A time without a UTC offset, or not in RFC 3339 at all, so that it
+    /// can't be placed on the board
+    UnreadableTime { line: String, time: String },
+    /// An expected time long before the departures were fetched
+    Past { line: String, time: DateTime<Utc> },
+    /// An expected time more than [`HORIZON`] after the departures were
+    /// fetched
+    TooLate { line: String, time: DateTime<Utc> },
+    /// A departure from a stop that wasn't asked for
+    UnknownStop { line: String, stop: String },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnreadableTime { line, time } => {
+                write!(f, "{line}: {time:?} isn't an RFC 3339 time with an offset")
+            }
+            Self::Past { line, time } => write!(f, "{line}: departed at {time}"),
+            Self::TooLate { line, time } => {
+                write!(f, "{line}: departs at {time}, more than a day away")
+            }
+            Self::UnknownStop { line, stop } => {
+                write!(f, "{line}: departs from {stop}, which wasn't asked for")
+            }
+        }
+    }
+}
+
+/// The departures fetched once for one agency, and what's wrong with them
+pub struct Report {
+    pub agency: String,
+    pub departures: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} departures", self.agency, self.departures)?;
+        match self.violations.len() {
+            0 if self.departures == 0 => write!(f, ", so nothing could be checked"),
+            0 => write!(f, ", all conforming"),
+            count => {
+                write!(f, ", {count} not conforming:")?;
+                for violation in &self.violations {
+                    write!(f, "\n  {violation}")?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Check the departures read from a provider, fetched at `fetched_at` for
+/// `stops`, against the invariants that the board relies on every provider
+/// to hold, whatever its API looks like
+pub fn check<'a>(
+    departures: impl IntoIterator<Item = Departure<'a>>,
+    stops: &[String],
+    fetched_at: DateTime<Utc>,
+) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    for departure in departures {
+        let line = departure.line.unwrap_or("(no line)").to_owned();
+
+        if !stops.iter().any(|stop| stop == departure.stop) {
+            violations.push(Violation::UnknownStop {
+                line: line.clone(),
+                stop: departure.stop.to_owned(),
+            });
+        }
+
+        for time in [departure.aimed, departure.expected].into_iter().flatten() {
+            if DateTime::parse_from_rfc3339(time).is_err() {
+                violations.push(Violation::UnreadableTime {
+                    line: line.clone(),
+                    time: time.to_owned(),
+                });
+            }
+        }
+
+        let Some(expected) = departure
+            .expected
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        else {
+            continue;
+        };
+        let expected = expected.with_timezone(&Utc);
+
+        if expected < fetched_at - PAST_GRACE {
+            violations.push(Violation::Past {
+                line,
+                time: expected,
+            });
+        } else if expected > fetched_at + HORIZON {
+            violations.push(Violation::TooLate {
+                line,
+                time: expected,
+            });
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{extract::State, http::StatusCode, http::Uri, response::IntoResponse, Json};
+    use chrono::TimeZone;
+    use serde::Deserialize;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        api_client::Client,
+        config::{FeedFormat, Provider, SourceConfig},
+    };
+
+    /// Every request a provider makes for one agency, and what upstream
+    /// answers with, as kept in `tests/fixtures/providers`
+    #[derive(Deserialize)]
+    struct Fixture {
+        /// The source, with its `url` relative to the stub server's
+        source: SourceConfig,
+        agency: String,
+        stops: Vec<String>,
+        fetched_at: DateTime<Utc>,
+        exchanges: Vec<Exchange>,
+    }
+
+    #[derive(Deserialize, Clone)]
+    struct Exchange {
+        /// Path and query string of the request
+        request: String,
+        /// Variables of a GraphQL query, which is POSTed to `request`
+        variables: Option<Value>,
+        response: Value,
+    }
+
+    /// A fixture for every provider, so that a new provider can't be added
+    /// without one
+    fn fixture(provider: Provider) -> &'static str {
+        match provider {
+            Provider::Siri => include_str!("../tests/fixtures/providers/siri.json"),
+            Provider::Tfl => include_str!("../tests/fixtures/providers/tfl.json"),
+            Provider::Oba => include_str!("../tests/fixtures/providers/oba.json"),
+            Provider::Mbta => include_str!("../tests/fixtures/providers/mbta.json"),
+            Provider::Transitous => include_str!("../tests/fixtures/providers/transitous.json"),
+            Provider::Digitransit => include_str!("../tests/fixtures/providers/digitransit.json"),
+        }
+    }
+
+    const PROVIDERS: [Provider; 6] = [
+        Provider::Siri,
+        Provider::Tfl,
+        Provider::Oba,
+        Provider::Mbta,
+        Provider::Transitous,
+        Provider::Digitransit,
+    ];
+
+    type Served = Arc<Mutex<Vec<(Exchange, bool)>>>;
+
+    /// Answer each request with the response of the exchange it matches
+    async fn upstream(State(served): State<Served>, uri: Uri, body: String) -> impl IntoResponse {
+        let variables = serde_json::from_str::<Value>(&body)
+            .ok()
+            .and_then(|query| query.get("variables").cloned());
+
+        let mut served = served.lock().unwrap();
+        let Some((exchange, answered)) = served.iter_mut().find(|(exchange, _)| {
+            Some(exchange.request.as_str()) == uri.path_and_query().map(|path| path.as_str())
+                && exchange.variables == variables
+        }) else {
+            return (StatusCode::NOT_FOUND, format!("unexpected request {uri}")).into_response();
+        };
+
+        *answered = true;
+        Json(exchange.response.clone()).into_response()
+    }
+
+    /// Run a provider's fixture through the same fetching and parsing that
+    /// `--check-provider` does, against a server standing in for upstream
+    async fn run(provider: Provider) -> Report {
+        let fixture: Fixture = serde_json::from_str(fixture(provider)).unwrap();
+        assert_eq!(fixture.source.provider, provider);
+
+        let served: Served = Arc::new(Mutex::new(
+            fixture
+                .exchanges
+                .into_iter()
+                .map(|exchange| (exchange, false))
+                .collect(),
+        ));
+        let app = axum::Router::new()
+            .fallback(upstream)
+            .with_state(served.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let source = SourceConfig {
+            url: format!("http://{address}{}", fixture.source.url),
+            ..fixture.source
+        };
+        let client = Client::new(String::new(), HashMap::new(), 0, 2000, None);
+        let report = client
+            .check_source(
+                &fixture.agency,
+                &source,
+                &fixture.stops,
+                FeedFormat::Auto,
+                fixture.fetched_at,
+            )
+            .await
+            .unwrap_or_else(|e| panic!("{provider:?}: {e:?}"));
+
+        for (exchange, answered) in served.lock().unwrap().iter() {
+            assert!(
+                answered,
+                "{provider:?} never requested {}",
+                exchange.request
+            );
+        }
+
+        report
+    }
+
+    #[tokio::test]
+    async fn every_provider_conforms() {
+        for provider in PROVIDERS {
+            let report = run(provider).await;
+
+            assert!(report.departures > 0, "{provider:?}: {report}");
+            assert!(report.violations.is_empty(), "{provider:?}: {report}");
+        }
+    }
+
+    #[test]
+    fn catches_violations() {
+        let fetched_at = Utc.with_ymd_and_hms(2024, 3, 1, 17, 30, 0).unwrap();
+        let stops = vec![String::from("15696")];
+        let departure = |stop, expected| Departure {
+            stop,
+            line: Some("N"),
+            expected: Some(expected),
+            aimed: None,
+        };
+
+        let violations = check(
+            [
+                // just left, and in the feed a little longer
+                departure("15696", "2024-03-01T17:25:00Z"),
+                departure("15696", "2024-03-01T09:31:00-08:00"),
+                // local time without an offset
+                departure("15696", "2024-03-01T09:31:00"),
+                // milliseconds read as seconds
+                departure("15696", "2078-05-23T10:00:00Z"),
+                departure("15697", "2024-03-01T17:35:00Z"),
+            ],
+            &stops,
+            fetched_at,
+        );
+
+        assert_eq!(
+            violations,
+            [
+                Violation::UnreadableTime {
+                    line: String::from("N"),
+                    time: String::from("2024-03-01T09:31:00"),
+                },
+                Violation::TooLate {
+                    line: String::from("N"),
+                    time: Utc.with_ymd_and_hms(2078, 5, 23, 10, 0, 0).unwrap(),
+                },
+                Violation::UnknownStop {
+                    line: String::from("N"),
+                    stop: String::from("15697"),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use eyre::{bail, Result};
+use png::{BitDepth, ColorType, Compression, Decoder, Encoder, Transformations};
+use serde::Deserialize;
+
+/// E-ink device a screen route is drawn for, so that the right resolution and
+/// grey levels don't have to be passed by hand
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Device {
+    /// Kindle Paperwhite 3 and later Paperwhites of the same resolution
+    KindlePw3,
+    /// Kobo Clara HD and Clara 2E
+    KoboClara,
+    Remarkable2,
+    /// TRMNL and other ESP32 panels with a 7.5" black and white screen
+    Trmnl,
+}
+
+/// What a [`Device`]'s screen can show
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeviceProfile {
+    /// Panel width in pixels, held upright
+    pub width: u32,
+    /// Panel height in pixels, held upright
+    pub height: u32,
+    /// Whether landscape boards are rotated to fit the portrait framebuffer,
+    /// as kindling does for its `kindle` render target
+    pub rotate: bool,
+    /// Bits per pixel of grey the panel can show: 1, 2, 4, or 8
+    pub bit_depth: u8,
+    /// Whether the image is dithered, rather than rounded, to those levels
+    pub dither: bool,
+}
+
+impl Device {
+    pub fn profile(self) -> DeviceProfile {
+        match self {
+            Device::KindlePw3 => DeviceProfile {
+                width: 1072,
+                height: 1448,
+                rotate: true,
+                bit_depth: 4,
+                dither: false,
+            },
+            Device::KoboClara => DeviceProfile {
+                width: 1072,
+                height: 1448,
+                rotate: true,
+                bit_depth: 4,
+                dither: true,
+            },
+            Device::Remarkable2 => DeviceProfile {
+                width: 1404,
+                height: 1872,
+                rotate: true,
+                bit_depth: 2,
+                dither: true,
+            },
+            Device::Trmnl => DeviceProfile {
+                width: 800,
+                height: 480,
+                rotate: false,
+                bit_depth: 1,
+                dither: true,
+            },
+        }
+    }
+}
+
+/// Re-encode a PNG as greyscale with `bit_depth` bits per pixel, dithering
+/// with Floyd-Steinberg error diffusion if `dither`, so that a panel showing
+/// few greys gets smooth gradients rather than banding. `iTXt` chunks, such as
+/// the departures on the board, are kept.
+pub fn quantize(data: &[u8], bit_depth: u8, dither: bool) -> Result<Vec<u8>> {
+    let depth = match bit_depth {
+        1 => BitDepth::One,
+        2 => BitDepth::Two,
+        4 => BitDepth::Four,
+        8 => BitDepth::Eight,
+        _ => bail!("unsupported bit depth {bit_depth}, expected 1, 2, 4, or 8"),
+    };
+
+    let mut decoder = Decoder::new(data);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info()?;
+    let text = reader
+        .info()
+        .utf8_text
+        .iter()
+        .map(|chunk| Ok((chunk.keyword.clone(), chunk.get_text()?)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+
+    let channels = info.color_type.samples();
+    let width = info.width as usize;
+    let height = info.height as usize;
+
+    let mut luma: Vec<f32> = buf
+        .chunks_exact(info.line_size)
+        .take(height)
+        .flat_map(|line| line[..width * channels].chunks_exact(channels))
+        .map(|pixel| match info.color_type {
+            ColorType::Rgb | ColorType::Rgba => {
+                let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(f32::from);
+                r * 0.299 + g * 0.587 + b * 0.114
+            }
+            _ => f32::from(pixel[0]),
+        })
+        .collect();
+
+    let max_level = (1u16 << bit_depth) - 1;
+    let step = 255.0 / f32::from(max_level);
+
+    let mut levels = vec![0u8; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let at = y * width + x;
+            let level = (luma[at] / step).round().clamp(0.0, f32::from(max_level));
+            levels[at] = level as u8;
+
+            if !dither {
+                continue;
+            }
+
+            let error = luma[at] - level * step;
+            let mut spread = |dx: isize, dy: usize, weight: f32| {
+                let nx = x as isize + dx;
+                if nx < 0 || nx as usize >= width || y + dy >= height {
+                    return;
+                }
+                luma[(y + dy) * width + nx as usize] += error * weight;
+            };
+            spread(1, 0, 7.0 / 16.0);
+            spread(-1, 1, 3.0 / 16.0);
+            spread(0, 1, 5.0 / 16.0);
+            spread(1, 1, 1.0 / 16.0);
+        }
+    }
+
+    let per_byte = 8 / bit_depth as usize;
+    let mut packed = Vec::with_capacity(width.div_ceil(per_byte) * height);
+    for row in levels.chunks_exact(width) {
+        for pixels in row.chunks(per_byte) {
+            let byte = pixels.iter().enumerate().fold(0u8, |byte, (i, level)| {
+                byte | (level << (8 - bit_depth as usize * (i + 1)))
+            });
+            packed.push(byte);
+        }
+    }
+
+    let mut quantized = Vec::new();
+
+    let mut encoder = Encoder::new(&mut quantized, info.width, info.height);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(depth);
+    encoder.set_compression(Compression::Best);
+    for (keyword, text) in text {
+        encoder.add_itxt_chunk(keyword, text)?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&packed)?;
+    writer.finish()?;
+
+    Ok(quantized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png_metadata;
+
+    #[test]
+    fn quantizing_keeps_the_departures() {
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, 4, 2);
+        encoder.set_color(ColorType::Grayscale);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer
+            .write_image_data(&[0, 60, 120, 255, 255, 120, 60, 0])
+            .unwrap();
+        writer.finish().unwrap();
+
+        let departures = r#"[{"line":"N","destination":"Ocean Beach","departures":[]}]"#;
+        let png = png_metadata::with_departures(&png, departures).unwrap();
+        let quantized = quantize(&png, 2, true).unwrap();
+
+        let reader = Decoder::new(quantized.as_slice()).read_info().unwrap();
+        let text = &reader.info().utf8_text;
+        assert_eq!(text.len(), 1);
+        assert_eq!(text[0].keyword, "Departures");
+        assert_eq!(text[0].get_text().unwrap(), departures);
+    }
+}
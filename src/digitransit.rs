@@ -0,0 +1,184 @@
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::error::ParseError;
+
+/// Departures asked of an OpenTripPlanner GraphQL API for one stop, with
+/// cancelled trips left in so they can be shown as such
+const STOPTIMES_QUERY: &str = "
+query Departures($stop: String!, $departures: Int!) {
+  stop(id: $stop) {
+    platformCode
+    stoptimesWithoutPatterns(numberOfDepartures: $departures, omitCanceled: false) {
+      serviceDay
+      scheduledDeparture
+      realtimeDeparture
+      realtime
+      realtimeState
+      headsign
+      trip {
+        gtfsId
+        directionId
+        route {
+          gtfsId
+          shortName
+        }
+      }
+    }
+  }
+}";
+
+/// Most departures asked for at each stop
+const DEPARTURES: u32 = 30;
+
+/// Body of a Digitransit (OpenTripPlanner) GraphQL response to
+/// [`STOPTIMES_QUERY`]
+#[derive(Deserialize)]
+pub(crate) struct Response {
+    data: Option<Data>,
+    #[serde(default)]
+    errors: Vec<GraphqlError>,
+}
+
+#[derive(Deserialize)]
+struct GraphqlError {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    /// `null` for a stop id the API doesn't know
+    stop: Option<Stop>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Stop {
+    /// Track or platform, given for trains and some bus terminals
+    platform_code: Option<String>,
+    #[serde(default)]
+    stoptimes_without_patterns: Vec<StopTime>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StopTime {
+    /// Seconds since the epoch at the start of the service day
+    service_day: i64,
+    /// Seconds since the start of the service day, which can be more than a
+    /// day's worth for trips running past midnight
+    scheduled_departure: i64,
+    realtime_departure: i64,
+    #[serde(default)]
+    realtime: bool,
+    /// `SCHEDULED`, `UPDATED`, `CANCELED`, `ADDED`, or `MODIFIED`
+    realtime_state: Option<String>,
+    headsign: Option<String>,
+    trip: Option<Trip>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Trip {
+    gtfs_id: String,
+    /// The trip's GTFS `direction_id`, `0` or `1`
+    direction_id: Option<String>,
+    route: Route,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Route {
+    gtfs_id: String,
+    short_name: Option<String>,
+}
+
+/// A departure from one stop, with its times as RFC 3339
+pub(crate) struct Departure {
+    pub stop_id: String,
+    pub line: String,
+    /// The trip's GTFS `direction_id`, `0` or `1`
+    pub direction: Option<String>,
+    pub headsign: Option<String>,
+    /// The real-time departure, which is the timetabled one for trips not
+    /// being tracked
+    pub expected: Option<String>,
+    pub scheduled: Option<String>,
+    pub cancelled: bool,
+    pub track: Option<String>,
+    pub trip_id: Option<String>,
+}
+
+impl Response {
+    /// The departures from `stop`, or the errors the API answered with in
+    /// place of them
+    pub(crate) fn departures(self, stop: &str) -> Result<Vec<Departure>, ParseError> {
+        if !self.errors.is_empty() {
+            let messages = self.errors.into_iter().map(|error| error.message);
+
+            return Err(ParseError::Graphql(messages.collect::<Vec<_>>().join("; ")));
+        }
+
+        let Some(found) = self.data.and_then(|data| data.stop) else {
+            return Err(ParseError::Graphql(format!("no stop with id {stop}")));
+        };
+        let track = found.platform_code.filter(|track| !track.is_empty());
+
+        let departures = found
+            .stoptimes_without_patterns
+            .into_iter()
+            .map(|stop_time| {
+                let at = |offset| time(stop_time.service_day + offset);
+                let (line, direction, trip_id) = match stop_time.trip {
+                    Some(trip) => (
+                        trip.route.short_name.unwrap_or(trip.route.gtfs_id),
+                        trip.direction_id,
+                        Some(trip.gtfs_id),
+                    ),
+                    None => (String::new(), None, None),
+                };
+
+                Departure {
+                    stop_id: stop.to_owned(),
+                    line,
+                    direction,
+                    headsign: stop_time.headsign.filter(|headsign| !headsign.is_empty()),
+                    expected: if stop_time.realtime {
+                        at(stop_time.realtime_departure)
+                    } else {
+                        at(stop_time.scheduled_departure)
+                    },
+                    scheduled: at(stop_time.scheduled_departure),
+                    cancelled: stop_time.realtime_state.as_deref() == Some("CANCELED"),
+                    track: track.clone(),
+                    trip_id,
+                }
+            })
+            .collect();
+
+        Ok(departures)
+    }
+}
+
+fn time(seconds: i64) -> Option<String> {
+    DateTime::from_timestamp(seconds, 0).map(|time| time.to_rfc3339())
+}
+
+/// URL of the GraphQL endpoint, with the subscription key Digitransit asks
+/// for, if there is one
+pub(crate) fn graphql_url(base: &str, key: Option<&str>) -> String {
+    match key {
+        Some(key) => format!("{base}?digitransit-subscription-key={key}"),
+        None => base.to_owned(),
+    }
+}
+
+/// Body to POST for the departures from the stop with this GTFS id, such as
+/// `HSL:1040129`
+pub(crate) fn stoptimes_query(stop: &str) -> serde_json::Value {
+    json!({
+        "query": STOPTIMES_QUERY,
+        "variables": { "stop": stop, "departures": DEPARTURES },
+    })
+}
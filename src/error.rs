@@ -0,0 +1,261 @@
+use std::{cell::RefCell, path::PathBuf};
+
+use axum::{
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use thiserror::Error;
+
+use crate::api_client::REFRESH_INTERVAL;
+
+tokio::task_local! {
+    /// Failure behind the error screen drawn in place of the board while
+    /// serving the current request
+    pub static BOARD_ERROR: RefCell<Option<Failure>>;
+}
+
+/// Failures to get departures from upstream, or from a remote instance
+#[derive(Debug, Error)]
+pub enum FetchError {
+    #[error("transit API unreachable")]
+    Unreachable(#[source] reqwest::Error),
+
+    #[error("transit API returned an error")]
+    Upstream(#[source] reqwest::Error),
+
+    #[error("upstream data not modified, but nothing has been fetched")]
+    NotModified,
+
+    #[error("no departures have been fetched for agency {agency} yet")]
+    NotFetched { agency: String },
+
+    #[error("{url} has no agency {agency}")]
+    MissingAgency { url: String, agency: String },
+
+    #[error("no stop named {name}")]
+    UnknownStop { name: String },
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(error: reqwest::Error) -> Self {
+        // the URL holds the API key, and errors end up on the board
+        let error = error.without_url();
+
+        if error.is_status() || error.is_decode() {
+            Self::Upstream(error)
+        } else {
+            Self::Unreachable(error)
+        }
+    }
+}
+
+/// A response from upstream that doesn't match the schema
+#[derive(Debug, Error)]
+pub enum ParseError {
+    #[error("parsing JSON response")]
+    Json(#[from] serde_path_to_error::Error<serde_json::Error>),
+
+    #[error("parsing XML response")]
+    Xml(#[from] quick_xml::DeError),
+
+    #[error("GraphQL query failed: {0}")]
+    Graphql(String),
+}
+
+impl ParseError {
+    /// Where in the response the schema was violated, if known
+    pub fn schema_path(&self) -> Option<String> {
+        match self {
+            Self::Json(error) => Some(error.path().to_string()),
+            Self::Xml(_) | Self::Graphql(_) => None,
+        }
+    }
+}
+
+/// A problem with `stops.yml` or the files it refers to
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("reading {}", path.display())]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("parsing {}", path.display())]
+    Invalid {
+        path: PathBuf,
+        #[source]
+        source: serde_path_to_error::Error<serde_yaml::Error>,
+    },
+
+    #[error("reading {field} from {}", path.display())]
+    Secret {
+        field: &'static str,
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("only one of {field} and {field}_file can be set")]
+    SecretConflict { field: &'static str },
+
+    #[error("{field} or {field}_file must be set")]
+    MissingSecret { field: &'static str },
+
+    #[error("agency {agency} is fetched from source {name}, which isn't in sources")]
+    UnknownSource { agency: String, name: String },
+
+    #[error(
+        "{sections} sections in layout.{column} can't fit on screen {screen}, which has room \
+         for at most {fit} in its {height} pixels even with nothing in them; move some to the \
+         other column, or leave them off"
+    )]
+    SectionsDontFit {
+        screen: String,
+        column: &'static str,
+        sections: usize,
+        fit: usize,
+        height: u32,
+    },
+
+    #[error("parsing the summary template of a section of agency {agency}")]
+    SummaryTemplate {
+        agency: String,
+        #[source]
+        source: minijinja::Error,
+    },
+
+    #[error("footer.time_format {format:?} has a specifier that isn't understood")]
+    TimeFormat { format: String },
+
+    #[error("trmnl.screen {screen} isn't the path of any of the screens")]
+    TrmnlScreen { screen: String },
+
+    #[error("failed to load fallback font {path}")]
+    FallbackFont { path: String },
+
+    #[error("failed to load monospace font {path}")]
+    MonospaceFont { path: String },
+
+    #[error("git {command} failed: {stderr}")]
+    Git {
+        command: &'static str,
+        stderr: String,
+    },
+
+    #[error("painter {painter} needs transit-kindle built with the {feature} feature")]
+    PainterNotBuilt {
+        painter: &'static str,
+        feature: &'static str,
+    },
+}
+
+/// Failures drawing or encoding the board
+#[derive(Debug, Error)]
+pub enum RenderError {
+    #[error("failed to initialize skia bitmap")]
+    Bitmap,
+
+    #[error("failed to construct skia canvas")]
+    Canvas,
+
+    #[error("failed to encode skia image")]
+    Encode,
+
+    #[error("failed to shape text {0:?}")]
+    Shaping(String),
+
+    #[error("drawing panicked: {0}")]
+    Panicked(String),
+
+    #[error("rendering built-in stops.html")]
+    Template(#[from] askama::Error),
+
+    #[error("rendering stops.html from {}", dir.display())]
+    RuntimeTemplate {
+        dir: PathBuf,
+        #[source]
+        source: minijinja::Error,
+    },
+
+    #[error("rendering a section's summary")]
+    Summary(#[source] minijinja::Error),
+}
+
+/// What kind of failure an error is, going by the first typed error in its
+/// chain, which decides how it's shown and what status it's served with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Failure {
+    /// A short description of what went wrong, for the error screen
+    pub headline: &'static str,
+    /// Whether the failure is upstream's, and should clear up by itself
+    pub upstream: bool,
+}
+
+impl Failure {
+    pub fn of(error: &eyre::Report) -> Self {
+        let upstream = |headline| Self {
+            headline,
+            upstream: true,
+        };
+        let local = |headline| Self {
+            headline,
+            upstream: false,
+        };
+
+        for cause in error.chain() {
+            if let Some(error) = cause.downcast_ref::<FetchError>() {
+                return upstream(match error {
+                    FetchError::Unreachable(_) => "transit API unreachable",
+                    FetchError::Upstream(_) | FetchError::NotModified => "transit API failed",
+                    FetchError::NotFetched { .. } => "waiting for departures",
+                    FetchError::MissingAgency { .. } => "remote agency missing",
+                    FetchError::UnknownStop { .. } => "stop not found",
+                });
+            }
+            if cause.is::<ParseError>() {
+                return upstream("transit API sent unreadable data");
+            }
+            if cause.is::<ConfigError>() {
+                return local("configuration problem");
+            }
+            if cause.is::<RenderError>() {
+                return local("board could not be drawn");
+            }
+        }
+
+        local("something went wrong")
+    }
+
+    /// `503 Service Unavailable` for upstream failures, which will be retried
+    /// at the next refresh, and `500 Internal Server Error` for anything else
+    pub fn status(self) -> StatusCode {
+        if self.upstream {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    /// Give a response this failure's status, telling clients to try again
+    /// after the next refresh if it's upstream's failure
+    pub fn apply(self, response: &mut Response) {
+        *response.status_mut() = self.status();
+
+        if self.upstream {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(REFRESH_INTERVAL.as_secs()),
+            );
+        }
+    }
+
+    /// A plain text response describing an error
+    pub fn response(self, error: &eyre::Report) -> Response {
+        let mut response = format!("{}\n\n{error:?}", self.headline).into_response();
+        self.apply(&mut response);
+
+        response
+    }
+}
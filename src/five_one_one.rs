@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Body of 511.org's `transit/stops`, every stop an operator serves
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub(crate) struct StopsResponse {
+    contents: Contents,
+}
+
+#[derive(Deserialize)]
+struct Contents {
+    #[serde(rename = "dataObjects")]
+    data_objects: DataObjects,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DataObjects {
+    #[serde(default)]
+    scheduled_stop_point: Vec<StopPoint>,
+}
+
+#[derive(Deserialize)]
+struct StopPoint {
+    /// The stop code, as stops are configured by
+    id: String,
+    #[serde(rename = "Name")]
+    name: Option<String>,
+}
+
+impl StopsResponse {
+    /// Names of those of `stops` that the operator serves, keyed by code
+    pub(crate) fn names(self, stops: &[String]) -> HashMap<String, String> {
+        self.contents
+            .data_objects
+            .scheduled_stop_point
+            .into_iter()
+            .filter(|stop| stops.contains(&stop.id))
+            .filter_map(|stop| Some((stop.id, stop.name.filter(|name| !name.is_empty())?)))
+            .collect()
+    }
+}
+
+/// URL of every stop an agency serves, which is one request against the 511
+/// rate limit however many stops there are
+pub(crate) fn stops_url(api_key: &str, agency: &str) -> String {
+    format!("https://api.511.org/transit/stops?api_key={api_key}&operator_id={agency}&format=json")
+}
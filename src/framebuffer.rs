@@ -0,0 +1,422 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use eyre::{bail, Context, Result};
+use tracing::{debug, info, warn};
+
+use crate::{
+    api_client::DataAccess,
+    config::{ConfigFile, Orientation},
+    layout::data_to_layout,
+    output::{self, OutputFormat, OutputParams},
+    render::SharedRenderData,
+};
+
+/// How often the board is redrawn, so that departure times count down
+const FRAME_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Every this many frames, the whole panel is flashed to clear ghosting
+const FULL_REFRESH_EVERY: u32 = 10;
+
+const FBIOGET_VSCREENINFO: u32 = 0x4600;
+const FBIOGET_FSCREENINFO: u32 = 0x4602;
+
+/// `_IOW('F', 0x2E, struct mxcfb_update_data)` for the update struct of the
+/// Paperwhite 2 and later, and of earlier Kindles, as used by `eips`
+const MXCFB_SEND_UPDATE: u32 = 0x4048_462e;
+const MXCFB_SEND_UPDATE_LEGACY: u32 = 0x4040_462e;
+
+const WAVEFORM_MODE_DU: u32 = 1;
+const WAVEFORM_MODE_GC16: u32 = 2;
+const WAVEFORM_MODE_AUTO: u32 = 257;
+const UPDATE_MODE_PARTIAL: u32 = 0;
+const UPDATE_MODE_FULL: u32 = 1;
+const TEMP_USE_AMBIENT: i32 = 0x1000;
+
+/// `grayscale` of an 8-bit framebuffer whose levels run from white to black
+const GRAYSCALE_8BIT_INVERTED: u32 = 2;
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct FbVarScreeninfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct FbFixScreeninfo {
+    id: [u8; 16],
+    smem_start: libc::c_ulong,
+    smem_len: u32,
+    kind: u32,
+    type_aux: u32,
+    visual: u32,
+    xpanstep: u16,
+    ypanstep: u16,
+    ywrapstep: u16,
+    line_length: u32,
+    mmio_start: libc::c_ulong,
+    mmio_len: u32,
+    accel: u32,
+    capabilities: u16,
+    reserved: [u16; 2],
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct MxcfbRect {
+    top: u32,
+    left: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct MxcfbAltBufferData {
+    phys_addr: u32,
+    width: u32,
+    height: u32,
+    alt_update_region: MxcfbRect,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct MxcfbUpdateData {
+    update_region: MxcfbRect,
+    waveform_mode: u32,
+    update_mode: u32,
+    update_marker: u32,
+    hist_bw_waveform_mode: u32,
+    hist_gray_waveform_mode: u32,
+    temp: i32,
+    flags: u32,
+    alt_buffer_data: MxcfbAltBufferData,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct MxcfbUpdateDataLegacy {
+    update_region: MxcfbRect,
+    waveform_mode: u32,
+    update_mode: u32,
+    update_marker: u32,
+    temp: i32,
+    flags: u32,
+    alt_buffer_data: MxcfbAltBufferData,
+}
+
+/// A Linux framebuffer device, such as a jailbroken Kindle's `/dev/fb0`
+pub struct Framebuffer {
+    path: PathBuf,
+    file: File,
+    var: FbVarScreeninfo,
+    line_length: u32,
+    /// Number of updates sent, used as each update's marker
+    updates: u32,
+}
+
+/// Call an ioctl which reads into or from `arg`
+fn ioctl<T>(file: &File, request: u32, arg: &mut T) -> std::io::Result<()> {
+    // SAFETY: `arg` is a `repr(C)` struct matching the layout the request expects
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T) };
+
+    if result < 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl Framebuffer {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .wrap_err_with(|| format!("opening framebuffer {}", path.display()))?;
+
+        let mut var = FbVarScreeninfo::default();
+        ioctl(&file, FBIOGET_VSCREENINFO, &mut var)
+            .wrap_err("reading the framebuffer's screen info")?;
+
+        let mut fix = FbFixScreeninfo::default();
+        ioctl(&file, FBIOGET_FSCREENINFO, &mut fix)
+            .wrap_err("reading the framebuffer's fixed info")?;
+
+        if ![8, 16, 32].contains(&var.bits_per_pixel) {
+            bail!(
+                "framebuffer {} has {} bits per pixel, only 8, 16, and 32 are supported",
+                path.display(),
+                var.bits_per_pixel
+            );
+        }
+
+        info!(
+            path = %path.display(),
+            width = var.xres,
+            height = var.yres,
+            bits_per_pixel = var.bits_per_pixel,
+            "opened framebuffer"
+        );
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            var,
+            line_length: fix.line_length,
+            updates: 0,
+        })
+    }
+
+    /// Size of the visible screen, in pixels
+    pub fn size(&self) -> (u32, u32) {
+        (self.var.xres, self.var.yres)
+    }
+
+    /// Copy 8-bit grey pixels of the given size onto the screen, rotating them
+    /// a quarter turn clockwise if `rotate`, and ask the display to update
+    pub fn show(&mut self, grey: &[u8], (width, height): (u32, u32), rotate: bool) -> Result<()> {
+        let (xres, yres) = self.size();
+        let bytes_per_pixel = (self.var.bits_per_pixel / 8) as usize;
+
+        let mut frame = vec![0u8; self.line_length as usize * yres as usize];
+
+        for y in 0..yres {
+            for x in 0..xres {
+                // the source pixel which lands at (x, y)
+                let (sx, sy) = if rotate { (y, height - 1 - x) } else { (x, y) };
+                let level = if sx < width && sy < height {
+                    grey[(sy * width + sx) as usize]
+                } else {
+                    255
+                };
+
+                let at = y as usize * self.line_length as usize + x as usize * bytes_per_pixel;
+                self.encode_pixel(level, &mut frame[at..at + bytes_per_pixel]);
+            }
+        }
+
+        let offset = self.var.yoffset as u64 * self.line_length as u64;
+        self.file
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.file.write_all(&frame))
+            .wrap_err_with(|| format!("writing to framebuffer {}", self.path.display()))?;
+
+        self.send_update();
+
+        Ok(())
+    }
+
+    fn encode_pixel(&self, level: u8, pixel: &mut [u8]) {
+        match self.var.bits_per_pixel {
+            8 if self.var.grayscale == GRAYSCALE_8BIT_INVERTED => pixel[0] = 255 - level,
+            8 => pixel[0] = level,
+            _ => {
+                let mut value = 0u32;
+                // the transparency channel, if any, is always opaque
+                let channels = [
+                    (self.var.red, level),
+                    (self.var.green, level),
+                    (self.var.blue, level),
+                    (self.var.transp, 255),
+                ];
+                for (field, level) in channels {
+                    if field.length == 0 {
+                        continue;
+                    }
+                    value |= (level as u32 >> (8 - field.length.min(8))) << field.offset;
+                }
+
+                let bytes = value.to_le_bytes();
+                pixel.copy_from_slice(&bytes[..pixel.len()]);
+            }
+        }
+    }
+
+    /// Ask an e-ink display to redraw from the framebuffer, with the
+    /// `MXCFB_SEND_UPDATE` ioctl `eips` uses. Plain framebuffers don't need
+    /// telling, so a display which doesn't understand it is left alone.
+    fn send_update(&mut self) {
+        let full = self.updates.is_multiple_of(FULL_REFRESH_EVERY);
+        self.updates = self.updates.wrapping_add(1);
+
+        let region = MxcfbRect {
+            top: 0,
+            left: 0,
+            width: self.var.xres,
+            height: self.var.yres,
+        };
+        let update_mode = if full {
+            UPDATE_MODE_FULL
+        } else {
+            UPDATE_MODE_PARTIAL
+        };
+
+        let mut update = MxcfbUpdateData {
+            update_region: region,
+            waveform_mode: WAVEFORM_MODE_AUTO,
+            update_mode,
+            update_marker: self.updates,
+            hist_bw_waveform_mode: WAVEFORM_MODE_DU,
+            hist_gray_waveform_mode: WAVEFORM_MODE_GC16,
+            temp: TEMP_USE_AMBIENT,
+            ..Default::default()
+        };
+        if ioctl(&self.file, MXCFB_SEND_UPDATE, &mut update).is_ok() {
+            return;
+        }
+
+        let mut update = MxcfbUpdateDataLegacy {
+            update_region: region,
+            waveform_mode: WAVEFORM_MODE_AUTO,
+            update_mode,
+            update_marker: self.updates,
+            temp: TEMP_USE_AMBIENT,
+            ..Default::default()
+        };
+        if let Err(error) = ioctl(&self.file, MXCFB_SEND_UPDATE_LEGACY, &mut update) {
+            debug!(
+                ?error,
+                "framebuffer isn't an e-ink display, not sending update"
+            );
+        }
+    }
+}
+
+/// Decode a PNG drawn by the renderer into 8-bit grey pixels
+fn decode_grey(png_data: &[u8]) -> Result<(Vec<u8>, (u32, u32))> {
+    let mut decoder = png::Decoder::new(png_data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    pixels.truncate(info.buffer_size());
+
+    let channels = info.color_type.samples();
+    let grey = match info.color_type {
+        png::ColorType::Grayscale | png::ColorType::GrayscaleAlpha => {
+            pixels.chunks(channels).map(|pixel| pixel[0]).collect()
+        }
+        _ => pixels
+            .chunks(channels)
+            .map(|pixel| ((pixel[0] as u32 * 3 + pixel[1] as u32 * 6 + pixel[2] as u32) / 10) as u8)
+            .collect(),
+    };
+
+    Ok((grey, (info.width, info.height)))
+}
+
+/// Draw the board onto a framebuffer every minute, for running on the
+/// display's own device rather than serving HTTP
+pub async fn run(
+    data_access: Arc<DataAccess>,
+    shared: Arc<SharedRenderData>,
+    config_file: ConfigFile,
+    path: &Path,
+) -> Result<()> {
+    let mut framebuffer = Framebuffer::open(path)?;
+    let (xres, yres) = framebuffer.size();
+
+    // a landscape board on a portrait panel, like a Kindle's, is turned
+    // sideways to fill it
+    let landscape = config_file
+        .screens
+        .first()
+        .map_or(Orientation::Landscape, |screen| screen.orientation)
+        == Orientation::Landscape;
+    let rotate = landscape && xres < yres;
+    let (width, height) = if rotate { (yres, xres) } else { (xres, yres) };
+
+    let params = OutputParams {
+        width: width as i32,
+        height: height as i32,
+        ..OutputParams::default()
+    };
+
+    while data_access.last_attempt().is_none() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    loop {
+        let data_access = data_access.clone();
+        let shared = shared.clone();
+        let config_file = config_file.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let drawn = data_access
+                .stop_data(&config_file)
+                .wrap_err("load stop data")
+                .and_then(|stop_data| {
+                    let layout = data_to_layout(stop_data, &config_file);
+                    Ok(output::render(
+                        shared.clone(),
+                        &layout,
+                        OutputFormat::Png,
+                        params,
+                    )?)
+                });
+
+            let image = match drawn {
+                Ok(x) => x,
+                Err(error) => {
+                    warn!(?error, "failed to draw board, drawing error screen");
+                    output::render_error_png(shared, &error, params)?
+                }
+            };
+
+            let (grey, size) = decode_grey(&image)?;
+            framebuffer.show(&grey, size, rotate)?;
+
+            Ok::<_, eyre::Report>(framebuffer)
+        })
+        .await?;
+
+        framebuffer = result?;
+
+        tokio::time::sleep(FRAME_INTERVAL).await;
+    }
+}
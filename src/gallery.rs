@@ -0,0 +1,143 @@
+use std::{fmt::Write, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use tracing::warn;
+
+use crate::{
+    api_client::DataAccess,
+    config::{ConfigFile, Profile},
+    error::Failure,
+    layout::data_to_layout,
+    output::{self, OutputFormat, OutputParams},
+    render::SharedRenderData,
+};
+
+/// Profiles the gallery draws the board with, and their names in
+/// `stops.yml`
+const PROFILES: [(Profile, &str); 2] = [
+    (Profile::Standard, "standard"),
+    (Profile::LargePrint, "large_print"),
+];
+
+/// Sizes the gallery draws the board at, relative to the default image size
+const SCALES: [f32; 3] = [0.75, 1.0, 1.5];
+
+#[derive(Clone)]
+struct GalleryState {
+    data_access: Arc<DataAccess>,
+    config_file: ConfigFile,
+}
+
+#[derive(Deserialize)]
+struct GalleryParams {
+    profile: Profile,
+    scale: f32,
+}
+
+/// `/debug/gallery`, a page showing the current board drawn with every
+/// profile at several sizes, for working on the board's styling
+pub fn router(data_access: Arc<DataAccess>, config_file: ConfigFile) -> Router {
+    Router::new()
+        .route("/debug/gallery", get(gallery_page))
+        .route("/debug/gallery.png", get(gallery_image))
+        .with_state(GalleryState {
+            data_access,
+            config_file,
+        })
+}
+
+async fn gallery_page() -> impl IntoResponse {
+    let defaults = OutputParams::default();
+
+    let mut figures = String::new();
+    for (_, profile) in PROFILES {
+        for scale in SCALES {
+            let _ = write!(
+                figures,
+                r#"<figure><img src="gallery.png?profile={profile}&scale={scale}" width="{width}" height="{height}"><figcaption>{profile} at {scale}&times;</figcaption></figure>"#,
+                width = (defaults.width as f32 * scale) as i32,
+                height = (defaults.height as f32 * scale) as i32,
+            );
+        }
+    }
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>transit-kindle gallery</title>
+<style>
+body {{ font-family: sans-serif; }}
+figure {{ display: inline-block; vertical-align: top; }}
+img {{ border: 1px solid #888; }}
+</style>
+</head>
+<body>
+<h1>Gallery</h1>
+{figures}
+</body>
+</html>
+"#
+    );
+
+    ([(header::CACHE_CONTROL, "no-store")], Html(body))
+}
+
+async fn gallery_image(
+    State(state): State<GalleryState>,
+    Query(params): Query<GalleryParams>,
+) -> Response {
+    if !(0.1..=4.0).contains(&params.scale) {
+        return (StatusCode::BAD_REQUEST, "scale must be between 0.1 and 4").into_response();
+    }
+
+    let mut config_file = state.config_file;
+    config_file.profile = params.profile;
+    config_file.glance = None;
+
+    let defaults = OutputParams::default();
+    let output_params = OutputParams {
+        width: (defaults.width as f32 * params.scale) as i32,
+        height: (defaults.height as f32 * params.scale) as i32,
+        ..defaults
+    };
+
+    let image = state
+        .data_access
+        .stop_data(&config_file)
+        .and_then(|stop_data| {
+            let layout = data_to_layout(stop_data, &config_file);
+            let shared = SharedRenderData::new(&config_file)?;
+
+            Ok(output::render(
+                shared,
+                &layout,
+                OutputFormat::Png,
+                output_params,
+            )?)
+        });
+
+    match image {
+        Ok(image) => (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            image,
+        )
+            .into_response(),
+        Err(error) => {
+            warn!(?error, "failed to draw gallery image");
+
+            Failure::of(&error).response(&error)
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use eyre::{Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    bearing::{initial_bearing, BearingSource},
+    calendar::ServiceCalendar,
+};
+
+/// Route shape and timetable facts worked out from an agency's GTFS feed,
+/// for the trips that serve the configured stops
+pub struct GtfsSchedule {
+    /// Keyed by route short name (or id) and trip headsign
+    bearings: HashMap<(String, String), f32>,
+    /// Latest scheduled departure from the configured stops, in seconds after
+    /// the start of the service day, for each service id. Keyed like
+    /// `bearings`.
+    last_departures: HashMap<(String, String), HashMap<String, u32>>,
+    /// Names of the configured stops, keyed by the id or code each is
+    /// configured by
+    stop_names: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct GtfsStop {
+    stop_id: String,
+    #[serde(default)]
+    stop_code: Option<String>,
+    #[serde(default)]
+    stop_name: Option<String>,
+    stop_lat: f64,
+    stop_lon: f64,
+}
+
+#[derive(Deserialize)]
+struct GtfsRoute {
+    route_id: String,
+    #[serde(default)]
+    route_short_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GtfsTrip {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    #[serde(default)]
+    trip_headsign: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GtfsStopTime {
+    trip_id: String,
+    stop_id: String,
+    stop_sequence: u32,
+    #[serde(default)]
+    departure_time: Option<String>,
+}
+
+struct StopTime {
+    sequence: u32,
+    stop_id: String,
+    departure: Option<u32>,
+}
+
+impl GtfsSchedule {
+    /// Read `stops.txt`, `routes.txt`, `trips.txt`, and `stop_times.txt` from
+    /// an unzipped GTFS feed. `stop_times.txt` is expected to be grouped by
+    /// trip, as feeds are in practice, so that it can be streamed.
+    pub fn load(dir: &Path, configured_stops: &[String]) -> Result<Self> {
+        let stops = read::<GtfsStop>(&dir.join("stops.txt"))?;
+
+        // SIRI stop refs can be either the GTFS stop id or its public code
+        let configured = stops
+            .iter()
+            .filter(|stop| {
+                configured_stops.contains(&stop.stop_id)
+                    || stop
+                        .stop_code
+                        .as_ref()
+                        .is_some_and(|code| configured_stops.contains(code))
+            })
+            .map(|stop| stop.stop_id.clone())
+            .collect::<HashSet<_>>();
+
+        let stop_names = stops
+            .iter()
+            .filter_map(|stop| Some((stop, stop.stop_name.clone()?)))
+            .flat_map(|(stop, name)| {
+                [Some(&stop.stop_id), stop.stop_code.as_ref()]
+                    .into_iter()
+                    .flatten()
+                    .filter(|key| configured_stops.contains(key))
+                    .map(move |key| (key.clone(), name.clone()))
+            })
+            .collect();
+
+        let positions = stops
+            .into_iter()
+            .map(|stop| (stop.stop_id, (stop.stop_lat, stop.stop_lon)))
+            .collect::<HashMap<_, _>>();
+
+        let route_names = read::<GtfsRoute>(&dir.join("routes.txt"))?
+            .into_iter()
+            .map(|route| {
+                let name = route.route_short_name.filter(|name| !name.is_empty());
+                (route.route_id.clone(), name.unwrap_or(route.route_id))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let trips = read::<GtfsTrip>(&dir.join("trips.txt"))?
+            .into_iter()
+            .filter_map(|trip| {
+                let line = route_names.get(&trip.route_id)?.clone();
+                let key = (line, trip.trip_headsign.unwrap_or_default());
+                Some((trip.trip_id, (key, trip.service_id)))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut bearings = HashMap::new();
+        let mut last_departures = HashMap::<_, HashMap<_, u32>>::new();
+
+        let mut add_trip = |trip_id: &str, stop_times: &mut Vec<StopTime>| {
+            stop_times.sort_by_key(|stop_time| stop_time.sequence);
+
+            let Some((key, service_id)) = trips.get(trip_id) else {
+                return;
+            };
+
+            let departing = stop_times
+                .iter()
+                .position(|stop_time| configured.contains(&stop_time.stop_id));
+
+            let Some(idx) = departing else {
+                return;
+            };
+
+            if let Some(next) = stop_times.get(idx + 1) {
+                let from = positions.get(&stop_times[idx].stop_id);
+                let to = positions.get(&next.stop_id);
+
+                if let (Some(from), Some(to)) = (from, to) {
+                    bearings
+                        .entry(key.clone())
+                        .or_insert_with(|| initial_bearing(*from, *to));
+                }
+
+                // a trip's final stop isn't a departure
+                if let Some(departure) = stop_times[idx].departure {
+                    let last = last_departures
+                        .entry(key.clone())
+                        .or_default()
+                        .entry(service_id.clone())
+                        .or_default();
+                    *last = departure.max(*last);
+                }
+            }
+        };
+
+        let path = dir.join("stop_times.txt");
+        let mut reader = csv::Reader::from_path(&path)
+            .wrap_err_with(|| format!("reading {}", path.display()))?;
+
+        let mut current_trip = String::new();
+        let mut stop_times = Vec::new();
+
+        for stop_time in reader.deserialize::<GtfsStopTime>() {
+            let stop_time = stop_time.wrap_err_with(|| format!("reading {}", path.display()))?;
+
+            if stop_time.trip_id != current_trip {
+                add_trip(&current_trip, &mut stop_times);
+                stop_times.clear();
+                current_trip = stop_time.trip_id;
+            }
+
+            stop_times.push(StopTime {
+                sequence: stop_time.stop_sequence,
+                stop_id: stop_time.stop_id,
+                departure: stop_time.departure_time.as_deref().and_then(parse_time),
+            });
+        }
+        add_trip(&current_trip, &mut stop_times);
+
+        Ok(Self {
+            bearings,
+            last_departures,
+            stop_names,
+        })
+    }
+
+    /// Name of a configured stop, such as `Church St & Duboce Ave`
+    pub fn stop_name(&self, stop: &str) -> Option<&str> {
+        self.stop_names.get(stop).map(String::as_str)
+    }
+
+    /// The last scheduled departure of the service day that's still to come,
+    /// for trips on a line towards a destination. A service day can run past
+    /// midnight, so yesterday's service is checked too. Service days are
+    /// counted in `timezone`.
+    pub fn last_departure(
+        &self,
+        line: &str,
+        destination: &str,
+        calendar: &ServiceCalendar,
+        now: DateTime<Utc>,
+        timezone: Tz,
+    ) -> Option<DateTime<Utc>> {
+        let services = self
+            .last_departures
+            .get(&(line.to_owned(), destination.to_owned()))?;
+        let today = now.with_timezone(&timezone).date_naive();
+
+        [today.pred_opt()?, today]
+            .into_iter()
+            .filter_map(|date| {
+                let seconds = services
+                    .iter()
+                    .filter(|(service_id, _)| calendar.runs(service_id, date))
+                    .map(|(_, seconds)| *seconds)
+                    .max()?;
+
+                // GTFS times are measured from noon minus 12 hours, which is
+                // midnight except on days when the clocks change
+                let noon = timezone
+                    .from_local_datetime(&date.and_hms_opt(12, 0, 0)?)
+                    .single()?;
+
+                Some(noon - Duration::hours(12) + Duration::seconds(seconds.into()))
+            })
+            .filter(|last| *last >= now)
+            .min()
+            .map(|last| last.with_timezone(&Utc))
+    }
+}
+
+impl BearingSource for GtfsSchedule {
+    fn bearing(&self, line: &str, destination: &str) -> Option<f32> {
+        self.bearings
+            .get(&(line.to_owned(), destination.to_owned()))
+            .copied()
+    }
+}
+
+fn read<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<Vec<T>> {
+    csv::Reader::from_path(path)
+        .and_then(|mut reader| reader.deserialize().collect())
+        .wrap_err_with(|| format!("reading {}", path.display()))
+}
+
+/// Parse a GTFS `H:MM:SS` time, which can go past `24:00:00` for trips after
+/// midnight, into seconds
+fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.trim().splitn(3, ':').map(|part| part.parse::<u32>());
+
+    let hours = parts.next()?.ok()?;
+    let minutes = parts.next()?.ok()?;
+    let seconds = parts.next()?.ok()?;
+
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
@@ -1,46 +1,239 @@
-use std::sync::Arc;
+use std::{marker::PhantomData, path::Path, sync::Arc};
 
+#[cfg(feature = "kindling")]
 use axum::async_trait;
+#[cfg(not(feature = "kindling"))]
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
 use eyre::{Context, Result};
-use kindling::Orientation;
+#[cfg(not(feature = "kindling"))]
+use serde::Deserialize;
+use skia_safe::{Canvas, Color4f};
+use tracing::warn;
 
 use crate::{
     api_client::DataAccess,
+    config::{ConfigFile, Orientation},
+    error::{Failure, RenderError, BOARD_ERROR},
+    html,
     layout::{data_to_layout, Layout},
+    output::{self, OutputFormat, OutputParams},
+    overrides::LayoutOverrides,
+    png_metadata,
     render::{Render, SharedRenderData},
-    ConfigFile,
+    text,
 };
 
-pub(crate) struct TransitHandler {
+/// Kindling reads a handler's orientation from its type, so each orientation
+/// is a separate type parameter of [`TransitHandler`], for both kinds of
+/// screen route
+pub(crate) trait ScreenOrientation: 'static + Send + Sync {
+    const ORIENTATION: Orientation;
+}
+
+pub(crate) struct Landscape;
+
+impl ScreenOrientation for Landscape {
+    const ORIENTATION: Orientation = Orientation::Landscape;
+}
+
+pub(crate) struct Portrait;
+
+impl ScreenOrientation for Portrait {
+    const ORIENTATION: Orientation = Orientation::Portrait;
+}
+
+/// What a screen route draws: the board, or what went wrong loading it
+pub(crate) enum Screen {
+    Board(Layout),
+    Error(eyre::Report),
+}
+
+pub(crate) struct TransitHandler<O = Landscape> {
     pub(crate) data_access: Arc<DataAccess>,
     pub(crate) config_file: ConfigFile,
     pub(crate) shared: Arc<SharedRenderData>,
+    pub(crate) orientation: PhantomData<O>,
 }
 
-#[async_trait]
-impl kindling::Handler for TransitHandler {
-    type Data = Layout;
-
-    async fn load(&self) -> Result<Self::Data> {
+impl<O: ScreenOrientation> TransitHandler<O> {
+    /// Lay out the latest stop data, with the current request's overrides
+    pub(crate) async fn layout(&self) -> Result<Layout> {
         let stop_data = self
             .data_access
-            .load_stop_data(self.config_file.clone())
-            .await
+            .stop_data(&self.config_file)
             .wrap_err("load stop data")?;
 
-        let layout = data_to_layout(stop_data, &self.config_file);
+        let config_file = LayoutOverrides::current(&self.config_file);
+        let layout = data_to_layout(stop_data, &config_file);
+        png_metadata::record_departures(&layout);
 
         Ok(layout)
     }
 
-    fn draw(&self, canvas: &skia_safe::Canvas, layout: Layout) -> Result<()> {
-        let ctx = Render::new(canvas, self.shared.clone())?;
-        ctx.draw(&layout)?;
+    /// Load the current layout and render it to one of the non-kindling output formats
+    pub(crate) async fn render(
+        &self,
+        format: OutputFormat,
+        params: OutputParams,
+    ) -> Result<Vec<u8>> {
+        let layout = self.layout().await?;
+        let shared = self.shared.clone();
+
+        Ok(output::blocking(move || output::render(shared, &layout, format, params)).await?)
+    }
+
+    /// The board, or what went wrong loading it, which is noted for the
+    /// response's status. Never fails, so that the display shows what went
+    /// wrong rather than a generic error image.
+    pub(crate) async fn screen(&self) -> Screen {
+        match self.layout().await {
+            Ok(layout) => Screen::Board(layout),
+            Err(error) => {
+                warn!(?error, "failed to load board, drawing error screen");
+
+                let _ = BOARD_ERROR.try_with(|board_error| {
+                    *board_error.borrow_mut() = Some(Failure::of(&error));
+                });
+
+                Screen::Error(error)
+            }
+        }
+    }
+
+    /// Draw a screen onto a canvas the size of the image. If drawing the board
+    /// panics, the error screen is drawn in its place, so that the display
+    /// says what went wrong.
+    pub(crate) fn draw_screen(&self, canvas: &Canvas, screen: Screen) -> Result<(), RenderError> {
+        let drawn = output::isolate(|| {
+            let ctx = Render::new(canvas, self.shared.clone())?;
+
+            match screen {
+                Screen::Board(layout) => ctx.draw(&layout),
+                Screen::Error(error) => ctx.draw_error(Failure::of(&error).headline, &error),
+            }
+        });
+
+        let Err(panic @ RenderError::Panicked(_)) = drawn else {
+            return drawn;
+        };
+        let error = eyre::Report::new(panic);
+        warn!(?error, "drawing the screen panicked, drawing error screen");
+
+        let _ = BOARD_ERROR.try_with(|board_error| {
+            *board_error.borrow_mut() = Some(Failure::of(&error));
+        });
+
+        canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
+        output::isolate(|| {
+            Render::new(canvas, self.shared.clone())?
+                .draw_error(Failure::of(&error).headline, &error)
+        })
+    }
+
+    /// Load the current layout and render it as the HTML view
+    pub(crate) async fn html(&self) -> Result<String> {
+        let layout = self.layout().await?;
 
-        Ok(())
+        Ok(html::render(
+            &layout,
+            self.config_file.templates_dir.as_deref().map(Path::new),
+        )?)
     }
 
-    fn orientation() -> Orientation {
-        Orientation::Landscape
+    /// Load the current layout and summarize it as plain text
+    pub(crate) async fn text(&self) -> Result<String> {
+        Ok(text::summary(&self.layout().await?))
+    }
+}
+
+#[cfg(feature = "kindling")]
+#[async_trait]
+impl<O: ScreenOrientation> kindling::Handler for TransitHandler<O> {
+    type Data = Screen;
+
+    async fn load(&self) -> Result<Self::Data> {
+        Ok(self.screen().await)
+    }
+
+    fn draw(&self, canvas: &Canvas, screen: Screen) -> Result<()> {
+        Ok(self.draw_screen(canvas, screen)?)
+    }
+
+    fn orientation() -> kindling::Orientation {
+        match O::ORIENTATION {
+            Orientation::Landscape => kindling::Orientation::Landscape,
+            Orientation::Portrait => kindling::Orientation::Portrait,
+        }
+    }
+}
+
+/// Who a screen route draws the board for, as kindling's `target`
+#[cfg(not(feature = "kindling"))]
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Target {
+    /// Turned to fit a portrait framebuffer if the screen is landscape
+    Kindle,
+    Browser,
+}
+
+/// Query parameters of a screen route, with kindling's defaults
+#[cfg(not(feature = "kindling"))]
+#[derive(Deserialize)]
+pub(crate) struct ScreenParams {
+    #[serde(default = "default_target")]
+    target: Target,
+    #[serde(default = "default_width", deserialize_with = "output::dimension")]
+    width: i32,
+    #[serde(default = "default_height", deserialize_with = "output::dimension")]
+    height: i32,
+}
+
+#[cfg(not(feature = "kindling"))]
+fn default_target() -> Target {
+    Target::Browser
+}
+
+#[cfg(not(feature = "kindling"))]
+fn default_width() -> i32 {
+    754
+}
+
+#[cfg(not(feature = "kindling"))]
+fn default_height() -> i32 {
+    1058
+}
+
+/// A screen's PNG, drawn as kindling would, for builds without it
+#[cfg(not(feature = "kindling"))]
+pub(crate) async fn screen_png<O: ScreenOrientation>(
+    State(handler): State<Arc<TransitHandler<O>>>,
+    Query(params): Query<ScreenParams>,
+) -> Response {
+    let screen = handler.screen().await;
+    let rotate = params.target == Target::Kindle && O::ORIENTATION == Orientation::Landscape;
+
+    let png = crate::output::blocking(move || {
+        crate::output::render_screen_png(
+            &handler.shared,
+            O::ORIENTATION,
+            (params.width, params.height),
+            rotate,
+            |canvas| handler.draw_screen(canvas, screen),
+        )
+    })
+    .await;
+
+    match png {
+        Ok(png) => ([(header::CONTENT_TYPE, "image/png")], png).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to draw screen");
+
+            (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+        }
     }
 }
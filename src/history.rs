@@ -0,0 +1,319 @@
+use std::{
+    fmt::Write,
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+    time::Duration as StdDuration,
+};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use eyre::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{
+    api_client::DataAccess,
+    config::ConfigFile,
+    error::Failure,
+    layout::data_to_layout,
+    output::{self, OutputFormat, OutputParams},
+    png_optimize,
+    render::SharedRenderData,
+};
+
+/// How often the recorder checks whether a refresh has finished
+const POLL_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+/// The board as drawn after each refresh, kept for a while so that it can be
+/// looked at again later
+pub struct History {
+    db: Mutex<Connection>,
+    keep: Duration,
+    /// Zone that times asked for are read in and days are listed by
+    timezone: Tz,
+}
+
+impl History {
+    pub fn open(path: &Path, keep_days: i64, timezone: Tz) -> Result<Self> {
+        let db = Connection::open(path)
+            .wrap_err_with(|| format!("opening history database {}", path.display()))?;
+
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS snapshots (
+                taken TEXT PRIMARY KEY,
+                png BLOB NOT NULL
+            );",
+        )
+        .wrap_err("creating history tables")?;
+
+        Ok(Self {
+            db: Mutex::new(db),
+            keep: Duration::days(keep_days),
+            timezone,
+        })
+    }
+
+    /// Keep a PNG of the board drawn at `taken`, forgetting those older than
+    /// the configured number of days
+    pub fn record(&self, taken: DateTime<Utc>, png: &[u8]) -> Result<()> {
+        let db = self.db.lock().unwrap();
+
+        db.execute(
+            "INSERT OR REPLACE INTO snapshots (taken, png) VALUES (?1, ?2)",
+            params![taken, png],
+        )?;
+        db.execute(
+            "DELETE FROM snapshots WHERE taken < ?1",
+            params![taken - self.keep],
+        )?;
+
+        Ok(())
+    }
+
+    /// The latest snapshot taken no later than `at`
+    pub fn at(&self, at: DateTime<Utc>) -> Result<Option<(DateTime<Utc>, Vec<u8>)>> {
+        let db = self.db.lock().unwrap();
+
+        Ok(db
+            .query_row(
+                "SELECT taken, png FROM snapshots WHERE taken <= ?1
+                    ORDER BY taken DESC LIMIT 1",
+                params![at],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?)
+    }
+
+    /// Times of the snapshots taken from `start` until `end`
+    pub fn taken_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<DateTime<Utc>>> {
+        let db = self.db.lock().unwrap();
+
+        let mut statement = db.prepare(
+            "SELECT taken FROM snapshots WHERE taken >= ?1 AND taken < ?2 ORDER BY taken",
+        )?;
+        let taken = statement
+            .query_map(params![start, end], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(taken)
+    }
+}
+
+/// Draw the board after every refresh and keep it in the history, until the
+/// departures stop being fetched when the config is reloaded
+pub async fn record(
+    history: Arc<History>,
+    data_access: Weak<DataAccess>,
+    shared: Arc<SharedRenderData>,
+    config_file: ConfigFile,
+) {
+    let mut recorded = None;
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let Some(data_access) = data_access.upgrade() else {
+            return;
+        };
+
+        let attempt = data_access.last_attempt();
+        if attempt.is_none() || attempt == recorded {
+            continue;
+        }
+        recorded = attempt;
+
+        let history = history.clone();
+        let shared = shared.clone();
+        let config_file = config_file.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let params = OutputParams::default();
+
+            let drawn = data_access
+                .stop_data(&config_file)
+                .wrap_err("load stop data")
+                .and_then(|stop_data| {
+                    let layout = data_to_layout(stop_data, &config_file);
+                    Ok(output::render(
+                        shared.clone(),
+                        &layout,
+                        OutputFormat::Png,
+                        params,
+                    )?)
+                });
+
+            let png = match drawn {
+                Ok(x) => x,
+                Err(error) => output::render_error_png(shared, &error, params)?,
+            };
+
+            history.record(Utc::now(), &png_optimize::optimize(&png)?)
+        })
+        .await;
+
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => warn!(?error, "failed to record board history"),
+            Err(error) => warn!(?error, "board history task failed"),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryParams {
+    /// Local time to show the board at, e.g. `2024-05-01T08:00`
+    at: Option<String>,
+    /// Local day to list the snapshots of, e.g. `2024-05-01`
+    day: Option<NaiveDate>,
+}
+
+/// The board as it was at `?at=`, or a page listing the snapshots taken on
+/// `?day=`, today by default
+#[utoipa::path(
+    get,
+    path = "/history",
+    params(HistoryParams),
+    responses(
+        (status = 200, description = "The board as a PNG with `at`, otherwise a page of the day's snapshots", content_type = "image/png"),
+        (status = 400, description = "`at` or `day` couldn't be read"),
+        (status = 404, description = "No board was recorded by `at`"),
+    )
+)]
+pub async fn history_page(
+    State(history): State<Arc<History>>,
+    Query(params): Query<HistoryParams>,
+) -> Response {
+    match params.at {
+        Some(at) => snapshot(history, &at).await,
+        None => timeline(history, params.day).await,
+    }
+}
+
+async fn snapshot(history: Arc<History>, at: &str) -> Response {
+    let Some(at) = parse_local(at, history.timezone) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "at must be a local time like 2024-05-01T08:00",
+        )
+            .into_response();
+    };
+
+    match tokio::task::spawn_blocking(move || history.at(at)).await {
+        Ok(Ok(Some((_, png)))) => (
+            [
+                (header::CONTENT_TYPE, "image/png"),
+                (header::CACHE_CONTROL, "no-store"),
+            ],
+            png,
+        )
+            .into_response(),
+        Ok(Ok(None)) => (StatusCode::NOT_FOUND, "no board was recorded by then").into_response(),
+        Ok(Err(error)) => Failure::of(&error).response(&error),
+        Err(error) => {
+            warn!(?error, "board history task failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn timeline(history: Arc<History>, day: Option<NaiveDate>) -> Response {
+    let timezone = history.timezone;
+    let day = day.unwrap_or_else(|| Utc::now().with_timezone(&timezone).date_naive());
+
+    let midnight = |day| local_midnight(day, timezone);
+    let (Some(start), Some(end)) = (midnight(day), day.succ_opt().and_then(midnight)) else {
+        return (StatusCode::BAD_REQUEST, "day is out of range").into_response();
+    };
+
+    let taken = match tokio::task::spawn_blocking(move || history.taken_between(start, end)).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(error)) => return Failure::of(&error).response(&error),
+        Err(error) => {
+            warn!(?error, "board history task failed");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut items = String::new();
+    for taken in &taken {
+        let local = taken.with_timezone(&timezone);
+        let _ = write!(
+            items,
+            r#"<li><a href="history?at={}">{}</a></li>"#,
+            local.format("%Y-%m-%dT%H:%M:%S"),
+            local.format("%-I:%M:%S %p"),
+        );
+    }
+    if items.is_empty() {
+        items = String::from("<li>No boards were recorded on this day.</li>");
+    }
+
+    let nav = |day: Option<NaiveDate>, label: &str| match day {
+        Some(day) => format!(r#"<a href="history?day={day}">{label}</a>"#),
+        None => String::new(),
+    };
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>transit-kindle history</title>
+<style>
+body {{ font-family: sans-serif; }}
+ol {{ columns: 12em; }}
+</style>
+</head>
+<body>
+<h1>Boards on {day}</h1>
+<p>{previous} {next}</p>
+<p>Show the board at any time with <code>?at=2024-05-01T08:00</code>, in the {timezone} time zone.</p>
+<ol>
+{items}
+</ol>
+</body>
+</html>
+"#,
+        day = day.format("%A, %B %-d, %Y"),
+        previous = nav(day.pred_opt(), "&larr; Previous day"),
+        next = nav(day.succ_opt(), "Next day &rarr;"),
+    );
+
+    ([(header::CACHE_CONTROL, "no-store")], Html(body)).into_response()
+}
+
+/// A time in `timezone` like `2024-05-01T08:00`, with optional seconds, or a
+/// full RFC 3339 timestamp
+fn parse_local(text: &str, timezone: Tz) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+
+    let naive = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())?;
+
+    timezone
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn local_midnight(day: NaiveDate, timezone: Tz) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(&day.and_hms_opt(0, 0, 0)?)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
@@ -0,0 +1,139 @@
+use std::{fmt::Display, path::Path};
+
+use askama::Template;
+use chrono::{DateTime, Utc};
+use minijinja::{context, path_loader, Environment};
+
+use crate::{error::RenderError, layout::Layout};
+
+#[derive(Template)]
+#[template(path = "stops.html")]
+struct StopsTemplate<'a> {
+    layout: &'a Layout,
+    now: DateTime<Utc>,
+}
+
+/// Render the HTML view of the board. A `stops.html` in `templates_dir` is
+/// used in place of the built-in template, and can include other templates
+/// from the same directory. Both get the board as `layout` and the time it
+/// was drawn as `now`, and can use the filters in [`filters`].
+pub fn render(layout: &Layout, templates_dir: Option<&Path>) -> Result<String, RenderError> {
+    let now = Utc::now();
+
+    if let Some(dir) = templates_dir.filter(|dir| dir.join("stops.html").exists()) {
+        // a fresh environment each time, so edits show up on the next reload
+        let mut env = Environment::new();
+        env.set_loader(path_loader(dir));
+        env.add_filter(
+            "pluralize",
+            |count: i64, singular: String, plural: String| {
+                filters::pluralize(count, &singular, &plural).map_err(template_error)
+            },
+        );
+        env.add_filter("duration", |minutes: i64| {
+            filters::duration(minutes).map_err(template_error)
+        });
+        let timezone = layout.timezone;
+        env.add_filter("clock", move |time: String| {
+            filters::clock(parse_time(&time)?, timezone).map_err(template_error)
+        });
+        env.add_filter("age", move |time: String| {
+            filters::age(parse_time(&time)?, now).map_err(template_error)
+        });
+
+        return env
+            .get_template("stops.html")
+            .and_then(|template| template.render(context! { layout, now }))
+            .map_err(|source| RenderError::RuntimeTemplate {
+                dir: dir.to_owned(),
+                source,
+            });
+    }
+
+    Ok(StopsTemplate { layout, now }.render()?)
+}
+
+/// Times reach runtime templates serialized as RFC 3339 strings
+fn parse_time(time: &str) -> Result<DateTime<Utc>, minijinja::Error> {
+    time.parse().map_err(template_error)
+}
+
+fn template_error(error: impl Display) -> minijinja::Error {
+    minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, error.to_string())
+}
+
+/// Filters available to the HTML templates
+pub mod filters {
+    use chrono::{DateTime, Utc};
+    use chrono_tz::Tz;
+
+    /// A filter argument, however many times askama has borrowed it
+    pub trait Arg<T> {
+        fn get(&self) -> T;
+    }
+
+    impl Arg<i64> for i64 {
+        fn get(&self) -> i64 {
+            *self
+        }
+    }
+
+    impl Arg<DateTime<Utc>> for DateTime<Utc> {
+        fn get(&self) -> DateTime<Utc> {
+            *self
+        }
+    }
+
+    impl Arg<Tz> for Tz {
+        fn get(&self) -> Tz {
+            *self
+        }
+    }
+
+    impl<T, U: Arg<T> + ?Sized> Arg<T> for &U {
+        fn get(&self) -> T {
+            (**self).get()
+        }
+    }
+
+    /// `{{ count|pluralize("departure", "departures") }}` gives e.g.
+    /// `1 departure` or `3 departures`
+    pub fn pluralize(count: impl Arg<i64>, singular: &str, plural: &str) -> askama::Result<String> {
+        let count = count.get();
+
+        Ok(format!(
+            "{count} {}",
+            if count == 1 { singular } else { plural }
+        ))
+    }
+
+    /// A number of minutes as e.g. `45 min` or `1 h 5 min`
+    pub fn duration(minutes: impl Arg<i64>) -> askama::Result<String> {
+        let minutes = minutes.get();
+        let (hours, minutes) = (minutes / 60, minutes % 60);
+
+        Ok(match (hours, minutes) {
+            (0, minutes) => format!("{minutes} min"),
+            (hours, 0) => format!("{hours} h"),
+            (hours, minutes) => format!("{hours} h {minutes} min"),
+        })
+    }
+
+    /// Time of day in the board's zone, e.g. `12:42 AM`
+    pub fn clock(time: impl Arg<DateTime<Utc>>, timezone: impl Arg<Tz>) -> askama::Result<String> {
+        Ok(time
+            .get()
+            .with_timezone(&timezone.get())
+            .format("%-I:%M %p")
+            .to_string())
+    }
+
+    /// How long before `now` a time was, e.g. `3 min`, for showing how fresh
+    /// an agency's data is
+    pub fn age(
+        time: impl Arg<DateTime<Utc>>,
+        now: impl Arg<DateTime<Utc>>,
+    ) -> askama::Result<String> {
+        duration((now.get() - time.get()).num_minutes().max(0))
+    }
+}
@@ -0,0 +1,159 @@
+use skia_safe::{
+    paint::{Cap, Join, Style},
+    Canvas, Color4f, Paint, Path, Rect,
+};
+
+use crate::layout_engine::Bounds;
+
+/// Status glyphs drawn as vector paths, so they look the same regardless of
+/// which characters the bundled font happens to cover
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Icon {
+    Check,
+    Warning,
+    Cross,
+    Clock,
+}
+
+/// One stroke of an icon, in board coordinates, so that any painter can draw
+/// the same icons
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stroke {
+    Path {
+        points: Vec<(f32, f32)>,
+        closed: bool,
+    },
+    RoundRect {
+        bounds: Bounds,
+        radius: f32,
+    },
+    Circle {
+        center: (f32, f32),
+        radius: f32,
+    },
+}
+
+impl Icon {
+    /// Width of the icon's strokes, which are drawn with round caps and joins
+    pub fn stroke_width(size: f32) -> f32 {
+        (size / 8.0).max(1.0)
+    }
+
+    /// The strokes making up the icon in the square of side `size` whose
+    /// bottom-left corner is at `(x, y)`, so that it sits on the same baseline
+    /// as surrounding text
+    pub fn strokes(self, (x, y): (f32, f32), size: f32) -> Vec<Stroke> {
+        let top = y - size;
+        // map a point in the unit square onto the icon's square
+        let at = |px: f32, py: f32| (x + px * size, top + py * size);
+        let path = |points: &[(f32, f32)], closed| Stroke::Path {
+            points: points.to_vec(),
+            closed,
+        };
+        let frame = Stroke::RoundRect {
+            bounds: Bounds::new(x, top, x + size, y),
+            radius: size / 6.0,
+        };
+
+        match self {
+            Icon::Check => vec![
+                frame,
+                path(&[at(0.22, 0.52), at(0.42, 0.74), at(0.78, 0.28)], false),
+            ],
+            Icon::Cross => vec![
+                frame,
+                path(&[at(0.28, 0.28), at(0.72, 0.72)], false),
+                path(&[at(0.72, 0.28), at(0.28, 0.72)], false),
+            ],
+            Icon::Warning => vec![
+                path(&[at(0.5, 0.04), at(0.97, 0.94), at(0.03, 0.94)], true),
+                path(&[at(0.5, 0.36), at(0.5, 0.62)], false),
+                path(&[at(0.5, 0.78), at(0.5, 0.79)], false),
+            ],
+            Icon::Clock => vec![
+                Stroke::Circle {
+                    center: at(0.5, 0.5),
+                    radius: size * 0.46,
+                },
+                path(&[at(0.5, 0.5), at(0.5, 0.2)], false),
+                path(&[at(0.5, 0.5), at(0.72, 0.62)], false),
+            ],
+        }
+    }
+
+    /// Draw the icon into the square of side `size` whose bottom-left corner is
+    /// at `(x, y)`
+    pub fn draw(self, canvas: &Canvas, (x, y): (f32, f32), size: f32, color: Color4f) {
+        let mut paint = Paint::new(color, None);
+        paint
+            .set_anti_alias(true)
+            .set_style(Style::Stroke)
+            .set_stroke_width(Self::stroke_width(size))
+            .set_stroke_cap(Cap::Round)
+            .set_stroke_join(Join::Round);
+
+        for stroke in self.strokes((x, y), size) {
+            match stroke {
+                Stroke::Path { points, closed } => {
+                    canvas.draw_path(&skia_path(&points, closed), &paint);
+                }
+                Stroke::RoundRect { bounds, radius } => {
+                    canvas.draw_round_rect(
+                        Rect::new(bounds.left, bounds.top, bounds.right, bounds.bottom),
+                        radius,
+                        radius,
+                        &paint,
+                    );
+                }
+                Stroke::Circle { center, radius } => {
+                    canvas.draw_circle(center, radius, &paint);
+                }
+            }
+        }
+    }
+}
+
+fn skia_path(points: &[(f32, f32)], closed: bool) -> Path {
+    let mut path = Path::new();
+
+    if let Some((first, rest)) = points.split_first() {
+        path.move_to(*first);
+        for point in rest {
+            path.line_to(*point);
+        }
+    }
+    if closed {
+        path.close();
+    }
+
+    path
+}
+
+/// Corners of an arrow pointing along a compass bearing, with north straight
+/// up, in the square of side `size` whose bottom-left corner is at `(x, y)`
+pub fn arrow_points((x, y): (f32, f32), size: f32, bearing: f32) -> [(f32, f32); 4] {
+    let center = (x + size / 2.0, y - size / 2.0);
+    let (sin, cos) = bearing.to_radians().sin_cos();
+
+    // a narrow arrowhead pointing up, rotated clockwise about the center of
+    // the square
+    [(0.0, -0.45), (0.32, 0.4), (0.0, 0.22), (-0.32, 0.4)].map(|(px, py)| {
+        let (dx, dy) = (px * size, py * size);
+        (
+            center.0 + dx * cos - dy * sin,
+            center.1 + dx * sin + dy * cos,
+        )
+    })
+}
+
+/// Draw an arrow pointing along a compass bearing, with north straight up,
+/// into the square of side `size` whose bottom-left corner is at `(x, y)`
+pub fn draw_arrow(canvas: &Canvas, (x, y): (f32, f32), size: f32, bearing: f32, color: Color4f) {
+    let mut paint = Paint::new(color, None);
+    paint.set_anti_alias(true).set_style(Style::Fill);
+
+    canvas.draw_path(
+        &skia_path(&arrow_points((x, y), size, bearing), true),
+        &paint,
+    );
+}
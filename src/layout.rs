@@ -1,85 +1,399 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+};
 
-use chrono::prelude::*;
+use chrono::{prelude::*, Duration};
+use chrono_tz::Tz;
 use eyre::{bail, Result};
 use itertools::Itertools;
+use serde::Serialize;
 use tracing::warn;
 
 use crate::{
-    api_client::{StopData, Upcoming},
-    config::{ConfigFile, SectionConfig, SideConfig, TextSectionConfig},
+    api_client::StopData,
+    calendar::ServiceDay,
+    config::{
+        AgencySectionConfig, ConfigFile, Profile, SectionConfig, SectionStyle, TextSectionConfig,
+    },
 };
 
+#[derive(Serialize)]
 pub struct Layout {
     pub left: Column,
     pub right: Column,
 
+    /// Whether the rows in `left` are split between both columns as they're
+    /// drawn, to balance their heights
+    pub balance: bool,
+
     /// Mapping of names of agencies to the timestamp that their data was last refreshed
     pub all_agencies: HashMap<String, DateTime<Utc>>,
+
+    /// Names of agencies with at least one section that could not be generated
+    pub failed_agencies: BTreeSet<String>,
+
+    /// Names of agencies whose data is old enough to be shown as critically
+    /// stale, which the board warns about in a banner
+    pub stale_agencies: BTreeSet<String>,
+
+    /// Profile the board is drawn with, which changes during glance hours
+    pub profile: Profile,
+
+    /// Zone the board's clock times are shown in
+    pub timezone: Tz,
+
+    /// Journeys left off the board for missing a field, counted in the
+    /// footer of debug builds
+    pub incomplete_journeys: usize,
 }
 
+#[derive(Serialize)]
 pub struct Column {
     pub rows: Vec<Row>,
 }
 
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
 pub enum Row {
     Agency(Agency),
-    Text(String),
+    Text(Text),
 }
 
+impl Row {
+    pub fn style(&self) -> &SectionStyle {
+        match self {
+            Row::Agency(agency) => &agency.style,
+            Row::Text(text) => &text.style,
+        }
+    }
+}
+
+#[derive(Serialize)]
 pub struct Agency {
     pub lines: Vec<Line>,
+
+    /// Shown in place of the lines when the agency isn't running, e.g. on a
+    /// holiday
+    pub notice: Option<String>,
+
+    /// Columns of departure times, when the section is drawn as a timetable
+    /// grid rather than a list
+    pub timetable: Option<usize>,
+
+    /// Where the departures leave from, such as "at Church St & Duboce Ave",
+    /// drawn small at the top of the section
+    pub stop: Option<String>,
+
+    pub style: SectionStyle,
+
+    /// Template of the sentence `/summary.json` reads the section out as, if
+    /// not the default
+    pub summary: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Text {
+    pub text: String,
+    pub style: SectionStyle,
 }
 
+/// Most departure times listed for a line
+const MAX_DEPARTURES: usize = 4;
+
+#[derive(Serialize)]
 pub struct Line {
     pub id: String,
     pub destination: String,
-    pub departure_minutes: Vec<i64>,
+
+    /// Second name for the destination, drawn smaller below it
+    pub secondary_destination: Option<String>,
+
+    pub departures: Vec<Departure>,
+
+    /// Set for sections which summarize departures rather than listing them
+    pub window: Option<Window>,
+
+    /// Compass bearing the line travels in, for the direction arrow
+    pub bearing: Option<f32>,
+
+    /// Colour the agency gives the line, which its bubble is shaded by
+    pub color: Option<[u8; 3]>,
+
+    /// Set when the line's last departure of the night is coming up soon
+    pub last_departure: Option<DateTime<Utc>>,
+
+    /// Drawn first in its section, in larger text
+    pub pinned: bool,
+}
+
+/// Number of departures within the next `minutes`
+#[derive(Serialize)]
+pub struct Departure {
+    /// Minutes until departure, less the configured lead time
+    pub minutes: i64,
+
+    /// When the departure is expected
+    pub time: DateTime<Utc>,
+
+    /// Whether the vehicle is wheelchair accessible, if known
+    pub accessible: Option<bool>,
+
+    /// Minutes behind the timetable, negative when early, if known
+    pub delay_minutes: Option<i64>,
+
+    /// Kept on the board, marked, for feeds which list cancelled trips
+    pub cancelled: bool,
+
+    /// Track or platform the vehicle leaves from, if known
+    pub track: Option<String>,
+}
+
+impl Departure {
+    /// Time of day the departure is expected, e.g. `5:42`, marked with a
+    /// wheelchair symbol if it's accessible
+    pub fn clock_text(&self, timezone: Tz) -> String {
+        let time = self.time.with_timezone(&timezone).format("%-I:%M");
+
+        self.marked(time)
+    }
+
+    /// A time, marked with a wheelchair symbol if the departure is
+    /// accessible, and crossed out with `×` if it's cancelled
+    fn marked(&self, time: impl Display) -> String {
+        let time = match self.accessible {
+            Some(true) => format!("{time}\u{267F}"),
+            _ => time.to_string(),
+        };
+
+        if self.cancelled {
+            format!("\u{d7}{time}")
+        } else {
+            time
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Window {
+    pub minutes: i64,
+    pub count: usize,
 }
 
 impl Line {
+    /// How light the line's colour is, from 0 for black to 1 for white
+    pub fn luminance(&self) -> Option<f32> {
+        let [r, g, b] = self.color?;
+
+        Some((0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32) / 255.0)
+    }
+
+    /// Inline style giving the HTML view's bubble the line's colour, with
+    /// white text on dark colours
+    pub fn bubble_style(&self) -> Option<String> {
+        let [r, g, b] = self.color?;
+        let text = if self.luminance()? < 0.5 {
+            "white"
+        } else {
+            "black"
+        };
+
+        Some(format!(
+            "background-color: rgb({r}, {g}, {b}); color: {text}"
+        ))
+    }
+
+    /// Departure minutes separated by commas, with accessible departures
+    /// marked by a wheelchair symbol and cancelled ones by `×`
     pub fn departure_minutes_str(&self) -> String {
-        self.departure_minutes.iter().join(", ")
+        self.departures
+            .iter()
+            .map(|departure| departure.marked(departure.minutes))
+            .join(", ")
+    }
+
+    /// Track the next departure leaves from, if the feed gives one
+    pub fn track(&self) -> Option<&str> {
+        self.departures.first()?.track.as_deref()
+    }
+
+    /// Departure times as drawn on the board, e.g. `3, 12 min`, or
+    /// `3 min (7 in 60)` for a windowed section, followed by the next
+    /// departure's track, e.g. `3, 12 min · TRACK 4`, and the last departure
+    /// of the night if it's close, e.g. `3, 12 min · LAST 12:42 AM`
+    pub fn departure_text(&self, timezone: Tz) -> String {
+        let mut text = match &self.window {
+            Some(Window { minutes, count }) => {
+                format!(
+                    "{} min ({count} in {minutes})",
+                    self.departure_minutes_str()
+                )
+            }
+            None => format!("{} min", self.departure_minutes_str()),
+        };
+
+        if let Some(track) = self.track() {
+            text = format!("{text} \u{b7} TRACK {track}");
+        }
+
+        match self.last_departure {
+            Some(last) => format!(
+                "{text} \u{b7} LAST {}",
+                last.with_timezone(&timezone).format("%-I:%M %p")
+            ),
+            None => text,
+        }
+    }
+
+    /// Departure times as shown on split-flap cells, which have no room for
+    /// punctuation, e.g. `3 12`, `3 7/60` for a windowed section, or
+    /// `3 12 LAST 12:42` near the last departure of the night
+    pub fn flap_text(&self, timezone: Tz) -> String {
+        let minutes = self
+            .departures
+            .iter()
+            .map(|departure| match departure.accessible {
+                Some(true) => format!("{}\u{267F}", departure.minutes),
+                _ => departure.minutes.to_string(),
+            })
+            .join(" ");
+
+        let text = match &self.window {
+            Some(Window {
+                minutes: window,
+                count,
+            }) => format!("{minutes} {count}/{window}"),
+            None => minutes,
+        };
+
+        match self.last_departure {
+            Some(last) => format!(
+                "{text} LAST {}",
+                last.with_timezone(&timezone).format("%-I:%M")
+            ),
+            None => text,
+        }
     }
 }
 
 pub fn data_to_layout(stop_data: StopData, config_file: &ConfigFile) -> Layout {
     let mut all_agencies = HashMap::new();
+    let mut failed_agencies = stop_data.degraded.clone();
+
+    let now = Utc::now();
+    let profile = config_file.profile_at(now);
+
+    // large print only has room for the line departing soonest
+    let max_lines = match profile {
+        Profile::Standard => config_file.layout.max_lines,
+        Profile::LargePrint => Some(config_file.layout.max_lines.map_or(1, |max| max.min(1))),
+    };
+
+    let options = LineOptions {
+        max_lines,
+        lead_minutes: config_file.layout.departure_lead_minutes,
+        hide_inaccessible: config_file.layout.hide_inaccessible,
+        last_departure_minutes: config_file.layout.last_departure_minutes,
+        secondary_destinations: &config_file.secondary_destinations,
+        line_colors: &config_file.bubble_palette.colors,
+    };
+
+    // sections to balance are all laid out in the left column, and split
+    // between the two once their heights are known
+    let balance = !config_file.layout.sections.is_empty();
+    let (left, right) = if balance {
+        (&config_file.layout.sections, &Vec::new())
+    } else {
+        (
+            &config_file.layout.left.sections,
+            &config_file.layout.right.sections,
+        )
+    };
 
-    let left = column(&stop_data, &config_file.layout.left, &mut all_agencies);
-    let right = column(&stop_data, &config_file.layout.right, &mut all_agencies);
+    let left = column(
+        &stop_data,
+        left,
+        &options,
+        &mut all_agencies,
+        &mut failed_agencies,
+    );
+    let right = column(
+        &stop_data,
+        right,
+        &options,
+        &mut all_agencies,
+        &mut failed_agencies,
+    );
+
+    let stale_agencies = all_agencies
+        .iter()
+        .filter(|(_, live_time)| {
+            now - **live_time >= Duration::minutes(config_file.footer.critical_minutes)
+        })
+        .map(|(agency, _)| agency.clone())
+        .collect();
 
     Layout {
         left,
         right,
+        balance,
         all_agencies,
+        failed_agencies,
+        stale_agencies,
+        profile,
+        timezone: config_file.timezone,
+        incomplete_journeys: stop_data.incomplete_journeys,
     }
 }
 
+/// Settings which apply to every line on the board
+struct LineOptions<'a> {
+    max_lines: Option<usize>,
+    lead_minutes: i64,
+    hide_inaccessible: bool,
+    last_departure_minutes: Option<i64>,
+    secondary_destinations: &'a HashMap<String, String>,
+    /// Colours given to lines in `bubble_palette`, by id
+    line_colors: &'a HashMap<String, [u8; 3]>,
+}
+
 fn column(
     stop_data: &StopData,
-    side: &SideConfig,
+    sections: &[SectionConfig],
+    options: &LineOptions,
     all_agencies: &mut HashMap<String, DateTime<Utc>>,
+    failed_agencies: &mut BTreeSet<String>,
 ) -> Column {
     let mut rows = Vec::new();
 
-    for section in &side.sections {
+    for section in sections {
         match section {
             SectionConfig::AgencySection(agency_section) => {
-                match agency(
-                    stop_data,
-                    &agency_section.agency,
-                    &agency_section.direction,
-                    all_agencies,
-                ) {
-                    Ok(x) => rows.push(Row::Agency(x)),
+                match agency(stop_data, agency_section, options, all_agencies) {
+                    Ok(mut x) => {
+                        if let Some(max_lines) = options.max_lines {
+                            // keep the pinned lines, then those departing soonest
+                            x.lines.sort_by_key(|line| {
+                                (
+                                    !line.pinned,
+                                    line.departures.first().map(|departure| departure.minutes),
+                                )
+                            });
+                            x.lines.truncate(max_lines);
+                        }
+                        rows.push(Row::Agency(x))
+                    }
                     Err(e) => {
                         warn!(error = %e, "failed to generate agency data");
+                        failed_agencies.insert(agency_section.agency.clone());
                     }
                 }
             }
-            SectionConfig::TextSection(TextSectionConfig { text }) => {
-                rows.push(Row::Text(text.clone()));
+            SectionConfig::TextSection(TextSectionConfig { text, style }) => {
+                rows.push(Row::Text(Text {
+                    text: text.clone(),
+                    style: *style,
+                }));
             }
         }
     }
@@ -89,10 +403,39 @@ fn column(
 
 fn agency(
     stop_data: &StopData,
-    agency_name: &str,
-    direction: &str,
+    section: &AgencySectionConfig,
+    options: &LineOptions,
     all_agencies: &mut HashMap<String, DateTime<Utc>>,
 ) -> Result<Agency> {
+    let agency_name = &section.agency;
+    let direction = &section.direction;
+
+    // with nothing scheduled today an empty section is expected, not an error
+    let notice = match stop_data.service.get(agency_name) {
+        Some(ServiceDay::Holiday) => Some("No service today (holiday)"),
+        Some(ServiceDay::NoService) => Some("No service today"),
+        Some(ServiceDay::Running) | None => None,
+    };
+
+    if let Some(notice) = notice {
+        let has_departures = stop_data
+            .agencies
+            .get(agency_name)
+            .and_then(|agency| agency.directions.get(direction))
+            .is_some_and(|lines| !lines.lines.is_empty());
+
+        if !has_departures {
+            return Ok(Agency {
+                lines: Vec::new(),
+                notice: Some(notice.to_owned()),
+                timetable: section.timetable_columns,
+                stop: None,
+                style: section.style,
+                summary: section.summary.clone(),
+            });
+        }
+    }
+
     let agency = match stop_data.agencies.get(agency_name) {
         Some(x) => x,
         None => {
@@ -114,14 +457,172 @@ fn agency(
     };
 
     let mut lines = Vec::new();
+    let now = Utc::now();
 
+    // lines are ordered by id and then destination, so each line's branches
+    // are together
     for (line, upcoming) in &lines_in.lines {
+        if section.hidden(&line.line) {
+            continue;
+        }
+
+        let departures = upcoming
+            .iter()
+            .filter(|upcoming| !(options.hide_inaccessible && upcoming.accessible() == Some(false)))
+            .map(|upcoming| Departure {
+                minutes: upcoming.minutes() - options.lead_minutes,
+                time: upcoming.time(),
+                accessible: upcoming.accessible(),
+                delay_minutes: upcoming.delay_minutes(),
+                cancelled: upcoming.cancelled(),
+                track: upcoming.track().map(str::to_owned),
+            })
+            .filter(|departure| departure.minutes >= 0);
+
+        let (shown, window) = match (section.timetable_columns, section.window_minutes) {
+            (Some(columns), _) => (departures.take(columns).collect(), None),
+            (None, Some(window)) => {
+                let count = departures
+                    .clone()
+                    .filter(|departure| departure.minutes <= window)
+                    .count();
+
+                (
+                    departures.take(1).collect::<Vec<_>>(),
+                    Some(Window {
+                        minutes: window,
+                        count,
+                    }),
+                )
+            }
+            (None, None) => (departures.take(MAX_DEPARTURES).collect(), None),
+        };
+
+        // a line whose departures are all within the lead time has nothing to show
+        if shown.is_empty() {
+            continue;
+        }
+
+        let last_departure = options.last_departure_minutes.and_then(|minutes| {
+            let warning = Duration::minutes(minutes);
+
+            let last = stop_data.last_departures.get(line).copied().or_else(|| {
+                // without a timetable, the last departure in the feed before
+                // the service ends is the last of the night, once the end is
+                // near enough that the feed covers it
+                let end = *stop_data.service_end.get(agency_name)?;
+                if end - now > warning {
+                    return None;
+                }
+
+                upcoming
+                    .iter()
+                    .map(|upcoming| upcoming.time())
+                    .rfind(|time| *time < end)
+            })?;
+
+            (last - now <= warning).then_some(last)
+        });
+
         lines.push(Line {
             id: line.line.clone(),
             destination: line.destination.clone(),
-            departure_minutes: upcoming.iter().map(Upcoming::minutes).collect(),
+            secondary_destination: options
+                .secondary_destinations
+                .get(&line.destination)
+                .cloned(),
+            departures: shown,
+            window,
+            bearing: stop_data.bearings.get(line).copied(),
+            color: options
+                .line_colors
+                .get(&line.line)
+                .or(stop_data.line_colors.get(line))
+                .copied(),
+            last_departure,
+            pinned: section.pinned(&line.line),
         })
     }
 
-    Ok(Agency { lines })
+    let mut lines = merge_branches(lines, section);
+
+    let stop_names = lines_in
+        .stops
+        .iter()
+        .filter_map(|stop| stop.name.as_deref())
+        .collect::<Vec<_>>();
+    let stop = (section.show_stop && !stop_names.is_empty())
+        .then(|| format!("at {}", stop_names.join(" / ")));
+
+    // the sort is stable, so the other lines keep their order
+    lines.sort_by_key(|line| !line.pinned);
+
+    Ok(Agency {
+        lines,
+        notice: None,
+        timetable: section.timetable_columns,
+        stop,
+        style: section.style,
+        summary: section.summary.clone(),
+    })
+}
+
+/// Each line's branches past its `max_branches`, leaving the soonest,
+/// merged into one row with their departures together
+fn merge_branches(lines: Vec<Line>, section: &AgencySectionConfig) -> Vec<Line> {
+    let mut merged = Vec::new();
+
+    for (id, branches) in &lines.into_iter().group_by(|line| line.id.clone()) {
+        let mut branches = branches.collect::<Vec<_>>();
+
+        let Some(max) = section.max_branches(&id) else {
+            merged.extend(branches);
+            continue;
+        };
+        // a row saying there's one more branch may as well be that branch
+        if branches.len() <= max + 1 {
+            merged.extend(branches);
+            continue;
+        }
+
+        branches.sort_by_key(|branch| branch.departures.first().map(|departure| departure.time));
+        let rest = branches.split_off(max);
+
+        let shown = rest
+            .iter()
+            .map(|branch| branch.departures.len())
+            .max()
+            .unwrap_or_default();
+        let window = rest
+            .iter()
+            .filter_map(|branch| branch.window.as_ref())
+            .map(|window| (window.minutes, window.count))
+            .reduce(|(minutes, total), (_, count)| (minutes, total + count))
+            .map(|(minutes, count)| Window { minutes, count });
+
+        let first = &rest[0];
+        let mut merged_branches = Line {
+            id,
+            destination: format!("…and {} more branches", rest.len()),
+            secondary_destination: None,
+            departures: Vec::new(),
+            window,
+            // the branches part ways, so there's no one way they all go
+            bearing: None,
+            color: first.color,
+            last_departure: None,
+            pinned: first.pinned,
+        };
+        merged_branches.departures = rest
+            .into_iter()
+            .flat_map(|branch| branch.departures)
+            .sorted_by_key(|departure| departure.time)
+            .take(shown)
+            .collect();
+
+        merged.extend(branches);
+        merged.push(merged_branches);
+    }
+
+    merged
 }
@@ -0,0 +1,1990 @@
+use std::{collections::hash_map::DefaultHasher, hash::Hasher};
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::{Tz, US::Pacific};
+use itertools::Itertools;
+use tracing::warn;
+
+use crate::{
+    config::{
+        BubblePaletteConfig, FooterConfig, GrayRamp, Profile, SectionStyle, Theme, TitleDirection,
+        TitleStyle,
+    },
+    error::RenderError,
+    icons::Icon,
+    layout::{Agency, Layout, Line, Row, Text},
+};
+
+/// Font sizes and spacing used to lay out the board, selected by the
+/// configured [`Profile`]
+pub struct Metrics {
+    pub font_size: f32,
+    pub departure_font_size: f32,
+    pub secondary_font_size: f32,
+    /// Size of everything on a pinned line
+    pub pinned_font_size: f32,
+
+    /// Distance from a row separator to the top of the next row
+    pub row_gap: f32,
+    pub agency_padding_top: f32,
+    pub agency_padding_bottom: f32,
+    pub line_spacing: f32,
+    /// Extra height taken by a line whose destination has a secondary name
+    pub secondary_line_height: f32,
+    /// Extra height taken by a pinned line, for its larger text
+    pub pinned_line_height: f32,
+    /// Extra height taken by the stop name at the top of a section
+    pub stop_line_height: f32,
+    pub text_row_height: f32,
+    pub text_row_baseline: f32,
+    pub footer_height: f32,
+    pub footer_baseline: f32,
+    pub margin: f32,
+    pub bubble_radius: f32,
+
+    pub line_divider_shade: f32,
+    /// Range of grey levels that line id bubbles are mapped into
+    pub bubble_shades: (f32, f32),
+    /// Whether departure times fade into the destination text with a gradient
+    pub gradients: bool,
+}
+
+impl Metrics {
+    pub fn for_profile(profile: Profile) -> Self {
+        match profile {
+            Profile::Standard => Self {
+                font_size: 24.0,
+                departure_font_size: 24.0,
+                secondary_font_size: 16.0,
+                pinned_font_size: 32.0,
+                row_gap: 28.0,
+                agency_padding_top: 4.0,
+                agency_padding_bottom: 15.0,
+                line_spacing: 48.0,
+                secondary_line_height: 20.0,
+                pinned_line_height: 10.0,
+                stop_line_height: 30.0,
+                text_row_height: 40.0,
+                text_row_baseline: 28.0,
+                footer_height: 40.0,
+                footer_baseline: 10.0,
+                margin: 20.0,
+                bubble_radius: 24.0,
+                line_divider_shade: 0.7,
+                bubble_shades: (0.5, 0.9),
+                gradients: true,
+            },
+            Profile::LargePrint => Self {
+                font_size: 36.0,
+                departure_font_size: 64.0,
+                secondary_font_size: 28.0,
+                pinned_font_size: 72.0,
+                row_gap: 42.0,
+                agency_padding_top: 20.0,
+                agency_padding_bottom: 24.0,
+                line_spacing: 80.0,
+                secondary_line_height: 32.0,
+                pinned_line_height: 12.0,
+                stop_line_height: 44.0,
+                text_row_height: 56.0,
+                text_row_baseline: 42.0,
+                footer_height: 52.0,
+                footer_baseline: 12.0,
+                margin: 24.0,
+                bubble_radius: 36.0,
+                line_divider_shade: 0.0,
+                bubble_shades: (0.8, 0.95),
+                gradients: false,
+            },
+        }
+    }
+
+    /// Most sections a column of this height could ever hold, were each as
+    /// short as a section can be
+    pub fn sections_fitting(&self, height: f32) -> usize {
+        let shortest = self
+            .text_row_height
+            .min(self.agency_padding_top + self.agency_padding_bottom);
+        let room = height - self.footer_height - shortest;
+        if room < 0.0 {
+            return 0;
+        }
+
+        1 + (room / (self.row_gap + shortest)) as usize
+    }
+
+    /// Size of the given font
+    pub fn font_size(&self, font: FontKind) -> f32 {
+        match font {
+            FontKind::Regular | FontKind::Bold => self.font_size,
+            FontKind::Departure | FontKind::Flap => self.departure_font_size,
+            FontKind::Secondary => self.secondary_font_size,
+            FontKind::Pinned => self.pinned_font_size,
+        }
+    }
+}
+
+/// Which of the board's fonts a piece of text is set in
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FontKind {
+    Regular,
+    Bold,
+    Departure,
+    Secondary,
+    /// The monospace font of the split-flap theme, at the departure size
+    Flap,
+    /// Everything on a pinned line
+    Pinned,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Size of a piece of text, as the painter will draw it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextExtent {
+    pub width: f32,
+    /// Height of the font above the baseline, as a negative offset
+    pub ascent: f32,
+    /// Height of the text's glyphs above the baseline, as a negative offset
+    pub ink_top: f32,
+}
+
+/// Measures text the way the painter will draw it
+pub trait Measure {
+    fn measure(&self, text: &str, font: FontKind) -> Result<TextExtent, RenderError>;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Bounds {
+    pub fn new(left: f32, top: f32, right: f32, bottom: f32) -> Self {
+        Self {
+            left,
+            top,
+            right,
+            bottom,
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.right - self.left
+    }
+
+    pub fn height(&self) -> f32 {
+        self.bottom - self.top
+    }
+
+    /// Whether the bounds share more than a sliver of area with `other`
+    pub fn overlaps(&self, other: &Bounds) -> bool {
+        const TOLERANCE: f32 = 0.5;
+
+        self.left + TOLERANCE < other.right
+            && other.left + TOLERANCE < self.right
+            && self.top + TOLERANCE < other.bottom
+            && other.top + TOLERANCE < self.bottom
+    }
+
+    /// Grow the bounds by `dx` on the left and right, and `dy` on the top and
+    /// bottom
+    pub fn outset(&self, dx: f32, dy: f32) -> Self {
+        Self::new(
+            self.left - dx,
+            self.top - dy,
+            self.right + dx,
+            self.bottom + dy,
+        )
+    }
+}
+
+/// Something to draw, positioned on the board. Shades are grey levels from
+/// 0.0 (black) to 1.0 (white).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Primitive {
+    Rect {
+        bounds: Bounds,
+        shade: f32,
+    },
+    RoundRect {
+        bounds: Bounds,
+        radius: f32,
+        shade: f32,
+    },
+    /// The outline of a rectangle
+    Outline {
+        bounds: Bounds,
+        width: f32,
+        shade: f32,
+    },
+    /// A straight line, where a `width` of zero is as thin as can be drawn
+    Line {
+        from: (f32, f32),
+        to: (f32, f32),
+        width: f32,
+        shade: f32,
+    },
+    /// Text with its left edge at `x` and its baseline at `y`
+    Text {
+        text: String,
+        font: FontKind,
+        x: f32,
+        y: f32,
+        shade: f32,
+    },
+    /// Text reading upward, turned a quarter turn counter-clockwise about
+    /// the left end of its baseline at `(x, y)`
+    VerticalText {
+        text: String,
+        font: FontKind,
+        x: f32,
+        y: f32,
+        shade: f32,
+    },
+    /// A gradient from `shade` at the right edge to transparent at the left
+    Fade {
+        bounds: Bounds,
+        shade: f32,
+    },
+    /// An icon in the square of side `size` whose bottom-left corner is at
+    /// `(x, y)`
+    Icon {
+        icon: Icon,
+        x: f32,
+        y: f32,
+        size: f32,
+        shade: f32,
+    },
+    /// An arrow pointing along a compass bearing, in the square of side
+    /// `size` whose bottom-left corner is at `(x, y)`
+    Arrow {
+        x: f32,
+        y: f32,
+        size: f32,
+        bearing: f32,
+        shade: f32,
+    },
+}
+
+impl Primitive {
+    fn shade_mut(&mut self) -> &mut f32 {
+        match self {
+            Primitive::Rect { shade, .. }
+            | Primitive::RoundRect { shade, .. }
+            | Primitive::Outline { shade, .. }
+            | Primitive::Line { shade, .. }
+            | Primitive::Text { shade, .. }
+            | Primitive::VerticalText { shade, .. }
+            | Primitive::Fade { shade, .. }
+            | Primitive::Icon { shade, .. }
+            | Primitive::Arrow { shade, .. } => shade,
+        }
+    }
+}
+
+/// How up to date an agency's data is, as shown in the footer
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Freshness {
+    Fresh,
+    Warning,
+    Critical,
+    Failed,
+}
+
+impl Freshness {
+    fn icon(self) -> Icon {
+        match self {
+            Freshness::Fresh => Icon::Check,
+            Freshness::Warning => Icon::Clock,
+            Freshness::Critical => Icon::Warning,
+            Freshness::Failed => Icon::Cross,
+        }
+    }
+}
+
+/// Bounds and positions noted while laying out the board with the debug
+/// overlay on, which are outlined over the finished board so that nothing
+/// covers them
+#[derive(Default)]
+struct DebugOverlay {
+    /// Each row's bounds, with a label naming the row
+    rows: Vec<(String, Bounds)>,
+    /// Bounds of each piece of text
+    text: Vec<Bounds>,
+    /// Where the y cursor was after each line and row, with the left edge of
+    /// its column
+    cursor: Vec<(f32, f32)>,
+}
+
+/// Where the columns of a timetable section are
+struct TimetableGrid {
+    /// Left edge of the soonest departure's column
+    left: f32,
+    cell_width: f32,
+    columns: usize,
+}
+
+/// Works out where everything on the board goes, as a list of primitives for
+/// a painter to draw in order. Nothing here draws, so the spacing can be
+/// checked without rendering images.
+pub struct LayoutEngine<'a, M> {
+    metrics: &'a Metrics,
+    footer: &'a FooterConfig,
+    measure: &'a M,
+
+    width: f32,
+    height: f32,
+    /// Time the board is drawn at, for the footer's clock and ages
+    now: DateTime<Utc>,
+    /// Zone the footer's clock and departure times are shown in
+    timezone: Tz,
+
+    theme: Theme,
+    /// Least difference in shade between a line's bubble and the one above
+    bubble_contrast: f32,
+    gray_ramp: Option<&'a GrayRamp>,
+
+    y: f32,
+    primitives: Vec<Primitive>,
+    debug: Option<DebugOverlay>,
+}
+
+/// Find text which is drawn over by later text or filled shapes, as pairs of
+/// the covered text's index and the index of what covers it. Backgrounds are
+/// drawn before the text on them, and fades are meant to run over text, so
+/// neither counts. Rectangles drawn to hide text that runs under departure
+/// times do, since the text would show again as soon as spacing changed.
+pub fn collisions<M: Measure>(
+    primitives: &[Primitive],
+    measure: &M,
+) -> Result<Vec<(usize, usize)>, RenderError> {
+    let mut drawn: Vec<(usize, Bounds)> = Vec::new();
+    let mut collisions = Vec::new();
+
+    for (index, primitive) in primitives.iter().enumerate() {
+        let (bounds, is_text) = match primitive {
+            Primitive::Text {
+                text, font, x, y, ..
+            } => {
+                let extent = measure.measure(text, *font)?;
+                (
+                    Bounds::new(*x, y + extent.ink_top, x + extent.width, *y),
+                    true,
+                )
+            }
+            Primitive::VerticalText {
+                text, font, x, y, ..
+            } => {
+                let extent = measure.measure(text, *font)?;
+                (
+                    Bounds::new(x + extent.ink_top, y - extent.width, *x, *y),
+                    true,
+                )
+            }
+            Primitive::Rect { bounds, .. } | Primitive::RoundRect { bounds, .. } => {
+                (*bounds, false)
+            }
+            _ => continue,
+        };
+
+        collisions.extend(
+            drawn
+                .iter()
+                .filter(|(_, earlier)| earlier.overlaps(&bounds))
+                .map(|(earlier, _)| (*earlier, index)),
+        );
+
+        if is_text {
+            drawn.push((index, bounds));
+        }
+    }
+
+    Ok(collisions)
+}
+
+/// Left edge of text of the given width, when aligned to `x`
+fn align_left_edge(x: f32, width: f32, align: Align) -> f32 {
+    match align {
+        Align::Left => x,
+        Align::Center => x - (width / 2.0),
+        Align::Right => x - width,
+    }
+}
+
+/// Bounds of the glyphs of measured text
+fn text_bounds(extent: &TextExtent, (x, y): (f32, f32), align: Align) -> Bounds {
+    let left = align_left_edge(x, extent.width, align);
+    Bounds::new(left, y + extent.ink_top, left + extent.width, y)
+}
+
+fn map_range(from_range: (f32, f32), to_range: (f32, f32), s: f32) -> f32 {
+    to_range.0 + (s - from_range.0) * (to_range.1 - to_range.0) / (from_range.1 - from_range.0)
+}
+
+impl<'a, M: Measure> LayoutEngine<'a, M> {
+    pub fn new(
+        metrics: &'a Metrics,
+        footer: &'a FooterConfig,
+        measure: &'a M,
+        (width, height): (f32, f32),
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            metrics,
+            footer,
+            measure,
+            width,
+            height,
+            now,
+            timezone: Pacific,
+            theme: Theme::Standard,
+            bubble_contrast: BubblePaletteConfig::default().min_contrast,
+            gray_ramp: None,
+            y: 0.0,
+            primitives: Vec::new(),
+            debug: None,
+        }
+    }
+
+    /// Draw departure times in the given theme
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Keep the bubbles of lines without a colour at least `min_contrast`
+    /// apart in shade from the bubble above them
+    pub fn bubble_contrast(mut self, min_contrast: f32) -> Self {
+        self.bubble_contrast = min_contrast;
+        self
+    }
+
+    /// Outline every row and piece of text, and mark the y cursor, over the
+    /// board
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug.then(DebugOverlay::default);
+        self
+    }
+
+    /// Show the footer's clock in `timezone`. Boards are shown in their
+    /// layout's zone.
+    pub fn timezone(mut self, timezone: Tz) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Draw each grey as the shade the panel's ramp gives for it
+    pub fn gray_ramp(mut self, gray_ramp: &'a GrayRamp) -> Self {
+        self.gray_ramp = Some(gray_ramp);
+        self
+    }
+
+    /// The primitives laid out, with their greys moved along the ramp
+    fn finish(mut self) -> Vec<Primitive> {
+        if let Some(gray_ramp) = self.gray_ramp {
+            for primitive in &mut self.primitives {
+                let shade = primitive.shade_mut();
+                *shade = gray_ramp.map(*shade);
+            }
+        }
+
+        self.primitives
+    }
+
+    fn x_midpoint(&self) -> f32 {
+        self.width / 2.0
+    }
+
+    /// Place `text` with its baseline at `y`
+    fn text(
+        &mut self,
+        text: &str,
+        (x, y): (f32, f32),
+        font: FontKind,
+        shade: f32,
+        align: Align,
+    ) -> Result<TextExtent, RenderError> {
+        let extent = self.measure.measure(text, font)?;
+        let left = align_left_edge(x, extent.width, align);
+
+        self.primitives.push(Primitive::Text {
+            text: text.to_owned(),
+            font,
+            x: left,
+            y,
+            shade,
+        });
+
+        if let Some(debug) = &mut self.debug {
+            debug
+                .text
+                .push(Bounds::new(left, y + extent.ascent, left + extent.width, y));
+        }
+
+        Ok(extent)
+    }
+
+    /// Note where the y cursor is, for the debug overlay
+    fn note_cursor(&mut self, x: f32) {
+        if let Some(debug) = &mut self.debug {
+            // the end of a row is also the end of its last line
+            if debug.cursor.last() != Some(&(x, self.y)) {
+                debug.cursor.push((x, self.y));
+            }
+        }
+    }
+
+    /// Place the separator above a row, unless it's at the top of its column
+    fn separator(&mut self, style: &SectionStyle, x1: f32, x2: f32) {
+        if self.y > 0.0 {
+            let border_weight = style.border_weight.unwrap_or(2.0);
+            if border_weight > 0.0 {
+                self.primitives.push(Primitive::Line {
+                    from: (x1, self.y),
+                    to: (x2, self.y),
+                    width: border_weight,
+                    shade: 0.0,
+                });
+            }
+            self.y += self.metrics.row_gap;
+        }
+    }
+
+    fn row(&mut self, row: &Row, x1: f32, x2: f32) -> Result<(), RenderError> {
+        self.separator(row.style(), x1, x2);
+
+        match row {
+            Row::Agency(agency) => self.agency_row(agency, x1, x2)?,
+            Row::Text(text) => self.text_row(text, x1, x2)?,
+        }
+
+        Ok(())
+    }
+
+    fn agency_row(&mut self, agency: &Agency, x1: f32, x2: f32) -> Result<(), RenderError> {
+        let metrics = self.metrics;
+
+        let background = agency.style.background.unwrap_or(1.0);
+
+        if agency.style.background.is_some() {
+            let secondary_height = agency
+                .lines
+                .iter()
+                .filter(|line| line.secondary_destination.is_some())
+                .count() as f32
+                * metrics.secondary_line_height;
+            let pinned_height = agency.lines.iter().filter(|line| line.pinned).count() as f32
+                * metrics.pinned_line_height;
+            let stop_height = if agency.stop.is_some() {
+                metrics.stop_line_height
+            } else {
+                0.0
+            };
+            let lines_height = metrics.line_spacing * agency.lines.len().saturating_sub(1) as f32
+                + secondary_height
+                + pinned_height
+                + stop_height;
+            self.primitives.push(Primitive::Rect {
+                bounds: Bounds::new(
+                    x1,
+                    self.y - metrics.row_gap,
+                    x2,
+                    self.y
+                        + metrics.agency_padding_top
+                        + lines_height
+                        + metrics.agency_padding_bottom,
+                ),
+                shade: background,
+            });
+        }
+
+        self.y += metrics.agency_padding_top;
+
+        if let Some(stop) = &agency.stop {
+            // level with where the first line's text would have started
+            let baseline = self.y - metrics.font_size + metrics.secondary_font_size;
+            self.text(
+                stop,
+                (x1 + metrics.margin, baseline),
+                FontKind::Secondary,
+                0.0,
+                Align::Left,
+            )?;
+            self.y += metrics.stop_line_height;
+        }
+
+        if let Some(notice) = &agency.notice {
+            self.text(
+                notice,
+                (x1 + metrics.margin, self.y),
+                FontKind::Regular,
+                0.0,
+                Align::Left,
+            )?;
+            self.y += metrics.agency_padding_bottom;
+
+            return Ok(());
+        }
+
+        let lines_len = agency.lines.len();
+
+        let grid = match agency.timetable {
+            Some(columns) => Some(self.timetable_grid(agency, columns, x2)?),
+            None => None,
+        };
+        let grid_top = self.y - metrics.font_size - 10.0;
+
+        let mut above = None;
+        for (idx, line) in agency.lines.iter().enumerate() {
+            let x = x1 + metrics.margin;
+
+            let font = if line.pinned {
+                // the larger text pushes the line down, rather than up into
+                // the one above
+                self.y += metrics.pinned_line_height;
+                FontKind::Pinned
+            } else {
+                FontKind::Regular
+            };
+
+            let shade = self.bubble_shade(line, above);
+            above = Some(shade);
+            let line_id_bounds = self.line_id_bubble(&line.id, shade, x, font)?;
+            let mut destination_x = x + line_id_bounds.width();
+
+            if let Some(bearing) = line.bearing {
+                let size = metrics.font_size * 0.8;
+                self.primitives.push(Primitive::Arrow {
+                    x: destination_x,
+                    y: self.y,
+                    size,
+                    bearing,
+                    shade: 0.0,
+                });
+                destination_x += size + metrics.font_size / 3.0;
+            }
+
+            self.text(
+                &line.destination,
+                (destination_x, self.y),
+                font,
+                0.0,
+                Align::Left,
+            )?;
+
+            match &grid {
+                Some(grid) => self.timetable_cells(grid, line, background)?,
+                None => self.departure_times(x2, line, background)?,
+            }
+
+            if let Some(secondary) = &line.secondary_destination {
+                self.text(
+                    secondary,
+                    (destination_x, self.y + metrics.font_size * 1.1),
+                    FontKind::Secondary,
+                    0.0,
+                    Align::Left,
+                )?;
+                self.y += metrics.secondary_line_height;
+            }
+
+            self.note_cursor(x1);
+
+            if idx < (lines_len - 1) {
+                let divider_y = self.y + metrics.agency_padding_bottom;
+                self.primitives.push(Primitive::Line {
+                    from: (x1 + 2.0 * metrics.margin, divider_y),
+                    to: (x2 - 2.0 * metrics.margin, divider_y),
+                    width: 0.0,
+                    shade: metrics.line_divider_shade,
+                });
+                self.y += metrics.line_spacing;
+            } else {
+                self.y += metrics.agency_padding_bottom;
+            }
+        }
+
+        if let Some(grid) = grid.filter(|_| lines_len > 0) {
+            for column in 0..grid.columns {
+                let x = grid.left + grid.cell_width * column as f32;
+                self.primitives.push(Primitive::Line {
+                    from: (x, grid_top),
+                    to: (x, self.y),
+                    width: 0.0,
+                    shade: metrics.line_divider_shade,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Size the columns of a timetable section to fit its widest departure
+    /// time, ending at the section's margin
+    fn timetable_grid(
+        &self,
+        agency: &Agency,
+        columns: usize,
+        x2: f32,
+    ) -> Result<TimetableGrid, RenderError> {
+        let mut widest = self.measure.measure("12:59", FontKind::Regular)?.width;
+        for departure in agency.lines.iter().flat_map(|line| &line.departures) {
+            let width = self
+                .measure
+                .measure(&departure.clock_text(self.timezone), FontKind::Regular)?
+                .width;
+            widest = widest.max(width);
+        }
+
+        let cell_width = widest + self.metrics.margin;
+
+        Ok(TimetableGrid {
+            left: x2 - self.metrics.margin - cell_width * columns as f32,
+            cell_width,
+            columns,
+        })
+    }
+
+    /// Place a line's departures in the cells of a timetable grid, soonest
+    /// first, covering any destination text which runs under them
+    fn timetable_cells(
+        &mut self,
+        grid: &TimetableGrid,
+        line: &Line,
+        background: f32,
+    ) -> Result<(), RenderError> {
+        let metrics = self.metrics;
+
+        let cells = Bounds::new(
+            grid.left,
+            self.y - metrics.font_size - 10.0,
+            grid.left + grid.cell_width * grid.columns as f32,
+            self.y + 10.0,
+        );
+
+        self.primitives.push(Primitive::Rect {
+            bounds: cells,
+            shade: background,
+        });
+
+        if metrics.gradients {
+            self.primitives.push(Primitive::Fade {
+                bounds: Bounds::new(cells.left - 25.0, cells.top, cells.left, cells.bottom),
+                shade: background,
+            });
+        }
+
+        for (column, departure) in line.departures.iter().take(grid.columns).enumerate() {
+            let center = grid.left + grid.cell_width * (column as f32 + 0.5);
+            self.text(
+                &departure.clock_text(self.timezone),
+                (center, self.y),
+                FontKind::Regular,
+                0.0,
+                Align::Center,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn departure_times(&mut self, x: f32, line: &Line, background: f32) -> Result<(), RenderError> {
+        if self.theme == Theme::SplitFlap {
+            return self.split_flap(
+                &line.flap_text(self.timezone),
+                x - self.metrics.margin,
+                background,
+            );
+        }
+
+        let time_text = line.departure_text(self.timezone);
+        let font = if line.pinned {
+            FontKind::Pinned
+        } else {
+            FontKind::Departure
+        };
+
+        let time_point = (x - self.metrics.margin, self.y);
+
+        let extent = self.measure.measure(&time_text, font)?;
+        let time_rect_exact = text_bounds(&extent, time_point, Align::Right);
+        let time_rect = time_rect_exact.outset(15.0, 10.0);
+
+        self.primitives.push(Primitive::Rect {
+            bounds: time_rect,
+            shade: background,
+        });
+
+        if self.metrics.gradients {
+            self.primitives.push(Primitive::Fade {
+                bounds: Bounds::new(
+                    time_rect.left - 25.0,
+                    time_rect_exact.top,
+                    time_rect.left,
+                    time_rect.bottom,
+                ),
+                shade: background,
+            });
+        }
+
+        self.text(&time_text, time_point, font, 0.0, Align::Right)?;
+
+        Ok(())
+    }
+
+    /// Place `text` one character to a cell, right-aligned to `right`, with a
+    /// split across the middle of each cell like the flaps of a split-flap
+    /// board. Spaces are left as gaps between the cells.
+    fn split_flap(&mut self, text: &str, right: f32, background: f32) -> Result<(), RenderError> {
+        let size = self.metrics.departure_font_size;
+        let cell = self.measure.measure("0", FontKind::Flap)?;
+        let pad = size * 0.15;
+
+        let cell_width = cell.width + 2.0 * pad;
+        let gap = pad / 2.0;
+        let top = self.y + cell.ink_top - pad;
+        let bottom = self.y + pad;
+
+        let cells = text.chars().count() as f32;
+        let left = right - cells * (cell_width + gap) + gap;
+
+        let covered = Bounds::new(left, top, right, bottom).outset(15.0, 10.0);
+        self.primitives.push(Primitive::Rect {
+            bounds: covered,
+            shade: background,
+        });
+
+        if self.metrics.gradients {
+            self.primitives.push(Primitive::Fade {
+                bounds: Bounds::new(covered.left - 25.0, top, covered.left, covered.bottom),
+                shade: background,
+            });
+        }
+
+        let split = (top + bottom) / 2.0;
+
+        for (idx, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+
+            let x = left + idx as f32 * (cell_width + gap);
+            self.primitives.push(Primitive::RoundRect {
+                bounds: Bounds::new(x, top, x + cell_width, bottom),
+                radius: pad / 2.0,
+                shade: 0.15,
+            });
+
+            self.text(
+                &c.to_string(),
+                (x + cell_width / 2.0, self.y),
+                FontKind::Flap,
+                1.0,
+                Align::Center,
+            )?;
+
+            self.primitives.push(Primitive::Line {
+                from: (x, split),
+                to: (x + cell_width, split),
+                width: 1.0,
+                shade: background,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Shade of a line's bubble, kept within the profile's range of bubble
+    /// shades so the id stays readable: as light or dark as the line's own
+    /// colour, or else one of evenly spaced greys picked by its id, moving on
+    /// to the next that stands out from the bubble above
+    fn bubble_shade(&self, line: &Line, above: Option<f32>) -> f32 {
+        let (darkest, lightest) = self.metrics.bubble_shades;
+
+        if let Some(luminance) = line.luminance() {
+            return map_range((0.0, 1.0), (darkest, lightest), luminance);
+        }
+
+        // a little slack, so that a range which divides evenly into steps of
+        // the contrast isn't a step short after rounding
+        let min_contrast = self.bubble_contrast - 1e-4;
+        let steps = ((lightest - darkest) / min_contrast)
+            .floor()
+            .clamp(1.0, 16.0) as u64;
+        let shade = |step: u64| map_range((0.0, steps as f32), (darkest, lightest), step as f32);
+
+        let mut color_hasher = DefaultHasher::new();
+        color_hasher.write(line.id.as_bytes());
+        let hashed = color_hasher.finish() % (steps + 1);
+
+        (0..=steps)
+            .map(|offset| shade((hashed + offset) % (steps + 1)))
+            .find(|shade| above.is_none_or(|above| (shade - above).abs() >= min_contrast))
+            .unwrap_or(shade(hashed))
+    }
+
+    fn line_id_bubble(
+        &mut self,
+        line_id: &str,
+        shade: f32,
+        x: f32,
+        font: FontKind,
+    ) -> Result<Bounds, RenderError> {
+        let extent = self.measure.measure(line_id, font)?;
+        let bounds = text_bounds(&extent, (x, self.y), Align::Left).outset(10.0, 10.0);
+
+        self.primitives.push(Primitive::RoundRect {
+            bounds,
+            radius: self.metrics.bubble_radius,
+            shade,
+        });
+
+        self.text(line_id, (x, self.y), font, 0.0, Align::Left)?;
+
+        Ok(bounds)
+    }
+
+    /// Place the agency freshness statuses right-aligned along the footer
+    /// baseline
+    fn footer_statuses(
+        &mut self,
+        statuses: &[(&str, Freshness, Option<String>)],
+        baseline_y: f32,
+    ) -> Result<(), RenderError> {
+        let metrics = self.metrics;
+        let font = FontKind::Regular;
+        let icon_size = metrics.font_size * 0.75;
+        let gap = metrics.margin;
+
+        let mut items = Vec::new();
+        let mut total_width = 0.0;
+
+        for (agency, freshness, status) in statuses {
+            let label = format!("{agency}: ");
+            let label_width = self.measure.measure(&label, font)?.width;
+
+            let status = status.as_ref().map(|status| format!(" {status}"));
+            let status_width = match &status {
+                Some(status) => self.measure.measure(status, font)?.width,
+                None => 0.0,
+            };
+
+            total_width += label_width + icon_size + status_width;
+            items.push((label, label_width, *freshness, status, status_width));
+        }
+        total_width += gap * items.len().saturating_sub(1) as f32;
+
+        let mut x = self.width - metrics.margin - total_width;
+
+        for (label, label_width, freshness, status, status_width) in items {
+            // critically stale data is drawn in inverse video so it stands out
+            let shade = if freshness == Freshness::Critical {
+                let item_width = label_width + icon_size + status_width;
+                self.primitives.push(Primitive::Rect {
+                    bounds: Bounds::new(
+                        x - (gap / 2.0),
+                        self.height - metrics.footer_height,
+                        x + item_width + (gap / 2.0),
+                        self.height,
+                    ),
+                    shade: 0.0,
+                });
+
+                1.0
+            } else {
+                0.0
+            };
+
+            self.text(&label, (x, baseline_y), font, shade, Align::Left)?;
+            x += label_width;
+
+            self.primitives.push(Primitive::Icon {
+                icon: freshness.icon(),
+                x,
+                y: baseline_y,
+                size: icon_size,
+                shade,
+            });
+            x += icon_size;
+
+            if let Some(status) = status {
+                self.text(&status, (x, baseline_y), font, shade, Align::Left)?;
+            }
+            x += status_width + gap;
+        }
+
+        Ok(())
+    }
+
+    /// Place the footer's background and the current time, returning the
+    /// footer's baseline and where the time ends
+    fn footer_bar(&mut self) -> Result<(f32, f32), RenderError> {
+        let metrics = self.metrics;
+
+        let bottom_box_y = self.height - metrics.footer_height;
+        let baseline_y = self.height - metrics.footer_baseline;
+
+        self.primitives.push(Primitive::Rect {
+            bounds: Bounds::new(0.0, bottom_box_y, self.width, self.height),
+            shade: 0.8,
+        });
+
+        self.primitives.push(Primitive::Line {
+            from: (0.0, bottom_box_y),
+            to: (self.width, bottom_box_y),
+            width: 2.0,
+            shade: 0.0,
+        });
+
+        let time = self
+            .now
+            .with_timezone(&self.timezone)
+            .format(&self.footer.time_format)
+            .to_string();
+
+        let extent = self.text(
+            &time,
+            (metrics.margin, baseline_y),
+            FontKind::Regular,
+            0.0,
+            Align::Left,
+        )?;
+
+        Ok((baseline_y, metrics.margin + extent.width))
+    }
+
+    fn footer(&mut self, layout: &Layout) -> Result<(), RenderError> {
+        let (baseline_y, time_end) = self.footer_bar()?;
+
+        // debug builds count the journeys left off for missing a field, so
+        // that feed problems show up while working on the board
+        if cfg!(debug_assertions) && layout.incomplete_journeys > 0 {
+            self.text(
+                &format!(" \u{b7} {} incomplete", layout.incomplete_journeys),
+                (time_end, baseline_y),
+                FontKind::Regular,
+                0.0,
+                Align::Left,
+            )?;
+        }
+
+        let footer = self.footer;
+
+        let mut statuses = Vec::new();
+
+        for (agency_name, live_time) in &layout.all_agencies {
+            let age = self.now.signed_duration_since(*live_time);
+
+            let agency = crate::agencies::agency_readable(agency_name);
+
+            let (freshness, format) = if age < Duration::minutes(footer.warning_minutes) {
+                (Freshness::Fresh, &footer.fresh_format)
+            } else if age < Duration::minutes(footer.critical_minutes) {
+                (Freshness::Warning, &footer.warning_format)
+            } else {
+                (Freshness::Critical, &footer.critical_format)
+            };
+
+            let status = Some(format.replace("{mins}", &age.num_minutes().to_string()))
+                .filter(|status| !status.is_empty());
+
+            statuses.push((agency, freshness, status));
+        }
+
+        for agency_name in &layout.failed_agencies {
+            if !layout.all_agencies.contains_key(agency_name) {
+                let agency = crate::agencies::agency_readable(agency_name);
+                statuses.push((agency, Freshness::Failed, None));
+            }
+        }
+
+        self.footer_statuses(&statuses, baseline_y)?;
+
+        Ok(())
+    }
+
+    /// Split `text` into lines no wider than `width`, breaking between words
+    fn wrap_text(
+        &self,
+        text: &str,
+        font: FontKind,
+        width: f32,
+    ) -> Result<Vec<String>, RenderError> {
+        let mut lines = Vec::new();
+        let mut current = String::new();
+
+        for word in text.split_whitespace() {
+            if current.is_empty() {
+                current.push_str(word);
+                continue;
+            }
+
+            let candidate = format!("{current} {word}");
+            if self.measure.measure(&candidate, font)?.width > width {
+                lines.push(std::mem::replace(&mut current, word.to_owned()));
+            } else {
+                current = candidate;
+            }
+        }
+
+        if !current.is_empty() {
+            lines.push(current);
+        }
+
+        Ok(lines)
+    }
+
+    /// Background and text shades, and the font, a title is drawn with
+    fn title_look(style: &SectionStyle) -> (f32, f32, FontKind) {
+        let (background, shade) = if style.title_style == TitleStyle::Inverse {
+            (style.background.unwrap_or(0.0), 1.0)
+        } else {
+            (style.background.unwrap_or(0.8), 0.0)
+        };
+
+        let font = if style.title_style == TitleStyle::Normal {
+            FontKind::Regular
+        } else {
+            FontKind::Bold
+        };
+
+        (background, shade, font)
+    }
+
+    fn text_row(&mut self, text: &Text, x1: f32, x2: f32) -> Result<(), RenderError> {
+        let metrics = self.metrics;
+
+        let (background, shade, font) = Self::title_look(&text.style);
+
+        self.primitives.push(Primitive::Rect {
+            bounds: Bounds::new(x1, self.y, x2, self.y + metrics.text_row_height),
+            shade: background,
+        });
+        self.y += metrics.text_row_baseline;
+
+        self.text(
+            &text.text,
+            ((x1 + x2) / 2.0, self.y),
+            font,
+            shade,
+            Align::Center,
+        )?;
+
+        self.y += metrics.text_row_height - metrics.text_row_baseline;
+
+        Ok(())
+    }
+
+    /// Whether a title is drawn turned on its side, beside the sections below
+    /// it, rather than across the column
+    fn is_vertical(&self, text: &Text, x1: f32, x2: f32) -> Result<bool, RenderError> {
+        Ok(match text.style.title_direction {
+            TitleDirection::Horizontal => false,
+            TitleDirection::Vertical => true,
+            TitleDirection::Auto => {
+                let (_, _, font) = Self::title_look(&text.style);
+                let width = self.measure.measure(&text.text, font)?.width;
+
+                width > x2 - x1 - 2.0 * self.metrics.margin
+            }
+        })
+    }
+
+    /// Place a vertical title in a strip down the left of its column, from
+    /// `top` to the end of the sections beneath it, reading upward and
+    /// centered in the strip
+    fn vertical_title(&mut self, text: &Text, top: f32, x1: f32) -> Result<(), RenderError> {
+        let metrics = self.metrics;
+        let (background, shade, font) = Self::title_look(&text.style);
+
+        let strip = Bounds::new(x1, top, x1 + metrics.text_row_height, self.y);
+        self.primitives.push(Primitive::Rect {
+            bounds: strip,
+            shade: background,
+        });
+        self.primitives.push(Primitive::Line {
+            from: (strip.right, strip.top),
+            to: (strip.right, strip.bottom),
+            width: 2.0,
+            shade: 0.0,
+        });
+
+        let extent = self.measure.measure(&text.text, font)?;
+
+        // the glyphs stand to the left of the baseline once turned
+        let x = (strip.left + strip.right) / 2.0 - extent.ink_top / 2.0;
+        let y = (strip.top + strip.bottom) / 2.0 + extent.width / 2.0;
+
+        self.primitives.push(Primitive::VerticalText {
+            text: text.text.clone(),
+            font,
+            x,
+            y,
+            shade,
+        });
+
+        if let Some(debug) = &mut self.debug {
+            debug
+                .text
+                .push(Bounds::new(x + extent.ascent, y - extent.width, x, y));
+        }
+
+        Ok(())
+    }
+
+    /// Lay out an error screen in place of the board: the headline in a
+    /// banner, followed by each cause in the error's chain, for as many as fit
+    pub fn error_screen(
+        mut self,
+        headline: &str,
+        error: &eyre::Report,
+    ) -> Result<Vec<Primitive>, RenderError> {
+        let metrics = self.metrics;
+        let banner_height = metrics.text_row_height * 2.0;
+
+        self.primitives.push(Primitive::Rect {
+            bounds: Bounds::new(0.0, 0.0, self.width, banner_height),
+            shade: 0.0,
+        });
+        self.text(
+            headline,
+            (
+                self.x_midpoint(),
+                (banner_height + metrics.font_size * 0.7) / 2.0,
+            ),
+            FontKind::Bold,
+            1.0,
+            Align::Center,
+        )?;
+
+        let font = FontKind::Secondary;
+        let line_height = metrics.secondary_font_size * 1.4;
+        let bottom = self.height - metrics.footer_height - metrics.margin;
+
+        self.y = banner_height + metrics.row_gap;
+
+        'causes: for cause in error.chain() {
+            let lines =
+                self.wrap_text(&cause.to_string(), font, self.width - metrics.margin * 2.0)?;
+
+            for line in lines {
+                if self.y + line_height > bottom {
+                    break 'causes;
+                }
+
+                self.y += line_height;
+                self.text(&line, (metrics.margin, self.y), font, 0.0, Align::Left)?;
+            }
+
+            self.y += line_height / 2.0;
+        }
+
+        self.footer_bar()?;
+
+        Ok(self.finish())
+    }
+
+    /// Lay out a swatch of each grey a panel with `bit_depth` bits per pixel
+    /// shows, from black to white, labelled with its level and its shade, and
+    /// the shade it's drawn as if the ramp moves it
+    pub fn calibration(mut self, bit_depth: u8) -> Result<Vec<Primitive>, RenderError> {
+        let metrics = self.metrics;
+        let levels = 1usize << bit_depth;
+
+        self.text(
+            &format!("{levels} greys at {bit_depth} bits per pixel"),
+            (metrics.margin, metrics.margin + metrics.font_size),
+            FontKind::Bold,
+            0.0,
+            Align::Left,
+        )?;
+
+        let top = metrics.margin + metrics.font_size + metrics.row_gap;
+        let columns = (levels as f32).sqrt().ceil() as usize;
+        let rows = levels.div_ceil(columns);
+        let width = (self.width - metrics.margin * 2.0) / columns as f32;
+        let height = (self.height - top - metrics.margin) / rows as f32;
+        let gap = metrics.margin / 2.0;
+
+        for level in 0..levels {
+            let shade = level as f32 / (levels - 1) as f32;
+            let left = metrics.margin + (level % columns) as f32 * width;
+            let bottom = top + (level / columns + 1) as f32 * height;
+            let bounds = Bounds::new(left, bottom - height, left + width - gap, bottom - gap);
+
+            self.primitives.push(Primitive::Rect { bounds, shade });
+            self.primitives.push(Primitive::Outline {
+                bounds,
+                width: 1.0,
+                shade: 0.0,
+            });
+
+            let drawn = self.gray_ramp.map_or(shade, |ramp| ramp.map(shade));
+            let label = if (drawn - shade).abs() < 0.005 {
+                format!("{level}: {shade:.2}")
+            } else {
+                format!("{level}: {shade:.2} as {drawn:.2}")
+            };
+            self.text(
+                &label,
+                (bounds.left + gap, bounds.bottom - gap),
+                FontKind::Secondary,
+                if drawn < 0.5 { 1.0 } else { 0.0 },
+                Align::Left,
+            )?;
+        }
+
+        Ok(self.finish())
+    }
+
+    /// Lay out the board: each column's rows from the top down, the stale
+    /// data banner, and the footer
+    pub fn board(mut self, layout: &Layout) -> Result<Vec<Primitive>, RenderError> {
+        self.timezone = layout.timezone;
+
+        let [left, right] = if layout.balance {
+            self.balance(layout)?
+        } else {
+            [
+                layout.left.rows.iter().collect(),
+                layout.right.rows.iter().collect(),
+            ]
+        };
+
+        let columns = [
+            ("L", left, 0.0, self.x_midpoint()),
+            ("R", right, self.x_midpoint(), self.width),
+        ];
+
+        // the first primitive of each row, to say which rows collide
+        let mut starts = Vec::new();
+
+        for (side, rows, x1, x2) in columns {
+            self.y = 0.0;
+
+            // a vertical title and where its strip starts, while the sections
+            // beneath it are being placed
+            let mut title: Option<(&Text, f32)> = None;
+
+            for (idx, row) in rows.iter().enumerate() {
+                starts.push((format!("{side}{idx}"), self.primitives.len()));
+                let top = self.y;
+
+                match row {
+                    Row::Text(text) => {
+                        if let Some((text, top)) = title.take() {
+                            self.vertical_title(text, top, x1)?;
+                        }
+
+                        // a vertical title with nothing beneath it has no
+                        // strip to go in
+                        let titles_agency = matches!(rows.get(idx + 1), Some(Row::Agency(_)));
+                        if titles_agency && self.is_vertical(text, x1, x2)? {
+                            title = Some((text, self.y));
+                            continue;
+                        }
+
+                        self.row(row, x1, x2)?;
+                    }
+                    Row::Agency(agency) if title.is_some() => {
+                        if self.y > 0.0 {
+                            self.separator(&agency.style, x1, x2);
+                        } else {
+                            // nothing to separate from at the top of the
+                            // column, but the first line still needs room
+                            self.y += self.metrics.row_gap;
+                        }
+                        self.agency_row(agency, x1 + self.metrics.text_row_height, x2)?;
+                    }
+                    Row::Agency(_) => self.row(row, x1, x2)?,
+                }
+
+                if let Some(debug) = &mut self.debug {
+                    debug.rows.push((
+                        format!("{side}{idx} {top:.0}-{:.0}", self.y),
+                        Bounds::new(x1, top, x2, self.y),
+                    ));
+                }
+                self.note_cursor(x1);
+            }
+
+            if let Some((text, top)) = title {
+                self.vertical_title(text, top, x1)?;
+            }
+        }
+
+        self.primitives.push(Primitive::Line {
+            from: (self.x_midpoint(), 0.0),
+            to: (self.x_midpoint(), self.height),
+            width: 2.0,
+            shade: 0.0,
+        });
+
+        starts.push((String::from("footer"), self.primitives.len()));
+        self.stale_banner(layout)?;
+        self.footer(layout)?;
+
+        if cfg!(debug_assertions) {
+            let row_of = |index: usize| {
+                starts
+                    .iter()
+                    .rfind(|(_, start)| *start <= index)
+                    .map_or("", |(row, _)| row.as_str())
+            };
+
+            for (covered, by) in collisions(&self.primitives, self.measure)? {
+                warn!(
+                    row = row_of(covered),
+                    text = ?self.primitives[covered],
+                    covered_by = ?self.primitives[by],
+                    covering_row = row_of(by),
+                    "text on the board is drawn over",
+                );
+            }
+        }
+
+        if let Some(debug) = self.debug.take() {
+            self.debug_overlay(debug)?;
+        }
+
+        Ok(self.finish())
+    }
+
+    /// Split the rows of a balanced layout between the columns, keeping their
+    /// order, so that the taller column is as short as it can be. Rows which
+    /// still run into the footer are left off the end of their column.
+    fn balance<'l>(&self, layout: &'l Layout) -> Result<[Vec<&'l Row>; 2], RenderError> {
+        let rows = &layout.left.rows;
+
+        let heights = rows
+            .iter()
+            .map(|row| self.row_height(row))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // every row after the first in a column is preceded by a gap
+        let column_height = |heights: &[f32]| {
+            heights.iter().sum::<f32>()
+                + self.metrics.row_gap * heights.len().saturating_sub(1) as f32
+        };
+
+        let split = (0..=rows.len())
+            .min_by(|a, b| {
+                let tallest = |split: usize| {
+                    column_height(&heights[..split]).max(column_height(&heights[split..]))
+                };
+                tallest(*a).total_cmp(&tallest(*b))
+            })
+            .unwrap_or(0);
+
+        let mut bottom = self.height - self.metrics.footer_height;
+        if !layout.stale_agencies.is_empty() {
+            bottom -= self.metrics.text_row_height;
+        }
+
+        let fit = |range: std::ops::Range<usize>| {
+            let mut fits = range.start;
+            while fits < range.end && column_height(&heights[range.start..=fits]) <= bottom {
+                fits += 1;
+            }
+            rows[range.start..fits].iter().collect()
+        };
+
+        Ok([fit(0..split), fit(split..rows.len())])
+    }
+
+    /// Height a row takes at the top of a column
+    fn row_height(&self, row: &Row) -> Result<f32, RenderError> {
+        let mut scratch = LayoutEngine::new(
+            self.metrics,
+            self.footer,
+            self.measure,
+            (self.width, self.height),
+            self.now,
+        )
+        .theme(self.theme)
+        .bubble_contrast(self.bubble_contrast);
+        scratch.row(row, 0.0, self.x_midpoint())?;
+
+        Ok(scratch.y)
+    }
+
+    /// Warn across the bottom of the board, above the footer, when some
+    /// agency's departures are critically out of date
+    fn stale_banner(&mut self, layout: &Layout) -> Result<(), RenderError> {
+        if layout.stale_agencies.is_empty() {
+            return Ok(());
+        }
+
+        let metrics = self.metrics;
+        let bottom = self.height - metrics.footer_height;
+        let top = bottom - metrics.text_row_height;
+
+        let agencies = layout
+            .stale_agencies
+            .iter()
+            .map(|agency| crate::agencies::agency_readable(agency))
+            .join(", ");
+
+        self.primitives.push(Primitive::Rect {
+            bounds: Bounds::new(0.0, top, self.width, bottom),
+            shade: 0.0,
+        });
+        self.text(
+            &format!("Departures may be out of date: {agencies}"),
+            (self.x_midpoint(), top + metrics.text_row_baseline),
+            FontKind::Bold,
+            1.0,
+            Align::Center,
+        )?;
+
+        Ok(())
+    }
+
+    /// Outline each piece of text and row, label rows with their index and
+    /// extent, and mark where the y cursor stopped after each line and row
+    fn debug_overlay(&mut self, overlay: DebugOverlay) -> Result<(), RenderError> {
+        let metrics = self.metrics;
+
+        for bounds in overlay.text {
+            self.primitives.push(Primitive::Outline {
+                bounds,
+                width: 1.0,
+                shade: 0.5,
+            });
+        }
+
+        for (name, bounds) in overlay.rows {
+            self.primitives.push(Primitive::Outline {
+                bounds: bounds.outset(-1.0, -1.0),
+                width: 2.0,
+                shade: 0.0,
+            });
+            self.label(
+                &name,
+                (bounds.left + 4.0, bounds.top + metrics.secondary_font_size),
+            )?;
+        }
+
+        for (x, y) in overlay.cursor {
+            self.primitives.push(Primitive::Line {
+                from: (x, y),
+                to: (x + metrics.margin, y),
+                width: 2.0,
+                shade: 0.0,
+            });
+            self.label(&format!("y={y:.0}"), (x + metrics.margin + 2.0, y))?;
+        }
+
+        Ok(())
+    }
+
+    /// Small black text on a white background, for the debug overlay
+    fn label(&mut self, text: &str, (x, y): (f32, f32)) -> Result<(), RenderError> {
+        let extent = self.measure.measure(text, FontKind::Secondary)?;
+
+        self.primitives.push(Primitive::Rect {
+            bounds: Bounds::new(x, y + extent.ascent, x + extent.width, y),
+            shade: 1.0,
+        });
+        self.text(text, (x, y), FontKind::Secondary, 0.0, Align::Left)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::layout::{Column, Departure};
+
+    /// Every glyph is half as wide as the font is tall
+    struct FixedWidth<'a>(&'a Metrics);
+
+    impl Measure for FixedWidth<'_> {
+        fn measure(&self, text: &str, font: FontKind) -> Result<TextExtent, RenderError> {
+            let size = self.0.font_size(font);
+
+            Ok(TextExtent {
+                width: text.chars().count() as f32 * size / 2.0,
+                ascent: -size,
+                ink_top: -size * 0.7,
+            })
+        }
+    }
+
+    const SIZE: (f32, f32) = (1000.0, 700.0);
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 17, 30, 0).unwrap()
+    }
+
+    fn line(id: &str, destination: &str) -> Line {
+        Line {
+            id: id.to_owned(),
+            destination: destination.to_owned(),
+            secondary_destination: None,
+            departures: vec![Departure {
+                minutes: 4,
+                time: now() + Duration::minutes(4),
+                accessible: None,
+                delay_minutes: None,
+                cancelled: false,
+                track: None,
+            }],
+            window: None,
+            bearing: None,
+            color: None,
+            last_departure: None,
+            pinned: false,
+        }
+    }
+
+    fn layout(left: Vec<Row>) -> Layout {
+        Layout {
+            left: Column { rows: left },
+            right: Column { rows: Vec::new() },
+            balance: false,
+            all_agencies: HashMap::new(),
+            failed_agencies: BTreeSet::new(),
+            stale_agencies: BTreeSet::new(),
+            profile: Profile::Standard,
+            timezone: Pacific,
+            incomplete_journeys: 0,
+        }
+    }
+
+    fn heading(text: &str) -> Row {
+        Row::Text(Text {
+            text: text.to_owned(),
+            style: SectionStyle::default(),
+        })
+    }
+
+    fn agency(lines: Vec<Line>) -> Row {
+        Row::Agency(Agency {
+            lines,
+            notice: None,
+            timetable: None,
+            stop: None,
+            style: SectionStyle::default(),
+            summary: None,
+        })
+    }
+
+    fn texts(primitives: &[Primitive]) -> Vec<(&str, f32, f32)> {
+        primitives
+            .iter()
+            .filter_map(|primitive| match primitive {
+                Primitive::Text { text, x, y, .. } => Some((text.as_str(), *x, *y)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn baseline_of(primitives: &[Primitive], wanted: &str) -> f32 {
+        texts(primitives)
+            .into_iter()
+            .find(|(text, ..)| *text == wanted)
+            .unwrap()
+            .2
+    }
+
+    #[test]
+    fn rows_stack_down_the_column() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let primitives = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .board(&layout(vec![
+                heading("Muni"),
+                agency(vec![line("N", "Ocean Beach"), line("J", "Balboa Park")]),
+            ]))
+            .unwrap();
+
+        assert_eq!(baseline_of(&primitives, "Muni"), metrics.text_row_baseline);
+
+        let first = metrics.text_row_height + metrics.row_gap + metrics.agency_padding_top;
+        assert_eq!(baseline_of(&primitives, "Ocean Beach"), first);
+        assert_eq!(
+            baseline_of(&primitives, "Balboa Park"),
+            first + metrics.line_spacing
+        );
+
+        // the separator above the agency, at the bottom of the heading
+        assert!(primitives.contains(&Primitive::Line {
+            from: (0.0, metrics.text_row_height),
+            to: (SIZE.0 / 2.0, metrics.text_row_height),
+            width: 2.0,
+            shade: 0.0,
+        }));
+    }
+
+    #[test]
+    fn departure_times_are_right_aligned_in_their_column() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let primitives = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .board(&layout(vec![agency(vec![line("N", "Ocean Beach")])]))
+            .unwrap();
+
+        let (text, x, _) = texts(&primitives)
+            .into_iter()
+            .find(|(text, ..)| text.ends_with("min"))
+            .unwrap();
+        let width = measure.measure(text, FontKind::Departure).unwrap().width;
+
+        assert_eq!(x + width, SIZE.0 / 2.0 - metrics.margin);
+    }
+
+    #[test]
+    fn timetable_cells_fill_columns_ending_at_the_margin() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let mut n = line("N", "Ocean Beach");
+        n.departures.push(Departure {
+            minutes: 16,
+            time: now() + Duration::minutes(16),
+            accessible: None,
+            delay_minutes: None,
+            cancelled: false,
+            track: None,
+        });
+
+        let primitives = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .board(&layout(vec![Row::Agency(Agency {
+                lines: vec![n],
+                notice: None,
+                timetable: Some(3),
+                stop: None,
+                style: SectionStyle::default(),
+                summary: None,
+            })]))
+            .unwrap();
+
+        // 9:30 in Pacific time, a column's width is "12:59" plus the margin
+        let cell_width = 5.0 * metrics.font_size / 2.0 + metrics.margin;
+        let left = SIZE.0 / 2.0 - metrics.margin - 3.0 * cell_width;
+
+        let cells: Vec<_> = texts(&primitives)
+            .into_iter()
+            .filter(|(text, _, y)| text.contains(':') && *y < SIZE.1 - metrics.footer_height)
+            .map(|(text, x, _)| (text, x + text.len() as f32 * metrics.font_size / 4.0))
+            .collect();
+
+        assert_eq!(
+            cells,
+            vec![
+                ("9:34", left + cell_width * 0.5),
+                ("9:46", left + cell_width * 1.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn balanced_rows_move_to_the_right_column_in_order() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let mut board = layout(vec![
+            heading("Muni"),
+            agency(vec![line("N", "Ocean Beach"), line("J", "Balboa Park")]),
+            heading("BART"),
+            agency(vec![line("Y", "Millbrae"), line("R", "Richmond")]),
+        ]);
+        board.balance = true;
+
+        let primitives = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .board(&board)
+            .unwrap();
+
+        let x_of = |wanted: &str| {
+            texts(&primitives)
+                .into_iter()
+                .find(|(text, ..)| *text == wanted)
+                .unwrap()
+                .1
+        };
+
+        assert!(x_of("Ocean Beach") < SIZE.0 / 2.0);
+        assert!(x_of("Millbrae") > SIZE.0 / 2.0);
+        assert_eq!(baseline_of(&primitives, "BART"), metrics.text_row_baseline);
+    }
+
+    #[test]
+    fn destinations_running_under_departure_times_collide() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let board = |destination: &str| {
+            LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+                .board(&layout(vec![
+                    heading("Muni"),
+                    agency(vec![line("N", destination)]),
+                ]))
+                .unwrap()
+        };
+
+        let fits = board("Ocean Beach");
+        assert_eq!(collisions(&fits, &measure).unwrap(), Vec::new());
+
+        let long = board("Ocean Beach via Judah Street and La Playa");
+        let found = collisions(&long, &measure).unwrap();
+        assert!(!found.is_empty());
+        assert!(found.iter().all(|(covered, _)| matches!(
+            &long[*covered],
+            Primitive::Text { text, .. } if text.starts_with("Ocean Beach")
+        )));
+    }
+
+    #[test]
+    fn footer_statuses_end_at_the_margin() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let mut board = layout(Vec::new());
+        board
+            .all_agencies
+            .insert(String::from("SF"), now() - Duration::minutes(8));
+
+        let primitives = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .board(&board)
+            .unwrap();
+
+        let (text, x, y) = *texts(&primitives).last().unwrap();
+        let width = measure.measure(text, FontKind::Regular).unwrap().width;
+
+        assert_eq!(text, " 8 mins");
+        assert_eq!(x + width, SIZE.0 - metrics.margin);
+        assert_eq!(y, SIZE.1 - metrics.footer_baseline);
+    }
+
+    #[test]
+    fn error_causes_wrap_and_stop_above_the_footer() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+
+        let mut error = eyre::eyre!("root cause");
+        for _ in 0..40 {
+            error = error.wrap_err("a fairly long cause which needs more than one line to fit on the screen when it is drawn in the secondary font");
+        }
+
+        let primitives = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .error_screen("something went wrong", &error)
+            .unwrap();
+
+        let bottom = SIZE.1 - metrics.footer_height - metrics.margin;
+        let max_width = SIZE.0 - metrics.margin * 2.0;
+
+        let causes = primitives
+            .iter()
+            .filter_map(|primitive| match primitive {
+                Primitive::Text {
+                    text,
+                    font: FontKind::Secondary,
+                    y,
+                    ..
+                } => Some((text, *y)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert!(causes.len() > 2);
+        for (text, y) in causes {
+            assert!(y <= bottom, "{text:?} drawn below the causes area at {y}");
+            assert!(measure.measure(text, FontKind::Secondary).unwrap().width <= max_width);
+        }
+    }
+
+    #[test]
+    fn debug_overlay_only_when_asked_for() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+        let board = layout(vec![heading("Muni")]);
+
+        let outlines = |debug| {
+            LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+                .debug(debug)
+                .board(&board)
+                .unwrap()
+                .iter()
+                .filter(|primitive| matches!(primitive, Primitive::Outline { .. }))
+                .count()
+        };
+
+        assert_eq!(outlines(false), 0);
+        // the heading's text, the footer's clock, and the heading's row
+        assert_eq!(outlines(true), 3);
+    }
+
+    #[test]
+    fn calibration_swatches_are_drawn_along_the_gray_ramp() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+        let gray_ramp: GrayRamp = serde_yaml::from_str("[0, 0.2, 1]").unwrap();
+
+        let swatches = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .gray_ramp(&gray_ramp)
+            .calibration(2)
+            .unwrap()
+            .into_iter()
+            .filter_map(|primitive| match primitive {
+                Primitive::Rect { shade, .. } => Some((shade * 100.0).round() / 100.0),
+                _ => None,
+            })
+            .collect_vec();
+
+        assert_eq!(swatches, [0.0, 0.13, 0.47, 1.0]);
+    }
+
+    #[test]
+    fn neighbouring_bubbles_stand_apart() {
+        let metrics = Metrics::for_profile(Profile::Standard);
+        let footer = FooterConfig::default();
+        let measure = FixedWidth(&metrics);
+        let lines = (1..=12)
+            .map(|id| line(&id.to_string(), "Downtown"))
+            .collect();
+
+        let shades = LayoutEngine::new(&metrics, &footer, &measure, SIZE, now())
+            .board(&layout(vec![agency(lines)]))
+            .unwrap()
+            .into_iter()
+            .filter_map(|primitive| match primitive {
+                Primitive::RoundRect { shade, .. } => Some(shade),
+                _ => None,
+            })
+            .collect_vec();
+
+        assert_eq!(shades.len(), 12);
+        for shade in &shades {
+            assert!((0.5..=0.9).contains(shade), "{shade} is out of range");
+        }
+        for pair in shades.windows(2) {
+            assert!((pair[0] - pair[1]).abs() >= 0.099, "{pair:?} are too alike");
+        }
+    }
+}
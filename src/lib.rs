@@ -0,0 +1,52 @@
+pub mod accuracy;
+pub mod agencies;
+pub mod api_client;
+mod api_doc;
+pub mod bearing;
+pub mod calendar;
+pub mod calibration;
+pub mod config;
+pub mod config_repo;
+pub mod conformance;
+pub mod device;
+pub mod digitransit;
+pub mod error;
+pub mod five_one_one;
+pub mod framebuffer;
+#[cfg(feature = "debug-gallery")]
+pub mod gallery;
+pub mod gtfs;
+mod handler;
+pub mod history;
+pub mod html;
+pub mod icons;
+pub mod layout;
+pub mod layout_engine;
+pub mod listen;
+pub mod mbta;
+pub mod normalize;
+pub mod oba;
+pub mod output;
+pub mod overrides;
+pub mod pattern;
+pub mod png_metadata;
+pub mod png_optimize;
+pub mod proxy;
+pub mod rate_limit;
+pub mod remote;
+pub mod render;
+pub mod render_limit;
+pub mod selftest;
+pub mod server;
+pub mod service;
+pub mod shaping;
+pub mod status;
+pub mod summary;
+pub mod text;
+pub mod tfl;
+#[cfg(feature = "tiny-skia")]
+pub mod tiny_painter;
+pub mod transitous;
+pub mod trmnl;
+#[cfg(feature = "tui")]
+pub mod tui;
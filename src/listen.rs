@@ -0,0 +1,226 @@
+use std::{
+    net::SocketAddr,
+    os::{
+        fd::{FromRawFd, IntoRawFd, RawFd},
+        unix::net::UnixListener as StdUnixListener,
+    },
+    path::PathBuf,
+    str::FromStr,
+};
+
+use axum::Router;
+use eyre::{eyre, Context, Result};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use serde::{de, Deserialize, Deserializer};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    task::JoinSet,
+};
+use tower::{Service, ServiceExt};
+use tracing::{debug, info, warn};
+
+/// First file descriptor passed by systemd socket activation
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Where the HTTP server accepts connections, as given to `--listen`
+#[derive(Clone, Debug)]
+pub enum Listen {
+    /// `0.0.0.0:3001` or `tcp:0.0.0.0:3001`
+    Tcp(SocketAddr),
+
+    /// `unix:/run/transit-kindle.sock`
+    Unix(PathBuf),
+
+    /// `systemd`, every socket passed in through systemd socket activation
+    Systemd,
+}
+
+impl FromStr for Listen {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "systemd" {
+            return Ok(Listen::Systemd);
+        }
+
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Listen::Unix(PathBuf::from(path)));
+        }
+
+        let addr = s.strip_prefix("tcp:").unwrap_or(s);
+        let addr = addr
+            .parse()
+            .wrap_err_with(|| format!("invalid listen address {s:?}"))?;
+
+        Ok(Listen::Tcp(addr))
+    }
+}
+
+impl<'de> Deserialize<'de> for Listen {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+impl Listen {
+    /// The addresses given on the command line, or else in the config,
+    /// falling back to the sockets passed by systemd and then to `$PORT`, as
+    /// container platforms set it, or port 3001 on all interfaces
+    pub fn resolve(explicit: Vec<Listen>, configured: &[Listen]) -> Vec<Listen> {
+        if !explicit.is_empty() {
+            return explicit;
+        }
+
+        if !configured.is_empty() {
+            return configured.to_vec();
+        }
+
+        if systemd_listen_fds().is_some() {
+            return vec![Listen::Systemd];
+        }
+
+        let port = match std::env::var("PORT").ok().map(|port| port.parse()) {
+            Some(Ok(port)) => port,
+            Some(Err(error)) => {
+                warn!(%error, "ignoring $PORT, which isn't a port number");
+                3001
+            }
+            None => 3001,
+        };
+
+        vec![Listen::Tcp(SocketAddr::from(([0, 0, 0, 0], port)))]
+    }
+
+    /// Serve the app on every listener at once, until any of them fails
+    pub async fn serve_all(listeners: Vec<Listen>, app: Router) -> Result<()> {
+        let mut serving = JoinSet::new();
+        for listen in listeners {
+            serving.spawn(listen.serve(app.clone()));
+        }
+
+        join(serving).await
+    }
+
+    pub async fn serve(self, app: Router) -> Result<()> {
+        match self {
+            Listen::Tcp(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                info!(%addr, "listening!");
+
+                serve_tcp(listener, app).await
+            }
+            Listen::Unix(path) => {
+                // a socket left behind by a previous run would make bind fail
+                if path.exists() {
+                    std::fs::remove_file(&path)
+                        .wrap_err_with(|| format!("removing stale socket {}", path.display()))?;
+                }
+
+                let listener = UnixListener::bind(&path)
+                    .wrap_err_with(|| format!("binding unix socket {}", path.display()))?;
+                info!(path = %path.display(), "listening!");
+
+                serve_unix(listener, app).await
+            }
+            Listen::Systemd => {
+                let fds = systemd_listen_fds()
+                    .ok_or_else(|| eyre!("no sockets were passed by systemd (LISTEN_FDS)"))?;
+
+                let mut serving = JoinSet::new();
+                for fd in SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds as RawFd {
+                    serving.spawn(serve_systemd(fd, app.clone()));
+                }
+
+                join(serving).await
+            }
+        }
+    }
+}
+
+/// Wait for every listener to finish, returning the first error
+async fn join(mut serving: JoinSet<Result<()>>) -> Result<()> {
+    while let Some(result) = serving.join_next().await {
+        result.wrap_err("listener task failed")??;
+    }
+
+    Ok(())
+}
+
+async fn serve_systemd(fd: RawFd, app: Router) -> Result<()> {
+    // SAFETY: systemd guarantees the descriptors from SD_LISTEN_FDS_START on
+    // are open listening sockets that this process owns
+    let unix = unsafe { StdUnixListener::from_raw_fd(fd) };
+
+    if unix.local_addr().is_ok() {
+        unix.set_nonblocking(true)?;
+        info!(fd, "listening on unix socket from systemd");
+
+        serve_unix(UnixListener::from_std(unix)?, app).await
+    } else {
+        // SAFETY: as above, the descriptor was not a unix socket so it must be
+        // an inet one
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+        tcp.set_nonblocking(true)?;
+        info!(fd, addr = ?tcp.local_addr(), "listening on tcp socket from systemd");
+
+        serve_tcp(TcpListener::from_std(tcp)?, app).await
+    }
+}
+
+/// Number of sockets passed to this process by systemd socket activation
+fn systemd_listen_fds() -> Option<u32> {
+    let pid = std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+    if pid != std::process::id() {
+        return None;
+    }
+
+    std::env::var("LISTEN_FDS")
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|fds| *fds > 0)
+}
+
+async fn serve_tcp(listener: TcpListener, app: Router) -> Result<()> {
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+async fn serve_unix(listener: UnixListener, app: Router) -> Result<()> {
+    let mut make_service = app.into_make_service();
+
+    loop {
+        let (socket, _addr) = listener.accept().await?;
+
+        let tower_service = make_service
+            .call(&socket)
+            .await
+            .unwrap_or_else(|e| match e {});
+
+        tokio::spawn(async move {
+            let socket = TokioIo::new(socket);
+            let hyper_service = TowerToHyperService::new(tower_service.map_request(
+                |request: axum::http::Request<Incoming>| request.map(axum::body::Body::new),
+            ));
+
+            if let Err(error) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(socket, hyper_service)
+                .await
+            {
+                debug!(?error, "failed to serve unix socket connection");
+            }
+        });
+    }
+}
@@ -1,46 +1,259 @@
-use api_client::DataAccess;
+use clap::{Parser, Subcommand};
 use eyre::Result;
-use render::SharedRenderData;
-use std::io::IsTerminal;
+use std::{
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+};
+use tracing::info;
 use tracing_subscriber::EnvFilter;
+#[cfg(feature = "tui")]
+use transit_kindle::tui;
+use transit_kindle::{
+    api_client::DataAccess,
+    config::ConfigFile,
+    config_repo::{ConfigReload, ConfigRepo},
+    framebuffer,
+    layout::data_to_layout,
+    listen::Listen,
+    render::SharedRenderData,
+    selftest, server,
+    service::{self, PidFile},
+    text,
+};
 
-/// unwrap an option, `continue` if it's None
-macro_rules! opt_cont {
-    ($opt:expr) => {
-        match $opt {
-            Some(x) => x,
-            None => continue,
-        }
-    };
+#[derive(Parser)]
+struct Args {
+    /// Address to serve HTTP on: `HOST:PORT`, `unix:PATH`, or `systemd` for
+    /// socket-activated listeners. Can be given more than once to serve on
+    /// several at once. Defaults to `server.listen` in `stops.yml`, then to
+    /// the sockets systemd passed, if any, otherwise port `$PORT` or 3001 on
+    /// 0.0.0.0
+    #[arg(long)]
+    listen: Vec<Listen>,
+
+    /// Serve every request from another instance at this URL, caching its
+    /// responses, instead of fetching departures from 511. No `stops.yml` is
+    /// needed.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Run under systemd: keep cache files in `$STATE_DIRECTORY`, notify
+    /// readiness once the first refresh has finished, ping the watchdog
+    /// while refreshes are succeeding, and shut down cleanly on SIGTERM
+    #[arg(long)]
+    service: bool,
+
+    /// Write the process id to this file, removing it on shutdown
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Draw a made-up board showing every kind of row, and the error screen,
+    /// into this directory as PNGs, then exit. Fails if anything can't be
+    /// drawn.
+    #[arg(long, value_name = "DIR")]
+    selftest: Option<PathBuf>,
+
+    /// Fetch every agency taken from this source in `stops.yml` once, `511`
+    /// for those without a `source`, and check that the departures that come
+    /// back hold what the board expects of every provider: times with an
+    /// offset, none long past or more than a day away, and only from the
+    /// stops asked for. Exits with an error if any don't.
+    #[arg(long, value_name = "SOURCE")]
+    check_provider: Option<String>,
+
+    /// Draw the board straight onto this framebuffer device every minute,
+    /// such as `/dev/fb0` on a jailbroken Kindle, instead of serving HTTP
+    #[arg(long, value_name = "DEVICE")]
+    framebuffer: Option<PathBuf>,
+
+    /// Check out `stops.yml` from this Git repository rather than reading it
+    /// from the working directory. Changes pushed to it are applied when
+    /// `POST /config/reload` is called, or every `--config-poll-minutes`.
+    #[arg(long, value_name = "URL")]
+    config_git: Option<String>,
+
+    /// Branch of `--config-git` to follow
+    #[arg(long, default_value = "main")]
+    config_git_branch: String,
+
+    /// Path of the config file within `--config-git`
+    #[arg(long, default_value = "stops.yml")]
+    config_git_file: PathBuf,
+
+    /// Directory to check `--config-git` out in
+    #[arg(long, default_value = "config-repo")]
+    config_git_checkout: PathBuf,
+
+    /// Pull `--config-git` this often, for repositories which can't call the
+    /// reload webhook
+    #[arg(long, value_name = "MINUTES")]
+    config_poll_minutes: Option<u64>,
+
+    /// Token the reload webhook must be called with, either as a bearer token
+    /// or as `?token=`. Without one, anyone can ask for a reload.
+    #[arg(long, env = "TRANSIT_KINDLE_RELOAD_TOKEN", hide_env_values = true)]
+    reload_token: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-mod agencies;
-mod api_client;
-mod config;
-mod handler;
-mod layout;
-mod render;
-mod server;
+#[derive(Subcommand)]
+enum Command {
+    /// Fetch every agency once, write their cache files, and exit. For setups
+    /// which run this from cron rather than keeping the server running.
+    Fetch,
 
-use crate::config::*;
+    /// Lay out the board from the cache files, without fetching or drawing
+    /// anything, and print the layout as JSON
+    Layout {
+        /// Print the board as plain text instead, for reading over SSH
+        #[arg(long)]
+        text: bool,
+    },
+
+    /// Fetch departures as the server does and show the board in the
+    /// terminal as text, kept up to date, until `q` is pressed
+    #[cfg(feature = "tui")]
+    Tui,
+}
+
+impl Command {
+    /// Whether the command takes over the terminal, which logging would
+    /// garble
+    fn takes_over_terminal(&self) -> bool {
+        #[cfg(feature = "tui")]
+        if let Command::Tui = self {
+            return true;
+        }
+
+        false
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .with_ansi(std::io::stdout().is_terminal())
-        .init();
+    let args = Args::parse();
+
+    if !args
+        .command
+        .as_ref()
+        .is_some_and(Command::takes_over_terminal)
+    {
+        tracing_subscriber::fmt()
+            .with_env_filter(EnvFilter::from_default_env())
+            .with_ansi(std::io::stdout().is_terminal())
+            .init();
+    }
+
+    if let Some(primary) = args.proxy {
+        return server::serve_proxy(&primary, Listen::resolve(args.listen, &[])).await;
+    }
+
+    let repo = args.config_git.map(|url| ConfigRepo {
+        url,
+        branch: args.config_git_branch,
+        file: args.config_git_file,
+        checkout: args.config_git_checkout,
+    });
+
+    let (mut config_file, commit) = match &repo {
+        Some(repo) => {
+            let (config_file, commit) = repo.load().await?;
+            (config_file, Some(commit))
+        }
+        None => (ConfigFile::load(Path::new("stops.yml"))?, None),
+    };
+
+    if args.service {
+        service::use_state_directory(&mut config_file);
+    }
+
+    if let Some(dir) = args.selftest {
+        return selftest::run(&config_file, &dir);
+    }
 
-    let config_file = serde_yaml::from_reader::<_, ConfigFile>(std::fs::File::open("stops.yml")?)?;
+    if let Some(source) = args.check_provider {
+        return DataAccess::check_provider(config_file, &source).await;
+    }
 
     if std::env::var("TEST_CONFIG").is_ok() {
         return Ok(());
     }
 
-    let data_access = DataAccess::new(config_file.clone());
-    let shared_render_data = SharedRenderData::new();
+    match args.command {
+        Some(Command::Fetch) => return DataAccess::fetch_once(config_file).await,
+        Some(Command::Layout { text }) => {
+            let stop_data = DataAccess::cached_stop_data(&config_file).await?;
+            let layout = data_to_layout(stop_data, &config_file);
+
+            if text {
+                print!("{}", text::board(&layout));
+            } else {
+                println!("{}", serde_json::to_string_pretty(&layout)?);
+            }
+
+            return Ok(());
+        }
+        #[cfg(feature = "tui")]
+        Some(Command::Tui) => {
+            return tui::run(DataAccess::new(config_file.clone()), config_file).await;
+        }
+        None => {}
+    }
+
+    let _pid_file = args.pid_file.map(PidFile::create).transpose()?;
+
+    if let Some(device) = args.framebuffer {
+        let data_access = DataAccess::new(config_file.clone());
+        let shared_render_data = SharedRenderData::new(&config_file)?;
+
+        return framebuffer::run(data_access, shared_render_data, config_file, &device).await;
+    }
 
-    server::serve(data_access, shared_render_data, config_file).await?;
+    let listeners = Listen::resolve(args.listen, &config_file.server.listen);
+
+    let (app, data_access) = match repo.zip(commit) {
+        Some((repo, commit)) => {
+            let reload =
+                ConfigReload::new(repo, args.reload_token, args.service, config_file, commit)?;
+
+            if let Some(minutes) = args.config_poll_minutes {
+                tokio::spawn(reload.clone().poll(Duration::from_secs(minutes * 60)));
+            }
+
+            (reload.router(), reload.data_access())
+        }
+        None => {
+            let shared_render_data = SharedRenderData::new(&config_file)?;
+            let data_access = DataAccess::new(config_file.clone());
+
+            let app = server::app(data_access.clone(), shared_render_data, config_file, None);
+            (app, watch::channel(data_access).1)
+        }
+    };
+
+    let serve = Listen::serve_all(listeners, app);
+
+    if !args.service {
+        return serve.await;
+    }
+
+    tokio::spawn(service::supervise(data_access));
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = serve => result?,
+        _ = terminate.recv() => {
+            info!("received SIGTERM, shutting down");
+            let _ = service::notify("STOPPING=1");
+        }
+    }
 
     Ok(())
 }
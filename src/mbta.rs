@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Body of the MBTA V3 API's `/predictions`, in JSON:API form: the
+/// predictions, and the routes, trips, stops, schedules and route patterns
+/// they refer to
+#[derive(Deserialize)]
+pub(crate) struct Response {
+    data: Vec<Prediction>,
+    #[serde(default)]
+    included: Vec<Included>,
+}
+
+#[derive(Deserialize)]
+struct Prediction {
+    attributes: PredictionAttributes,
+    relationships: PredictionRelationships,
+}
+
+#[derive(Deserialize)]
+struct PredictionAttributes {
+    departure_time: Option<String>,
+    arrival_time: Option<String>,
+    direction_id: Option<u8>,
+    /// `CANCELLED` or `SKIPPED` for a trip that won't stop, which then has
+    /// no times of its own
+    schedule_relationship: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PredictionRelationships {
+    route: Relationship,
+    stop: Relationship,
+    trip: Relationship,
+    #[serde(default)]
+    schedule: Option<Relationship>,
+}
+
+#[derive(Deserialize, Default)]
+struct Relationship {
+    data: Option<ResourceId>,
+}
+
+impl Relationship {
+    fn id(&self) -> Option<&str> {
+        self.data.as_ref().map(|data| data.id.as_str())
+    }
+}
+
+#[derive(Deserialize)]
+struct ResourceId {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Included {
+    Route {
+        id: String,
+        attributes: RouteAttributes,
+    },
+    Trip {
+        id: String,
+        attributes: TripAttributes,
+        #[serde(default)]
+        relationships: TripRelationships,
+    },
+    Stop {
+        id: String,
+        attributes: StopAttributes,
+        #[serde(default)]
+        relationships: StopRelationships,
+    },
+    Schedule {
+        id: String,
+        attributes: ScheduleAttributes,
+    },
+    RoutePattern {
+        id: String,
+        attributes: RoutePatternAttributes,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct RouteAttributes {
+    short_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TripAttributes {
+    headsign: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct TripRelationships {
+    #[serde(default)]
+    route_pattern: Relationship,
+}
+
+#[derive(Deserialize)]
+struct StopAttributes {
+    /// Track or platform number, given for commuter rail
+    platform_code: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct StopRelationships {
+    #[serde(default)]
+    parent_station: Relationship,
+}
+
+#[derive(Deserialize)]
+struct ScheduleAttributes {
+    departure_time: Option<String>,
+    arrival_time: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RoutePatternAttributes {
+    /// e.g. `Alewife - Ashmont`
+    name: Option<String>,
+}
+
+/// A predicted departure from one of the configured stops
+pub(crate) struct Departure {
+    /// The configured stop, which may be the parent station of the platform
+    /// the prediction is for
+    pub stop_id: String,
+    pub line: String,
+    /// The trip's `direction_id`, `0` or `1`
+    pub direction: Option<String>,
+    /// The trip's headsign, or its route pattern's name
+    pub destination: Option<String>,
+    /// The prediction, or the timetabled time for a cancelled trip
+    pub expected: Option<String>,
+    pub scheduled: Option<String>,
+    pub cancelled: bool,
+    pub track: Option<String>,
+    pub trip_id: Option<String>,
+}
+
+impl Response {
+    /// The predictions at `stops`, or at platforms whose parent station is
+    /// one of them, and how many predictions there were in all
+    pub(crate) fn departures(self, stops: &[String]) -> (Vec<Departure>, usize) {
+        let mut routes = HashMap::new();
+        let mut trips = HashMap::new();
+        let mut platforms = HashMap::new();
+        let mut schedules = HashMap::new();
+        let mut patterns = HashMap::new();
+
+        for included in self.included {
+            match included {
+                Included::Route { id, attributes } => {
+                    routes.insert(id, attributes);
+                }
+                Included::Trip {
+                    id,
+                    attributes,
+                    relationships,
+                } => {
+                    trips.insert(id, (attributes, relationships));
+                }
+                Included::Stop {
+                    id,
+                    attributes,
+                    relationships,
+                } => {
+                    platforms.insert(id, (attributes, relationships));
+                }
+                Included::Schedule { id, attributes } => {
+                    schedules.insert(id, attributes);
+                }
+                Included::RoutePattern { id, attributes } => {
+                    patterns.insert(id, attributes);
+                }
+                Included::Other => {}
+            }
+        }
+
+        let visits = self.data.len();
+        let departures = self
+            .data
+            .into_iter()
+            .filter_map(|prediction| {
+                let attributes = prediction.attributes;
+                let relationships = prediction.relationships;
+
+                let stop = relationships.stop.id()?;
+                let platform = platforms.get(stop);
+                let stop_id = if stops.iter().any(|configured| configured == stop) {
+                    stop.to_owned()
+                } else {
+                    let parent = platform?.1.parent_station.id()?;
+                    stops
+                        .iter()
+                        .find(|configured| *configured == parent)?
+                        .clone()
+                };
+
+                let route = relationships.route.id()?;
+                let line = routes
+                    .get(route)
+                    .and_then(|route| route.short_name.clone())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| route.to_owned());
+
+                let trip = relationships.trip.id().and_then(|trip| trips.get(trip));
+                let destination = trip
+                    .and_then(|(trip, _)| trip.headsign.clone())
+                    .or_else(|| {
+                        let pattern = trip?.1.route_pattern.id()?;
+                        patterns.get(pattern)?.name.clone()
+                    });
+
+                let schedule = relationships
+                    .schedule
+                    .as_ref()
+                    .and_then(Relationship::id)
+                    .and_then(|schedule| schedules.get(schedule));
+                let scheduled = schedule.and_then(|schedule| {
+                    schedule
+                        .departure_time
+                        .clone()
+                        .or(schedule.arrival_time.clone())
+                });
+
+                let cancelled = matches!(
+                    attributes.schedule_relationship.as_deref(),
+                    Some("CANCELLED" | "SKIPPED")
+                );
+                let expected = attributes
+                    .departure_time
+                    .or(attributes.arrival_time)
+                    .or_else(|| cancelled.then(|| scheduled.clone()).flatten());
+
+                Some(Departure {
+                    stop_id,
+                    line,
+                    direction: attributes.direction_id.map(|id| id.to_string()),
+                    destination,
+                    expected,
+                    scheduled,
+                    cancelled,
+                    track: platform.and_then(|(platform, _)| platform.platform_code.clone()),
+                    trip_id: relationships.trip.id().map(str::to_owned),
+                })
+            })
+            .collect();
+
+        (departures, visits)
+    }
+}
+
+/// URL of the predictions at every stop in one request, along with what
+/// they refer to
+pub(crate) fn predictions_url(base: &str, stops: &[String], api_key: Option<&str>) -> String {
+    let mut url = format!(
+        "{}/predictions?filter%5Bstop%5D={}&include=route,trip,stop,schedule,trip.route_pattern",
+        base.trim_end_matches('/'),
+        stops.join(",")
+    );
+    if let Some(api_key) = api_key {
+        url.push_str(&format!("&api_key={api_key}"));
+    }
+
+    url
+}
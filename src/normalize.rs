@@ -0,0 +1,95 @@
+use chrono::{DateTime, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+
+use crate::config::{DestinationCase, DestinationNormalization};
+
+/// The response without the byte order mark 511 starts it with
+pub fn strip_bom(text: &str) -> &str {
+    let bom = unicode_bom::Bom::from(text.as_bytes());
+
+    &text[bom.len()..]
+}
+
+/// A time as RFC 3339, reading one without a UTC offset as being in
+/// `timezone`. `None` if it has no offset and there's no zone to put it in,
+/// or it isn't a time at all.
+pub fn time(text: &str, timezone: Option<Tz>) -> Option<String> {
+    if DateTime::parse_from_rfc3339(text).is_ok() {
+        return Some(text.to_owned());
+    }
+
+    let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f").ok()?;
+
+    timezone?
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.to_rfc3339())
+}
+
+/// A destination name in the case asked for
+pub fn destination(name: &str, case: DestinationCase) -> String {
+    match case {
+        DestinationCase::AsSent => name.to_owned(),
+        DestinationCase::Title => titlecase(name),
+    }
+}
+
+/// A destination name tidied as an agency's `normalize_destinations` asks,
+/// as in `  CIVIC CENTER   STATION` to `Civic Center`
+pub fn tidy(name: &str, normalization: &DestinationNormalization) -> String {
+    let mut name = if normalization.collapse_whitespace {
+        name.split_whitespace().collect::<Vec<_>>().join(" ")
+    } else {
+        name.to_owned()
+    };
+
+    if normalization.titlecase {
+        name = titlecase(&name);
+    }
+
+    for suffix in &normalization.strip_suffixes {
+        let Some(split) = name.len().checked_sub(suffix.len()) else {
+            continue;
+        };
+        let Some((rest, end)) = name.split_at_checked(split) else {
+            continue;
+        };
+        let rest = rest.trim_end();
+
+        // only whole words, after something else
+        if end.eq_ignore_ascii_case(suffix) && rest.len() < split && !rest.is_empty() {
+            name = rest.to_owned();
+        }
+    }
+
+    name
+}
+
+/// Each word in capitals given a capital only at its start, and after any
+/// hyphen or apostrophe, as in `O'FARRELL & BALBOA-PARK` to `O'Farrell &
+/// Balboa-Park`. Words already in mixed case, and those with digits, such as
+/// `3RD`, are left as they are.
+pub fn titlecase(name: &str) -> String {
+    name.split(' ')
+        .map(|word| {
+            if word.chars().any(|c| c.is_lowercase() || c.is_ascii_digit()) {
+                return word.to_owned();
+            }
+
+            let mut after_letter = false;
+            word.chars()
+                .flat_map(|c| {
+                    let cased = if after_letter {
+                        c.to_lowercase().collect::<Vec<_>>()
+                    } else {
+                        vec![c]
+                    };
+                    after_letter = c.is_alphabetic();
+
+                    cased
+                })
+                .collect()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
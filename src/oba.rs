@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use serde::Deserialize;
+
+/// Body of OneBusAway's `arrivals-and-departures-for-stop`
+#[derive(Deserialize)]
+pub(crate) struct Response {
+    data: Data,
+}
+
+#[derive(Deserialize)]
+struct Data {
+    entry: Entry,
+    #[serde(default)]
+    references: References,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    #[serde(default)]
+    arrivals_and_departures: Vec<ArrivalAndDeparture>,
+}
+
+/// Trips the arrivals refer to, which is where their direction is found
+#[derive(Deserialize, Default)]
+struct References {
+    #[serde(default)]
+    trips: Vec<Trip>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Trip {
+    id: String,
+    direction_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ArrivalAndDeparture {
+    stop_id: String,
+    route_id: Option<String>,
+    route_short_name: Option<String>,
+    trip_id: String,
+    trip_headsign: Option<String>,
+    #[serde(default)]
+    predicted: bool,
+    /// Milliseconds since the epoch, or 0 without a prediction
+    #[serde(default)]
+    predicted_departure_time: i64,
+    #[serde(default)]
+    scheduled_departure_time: i64,
+}
+
+/// A departure from one stop, with its trip's direction looked up and its
+/// times as RFC 3339, as SIRI gives them
+pub(crate) struct Departure {
+    pub stop_id: String,
+    pub line: Option<String>,
+    /// The trip's GTFS `direction_id`, `0` or `1`
+    pub direction: Option<String>,
+    pub headsign: Option<String>,
+    /// The prediction, or the timetabled time for trips not being tracked
+    pub expected: Option<String>,
+    pub scheduled: Option<String>,
+    pub trip_id: String,
+}
+
+impl Response {
+    pub(crate) fn departures(self) -> Vec<Departure> {
+        let directions: HashMap<_, _> = self
+            .data
+            .references
+            .trips
+            .into_iter()
+            .filter_map(|trip| Some((trip.id, trip.direction_id?)))
+            .collect();
+
+        self.data
+            .entry
+            .arrivals_and_departures
+            .into_iter()
+            .map(|departure| {
+                let scheduled = time(departure.scheduled_departure_time);
+                let expected = if departure.predicted {
+                    time(departure.predicted_departure_time)
+                } else {
+                    None
+                };
+
+                Departure {
+                    line: departure.route_short_name.or(departure.route_id),
+                    direction: directions.get(&departure.trip_id).cloned(),
+                    headsign: departure
+                        .trip_headsign
+                        .filter(|headsign| !headsign.is_empty()),
+                    expected: expected.or_else(|| scheduled.clone()),
+                    scheduled,
+                    stop_id: departure.stop_id,
+                    trip_id: departure.trip_id,
+                }
+            })
+            .collect()
+    }
+}
+
+fn time(millis: i64) -> Option<String> {
+    (millis > 0)
+        .then(|| DateTime::from_timestamp_millis(millis))
+        .flatten()
+        .map(|time| time.to_rfc3339())
+}
+
+/// URL of the departures from one stop. OneBusAway stop ids are prefixed
+/// with their agency's, e.g. `1_75403`.
+pub(crate) fn arrivals_url(base: &str, stop: &str, key: Option<&str>) -> String {
+    format!(
+        "{}/api/where/arrivals-and-departures-for-stop/{stop}.json?key={}",
+        base.trim_end_matches('/'),
+        key.unwrap_or("TEST")
+    )
+}
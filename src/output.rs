@@ -0,0 +1,407 @@
+use std::{
+    collections::VecDeque,
+    ops::{Deref, DerefMut},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::{Deserialize, Deserializer};
+use skia_safe::{
+    images, svg, AlphaType, Canvas, Color4f, ColorType, Data, EncodedImageFormat, ImageInfo, Rect,
+};
+use utoipa::{IntoParams, ToSchema};
+
+#[cfg(feature = "tiny-skia")]
+use crate::config::Painter;
+use crate::{
+    error::{Failure, RenderError},
+    layout::Layout,
+    overrides::{LayoutOverrides, LAYOUT_OVERRIDES},
+    render::{Render, SharedRenderData},
+};
+
+/// Image formats that the board can be rendered to outside of the screens'
+/// PNG routes
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Svg,
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Svg => "image/svg+xml",
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+}
+
+/// Size the board is drawn at when a route isn't given one
+pub const DEFAULT_WIDTH: i32 = 1058;
+pub const DEFAULT_HEIGHT: i32 = 754;
+
+/// Smallest width or height with room for any of the board, past its footer
+pub const MIN_DIMENSION: i32 = 200;
+/// Largest width or height drawn. Any larger is taken to be a mistake, which
+/// would take a lot of memory to draw.
+pub const MAX_DIMENSION: i32 = 8192;
+
+fn default_width() -> i32 {
+    DEFAULT_WIDTH
+}
+
+fn default_height() -> i32 {
+    DEFAULT_HEIGHT
+}
+
+/// A width or height in pixels which the board can be drawn at, so that a
+/// mistaken one is answered with `400 Bad Request` saying so, rather than
+/// failing while drawing
+pub(crate) fn dimension<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+    match i32::deserialize(deserializer)? {
+        dimension @ MIN_DIMENSION..=MAX_DIMENSION => Ok(dimension),
+        other => Err(serde::de::Error::custom(format!(
+            "{other} pixels can't be drawn, expected {MIN_DIMENSION} to {MAX_DIMENSION}"
+        ))),
+    }
+}
+
+fn default_quality() -> u32 {
+    90
+}
+
+#[derive(Deserialize, Clone, Copy, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OutputParams {
+    /// Width of the image in pixels
+    #[serde(default = "default_width", deserialize_with = "dimension")]
+    #[param(default = 1058)]
+    pub width: i32,
+
+    /// Height of the image in pixels
+    #[serde(default = "default_height", deserialize_with = "dimension")]
+    #[param(default = 754)]
+    pub height: i32,
+
+    /// Output format, for routes which don't imply one
+    pub format: Option<OutputFormat>,
+
+    /// Encoder quality from 0 to 100, used by the lossy formats
+    #[serde(default = "default_quality")]
+    #[param(default = 90)]
+    pub quality: u32,
+}
+
+impl Default for OutputParams {
+    fn default() -> Self {
+        Self {
+            width: default_width(),
+            height: default_height(),
+            format: None,
+            quality: default_quality(),
+        }
+    }
+}
+
+/// Draw the layout onto a canvas for the given format and encode the result
+pub fn render(
+    shared: Arc<SharedRenderData>,
+    layout: &Layout,
+    format: OutputFormat,
+    params: OutputParams,
+) -> Result<Vec<u8>, RenderError> {
+    let bounds = Rect::from_wh(params.width as f32, params.height as f32);
+
+    isolate(|| match format {
+        OutputFormat::Svg => {
+            let canvas = svg::Canvas::new(bounds, None);
+            canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
+
+            Render::new(&canvas, shared)?.draw(layout)?;
+
+            Ok(canvas.end().as_bytes().into())
+        }
+        #[cfg(feature = "tiny-skia")]
+        OutputFormat::Png if shared.painter == Painter::TinySkia => {
+            crate::tiny_painter::render_png(&shared, layout, params)
+        }
+        OutputFormat::Png => render_raster(&shared, params, EncodedImageFormat::PNG, |canvas| {
+            Render::new(canvas, shared.clone())?.draw(layout)
+        }),
+        OutputFormat::Jpeg => render_raster(&shared, params, EncodedImageFormat::JPEG, |canvas| {
+            Render::new(canvas, shared.clone())?.draw(layout)
+        }),
+        OutputFormat::Webp => render_raster(&shared, params, EncodedImageFormat::WEBP, |canvas| {
+            Render::new(canvas, shared.clone())?.draw(layout)
+        }),
+    })
+}
+
+/// Run a render on tokio's blocking threads, so that a slow one can't hold up
+/// the runtime's workers, which on a single-core host are also what accept
+/// connections. The current request's layout overrides are kept. How many
+/// renders run at once is bounded by `render_limit`, before they get here.
+pub(crate) async fn blocking<T: Send + 'static>(render: impl FnOnce() -> T + Send + 'static) -> T {
+    let overrides = LAYOUT_OVERRIDES.try_with(LayoutOverrides::clone).ok();
+
+    let rendered = tokio::task::spawn_blocking(move || match overrides {
+        Some(overrides) => LAYOUT_OVERRIDES.sync_scope(overrides, render),
+        None => render(),
+    })
+    .await;
+
+    rendered.unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()))
+}
+
+/// Draw the error screen shown in place of the board as a PNG
+pub fn render_error_png(
+    shared: Arc<SharedRenderData>,
+    error: &eyre::Report,
+    params: OutputParams,
+) -> Result<Vec<u8>, RenderError> {
+    #[cfg(feature = "tiny-skia")]
+    if shared.painter == Painter::TinySkia {
+        return crate::tiny_painter::render_error_png(
+            &shared,
+            Failure::of(error).headline,
+            error,
+            params,
+        );
+    }
+
+    isolate(|| {
+        render_raster(&shared, params, EncodedImageFormat::PNG, |canvas| {
+            Render::new(canvas, shared.clone())?.draw_error(Failure::of(error).headline, error)
+        })
+    })
+}
+
+/// Draw a labelled swatch of each grey a panel with `bit_depth` bits per pixel
+/// shows, as a PNG
+pub fn render_calibration_png(
+    shared: Arc<SharedRenderData>,
+    bit_depth: u8,
+    params: OutputParams,
+) -> Result<Vec<u8>, RenderError> {
+    #[cfg(feature = "tiny-skia")]
+    if shared.painter == Painter::TinySkia {
+        return crate::tiny_painter::render_calibration_png(&shared, bit_depth, params);
+    }
+
+    isolate(|| {
+        render_raster(&shared, params, EncodedImageFormat::PNG, |canvas| {
+            Render::new(canvas, shared.clone())?.draw_calibration(bit_depth)
+        })
+    })
+}
+
+/// Renders which have panicked since the server started
+static RENDER_PANICS: AtomicUsize = AtomicUsize::new(0);
+
+/// How many renders have panicked since the server started, for `/metrics`
+pub fn render_panics() -> usize {
+    RENDER_PANICS.load(Ordering::Relaxed)
+}
+
+/// Run a render, turning a panic in it, such as skia's over a font it can't
+/// read or a canvas with no pixels, into an error. One bad request then gets
+/// an error rather than taking the server, and every display, down with it.
+pub(crate) fn isolate<T>(
+    render: impl FnOnce() -> Result<T, RenderError>,
+) -> Result<T, RenderError> {
+    std::panic::catch_unwind(AssertUnwindSafe(render)).unwrap_or_else(|panic| {
+        RENDER_PANICS.fetch_add(1, Ordering::Relaxed);
+
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+
+        Err(RenderError::Panicked(message))
+    })
+}
+
+/// Draw a screen as kindling's PNG route does, for builds without it: the
+/// longer of the two dimensions runs along the screen's orientation, and
+/// with `rotate` a landscape image is turned to fit a portrait framebuffer
+#[cfg(not(feature = "kindling"))]
+pub(crate) fn render_screen_png(
+    shared: &SharedRenderData,
+    orientation: crate::config::Orientation,
+    (width, height): (i32, i32),
+    rotate: bool,
+    draw: impl FnOnce(&Canvas) -> Result<(), RenderError>,
+) -> Result<Vec<u8>, RenderError> {
+    let (big, small) = (width.max(height), width.min(height));
+    let size = match orientation {
+        crate::config::Orientation::Landscape => (big, small),
+        crate::config::Orientation::Portrait => (small, big),
+    };
+
+    let mut pixels = shared.pixels.take(size)?;
+    draw_onto(&mut pixels, draw)?;
+
+    if !rotate {
+        return encode(&pixels, EncodedImageFormat::PNG, None);
+    }
+
+    let mut rotated = shared.pixels.take((size.1, size.0))?;
+    draw_onto(&mut rotated, |canvas| {
+        let centre = size.1 as f32 / 2.0;
+        canvas.rotate(90.0, Some(skia_safe::Point::new(centre, centre)));
+        pixels.with_image(|image| {
+            canvas.draw_image(image, (0, 0), None);
+
+            Ok(())
+        })
+    })?;
+
+    encode(&rotated, EncodedImageFormat::PNG, None)
+}
+
+fn render_raster(
+    shared: &SharedRenderData,
+    params: OutputParams,
+    format: EncodedImageFormat,
+    draw: impl FnOnce(&Canvas) -> Result<(), RenderError>,
+) -> Result<Vec<u8>, RenderError> {
+    let mut pixels = shared.pixels.take((params.width, params.height))?;
+    draw_onto(&mut pixels, draw)?;
+
+    encode(&pixels, format, params.quality.min(100))
+}
+
+/// Clear the pixels to white and draw on them
+fn draw_onto(
+    pixels: &mut Pixels,
+    draw: impl FnOnce(&Canvas) -> Result<(), RenderError>,
+) -> Result<(), RenderError> {
+    let info = pixels.info.clone();
+    let canvas =
+        Canvas::from_raster_direct(&info, pixels, None, None).ok_or(RenderError::Canvas)?;
+    canvas.clear(Color4f::new(1.0, 1.0, 1.0, 1.0));
+
+    draw(&canvas)
+}
+
+fn encode(
+    pixels: &Pixels,
+    format: EncodedImageFormat,
+    quality: impl Into<Option<u32>>,
+) -> Result<Vec<u8>, RenderError> {
+    let quality = quality.into();
+
+    pixels.with_image(|image| {
+        let image_data = image
+            .encode(None, format, quality)
+            .ok_or(RenderError::Encode)?;
+
+        Ok(image_data.as_bytes().into())
+    })
+}
+
+/// Most pixel buffers kept between renders. A board and its rotated copy
+/// take two, so this leaves room for a couple of sizes of screen.
+const POOLED_BUFFERS: usize = 4;
+
+/// Width, height, and colour type of a pixel buffer
+type PixelsKey = (i32, i32, ColorType);
+
+/// Greyscale pixel buffers left over from earlier renders, most recently used
+/// first, so that each render draws into one rather than allocating a bitmap
+/// the size of the screen. Buffers are returned when the [`Pixels`] taken
+/// from the pool are dropped.
+#[derive(Default)]
+pub(crate) struct PixelPool {
+    buffers: Mutex<VecDeque<(PixelsKey, Vec<u8>)>>,
+}
+
+impl PixelPool {
+    /// Pixels of the given size, drawn on before or newly allocated
+    fn take(&self, size: (i32, i32)) -> Result<Pixels<'_>, RenderError> {
+        if size.0 <= 0 || size.1 <= 0 {
+            return Err(RenderError::Bitmap);
+        }
+        let info = ImageInfo::new(size, ColorType::Gray8, AlphaType::Unknown, None);
+        let key = (size.0, size.1, ColorType::Gray8);
+
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffer = match buffers.iter().position(|(pooled, _)| *pooled == key) {
+            Some(index) => buffers.remove(index).map(|(_, buffer)| buffer),
+            None => None,
+        };
+        let buffer = buffer.unwrap_or_else(|| vec![0; info.min_row_bytes() * size.1 as usize]);
+
+        Ok(Pixels {
+            pool: self,
+            key,
+            info,
+            buffer,
+        })
+    }
+
+    fn give(&self, key: PixelsKey, buffer: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers.push_front((key, buffer));
+        buffers.truncate(POOLED_BUFFERS);
+    }
+}
+
+/// A pixel buffer taken from the [`PixelPool`], which it goes back to when
+/// dropped
+struct Pixels<'a> {
+    pool: &'a PixelPool,
+    key: PixelsKey,
+    info: ImageInfo,
+    buffer: Vec<u8>,
+}
+
+impl Pixels<'_> {
+    /// Use the pixels as an image, for encoding or drawing elsewhere, which
+    /// borrows them rather than making a copy
+    fn with_image<T>(
+        &self,
+        use_image: impl FnOnce(&skia_safe::Image) -> Result<T, RenderError>,
+    ) -> Result<T, RenderError> {
+        // SAFETY: the image reads the buffer without owning it, so it must be
+        // gone before the buffer goes back to the pool. It's dropped before
+        // this returns, while `self` still holds the buffer, and `use_image`
+        // must not keep a clone of it.
+        let data = unsafe { Data::new_bytes(&self.buffer) };
+        let image = images::raster_from_data(&self.info, data, self.info.min_row_bytes())
+            .ok_or(RenderError::Bitmap)?;
+
+        use_image(&image)
+    }
+}
+
+impl Deref for Pixels<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl DerefMut for Pixels<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer
+    }
+}
+
+impl Drop for Pixels<'_> {
+    fn drop(&mut self) {
+        self.pool.give(self.key, std::mem::take(&mut self.buffer));
+    }
+}
@@ -0,0 +1,198 @@
+use axum::http::Uri;
+use serde::{Deserialize, Deserializer};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::{ConfigFile, LineFilter, MirrorConfig, RenderTarget, SectionConfig};
+
+tokio::task_local! {
+    /// Overrides parsed from the query string of the request being served
+    pub static LAYOUT_OVERRIDES: LayoutOverrides;
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Per-request changes to the configured layout, given in the query string so
+/// that one server can show slightly different boards to different devices
+#[derive(Deserialize, Clone, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LayoutOverrides {
+    /// Only show this side of the board
+    pub side: Option<Side>,
+
+    /// Comma-separated list of sections to keep, e.g. `bart,muni`
+    pub sections: Option<String>,
+
+    /// Most lines shown in each agency section
+    pub max_lines: Option<usize>,
+
+    /// Draw the bounds of each row and piece of text, and the positions rows
+    /// are drawn at, over the board, e.g. `debug=1`
+    #[serde(default, deserialize_with = "flag")]
+    #[param(value_type = Option<String>, example = "1")]
+    pub debug: bool,
+
+    /// Who the board is drawn for, from the query string's `target`, which
+    /// decides how its text is drawn
+    #[serde(skip)]
+    pub target: RenderTarget,
+}
+
+impl LayoutOverrides {
+    /// The config to lay out the board with, after applying the overrides for
+    /// the current request if there are any
+    pub fn current(config_file: &ConfigFile) -> ConfigFile {
+        LAYOUT_OVERRIDES
+            .try_with(|overrides| overrides.apply(config_file))
+            .unwrap_or_else(|_| config_file.clone())
+    }
+
+    /// Whether the current request asked for the debug overlay
+    pub fn debug() -> bool {
+        LAYOUT_OVERRIDES
+            .try_with(|overrides| overrides.debug)
+            .unwrap_or(false)
+    }
+
+    /// Who the current request's board is drawn for
+    pub fn target() -> RenderTarget {
+        LAYOUT_OVERRIDES
+            .try_with(|overrides| overrides.target)
+            .unwrap_or_default()
+    }
+
+    pub fn apply(&self, config_file: &ConfigFile) -> ConfigFile {
+        let mut config_file = config_file.clone();
+        let layout = &mut config_file.layout;
+
+        match self.side {
+            Some(Side::Left) => layout.right.sections.clear(),
+            Some(Side::Right) => layout.left.sections.clear(),
+            None => {}
+        }
+
+        if let Some(sections) = &self.sections {
+            let wanted = sections
+                .split(',')
+                .map(|name| name.trim().to_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect::<Vec<_>>();
+
+            filter_sections(&mut layout.left.sections, &wanted);
+            filter_sections(&mut layout.right.sections, &wanted);
+            filter_sections(&mut layout.sections, &wanted);
+        }
+
+        if let Some(max_lines) = self.max_lines {
+            layout.max_lines = Some(max_lines);
+        }
+
+        config_file
+    }
+}
+
+impl RenderTarget {
+    /// The target a request asks for with `?target=kindle`, or else the
+    /// browser
+    pub fn of(uri: &Uri) -> Self {
+        let kindle = uri
+            .query()
+            .is_some_and(|query| query.split('&').any(|param| param == "target=kindle"));
+
+        if kindle {
+            RenderTarget::Kindle
+        } else {
+            RenderTarget::Browser
+        }
+    }
+}
+
+/// A query flag, which is set by `1` or `true`
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let value = String::deserialize(deserializer)?;
+
+    Ok(matches!(value.as_str(), "1" | "true"))
+}
+
+/// Keep only the groups of sections which match one of the wanted names. A
+/// group is a text section along with the agency sections beneath it, and
+/// matches if the text contains the name or any of its agencies is named
+/// exactly, ignoring case.
+fn filter_sections(sections: &mut Vec<SectionConfig>, wanted: &[String]) {
+    retain_groups(sections, |group| {
+        wanted.iter().any(|name| group_matches(group, name))
+    });
+}
+
+/// The config a mirror of the board is laid out with, which is the board's
+/// without what the mirror hides
+pub fn mirror(config_file: &ConfigFile, mirror: &MirrorConfig) -> ConfigFile {
+    let mut config_file = config_file.clone();
+    let layout = &mut config_file.layout;
+
+    let hidden = mirror
+        .hide_sections
+        .iter()
+        .map(|name| name.trim().to_lowercase())
+        .collect::<Vec<_>>();
+
+    for sections in [
+        &mut layout.left.sections,
+        &mut layout.right.sections,
+        &mut layout.sections,
+    ] {
+        retain_groups(sections, |group| {
+            !hidden.iter().any(|name| group_matches(group, name))
+        });
+
+        for section in sections.iter_mut() {
+            let SectionConfig::AgencySection(section) = section else {
+                continue;
+            };
+
+            section
+                .lines
+                .extend(mirror.hide_lines.iter().map(|line| LineFilter {
+                    line: line.clone(),
+                    pin: false,
+                    max_branches: None,
+                    hide: true,
+                }));
+            section.show_stop &= !mirror.hide_stops;
+        }
+    }
+
+    config_file
+}
+
+/// Keep only the groups of sections for which `keep` is true, where a group
+/// is a text section along with the agency sections beneath it
+fn retain_groups(sections: &mut Vec<SectionConfig>, keep: impl Fn(&[SectionConfig]) -> bool) {
+    let mut groups: Vec<Vec<SectionConfig>> = Vec::new();
+
+    for section in sections.drain(..) {
+        match (&section, groups.last_mut()) {
+            (SectionConfig::AgencySection(_), Some(group)) => group.push(section),
+            _ => groups.push(vec![section]),
+        }
+    }
+
+    *sections = groups
+        .into_iter()
+        .filter(|group| keep(group))
+        .flatten()
+        .collect();
+}
+
+/// Whether the group's text contains the lowercase `name`, or any of its
+/// agencies is named exactly that, ignoring case
+fn group_matches(group: &[SectionConfig], name: &str) -> bool {
+    group.iter().any(|section| match section {
+        SectionConfig::AgencySection(agency) => agency.agency.to_lowercase() == name,
+        SectionConfig::TextSection(text) => text.text.to_lowercase().contains(name),
+    })
+}
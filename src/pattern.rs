@@ -0,0 +1,123 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use eyre::Result;
+use png::{BitDepth, ColorType, Encoder};
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::config::ConfigFile;
+
+/// Largest side of a pattern that will be drawn, so that a typo in the query
+/// string can't allocate gigabytes
+const MAX_SIDE: u32 = 4096;
+
+/// Grey levels an e-ink panel can show, which the stepped bars run through
+const LEVELS: u32 = 16;
+
+/// What `/pattern.png` draws
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Frame {
+    /// Every pixel black, flashed to clear ghosting
+    Black,
+    /// Every pixel white, flashed after the black frame
+    White,
+    /// Sixteen stepped grey bars above a smooth gradient, for checking that
+    /// every level shows and that ghosting has cleared
+    #[default]
+    Bars,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PatternParams {
+    #[serde(default)]
+    frame: Frame,
+    /// Width in pixels, the screen's by default
+    width: Option<u32>,
+    /// Height in pixels, the screen's by default
+    height: Option<u32>,
+}
+
+/// Size of the panel the first screen route is for, held upright, or
+/// kindling's default Kindle size
+pub fn screen_size(config_file: &ConfigFile) -> (u32, u32) {
+    config_file
+        .screens
+        .iter()
+        .find_map(|screen| screen.device)
+        .map_or((754, 1058), |device| {
+            let profile = device.profile();
+            (profile.width, profile.height)
+        })
+}
+
+/// Draw a test pattern of the given size as an 8-bit greyscale PNG
+pub fn render(frame: Frame, width: u32, height: u32) -> Result<Vec<u8>> {
+    let mut pixels = vec![0u8; width as usize * height as usize];
+
+    for (y, row) in pixels.chunks_exact_mut(width as usize).enumerate() {
+        for (x, pixel) in row.iter_mut().enumerate() {
+            *pixel = match frame {
+                Frame::Black => 0,
+                Frame::White => 255,
+                Frame::Bars if (y as u32) < height / 2 => {
+                    let step = x as u32 * LEVELS / width;
+                    (step * 255 / (LEVELS - 1)) as u8
+                }
+                Frame::Bars => (x as u32 * 255 / (width - 1).max(1)) as u8,
+            };
+        }
+    }
+
+    let mut data = Vec::new();
+
+    let mut encoder = Encoder::new(&mut data, width, height);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&pixels)?;
+    writer.finish()?;
+
+    Ok(data)
+}
+
+/// Serve a flash frame or grey bars sized to the screen, which scripts on the
+/// device can show every so often to clear e-ink ghosting
+#[utoipa::path(
+    get,
+    path = "/pattern.png",
+    params(PatternParams),
+    responses(
+        (status = 200, description = "The pattern as an 8-bit greyscale PNG", content_type = "image/png"),
+        (status = 400, description = "The size is out of range"),
+    )
+)]
+pub async fn pattern_image(
+    State(size): State<(u32, u32)>,
+    Query(params): Query<PatternParams>,
+) -> Response {
+    let width = params.width.unwrap_or(size.0);
+    let height = params.height.unwrap_or(size.1);
+
+    if !(1..=MAX_SIDE).contains(&width) || !(1..=MAX_SIDE).contains(&height) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("width and height must be between 1 and {MAX_SIDE}"),
+        )
+            .into_response();
+    }
+
+    match render(params.frame, width, height) {
+        Ok(data) => ([(header::CONTENT_TYPE, "image/png")], data).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to draw test pattern");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::cell::RefCell;
+
+use serde_json::json;
+
+use crate::layout::{Layout, Row};
+
+/// Keyword of the PNG text chunk which lists the departures on the board
+const DEPARTURES_KEYWORD: &str = "Departures";
+
+tokio::task_local! {
+    /// Departures drawn while serving the current request, as JSON
+    pub static DEPARTURES: RefCell<Option<String>>;
+}
+
+/// Record the departures in a layout for the request being served, if the
+/// request is being tracked
+pub fn record_departures(layout: &Layout) {
+    let lines = [&layout.left, &layout.right]
+        .into_iter()
+        .flat_map(|column| &column.rows)
+        .filter_map(|row| match row {
+            Row::Agency(agency) => Some(&agency.lines),
+            Row::Text(_) => None,
+        })
+        .flatten()
+        .map(|line| {
+            json!({
+                "line": line.id,
+                "destination": line.destination,
+                "departures": line
+                    .departures
+                    .iter()
+                    .map(|departure| departure.time)
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let _ = DEPARTURES.try_with(|departures| {
+        *departures.borrow_mut() = Some(serde_json::Value::from(lines).to_string());
+    });
+}
+
+/// Insert an `iTXt` chunk holding the departures right after the `IHDR`
+/// chunk, so that a client which knows the board's refresh schedule can grey
+/// out departures that have passed since it was drawn. Returns `None` if the
+/// data isn't a PNG.
+pub fn with_departures(png: &[u8], departures: &str) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    // signature, then IHDR's length, type, 13 bytes of data, and CRC
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+
+    if !png.starts_with(SIGNATURE) || png.get(12..16)? != b"IHDR" || png.len() < IHDR_END {
+        return None;
+    }
+
+    // keyword, then no compression, no language tag, and no translated keyword
+    let mut data = Vec::with_capacity(DEPARTURES_KEYWORD.len() + 5 + departures.len());
+    data.extend_from_slice(DEPARTURES_KEYWORD.as_bytes());
+    data.extend_from_slice(&[0, 0, 0, 0, 0]);
+    data.extend_from_slice(departures.as_bytes());
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(b"iTXt");
+    crc.update(&data);
+
+    let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+    out.extend_from_slice(&png[..IHDR_END]);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(b"iTXt");
+    out.extend_from_slice(&data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+    out.extend_from_slice(&png[IHDR_END..]);
+
+    Some(out)
+}
@@ -0,0 +1,64 @@
+use eyre::{bail, Result};
+use png::{
+    AdaptiveFilterType, BitDepth, ColorType, Compression, Decoder, Encoder, Transformations,
+};
+
+/// Re-encode a PNG as 16-level (4-bit) greyscale with adaptive filtering and
+/// the highest compression level. E-ink panels can't show more than 16 greys
+/// anyway, and packing two pixels per byte shrinks the image considerably.
+pub fn optimize(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = Decoder::new(data);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+
+    if info.bit_depth != BitDepth::Eight {
+        bail!("unexpected PNG bit depth {:?}", info.bit_depth);
+    }
+
+    let channels = info.color_type.samples();
+    let width = info.width as usize;
+
+    let mut packed = Vec::with_capacity(width.div_ceil(2) * info.height as usize);
+
+    for line in buf.chunks_exact(info.line_size).take(info.height as usize) {
+        let mut levels = line[..width * channels]
+            .chunks_exact(channels)
+            .map(|pixel| gray_level(info.color_type, pixel));
+
+        while let Some(high) = levels.next() {
+            let low = levels.next().unwrap_or(0);
+            packed.push((high << 4) | low);
+        }
+    }
+
+    let mut optimized = Vec::new();
+
+    let mut encoder = Encoder::new(&mut optimized, info.width, info.height);
+    encoder.set_color(ColorType::Grayscale);
+    encoder.set_depth(BitDepth::Four);
+    encoder.set_compression(Compression::Best);
+    encoder.set_adaptive_filter(AdaptiveFilterType::Adaptive);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&packed)?;
+    writer.finish()?;
+
+    Ok(optimized)
+}
+
+/// Quantize a single decoded pixel to one of 16 grey levels
+fn gray_level(color_type: ColorType, pixel: &[u8]) -> u8 {
+    let luma = match color_type {
+        ColorType::Rgb | ColorType::Rgba => {
+            let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(u32::from);
+            ((r * 299 + g * 587 + b * 114) / 1000) as u8
+        }
+        _ => pixel[0],
+    };
+
+    // 255 / 15 == 17, so this rounds to the nearest of the 16 levels
+    ((u16::from(luma) + 8) / 17) as u8
+}
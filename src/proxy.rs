@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Bytes,
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use eyre::Result;
+use tracing::{debug, warn};
+
+use crate::server;
+
+/// Most distinct URLs kept, so that a client requesting endless query string
+/// variations can't grow the cache without bound
+const MAX_ENTRIES: usize = 64;
+
+/// Most responses kept for one URL, one for each value of the request headers
+/// it varies on, so that endless User-Agents can't grow it either
+const MAX_VARIANTS: usize = 8;
+
+/// Headers from the primary's responses which are passed on to clients
+const FORWARDED_HEADERS: [HeaderName; 5] = [
+    header::CONTENT_TYPE,
+    header::CACHE_CONTROL,
+    header::ETAG,
+    header::LAST_MODIFIED,
+    header::VARY,
+];
+
+/// Headers from clients' requests which are passed on to the primary, which
+/// picks a screen's render target from the User-Agent
+const FORWARDED_REQUEST_HEADERS: [HeaderName; 1] = [header::USER_AGENT];
+
+/// Serves every request from another instance, caching responses for as long
+/// as the primary's `Cache-Control` allows and then revalidating them with
+/// their `ETag`, so that several displays only cost one set of 511 requests
+pub struct ReadThroughProxy {
+    http: reqwest::Client,
+    primary: String,
+    /// Responses by URL, one for each value of the forwarded request headers
+    /// that the primary's `Vary` names
+    cache: Mutex<HashMap<String, Vec<CachedResponse>>>,
+}
+
+#[derive(Clone)]
+struct CachedResponse {
+    headers: HeaderMap,
+    body: Bytes,
+    fresh_until: Instant,
+    /// The request's value of each forwarded header the response varies on,
+    /// which a request must share to be answered with it
+    vary: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl CachedResponse {
+    fn matches(&self, request: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request.get(name) == value.as_ref())
+    }
+
+    /// Whether `other` varies on the same headers but with different values,
+    /// so that both are kept
+    fn sits_beside(&self, other: &CachedResponse) -> bool {
+        self.vary.len() == other.vary.len()
+            && self
+                .vary
+                .iter()
+                .zip(&other.vary)
+                .all(|((name, _), (other_name, _))| name == other_name)
+            && self.vary != other.vary
+    }
+}
+
+impl ReadThroughProxy {
+    pub fn router(primary: &str) -> Router {
+        let proxy = Arc::new(Self {
+            http: reqwest::Client::new(),
+            primary: primary.trim_end_matches('/').to_owned(),
+            cache: Mutex::new(HashMap::new()),
+        });
+
+        Router::new().fallback(proxy_request).with_state(proxy)
+    }
+
+    async fn get(&self, path_and_query: &str, headers: &HeaderMap) -> Result<CachedResponse> {
+        let cached = self.stale(path_and_query, headers);
+
+        if let Some(cached) = &cached {
+            if cached.fresh_until > Instant::now() {
+                return Ok(cached.clone());
+            }
+        }
+
+        let mut request = self.http.get(format!("{}{path_and_query}", self.primary));
+        for name in FORWARDED_REQUEST_HEADERS {
+            if let Some(value) = headers.get(&name) {
+                request = request.header(name.as_str(), value.as_bytes());
+            }
+        }
+        if let Some(etag) = cached
+            .as_ref()
+            .and_then(|cached| cached.headers.get(header::ETAG))
+        {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_bytes());
+        }
+
+        let response = request.send().await?;
+
+        let fetched = match (response.status(), cached) {
+            (reqwest::StatusCode::NOT_MODIFIED, Some(cached)) => {
+                debug!(path_and_query, "primary response not modified");
+
+                CachedResponse {
+                    fresh_until: Instant::now() + max_age(response.headers()),
+                    ..cached
+                }
+            }
+            _ => {
+                let response = response.error_for_status()?;
+
+                let mut forwarded = HeaderMap::new();
+                for name in FORWARDED_HEADERS {
+                    for value in response.headers().get_all(name.as_str()) {
+                        if let Ok(value) = HeaderValue::from_bytes(value.as_bytes()) {
+                            forwarded.append(name.clone(), value);
+                        }
+                    }
+                }
+
+                CachedResponse {
+                    fresh_until: Instant::now() + max_age(response.headers()),
+                    vary: varied_on(response.headers(), headers),
+                    headers: forwarded,
+                    body: response.bytes().await?,
+                }
+            }
+        };
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_ENTRIES && !cache.contains_key(path_and_query) {
+            let oldest = cache
+                .iter()
+                .min_by_key(|(_, variants)| variants.iter().map(|cached| cached.fresh_until).max())
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                cache.remove(&oldest);
+            }
+        }
+
+        let variants = cache.entry(path_and_query.to_owned()).or_default();
+        variants.retain(|cached| cached.sits_beside(&fetched));
+        if variants.len() >= MAX_VARIANTS {
+            if let Some((oldest, _)) = variants
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, cached)| cached.fresh_until)
+            {
+                variants.remove(oldest);
+            }
+        }
+        variants.push(fetched.clone());
+
+        Ok(fetched)
+    }
+
+    /// The cached response for a request with `headers`, even if it's past
+    /// its `max-age`, for when the primary can't be reached
+    fn stale(&self, path_and_query: &str, headers: &HeaderMap) -> Option<CachedResponse> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(path_and_query)?
+            .iter()
+            .find(|cached| cached.matches(headers))
+            .cloned()
+    }
+}
+
+async fn proxy_request(State(proxy): State<Arc<ReadThroughProxy>>, request: Request) -> Response {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+
+    let cached = match proxy.get(path_and_query, request.headers()).await {
+        Ok(x) => x,
+        Err(error) => match proxy.stale(path_and_query, request.headers()) {
+            Some(x) => {
+                warn!(
+                    ?error,
+                    "failed to fetch from primary, serving stale response"
+                );
+                x
+            }
+            None => {
+                warn!(?error, "failed to fetch from primary");
+                return (StatusCode::BAD_GATEWAY, format!("{error:?}")).into_response();
+            }
+        },
+    };
+
+    let not_modified = cached.headers.get(header::ETAG).is_some_and(|etag| {
+        server::none_match(request.headers().get_all(header::IF_NONE_MATCH), etag)
+    });
+
+    if not_modified {
+        return (StatusCode::NOT_MODIFIED, cached.headers).into_response();
+    }
+
+    (cached.headers, cached.body).into_response()
+}
+
+/// The forwarded request headers which the primary's `Vary` names, with the
+/// values `request` gave them
+fn varied_on(
+    response: &reqwest::header::HeaderMap,
+    request: &HeaderMap,
+) -> Vec<(HeaderName, Option<HeaderValue>)> {
+    let named = response
+        .get_all(reqwest::header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect::<Vec<_>>();
+
+    FORWARDED_REQUEST_HEADERS
+        .into_iter()
+        .filter(|name| named.iter().any(|named| named == name.as_str()))
+        .map(|name| {
+            let value = request.get(&name).cloned();
+            (name, value)
+        })
+        .collect()
+}
+
+/// How long the primary allows a response to be reused for, going by its
+/// `Cache-Control: max-age`
+fn max_age(headers: &reqwest::header::HeaderMap) -> Duration {
+    headers
+        .get(reqwest::header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value
+                .split(',')
+                .find_map(|directive| directive.trim().strip_prefix("max-age="))
+        })
+        .and_then(|seconds| seconds.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default()
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    num::NonZeroU32,
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use governor::{
+    clock::{Clock, DefaultClock},
+    DefaultKeyedRateLimiter, Quota,
+};
+use serde::{de, Deserialize, Deserializer};
+use tracing::debug;
+
+use crate::{config::RateLimitConfig, server::forwarded_client};
+
+/// Clients tracked before those which have been quiet long enough to be back
+/// at a full allowance are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// An address, or a network of them such as `192.168.1.0/24`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    pub fn contains(self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+
+        match (self.addr, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address {addr:?}"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length {prefix:?}"))?,
+            None => max,
+        };
+
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl<'de> Deserialize<'de> for Network {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Limits how often each client can ask for the board, so that a public
+/// instance can't be made to spend all its CPU drawing it. Clients in the
+/// allowlist, and those connecting over a unix socket without a forwarded
+/// address, aren't limited.
+pub struct RateLimiter {
+    limiter: DefaultKeyedRateLimiter<IpAddr>,
+    allow: Vec<Network>,
+    trust_forwarded_headers: bool,
+    /// Routes which draw the board
+    paths: HashSet<String>,
+}
+
+impl RateLimiter {
+    pub fn new(
+        config: &RateLimitConfig,
+        trust_forwarded_headers: bool,
+        paths: impl IntoIterator<Item = String>,
+    ) -> Arc<Self> {
+        let per_minute = NonZeroU32::new(config.per_minute).unwrap_or(NonZeroU32::MIN);
+        let burst = NonZeroU32::new(config.burst).unwrap_or(NonZeroU32::MIN);
+
+        Arc::new(Self {
+            limiter: DefaultKeyedRateLimiter::keyed(
+                Quota::per_minute(per_minute).allow_burst(burst),
+            ),
+            allow: config.allow.clone(),
+            trust_forwarded_headers,
+            paths: paths.into_iter().collect(),
+        })
+    }
+
+    /// The client's address, from `X-Forwarded-For` if it's trusted and
+    /// holds one, otherwise the address the request came from
+    fn client(&self, request: &Request) -> Option<IpAddr> {
+        if self.trust_forwarded_headers {
+            let forwarded =
+                forwarded_client(request.headers()).and_then(|client| client.parse().ok());
+            if let Some(client) = forwarded {
+                return Some(client);
+            }
+        }
+
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    }
+}
+
+/// Turn away requests for the board from clients which have asked for it too
+/// often, with `429 Too Many Requests`, when rate limiting is configured
+pub async fn limit_rate(
+    State(limiter): State<Option<Arc<RateLimiter>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limiter = match limiter {
+        Some(x) if x.paths.contains(request.uri().path()) => x,
+        _ => return next.run(request).await,
+    };
+
+    // only clients on a unix socket have no address
+    let Some(client) = limiter.client(&request) else {
+        return next.run(request).await;
+    };
+
+    if limiter.allow.iter().any(|network| network.contains(client)) {
+        return next.run(request).await;
+    }
+
+    if limiter.limiter.len() > MAX_TRACKED_CLIENTS {
+        limiter.limiter.retain_recent();
+    }
+
+    if let Err(not_until) = limiter.limiter.check_key(&client) {
+        let wait = not_until.wait_time_from(DefaultClock::default().now());
+        debug!(%client, ?wait, "rate limited");
+
+        let mut response = (StatusCode::TOO_MANY_REQUESTS, "too many requests").into_response();
+        response.headers_mut().insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(wait.as_secs().max(1)),
+        );
+
+        return response;
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,89 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    api_client::{AgencyDirections, DataAccess},
+    config::{ConfigFile, RemoteConfig},
+    error::{Failure, FetchError},
+};
+
+/// Body of `/stops.json`, the departures an instance fetched itself
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct FederatedStops {
+    /// Departures keyed by agency name
+    pub agencies: HashMap<String, AgencyDirections>,
+}
+
+#[derive(Clone)]
+pub struct StopsJsonState {
+    pub data_access: Arc<DataAccess>,
+    pub config_file: ConfigFile,
+}
+
+/// Upcoming departures for this instance's configured stops. Agencies pulled
+/// from remotes are left out, so that instances pulling from each other can't
+/// pass the same departures back and forth.
+#[utoipa::path(
+    get,
+    path = "/stops.json",
+    responses(
+        (status = 200, description = "Departures for each configured agency", body = FederatedStops),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub async fn stops_json(State(state): State<StopsJsonState>) -> Response {
+    let mut config_file = state.config_file;
+    config_file.remotes.clear();
+
+    match state.data_access.stop_data(&config_file) {
+        Ok(data) => Json(FederatedStops {
+            agencies: data.agencies,
+        })
+        .into_response(),
+        Err(e) => {
+            warn!(error = ?e, "failed to load stop data for /stops.json");
+            Failure::of(&e).response(&e)
+        }
+    }
+}
+
+/// Fetch one agency's departures from another instance
+pub async fn fetch(
+    http: &reqwest::Client,
+    remote: &RemoteConfig,
+) -> Result<AgencyDirections, FetchError> {
+    let url = format!("{}/stops.json", remote.url.trim_end_matches('/'));
+
+    let mut stops = http
+        .get(&url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<FederatedStops>()
+        .await?;
+
+    let mut agency =
+        stops
+            .agencies
+            .remove(&remote.agency)
+            .ok_or_else(|| FetchError::MissingAgency {
+                url: url.clone(),
+                agency: remote.agency.clone(),
+            })?;
+
+    for lines in agency.directions.values_mut() {
+        for (line, _) in &mut lines.lines {
+            line.agency = remote.local_agency().to_owned();
+        }
+    }
+
+    Ok(agency)
+}
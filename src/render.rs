@@ -1,322 +1,446 @@
 use std::{
-    collections::{hash_map::DefaultHasher, HashMap},
-    hash::Hasher,
-    sync::Arc,
+    collections::HashMap,
+    sync::{Arc, Mutex},
 };
 
-use crate::layout::{Agency, Layout, Line, Row};
-use chrono::{prelude::*, Duration};
-use chrono_tz::US::Pacific;
-use eyre::{eyre, Result};
+use crate::{
+    config::{
+        AntiAlias, ConfigFile, FakeBold, FooterConfig, GrayRamp, Hinting, Painter, Profile,
+        TextRendering, TextRenderingConfig, Theme,
+    },
+    error::{ConfigError, RenderError},
+    icons,
+    layout::Layout,
+    layout_engine::{Bounds, FontKind, LayoutEngine, Measure, Metrics, Primitive, TextExtent},
+    output::PixelPool,
+    overrides::LayoutOverrides,
+    shaping::{ShapedText, TextShaper},
+};
+use chrono::Utc;
+use chrono_tz::Tz;
 use skia_safe::{
-    gradient_shader::GradientShaderColors, utils::text_utils::Align, Canvas, Color, Color4f, Font,
-    FontMgr, Paint, Rect, Shader, TextBlob, TileMode,
+    font::Edging, gradient_shader::GradientShaderColors, Canvas, Color, Color4f, Font, FontHinting,
+    FontMgr, FontStyle, Paint, PaintStyle, Rect, Shader, TileMode, Typeface,
 };
 
 pub struct SharedRenderData {
-    black_paint: Paint,
-    black_paint_heavy: Paint,
-    grey_paint: Paint,
-    light_grey_paint: Paint,
-    white_paint: Paint,
-    font: Font,
+    typeface: Typeface,
+    /// Font the split-flap theme draws its characters in
+    monospace: Typeface,
+    shaper: TextShaper,
+    /// Profile the error screen is drawn with. The board is drawn with the
+    /// profile its layout was made for.
+    pub(crate) profile: Profile,
+    pub(crate) footer: FooterConfig,
+    pub(crate) theme: Theme,
+    /// Least difference in shade between a line's bubble and the one above
+    pub(crate) bubble_contrast: f32,
+    pub painter: Painter,
+    /// How text is drawn for each render target
+    pub(crate) text_rendering: TextRenderingConfig,
+    pub(crate) gray_ramp: GrayRamp,
+    /// Zone the footer's clock is shown in
+    pub(crate) timezone: Tz,
+    /// Pixels the raster formats are drawn into, kept between renders
+    pub(crate) pixels: PixelPool,
+    /// Text shaped by earlier renders, along with the top of its glyphs, so
+    /// that what's on every board, such as "min" and the line ids, is only
+    /// shaped and measured once
+    shaped: Mutex<HashMap<ShapedKey, Arc<(ShapedText, f32)>>>,
+}
+
+/// Font, its size in the profile it's drawn with, its edges, and text
+type ShapedKey = (FontKind, u32, (AntiAlias, Hinting), String);
+
+/// Most pieces of text kept shaped. Departure times and the like change
+/// throughout the day, so the cache is emptied when it's full rather than
+/// growing without end.
+const MAX_SHAPED: usize = 4096;
+
+/// The bundled font at each of the sizes a profile draws text with
+struct Fonts {
+    regular: Font,
+    bold: Font,
+    departure: Font,
+    secondary: Font,
+    flap: Font,
+    pinned: Font,
 }
 
+impl Fonts {
+    fn new(
+        typeface: &Typeface,
+        monospace: &Typeface,
+        metrics: &Metrics,
+        text: TextRendering,
+    ) -> Self {
+        let font = |typeface: &Typeface, size: f32| {
+            let mut font = Font::new(typeface, size);
+            font.set_edging(match text.anti_alias {
+                AntiAlias::Off => Edging::Alias,
+                AntiAlias::Grayscale => Edging::AntiAlias,
+            });
+            font.set_hinting(match text.hinting {
+                Hinting::None => FontHinting::None,
+                Hinting::Slight => FontHinting::Slight,
+                Hinting::Normal => FontHinting::Normal,
+                Hinting::Full => FontHinting::Full,
+            });
+
+            font
+        };
+
+        let mut bold = font(typeface, metrics.font_size);
+        bold.set_embolden(true);
+
+        Self {
+            regular: font(typeface, metrics.font_size),
+            bold,
+            departure: font(typeface, metrics.departure_font_size),
+            secondary: font(typeface, metrics.secondary_font_size),
+            flap: font(monospace, metrics.departure_font_size),
+            pinned: font(typeface, metrics.pinned_font_size),
+        }
+    }
+
+    fn get(&self, font: FontKind) -> &Font {
+        match font {
+            FontKind::Regular => &self.regular,
+            FontKind::Bold => &self.bold,
+            FontKind::Departure => &self.departure,
+            FontKind::Secondary => &self.secondary,
+            FontKind::Flap => &self.flap,
+            FontKind::Pinned => &self.pinned,
+        }
+    }
+}
+
+/// Draws the primitives laid out by the [`LayoutEngine`] onto a skia canvas
 pub(crate) struct Render<'a> {
     shared: Arc<SharedRenderData>,
 
-    line_id_bubble_paint: Paint,
-
     canvas: &'a Canvas,
 
+    metrics: Metrics,
+    fonts: Fonts,
+    /// How text is drawn for the target the board is drawn for
+    text: TextRendering,
+
     width: f32,
     height: f32,
-    y: f32,
-
-    x_midpoint: f32,
 }
 
 impl SharedRenderData {
-    pub fn new() -> Arc<Self> {
-        let mut black_paint_heavy = Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None);
-        black_paint_heavy.set_stroke_width(2.0);
+    pub fn new(config_file: &ConfigFile) -> Result<Arc<Self>, ConfigError> {
+        if config_file.painter == Painter::TinySkia && !cfg!(feature = "tiny-skia") {
+            return Err(ConfigError::PainterNotBuilt {
+                painter: "tiny_skia",
+                feature: "tiny-skia",
+            });
+        }
 
         let font_mgr = FontMgr::new();
         let typeface = font_mgr
             .new_from_data(include_bytes!("../media/OpenSansEmoji.ttf"), None)
             .unwrap();
 
-        Arc::new(Self {
-            black_paint: Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None),
-            black_paint_heavy,
+        let monospace = match &config_file.monospace_font {
+            Some(path) => {
+                let data = std::fs::read(path).map_err(|source| ConfigError::Read {
+                    path: path.into(),
+                    source,
+                })?;
+                font_mgr
+                    .new_from_data(&data, None)
+                    .ok_or_else(|| ConfigError::MonospaceFont { path: path.clone() })?
+            }
+            None => font_mgr
+                .match_family_style("monospace", FontStyle::normal())
+                .unwrap_or_else(|| typeface.clone()),
+        };
+
+        Ok(Arc::new(Self {
+            typeface,
+            monospace,
+            shaper: TextShaper::new(&config_file.fallback_fonts)?,
+
+            profile: config_file.profile,
+            footer: config_file.footer.clone(),
+            theme: config_file.theme,
+            bubble_contrast: config_file.bubble_palette.min_contrast,
+            painter: config_file.painter,
+            text_rendering: config_file.text_rendering,
+            gray_ramp: config_file.gray_ramp.clone(),
+            timezone: config_file.timezone,
+            pixels: PixelPool::default(),
+            shaped: Mutex::default(),
+        }))
+    }
+}
 
-            grey_paint: Paint::new(Color4f::new(0.7, 0.7, 0.7, 1.0), None),
-            light_grey_paint: Paint::new(Color4f::new(0.8, 0.8, 0.8, 1.0), None),
-            white_paint: Paint::new(Color4f::new(1.0, 1.0, 1.0, 1.0), None),
+impl Measure for Render<'_> {
+    fn measure(&self, text: &str, font: FontKind) -> Result<TextExtent, RenderError> {
+        let shaped = self.shaped(text, font)?;
+        let (shaped, ink_top) = &*shaped;
 
-            font: Font::new(&typeface, 24.0),
+        Ok(TextExtent {
+            width: shaped.width,
+            ascent: shaped.ascent,
+            ink_top: *ink_top,
         })
     }
 }
 
-impl<'a> Render<'a> {
-    pub(crate) fn new(canvas: &'a Canvas, shared: Arc<SharedRenderData>) -> Result<Self> {
-        let mut line_bubble_paint = Paint::new(Color4f::new(0.8, 0.8, 0.8, 1.0), None);
-        line_bubble_paint.set_anti_alias(true);
+fn rect(bounds: Bounds) -> Rect {
+    Rect::new(bounds.left, bounds.top, bounds.right, bounds.bottom)
+}
 
+fn shade_color(shade: f32) -> Color4f {
+    Color4f::new(shade, shade, shade, 1.0)
+}
+
+fn shade_paint(shade: f32) -> Paint {
+    Paint::new(shade_color(shade), None)
+}
+
+impl<'a> Render<'a> {
+    pub(crate) fn new(
+        canvas: &'a Canvas,
+        shared: Arc<SharedRenderData>,
+    ) -> Result<Self, RenderError> {
         let width = canvas.image_info().width() as f32;
         let height = canvas.image_info().height() as f32;
 
+        let metrics = Metrics::for_profile(shared.profile);
+        let text = shared.text_rendering.get(LayoutOverrides::target());
+        let fonts = Fonts::new(&shared.typeface, &shared.monospace, &metrics, text);
+
         Ok(Self {
             canvas,
             shared,
 
-            line_id_bubble_paint: line_bubble_paint,
+            metrics,
+            fonts,
+            text,
 
             width,
             height,
-            y: 0.0,
-
-            x_midpoint: width / 2.0,
         })
     }
 
-    fn draw_row(&mut self, row: &Row, x1: f32, x2: f32) -> Result<()> {
-        if self.y > 0.0 {
-            self.canvas
-                .draw_line((x1, self.y), (x2, self.y), &self.shared.black_paint_heavy);
-            self.y += 28.0;
-        }
+    /// Text shaped in one of the board's fonts, along with the top of its
+    /// glyphs, shaping it only if no render has before
+    fn shaped(&self, text: &str, font: FontKind) -> Result<Arc<(ShapedText, f32)>, RenderError> {
+        let key = (
+            font,
+            self.metrics.font_size(font).to_bits(),
+            (self.text.anti_alias, self.text.hinting),
+            text.to_owned(),
+        );
 
-        match row {
-            Row::Agency(agency) => self.draw_agency_row(agency, x1, x2)?,
-            Row::Text(text) => self.draw_text_row(text, x1, x2),
+        if let Some(shaped) = self.shared.shaped.lock().unwrap().get(&key) {
+            return Ok(shaped.clone());
         }
 
-        Ok(())
-    }
-
-    fn draw_agency_row(&mut self, agency: &Agency, x1: f32, x2: f32) -> Result<()> {
-        self.y += 4.0;
-
-        let lines_len = agency.lines.len();
-
-        for (idx, line) in agency.lines.iter().enumerate() {
-            let x = x1 + 20.0;
-
-            let line_id_bounds = self.draw_line_id_bubble(&line.id, x)?;
-
-            self.canvas.draw_str(
-                &line.destination,
-                (x + line_id_bounds.width(), self.y),
-                &self.shared.font,
-                &self.shared.black_paint,
-            );
+        let skia_font = self.fonts.get(font);
+        let shaped = self.shared.shaper.shape(text, skia_font)?;
+        let (_, ink) = skia_font.measure_str(text, None);
+        let shaped = Arc::new((shaped, ink.top));
 
-            self.draw_departure_times(x2, line);
-
-            if idx < (lines_len - 1) {
-                self.canvas.draw_line(
-                    (x1 + 40.0, self.y + 15.0),
-                    (x2 - 40.0, self.y + 15.0),
-                    &self.shared.grey_paint,
-                );
-                self.y += 48.0;
-            } else {
-                self.y += 15.0;
-            }
+        let mut cache = self.shared.shaped.lock().unwrap();
+        if cache.len() >= MAX_SHAPED {
+            cache.clear();
         }
+        cache.insert(key, shaped.clone());
 
-        Ok(())
-    }
-
-    fn draw_departure_times(&mut self, x: f32, line: &Line) {
-        let mins = line.departure_minutes_str();
-        let time_text = format!("{mins} min");
-
-        let time_point = (x - 20.0, self.y);
-
-        let time_rect_exact = self.text_bounds_right_align(&time_text, time_point);
-        let time_rect = time_rect_exact.with_outset((15.0, 10.0));
-
-        let time_rect_left = Rect::new(
-            time_rect.left - 25.0,
-            time_rect_exact.top,
-            time_rect.left,
-            time_rect.bottom,
-        );
-
-        let white_opaque = Color::from_argb(255, 255, 255, 255);
-        let white_transparent = Color::from_argb(0, 255, 255, 255);
-
-        let mut gradiant = Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None);
-        gradiant.set_shader(Shader::linear_gradient(
-            (
-                (
-                    time_rect_left.right,
-                    time_rect_left.top + (0.5 * time_rect_left.height()),
-                ),
-                (
-                    time_rect_left.left,
-                    time_rect_left.top + (0.5 * time_rect_left.height()),
-                ),
-            ),
-            GradientShaderColors::Colors(&[white_opaque, white_transparent]),
-            Some(&[0.0f32, 1.0] as &[f32]),
-            TileMode::Repeat,
-            None,
-            None,
-        ));
-
-        self.canvas.draw_rect(time_rect, &self.shared.white_paint);
-
-        self.canvas.draw_rect(time_rect_left, &gradiant);
-
-        self.canvas.draw_str_align(
-            time_text,
-            time_point,
-            &self.shared.font,
-            &self.shared.black_paint,
-            Align::Right,
-        );
+        Ok(shaped)
     }
 
-    fn map_range(from_range: (f32, f32), to_range: (f32, f32), s: f32) -> f32 {
-        to_range.0 + (s - from_range.0) * (to_range.1 - to_range.0) / (from_range.1 - from_range.0)
+    fn engine(&self) -> LayoutEngine<'_, Self> {
+        LayoutEngine::new(
+            &self.metrics,
+            &self.shared.footer,
+            self,
+            (self.width, self.height),
+            Utc::now(),
+        )
+        .gray_ramp(&self.shared.gray_ramp)
+        .timezone(self.shared.timezone)
     }
 
-    fn text_bounds(&mut self, text: &str, (x, y): (f32, f32)) -> Rect {
-        let (text_width, text_measurements) = self
-            .shared
-            .font
-            .measure_str(text, Some(&self.shared.black_paint));
-        Rect::new(x, y + text_measurements.top, x + text_width, y)
+    /// Draw an error screen in place of the board: the headline in a banner,
+    /// followed by each cause in the error's chain
+    pub(crate) fn draw_error(
+        self,
+        headline: &str,
+        error: &eyre::Report,
+    ) -> Result<(), RenderError> {
+        let primitives = self.engine().error_screen(headline, error)?;
+        self.paint(&primitives)
     }
 
-    fn text_bounds_right_align(&mut self, text: &str, (x, y): (f32, f32)) -> Rect {
-        let (text_width, text_measurements) = self
-            .shared
-            .font
-            .measure_str(text, Some(&self.shared.black_paint));
-        Rect::new(x - text_width, y + text_measurements.top, x, y)
+    /// Draw a labelled swatch of each grey a panel with `bit_depth` bits per
+    /// pixel shows
+    pub(crate) fn draw_calibration(self, bit_depth: u8) -> Result<(), RenderError> {
+        let primitives = self.engine().calibration(bit_depth)?;
+        self.paint(&primitives)
     }
 
-    fn draw_line_id_bubble(&mut self, line_id: &str, x: f32) -> Result<Rect> {
-        let blob = TextBlob::new(line_id, &self.shared.font)
-            .ok_or(eyre!("failed to construct skia text blob"))?;
-
-        let bounds = self
-            .text_bounds(line_id, (x, self.y))
-            .with_outset((10.0, 10.0));
-
-        let mut color_hasher = DefaultHasher::new();
-        color_hasher.write(line_id.as_bytes());
-        let color_hash = color_hasher.finish() as f32;
-
-        // map a value in the space 0..u64::MAX to the space 0.3..0.9
-        let color = Self::map_range((0.0, u64::MAX as f32), (0.5, 0.9), color_hash);
-
-        self.line_id_bubble_paint
-            .set_color4f(Color4f::new(color, color, color, 1.0), None);
-
-        self.canvas
-            .draw_round_rect(bounds, 24.0, 24.0, &self.line_id_bubble_paint);
-
-        self.canvas
-            .draw_text_blob(&blob, (x, self.y), &self.shared.black_paint);
+    pub(crate) fn draw(mut self, layout: &Layout) -> Result<(), RenderError> {
+        if layout.profile != self.shared.profile {
+            self.metrics = Metrics::for_profile(layout.profile);
+            self.fonts = Fonts::new(
+                &self.shared.typeface,
+                &self.shared.monospace,
+                &self.metrics,
+                self.text,
+            );
+        }
 
-        Ok(bounds)
+        let primitives = self
+            .engine()
+            .debug(LayoutOverrides::debug())
+            .theme(self.shared.theme)
+            .bubble_contrast(self.shared.bubble_contrast)
+            .board(layout)?;
+        self.paint(&primitives)
     }
 
-    fn draw_footer(&mut self, all_agencies: &HashMap<String, DateTime<Utc>>) {
-        let bottom_box_y = self.height - 40.0;
-
-        self.canvas.draw_rect(
-            Rect::new(0.0, bottom_box_y, self.width, self.height),
-            &self.shared.light_grey_paint,
-        );
-
-        self.canvas.draw_line(
-            (0.0, bottom_box_y),
-            (self.width, bottom_box_y),
-            &self.shared.black_paint_heavy,
-        );
-
-        let now = Utc::now().with_timezone(&Pacific);
-        let time = now.format("%a %b %d - %H:%M").to_string();
-
-        let mut agency_str = String::new();
-
-        for (agency_name, live_time) in all_agencies {
-            let age = now.signed_duration_since(*live_time);
-
-            let agency = crate::agencies::agency_readable(agency_name);
-
-            let status = if age < Duration::minutes(5) {
-                // Checkbox emoji
-                String::from("\u{2611}")
-            } else {
-                format!("{} mins", age.num_minutes())
-            };
-
-            agency_str.push_str(&format!(" {agency}: {status},"));
+    fn paint(&self, primitives: &[Primitive]) -> Result<(), RenderError> {
+        for primitive in primitives {
+            match primitive {
+                Primitive::Rect { bounds, shade } => {
+                    self.canvas.draw_rect(rect(*bounds), &shade_paint(*shade));
+                }
+                Primitive::RoundRect {
+                    bounds,
+                    radius,
+                    shade,
+                } => {
+                    let mut paint = shade_paint(*shade);
+                    paint.set_anti_alias(true);
+
+                    self.canvas
+                        .draw_round_rect(rect(*bounds), *radius, *radius, &paint);
+                }
+                Primitive::Outline {
+                    bounds,
+                    width,
+                    shade,
+                } => {
+                    let mut paint = shade_paint(*shade);
+                    paint.set_style(PaintStyle::Stroke);
+                    paint.set_stroke_width(*width);
+
+                    self.canvas.draw_rect(rect(*bounds), &paint);
+                }
+                Primitive::Line {
+                    from,
+                    to,
+                    width,
+                    shade,
+                } => {
+                    let mut paint = shade_paint(*shade);
+                    paint.set_stroke_width(*width);
+
+                    self.canvas.draw_line(*from, *to, &paint);
+                }
+                Primitive::Text {
+                    text,
+                    font,
+                    x,
+                    y,
+                    shade,
+                } => self.paint_text(text, *font, (*x, *y), *shade)?,
+                Primitive::VerticalText {
+                    text,
+                    font,
+                    x,
+                    y,
+                    shade,
+                } => {
+                    self.canvas.save();
+                    self.canvas.rotate(-90.0, Some((*x, *y).into()));
+                    self.paint_text(text, *font, (*x, *y), *shade)?;
+                    self.canvas.restore();
+                }
+                Primitive::Fade { bounds, shade } => self.paint_fade(*bounds, *shade),
+                Primitive::Icon {
+                    icon,
+                    x,
+                    y,
+                    size,
+                    shade,
+                } => icon.draw(self.canvas, (*x, *y), *size, shade_color(*shade)),
+                Primitive::Arrow {
+                    x,
+                    y,
+                    size,
+                    bearing,
+                    shade,
+                } => icons::draw_arrow(self.canvas, (*x, *y), *size, *bearing, shade_color(*shade)),
+            }
         }
-        agency_str.pop();
-
-        self.canvas.draw_str_align(
-            agency_str,
-            (self.width - 20.0, self.height - 10.0),
-            &self.shared.font,
-            &self.shared.black_paint,
-            Align::Right,
-        );
 
-        self.canvas.draw_str_align(
-            time,
-            (20.0, self.height - 10.0),
-            &self.shared.font,
-            &self.shared.black_paint,
-            Align::Left,
-        );
+        Ok(())
     }
 
-    fn draw_text_row(&mut self, text: &str, x1: f32, x2: f32) {
-        self.canvas.draw_rect(
-            Rect::new(x1, self.y, x2, self.y + 40.0),
-            &self.shared.light_grey_paint,
+    /// Draw text with its left edge at `x` and baseline at `y`, reusing the
+    /// shaping done while it was laid out
+    fn paint_text(
+        &self,
+        text: &str,
+        font: FontKind,
+        (x, y): (f32, f32),
+        shade: f32,
+    ) -> Result<(), RenderError> {
+        let shaped = self.shaped(text, font)?;
+        let (shaped, _) = &*shaped;
+
+        self.canvas.draw_text_blob(
+            &shaped.blob,
+            (x, y + shaped.ascent),
+            &self.text_paint(font, shade),
         );
-        self.y += 28.0;
 
-        self.canvas.draw_str_align(
-            text,
-            ((x1 + x2) / 2.0, self.y),
-            &self.shared.font,
-            &self.shared.black_paint,
-            Align::Center,
-        );
-
-        self.y += 12.0;
+        Ok(())
     }
 
-    pub(crate) fn draw(mut self, layout: &Layout) -> Result<()> {
-        self.y = 0.0;
-        for row in &layout.left.rows {
-            self.draw_row(row, 0.0, self.x_midpoint)?;
-        }
+    /// Paint for text in one of the board's fonts, which outlines as well as
+    /// fills text set small enough to be fake bold
+    fn text_paint(&self, font: FontKind, shade: f32) -> Paint {
+        let mut paint = shade_paint(shade);
+        paint.set_anti_alias(self.text.anti_alias == AntiAlias::Grayscale);
 
-        self.y = 0.0;
-        for row in &layout.right.rows {
-            self.draw_row(row, self.x_midpoint, self.width)?;
+        if let Some(FakeBold { below, stroke }) = self.text.fake_bold {
+            if self.metrics.font_size(font) < below {
+                paint.set_style(PaintStyle::StrokeAndFill);
+                paint.set_stroke_width(stroke);
+            }
         }
 
-        self.canvas.draw_line(
-            (self.x_midpoint, 0.0),
-            (self.x_midpoint, self.height),
-            &self.shared.black_paint_heavy,
-        );
+        paint
+    }
 
-        self.draw_footer(&layout.all_agencies);
+    /// Fill with a gradient from `shade` at the right edge to transparent at
+    /// the left, so that departure times fade into the text beside them
+    fn paint_fade(&self, bounds: Bounds, shade: f32) {
+        let shade = (shade.clamp(0.0, 1.0) * 255.0) as u8;
+        let background_opaque = Color::from_argb(255, shade, shade, shade);
+        let background_transparent = Color::from_argb(0, shade, shade, shade);
 
-        Ok(())
+        let middle = bounds.top + (0.5 * bounds.height());
+
+        let mut gradiant = Paint::new(Color4f::new(0.0, 0.0, 0.0, 1.0), None);
+        gradiant.set_shader(Shader::linear_gradient(
+            ((bounds.right, middle), (bounds.left, middle)),
+            GradientShaderColors::Colors(&[background_opaque, background_transparent]),
+            Some(&[0.0f32, 1.0] as &[f32]),
+            TileMode::Repeat,
+            None,
+            None,
+        ));
+
+        self.canvas.draw_rect(rect(bounds), &gradiant);
     }
 }
@@ -0,0 +1,107 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+use crate::config::RenderLimitConfig;
+
+/// Limits how many boards are drawn at once, since each render is CPU-heavy
+/// and a burst of refreshes could otherwise starve a small host. Requests
+/// over the limit queue for a permit, and are turned away with `503` once the
+/// queue is full or they've waited too long.
+pub struct RenderLimiter {
+    permits: Arc<Semaphore>,
+    waiting: AtomicUsize,
+    config: RenderLimitConfig,
+    /// Routes which draw the board
+    paths: HashSet<String>,
+}
+
+/// Counts a request as waiting until it's dropped, which also covers
+/// requests whose client goes away while they wait
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl RenderLimiter {
+    pub fn new(config: RenderLimitConfig, paths: impl IntoIterator<Item = String>) -> Arc<Self> {
+        Arc::new(Self {
+            permits: Arc::new(Semaphore::new(config.permits.max(1))),
+            waiting: AtomicUsize::new(0),
+            config,
+            paths: paths.into_iter().collect(),
+        })
+    }
+}
+
+/// Hold a render permit while serving requests for the board
+pub async fn limit_renders(
+    State(limiter): State<Arc<RenderLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.paths.contains(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let permit = match limiter.permits.clone().try_acquire_owned() {
+        Ok(x) => x,
+        Err(_) => {
+            if limiter.waiting.fetch_add(1, Ordering::SeqCst) >= limiter.config.queue {
+                limiter.waiting.fetch_sub(1, Ordering::SeqCst);
+                warn!(uri = %request.uri(), "render queue full, turning request away");
+
+                return busy(&limiter);
+            }
+            let waiting = Waiting(&limiter.waiting);
+
+            let timeout = Duration::from_secs(limiter.config.timeout_secs);
+            let acquired =
+                tokio::time::timeout(timeout, limiter.permits.clone().acquire_owned()).await;
+            drop(waiting);
+
+            match acquired {
+                Ok(Ok(x)) => x,
+                _ => {
+                    warn!(uri = %request.uri(), "timed out waiting to render, turning request away");
+
+                    return busy(&limiter);
+                }
+            }
+        }
+    };
+
+    let response = next.run(request).await;
+    drop(permit);
+
+    response
+}
+
+fn busy(limiter: &RenderLimiter) -> Response {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(
+            header::RETRY_AFTER,
+            HeaderValue::from(limiter.config.timeout_secs.max(1)),
+        )],
+        "too many boards are being drawn, try again shortly",
+    )
+        .into_response()
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::Path,
+};
+
+use chrono::{Duration, Utc};
+use chrono_tz::Tz;
+use eyre::{Context, Result};
+use tracing::info;
+
+use crate::{
+    config::{ConfigFile, Profile, SectionStyle, TitleStyle},
+    error::FetchError,
+    layout::{Agency, Column, Departure, Layout, Line, Row, Text, Window},
+    output::{self, OutputFormat, OutputParams},
+    render::SharedRenderData,
+};
+
+/// Draw a made-up board with every kind of row and footer status, and the
+/// error screen, with the configured fonts and profile, writing both as PNGs
+/// to `dir`. Meant to be run before deploying, to catch anything that fails
+/// to render.
+pub fn run(config_file: &ConfigFile, dir: &Path) -> Result<()> {
+    let shared = SharedRenderData::new(config_file)?;
+
+    let board = output::render(
+        shared.clone(),
+        &synthetic_layout(config_file.profile, config_file.timezone),
+        OutputFormat::Png,
+        OutputParams::default(),
+    )
+    .wrap_err("drawing the self-test board")?;
+    write(&dir.join("selftest-board.png"), &board)?;
+
+    let error = eyre::Report::new(FetchError::NotFetched {
+        agency: String::from("SF"),
+    })
+    .wrap_err("self-test error screen");
+    let error_screen = output::render_error_png(shared, &error, OutputParams::default())
+        .wrap_err("drawing the self-test error screen")?;
+    write(&dir.join("selftest-error.png"), &error_screen)?;
+
+    Ok(())
+}
+
+fn write(path: &Path, data: &[u8]) -> Result<()> {
+    std::fs::write(path, data).wrap_err_with(|| format!("writing {}", path.display()))?;
+    info!(path = %path.display(), "self-test image written");
+
+    Ok(())
+}
+
+fn synthetic_layout(profile: Profile, timezone: Tz) -> Layout {
+    let now = Utc::now();
+
+    let departures = |minutes: &[i64]| {
+        minutes
+            .iter()
+            .enumerate()
+            .map(|(i, minutes)| Departure {
+                minutes: *minutes,
+                time: now + Duration::minutes(*minutes),
+                accessible: Some(i % 2 == 0),
+                delay_minutes: Some(i as i64 - 1),
+                cancelled: false,
+                track: None,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let line = |id: &str, destination: &str, minutes: &[i64]| Line {
+        id: id.to_owned(),
+        destination: destination.to_owned(),
+        secondary_destination: None,
+        departures: departures(minutes),
+        window: None,
+        bearing: None,
+        color: None,
+        last_departure: None,
+        pinned: false,
+    };
+
+    let mut commuter = line("CR", "Providence", &[6, 21]);
+    commuter.color = Some([0x80, 0x27, 0x6c]);
+    commuter.departures[0].track = Some(String::from("3"));
+    commuter.departures[1].cancelled = true;
+
+    let agency = Agency {
+        lines: vec![
+            Line {
+                pinned: true,
+                ..line("N", "Ocean Beach", &[2, 14, 27])
+            },
+            Line {
+                secondary_destination: Some(String::from("海灘")),
+                bearing: Some(135.0),
+                ..line("38R", "Fort Miley", &[5])
+            },
+            Line {
+                window: Some(Window {
+                    minutes: 60,
+                    count: 7,
+                }),
+                ..line("14", "Daly City", &[3, 11])
+            },
+            Line {
+                last_departure: Some(now + Duration::minutes(40)),
+                ..line("Owl", "Downtown", &[38])
+            },
+            commuter,
+        ],
+        notice: None,
+        timetable: None,
+        stop: None,
+        style: SectionStyle::default(),
+        summary: None,
+    };
+
+    let holiday = Agency {
+        lines: Vec::new(),
+        notice: Some(String::from("No service today (holiday)")),
+        timetable: None,
+        stop: None,
+        style: SectionStyle {
+            background: Some(0.9),
+            ..SectionStyle::default()
+        },
+        summary: None,
+    };
+
+    let timetable = Agency {
+        lines: vec![
+            line("KT", "Chinatown", &[4, 16, 28]),
+            line("L", "Wawona", &[9]),
+        ],
+        notice: None,
+        timetable: Some(3),
+        stop: Some(String::from("at Church St & Duboce Ave")),
+        style: SectionStyle::default(),
+        summary: None,
+    };
+
+    let text = |text: &str, title_style| {
+        Row::Text(Text {
+            text: text.to_owned(),
+            style: SectionStyle {
+                title_style,
+                ..SectionStyle::default()
+            },
+        })
+    };
+
+    Layout {
+        left: Column {
+            rows: vec![
+                text("Muni", TitleStyle::Bold),
+                Row::Agency(agency),
+                text("مرحبا · Right-to-left", TitleStyle::Normal),
+            ],
+        },
+        right: Column {
+            rows: vec![
+                text("Caltrain", TitleStyle::Inverse),
+                Row::Agency(holiday),
+                Row::Agency(timetable),
+            ],
+        },
+        balance: false,
+        all_agencies: HashMap::from([
+            (String::from("SF"), now),
+            (String::from("CT"), now - Duration::minutes(8)),
+            (String::from("AC"), now - Duration::minutes(30)),
+        ]),
+        failed_agencies: BTreeSet::from([String::from("BA")]),
+        stale_agencies: BTreeSet::from([String::from("AC")]),
+        profile,
+        timezone,
+        incomplete_journeys: 3,
+    }
+}
@@ -1,35 +1,942 @@
-use std::sync::Arc;
+use std::{cell::RefCell, marker::PhantomData, path::Path, sync::Arc, time::Instant};
 
-use axum::Router;
-use tokio::net::TcpListener;
-use tower::ServiceBuilder;
-use tower_http::trace::TraceLayer;
-use tracing::info;
+use axum::{
+    body::Body,
+    extract::{Query, Request, State},
+    http::{header, uri::PathAndQuery, HeaderMap, HeaderValue, StatusCode, Uri},
+    middleware::{self, Next},
+    response::{Html, IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use tower::{ServiceBuilder, ServiceExt};
+use tower_http::{compression::CompressionLayer, trace::TraceLayer};
+use tracing::{warn, Level};
+use utoipa::openapi::OpenApi;
 
-use crate::{api_client::DataAccess, config::ConfigFile, render::SharedRenderData};
+use crate::{
+    api_client::{DataAccess, REFRESH_INTERVAL},
+    api_doc,
+    calibration::{calibration_png, CalibrationState},
+    config::{ConfigFile, Orientation, PngOptimizationConfig, RenderTarget, ServerConfig},
+    config_repo::{reload_webhook, ConfigReload},
+    device,
+    error::{Failure, BOARD_ERROR},
+    handler::{Landscape, Portrait, ScreenOrientation, TransitHandler},
+    history::{self, history_page, History},
+    listen::Listen,
+    output::{self, OutputFormat, OutputParams},
+    overrides::{self, LayoutOverrides, LAYOUT_OVERRIDES},
+    pattern::{self, pattern_image},
+    png_metadata::{self, DEPARTURES},
+    png_optimize,
+    proxy::ReadThroughProxy,
+    rate_limit::{limit_rate, RateLimiter},
+    remote::{stops_json, StopsJsonState},
+    render::SharedRenderData,
+    render_limit::{limit_renders, RenderLimiter},
+    status::{
+        accuracy_page, directions_page, export_csv, metrics, status_page, DirectionsState,
+        StatusState,
+    },
+    summary,
+    trmnl::{self, Displays},
+};
 
-pub async fn serve(
+/// Every route for the config, mounted under its path prefix. With `reload`,
+/// a webhook for pulling a new config from its repository is added.
+pub fn app(
     data_access: Arc<DataAccess>,
     shared_render_data: Arc<SharedRenderData>,
     config_file: ConfigFile,
-) -> eyre::Result<()> {
-    let app = kindling::ApplicationBuilder::new(Router::new(), "http://transit.lilys.hair")
-        .add_handler(
-            "/stops.png",
-            crate::handler::TransitHandler {
-                shared: shared_render_data,
+    reload: Option<Arc<ConfigReload>>,
+) -> Router {
+    let started = Instant::now();
+    let displays = Arc::new(Displays::default());
+    let optimize_png = config_file.optimize_png;
+    let trust_forwarded_headers = config_file.server.trust_forwarded_headers;
+    let output_handler = Arc::new(transit_handler::<Landscape>(
+        &data_access,
+        &shared_render_data,
+        &config_file,
+    ));
+    let board_paths: Vec<String> = config_file
+        .screens
+        .iter()
+        .map(|screen| screen.path.clone())
+        .chain(config_file.mirrors.keys().map(|name| mirror_path(name)))
+        .chain(
+            [
+                "/stops.html",
+                "/stops.txt",
+                "/summary.json",
+                "/stops.svg",
+                "/stops.image",
+                "/debug/gallery.png",
+            ]
+            .map(String::from),
+        )
+        .collect();
+    let render_limiter = RenderLimiter::new(config_file.render_limit, board_paths.clone());
+    let rate_limiter = config_file
+        .rate_limit
+        .as_ref()
+        .map(|rate_limit| RateLimiter::new(rate_limit, trust_forwarded_headers, board_paths));
+
+    let app = screen_routes(&data_access, &shared_render_data, &config_file)
+        .merge(mirror_routes(
+            &data_access,
+            &shared_render_data,
+            &config_file,
+        ))
+        .route(
+            "/stops.html",
+            get(stops_html).with_state(output_handler.clone()),
+        )
+        .route(
+            "/stops.txt",
+            get(stops_txt).with_state(output_handler.clone()),
+        )
+        .route(
+            "/summary.json",
+            get(summary::summary_json).with_state(output_handler.clone()),
+        )
+        .route(
+            "/stops.svg",
+            get(stops_svg).with_state(output_handler.clone()),
+        )
+        .route("/stops.image", get(stops_image).with_state(output_handler))
+        .route(
+            "/api-doc",
+            get(api_doc).with_state(Arc::new(api_doc::document(&config_file))),
+        )
+        .route(
+            "/status",
+            get(status_page).with_state(StatusState {
+                data_access: data_access.clone(),
+                started,
+                displays: displays.clone(),
+            }),
+        )
+        .route(
+            "/stops.json",
+            get(stops_json).with_state(StopsJsonState {
                 data_access: data_access.clone(),
                 config_file: config_file.clone(),
-            },
+            }),
+        )
+        .route("/metrics", get(metrics).with_state(data_access.clone()))
+        .route(
+            "/debug/directions",
+            get(directions_page).with_state(DirectionsState {
+                data_access: data_access.clone(),
+                config_file: config_file.clone(),
+            }),
+        )
+        .route(
+            "/accuracy",
+            get(accuracy_page).with_state(data_access.clone()),
+        )
+        .route(
+            "/export.csv",
+            get(export_csv).with_state(data_access.clone()),
+        )
+        .route(
+            "/pattern.png",
+            get(pattern_image).with_state(pattern::screen_size(&config_file)),
+        )
+        .route(
+            "/calibration.png",
+            get(calibration_png).with_state(CalibrationState::new(
+                shared_render_data.clone(),
+                &config_file,
+            )),
+        );
+
+    let history = config_file.history.as_ref().and_then(|history| {
+        History::open(
+            Path::new(&history.db),
+            history.keep_days,
+            config_file.timezone,
+        )
+        .inspect_err(|e| warn!(error = ?e, "board history will not be recorded"))
+        .ok()
+        .map(Arc::new)
+    });
+
+    let app = match history {
+        Some(history) => {
+            tokio::spawn(history::record(
+                history.clone(),
+                Arc::downgrade(&data_access),
+                shared_render_data.clone(),
+                config_file.clone(),
+            ));
+
+            app.route("/history", get(history_page).with_state(history))
+        }
+        None => app,
+    };
+
+    let app = match reload {
+        Some(reload) => app.route("/config/reload", post(reload_webhook).with_state(reload)),
+        None => app,
+    };
+
+    let app = match &config_file.trmnl {
+        Some(trmnl) => app.merge(trmnl::router(
+            trmnl.clone(),
+            data_access.clone(),
+            displays,
+            config_file.server.public_url(),
+        )),
+        None => app,
+    };
+
+    #[cfg(feature = "debug-gallery")]
+    let app = app.merge(crate::gallery::router(
+        data_access.clone(),
+        config_file.clone(),
+    ));
+
+    let app = app.layer(
+        ServiceBuilder::new()
+            .layer(
+                TraceLayer::new_for_http().make_span_with(move |request: &Request| {
+                    let client = trust_forwarded_headers
+                        .then(|| forwarded_client(request.headers()))
+                        .flatten();
+
+                    tracing::span!(
+                        target: "tower_http::trace::make_span",
+                        Level::DEBUG,
+                        "request",
+                        method = %request.method(),
+                        uri = %request.uri(),
+                        version = ?request.version(),
+                        client = client.unwrap_or("-"),
+                    )
+                }),
+            )
+            .layer(middleware::from_fn_with_state(rate_limiter, limit_rate))
+            .layer(middleware::from_fn_with_state(
+                render_limiter,
+                limit_renders,
+            ))
+            .layer(CompressionLayer::new())
+            .layer(middleware::from_fn(entity_tags))
+            .layer(middleware::from_fn_with_state(
+                Arc::new(config_file.clone()),
+                screen_defaults,
+            ))
+            // after the screen's defaults, which can pick its target
+            .layer(middleware::from_fn(layout_overrides))
+            .layer(middleware::from_fn(departure_metadata))
+            .layer(middleware::from_fn_with_state(
+                data_access.clone(),
+                cache_headers,
+            ))
+            .layer(middleware::from_fn_with_state(
+                optimize_png,
+                optimize_png_responses,
+            ))
+            .layer(middleware::from_fn(board_errors)),
+    );
+
+    mount(app, &config_file.server)
+}
+
+fn transit_handler<O: ScreenOrientation>(
+    data_access: &Arc<DataAccess>,
+    shared_render_data: &Arc<SharedRenderData>,
+    config_file: &ConfigFile,
+) -> TransitHandler<O> {
+    TransitHandler {
+        shared: shared_render_data.clone(),
+        data_access: data_access.clone(),
+        config_file: config_file.clone(),
+        orientation: PhantomData,
+    }
+}
+
+/// The board's PNG route for each screen, with kindling's page, install
+/// script and refresh script alongside
+#[cfg(feature = "kindling")]
+fn screen_routes(
+    data_access: &Arc<DataAccess>,
+    shared_render_data: &Arc<SharedRenderData>,
+    config_file: &ConfigFile,
+) -> Router {
+    let mut screens =
+        kindling::ApplicationBuilder::new(Router::new(), config_file.server.public_url());
+    for screen in &config_file.screens {
+        screens = match screen.orientation {
+            Orientation::Landscape => screens.add_handler(
+                &screen.path,
+                transit_handler::<Landscape>(data_access, shared_render_data, config_file),
+            ),
+            Orientation::Portrait => screens.add_handler(
+                &screen.path,
+                transit_handler::<Portrait>(data_access, shared_render_data, config_file),
+            ),
+        };
+    }
+
+    screens.attach()
+}
+
+/// The board's PNG route for each screen, drawn without kindling
+#[cfg(not(feature = "kindling"))]
+fn screen_routes(
+    data_access: &Arc<DataAccess>,
+    shared_render_data: &Arc<SharedRenderData>,
+    config_file: &ConfigFile,
+) -> Router {
+    use crate::handler::screen_png;
+
+    config_file
+        .screens
+        .iter()
+        .fold(Router::new(), |router, screen| match screen.orientation {
+            Orientation::Landscape => {
+                router.route(
+                    &screen.path,
+                    get(screen_png::<Landscape>).with_state(Arc::new(
+                        transit_handler::<Landscape>(data_access, shared_render_data, config_file),
+                    )),
+                )
+            }
+            Orientation::Portrait => router.route(
+                &screen.path,
+                get(screen_png::<Portrait>).with_state(Arc::new(transit_handler::<Portrait>(
+                    data_access,
+                    shared_render_data,
+                    config_file,
+                ))),
+            ),
+        })
+}
+
+/// Where the mirror with this name is served
+pub(crate) fn mirror_path(name: &str) -> String {
+    format!("/mirrors/{name}.png")
+}
+
+/// The PNG route for each mirror, laid out from the board's data with the
+/// mirror's sections and lines left off
+fn mirror_routes(
+    data_access: &Arc<DataAccess>,
+    shared_render_data: &Arc<SharedRenderData>,
+    config_file: &ConfigFile,
+) -> Router {
+    config_file
+        .mirrors
+        .iter()
+        .fold(Router::new(), |router, (name, mirror)| {
+            let config_file = overrides::mirror(config_file, mirror);
+
+            router.route(
+                &mirror_path(name),
+                get(mirror_png).with_state(Arc::new(transit_handler::<Landscape>(
+                    data_access,
+                    shared_render_data,
+                    &config_file,
+                ))),
+            )
+        })
+}
+
+/// Serve a read-through cache of another instance
+pub async fn serve_proxy(primary: &str, listeners: Vec<Listen>) -> eyre::Result<()> {
+    let app = ReadThroughProxy::router(primary).layer(TraceLayer::new_for_http());
+
+    Listen::serve_all(listeners, app).await
+}
+
+/// Nest the app under the configured path prefix. When forwarded headers are
+/// trusted, requests from a proxy which strips the prefix itself (announcing
+/// it in `X-Forwarded-Prefix`) are routed as though it had been left on.
+fn mount(app: Router, server: &ServerConfig) -> Router {
+    let prefix = server.normalized_prefix();
+    if prefix.is_empty() {
+        return app;
+    }
+
+    let app = Router::new().nest(&prefix, app);
+    if !server.trust_forwarded_headers {
+        return app;
+    }
+
+    Router::new().fallback_service(
+        app.map_request(move |request: Request| restore_forwarded_prefix(request, &prefix)),
+    )
+}
+
+fn restore_forwarded_prefix(mut request: Request, prefix: &str) -> Request {
+    let forwarded_prefix = request
+        .headers()
+        .get("x-forwarded-prefix")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_end_matches('/'));
+
+    if forwarded_prefix != Some(prefix) || request.uri().path().starts_with(prefix) {
+        return request;
+    }
+
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .map_or("/", PathAndQuery::as_str);
+
+    let mut parts = request.uri().clone().into_parts();
+    parts.path_and_query = match format!("{prefix}{path_and_query}").parse() {
+        Ok(x) => Some(x),
+        Err(_) => return request,
+    };
+
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+
+    request
+}
+
+/// The client address recorded by the trusted proxy, which appends it as the
+/// last entry of `X-Forwarded-For`. Earlier entries come from the client, and
+/// can say anything.
+pub(crate) fn forwarded_client(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("x-forwarded-for")?
+        .to_str()
+        .ok()?
+        .rsplit(',')
+        .next()
+        .map(str::trim)
+        .filter(|client| !client.is_empty())
+}
+
+#[utoipa::path(
+    get,
+    path = "/stops.html",
+    params(LayoutOverrides),
+    responses(
+        (status = 200, description = "The board as a web page", content_type = "text/html", body = String),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub(crate) async fn stops_html(State(handler): State<Arc<TransitHandler>>) -> Response {
+    match handler.html().await {
+        Ok(html) => Html(html).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to render HTML view");
+
+            Failure::of(&error).response(&error)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stops.txt",
+    params(LayoutOverrides),
+    responses(
+        (status = 200, description = "The board as plain text, a line for each departure line, e.g. `N Ocean Beach: 3, 12 min`", content_type = "text/plain", body = String),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub(crate) async fn stops_txt(State(handler): State<Arc<TransitHandler>>) -> Response {
+    match handler.text().await {
+        Ok(text) => ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], text).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to summarize the board as text");
+
+            Failure::of(&error).response(&error)
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/stops.svg",
+    params(OutputParams, LayoutOverrides),
+    responses(
+        (status = 200, description = "The board as an SVG", content_type = "image/svg+xml", body = String),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub(crate) async fn stops_svg(
+    State(handler): State<Arc<TransitHandler>>,
+    Query(params): Query<OutputParams>,
+) -> Response {
+    handle_output(&handler, OutputFormat::Svg, params).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/stops.image",
+    params(OutputParams, LayoutOverrides),
+    responses(
+        (status = 200, description = "The board in the requested format", content_type = "image/png"),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub(crate) async fn stops_image(
+    State(handler): State<Arc<TransitHandler>>,
+    Query(params): Query<OutputParams>,
+) -> Response {
+    let format = params.format.unwrap_or(OutputFormat::Png);
+
+    handle_output(&handler, format, params).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/mirrors/{name}.png",
+    params(OutputParams, LayoutOverrides),
+    responses(
+        (status = 200, description = "The board without the mirror's hidden sections and lines", content_type = "image/png"),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub(crate) async fn mirror_png(
+    State(handler): State<Arc<TransitHandler>>,
+    Query(params): Query<OutputParams>,
+) -> Response {
+    handle_output(&handler, OutputFormat::Png, params).await
+}
+
+/// The OpenAPI description of this instance's routes
+async fn api_doc(State(document): State<Arc<OpenApi>>) -> Json<OpenApi> {
+    Json(OpenApi::clone(&document))
+}
+
+async fn handle_output(
+    handler: &TransitHandler,
+    format: OutputFormat,
+    params: OutputParams,
+) -> Response {
+    match handler.render(format, params).await {
+        Ok(data) => ([(header::CONTENT_TYPE, format.content_type())], data).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to render board");
+
+            Failure::of(&error).response(&error)
+        }
+    }
+}
+
+/// Tag image responses with a hash of their content, and answer requests
+/// which already have that content with `304 Not Modified`. A board that
+/// hasn't changed since a proxy last fetched it then isn't sent again.
+async fn entity_tags(request: Request, next: Next) -> Response {
+    let if_none_match = request
+        .headers()
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let response = next.run(request).await;
+
+    let is_image = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("image/"));
+
+    if !is_image || !response.status().is_success() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(x) => x,
+        Err(error) => {
+            warn!(?error, "failed to buffer image response");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to buffer image").into_response();
+        }
+    };
+
+    // weak, since compression changes the bytes sent but not the image
+    let etag = HeaderValue::from_str(&format!("W/\"{:08x}\"", crc32fast::hash(&body))).unwrap();
+
+    if none_match(&if_none_match, &etag) {
+        parts.status = StatusCode::NOT_MODIFIED;
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.insert(header::ETAG, etag);
+
+        return Response::from_parts(parts, Body::empty());
+    }
+
+    parts.headers.insert(header::ETAG, etag);
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Whether the values of `If-None-Match` list `etag`, or are `*`. Tags are
+/// compared weakly, as `If-None-Match` requires, so `W/"a"` matches `"a"`.
+pub(crate) fn none_match<'a>(
+    if_none_match: impl IntoIterator<Item = &'a HeaderValue>,
+    etag: &HeaderValue,
+) -> bool {
+    fn opaque(tag: &str) -> &str {
+        tag.trim().trim_start_matches("W/")
+    }
+
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+
+    if_none_match
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+/// Make the layout overrides in the query string available to the handlers
+/// serving this request
+async fn layout_overrides(request: Request, next: Next) -> Response {
+    let overrides = match Query::<LayoutOverrides>::try_from_uri(request.uri()) {
+        Ok(Query(x)) => LayoutOverrides {
+            target: RenderTarget::of(request.uri()),
+            ..x
+        },
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    LAYOUT_OVERRIDES.scope(overrides, next.run(request)).await
+}
+
+/// Fill in the size and render target of requests for a screen route from
+/// its device, unless the query string gives them, and reduce its PNGs to the
+/// screen's grey levels. Without a device, the target is picked from the
+/// client's User-Agent, so one route serves both Kindles and browsers.
+async fn screen_defaults(
+    State(config_file): State<Arc<ConfigFile>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let screen = match config_file
+        .screens
+        .iter()
+        .find(|screen| screen.path == request.uri().path())
+    {
+        Some(x) => x,
+        None => return next.run(request).await,
+    };
+
+    let mut defaults = Vec::new();
+    let mut by_user_agent = false;
+
+    match screen.device.map(|device| device.profile()) {
+        Some(profile) => {
+            let target = if profile.rotate { "kindle" } else { "browser" };
+            defaults.extend([
+                ("width", profile.width.to_string()),
+                ("height", profile.height.to_string()),
+                ("target", target.to_owned()),
+            ]);
+        }
+        None => {
+            let user_agent = request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|user_agent| user_agent.to_str().ok())
+                .unwrap_or_default();
+            let target = if is_kindle_user_agent(user_agent, &config_file.kindle_user_agents) {
+                "kindle"
+            } else {
+                "browser"
+            };
+            defaults.push(("target", target.to_owned()));
+            by_user_agent = true;
+        }
+    }
+
+    match with_query_defaults(request.uri(), &defaults) {
+        Some(uri) => *request.uri_mut() = uri,
+        // the query string gave everything, including the target
+        None => by_user_agent = false,
+    }
+
+    // kindling's routes read the size without `OutputParams`, so it's
+    // checked here before a huge one is allocated
+    let size = match Query::<ScreenSize>::try_from_uri(request.uri()) {
+        Ok(Query(x)) => x,
+        Err(rejection) => return rejection.into_response(),
+    };
+    if let Some(side) = [size.width, size.height]
+        .into_iter()
+        .flatten()
+        .find(|side| !(output::MIN_DIMENSION..=output::MAX_DIMENSION).contains(side))
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!(
+                "{side} pixels can't be drawn, expected {} to {}",
+                output::MIN_DIMENSION,
+                output::MAX_DIMENSION
+            ),
         )
-        .attach()
-        .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()));
+            .into_response();
+    }
+
+    let mut response = next.run(request).await;
+
+    if by_user_agent {
+        response
+            .headers_mut()
+            .append(header::VARY, HeaderValue::from_static("user-agent"));
+    }
+
+    let is_png = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "image/png");
+
+    let (bit_depth, dither) = match screen.quantize() {
+        Some(x) if is_png && response.status().is_success() => x,
+        _ => return response,
+    };
+
+    let (mut parts, body) = response.into_parts();
+
+    let original = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(x) => x,
+        Err(error) => {
+            warn!(?error, "failed to buffer PNG response");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to buffer PNG").into_response();
+        }
+    };
+
+    let encoded = original.clone();
+    let quantized =
+        match tokio::task::spawn_blocking(move || device::quantize(&encoded, bit_depth, dither))
+            .await
+        {
+            Ok(Ok(x)) => Body::from(x),
+            Ok(Err(error)) => {
+                warn!(?error, "failed to quantize PNG, sending original");
+                Body::from(original)
+            }
+            Err(error) => {
+                warn!(?error, "PNG quantization task failed, sending original");
+                Body::from(original)
+            }
+        };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, quantized)
+}
+
+/// Size a screen route is asked for in its query string
+#[derive(Deserialize)]
+struct ScreenSize {
+    width: Option<i32>,
+    height: Option<i32>,
+}
+
+/// Whether the User-Agent contains any of `kindle_user_agents`, ignoring case
+fn is_kindle_user_agent(user_agent: &str, kindle_user_agents: &[String]) -> bool {
+    let user_agent = user_agent.to_lowercase();
+
+    kindle_user_agents
+        .iter()
+        .any(|pattern| user_agent.contains(&pattern.to_lowercase()))
+}
+
+/// The URI with each of `defaults` appended to its query string, unless a
+/// parameter of that name is already there
+fn with_query_defaults(uri: &Uri, defaults: &[(&str, String)]) -> Option<Uri> {
+    let query = uri.query().unwrap_or_default();
+    let given: Vec<&str> = query
+        .split('&')
+        .filter_map(|param| param.split('=').next())
+        .collect();
+
+    let missing: Vec<String> = defaults
+        .iter()
+        .filter(|(name, _)| !given.contains(name))
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+
+    if missing.is_empty() {
+        return None;
+    }
+
+    let query = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(str::to_owned)
+        .chain(missing)
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = format!("{}?{query}", uri.path()).parse().ok();
+
+    Uri::from_parts(parts).ok()
+}
+
+/// Add the departures drawn on the board to PNG responses as a text chunk
+async fn departure_metadata(request: Request, next: Next) -> Response {
+    DEPARTURES
+        .scope(RefCell::new(None), async move {
+            let response = next.run(request).await;
+
+            let departures = DEPARTURES.with(|departures| departures.take());
+
+            let is_png = response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .is_some_and(|content_type| content_type == "image/png");
+
+            let departures = match departures {
+                Some(x) if is_png && response.status().is_success() => x,
+                _ => return response,
+            };
+
+            let (mut parts, body) = response.into_parts();
+
+            let original = match axum::body::to_bytes(body, usize::MAX).await {
+                Ok(x) => x,
+                Err(error) => {
+                    warn!(?error, "failed to buffer PNG response");
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "failed to buffer PNG")
+                        .into_response();
+                }
+            };
+
+            let body = match png_metadata::with_departures(&original, &departures) {
+                Some(x) => Body::from(x),
+                None => Body::from(original),
+            };
+
+            parts.headers.remove(header::CONTENT_LENGTH);
+
+            Response::from_parts(parts, body)
+        })
+        .await
+}
+
+/// Give responses which drew an error screen in place of the board the
+/// failure's status, so that they aren't cached as though they were the board
+async fn board_errors(request: Request, next: Next) -> Response {
+    BOARD_ERROR
+        .scope(RefCell::new(None), async move {
+            let mut response = next.run(request).await;
+
+            if let Some(failure) = BOARD_ERROR.with(|board_error| board_error.take()) {
+                failure.apply(&mut response);
+            }
+
+            response
+        })
+        .await
+}
+
+/// Re-encode PNG responses as 16-level greyscale when enabled for the
+/// requested render target
+async fn optimize_png_responses(
+    State(config): State<PngOptimizationConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let kindle_target = RenderTarget::of(request.uri()) == RenderTarget::Kindle;
+
+    let response = next.run(request).await;
+
+    let enabled = if kindle_target {
+        config.kindle
+    } else {
+        config.browser
+    };
+
+    let is_png = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "image/png");
+
+    if !enabled || !is_png {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let original = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(x) => x,
+        Err(error) => {
+            warn!(?error, "failed to buffer PNG response");
+            return (StatusCode::INTERNAL_SERVER_ERROR, "failed to buffer PNG").into_response();
+        }
+    };
+
+    let encoded = original.clone();
+    let optimized =
+        match tokio::task::spawn_blocking(move || png_optimize::optimize(&encoded)).await {
+            Ok(Ok(x)) => Body::from(x),
+            Ok(Err(error)) => {
+                warn!(?error, "failed to optimize PNG, sending original");
+                Body::from(original)
+            }
+            Err(error) => {
+                warn!(?error, "PNG optimization task failed, sending original");
+                Body::from(original)
+            }
+        };
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, optimized)
+}
+
+/// Mark image and HTML responses as cacheable until the next data refresh
+async fn cache_headers(
+    State(data_access): State<Arc<DataAccess>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+
+    let cacheable = response.status().is_success()
+        && !response.headers().contains_key(header::CACHE_CONTROL)
+        && response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| {
+                content_type.starts_with("image/") || content_type.starts_with("text/html")
+            });
+
+    if !cacheable {
+        return response;
+    }
 
-    let listener = TcpListener::bind(&"0.0.0.0:3001").await?;
+    let headers = response.headers_mut();
 
-    info!(port = 3001, "listening!");
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&format!("public, max-age={}", REFRESH_INTERVAL.as_secs())).unwrap(),
+    );
 
-    axum::serve(listener, app.into_make_service()).await?;
+    if let Some(last_refreshed) = data_access.last_refreshed() {
+        let last_modified = last_refreshed
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&last_modified).unwrap(),
+        );
+    }
 
-    Ok(())
+    response
 }
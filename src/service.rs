@@ -0,0 +1,162 @@
+use std::{
+    io,
+    os::{linux::net::SocketAddrExt, unix::net::UnixDatagram},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use eyre::{Context, Result};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::{
+    api_client::{DataAccess, REFRESH_INTERVAL},
+    config::ConfigFile,
+};
+
+/// A fetch loop that hasn't had a fully successful refresh for this long is
+/// considered stuck, and the watchdog is left to restart the process
+const UNHEALTHY_AFTER: Duration = Duration::from_secs(REFRESH_INTERVAL.as_secs() * 4);
+
+/// Send a state change to the service manager, as with `sd_notify(3)`.
+/// Returns whether there was a service manager to tell.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+
+    let socket = UnixDatagram::unbound()?;
+
+    // a leading `@` names a socket in the abstract namespace
+    match path.to_str().and_then(|path| path.strip_prefix('@')) {
+        Some(name) => {
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), Path::new(&path))?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// How often the service manager expects a watchdog ping, if it's watching
+fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    Some(Duration::from_micros(usec))
+}
+
+/// Tell the service manager once the first refresh has finished, and then
+/// ping its watchdog for as long as the fetch loop is healthy, so that a loop
+/// that has been failing for a long time gets the process restarted. The
+/// fetch loop followed is replaced whenever the config is reloaded.
+pub async fn supervise(data_access: watch::Receiver<Arc<DataAccess>>) {
+    let started = Instant::now();
+    let current = || data_access.borrow().clone();
+
+    while current().last_attempt().is_none() {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    match notify("READY=1\nSTATUS=Serving departures") {
+        Ok(true) => info!("told the service manager we're ready"),
+        Ok(false) => debug!("not started by a service manager, nothing to notify"),
+        Err(error) => warn!(?error, "failed to notify the service manager"),
+    }
+
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+
+    let mut healthy = true;
+
+    loop {
+        // pinging twice per interval leaves room for a slow tick
+        tokio::time::sleep(interval / 2).await;
+
+        let since_success = current()
+            .last_refreshed()
+            .map(|time| (Utc::now() - time).to_std().unwrap_or_default())
+            .unwrap_or_else(|| started.elapsed());
+
+        let now_healthy = since_success < UNHEALTHY_AFTER;
+
+        if now_healthy != healthy {
+            let status = if now_healthy {
+                info!("fetch loop recovered, resuming watchdog pings");
+                String::from("STATUS=Serving departures")
+            } else {
+                warn!(
+                    ?since_success,
+                    "fetch loop keeps failing, stopping watchdog pings"
+                );
+                format!(
+                    "STATUS=No successful refresh for {} minutes",
+                    since_success.as_secs() / 60
+                )
+            };
+
+            if let Err(error) = notify(&status) {
+                warn!(?error, "failed to notify the service manager");
+            }
+            healthy = now_healthy;
+        }
+
+        if healthy {
+            if let Err(error) = notify("WATCHDOG=1") {
+                warn!(?error, "failed to ping the service manager's watchdog");
+            }
+        }
+    }
+}
+
+/// Keep cache files in the service's state directory, unless the config
+/// already says where to keep them
+pub fn use_state_directory(config_file: &mut ConfigFile) {
+    if config_file.cache_dir.is_none() {
+        config_file.cache_dir = state_directory().map(|dir| dir.to_string_lossy().into_owned());
+    }
+}
+
+/// The state directory systemd created for the service, from
+/// `StateDirectory=`
+pub fn state_directory() -> Option<PathBuf> {
+    let dirs = std::env::var_os("STATE_DIRECTORY")?;
+
+    // several directories are separated by colons; the first is ours
+    let first = dirs.to_str()?.split(':').next()?;
+
+    Some(PathBuf::from(first))
+}
+
+/// A file holding the process id, removed when dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: PathBuf) -> Result<Self> {
+        std::fs::write(&path, format!("{}\n", std::process::id()))
+            .wrap_err_with(|| format!("writing pid file {}", path.display()))?;
+
+        Ok(Self { path })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            warn!(?error, path = %self.path.display(), "failed to remove pid file");
+        }
+    }
+}
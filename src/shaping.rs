@@ -0,0 +1,126 @@
+use skia_safe::{
+    shaper::{
+        run_handler::{Buffer, RunInfo},
+        RunHandler,
+    },
+    textlayout::TypefaceFontProvider,
+    Font, FontMgr, GlyphId, Point, Shaper, TextBlob,
+};
+
+use crate::error::{ConfigError, RenderError};
+
+/// Lays out text with bidi reordering and font fallback, so that right-to-left
+/// scripts and glyphs missing from the bundled font are drawn correctly.
+pub struct TextShaper {
+    shaper: Shaper,
+}
+
+pub struct ShapedText {
+    /// Glyphs positioned so that drawing the blob at `(x, y + ascent)` puts the
+    /// baseline at `y`
+    pub blob: TextBlob,
+    pub width: f32,
+    pub ascent: f32,
+}
+
+impl TextShaper {
+    /// Build a shaper which falls back to the given font files, in order, for
+    /// glyphs the primary font does not have. With no fallback fonts
+    /// configured, the system fonts are used instead.
+    pub fn new(fallback_fonts: &[String]) -> Result<Self, ConfigError> {
+        let font_mgr = FontMgr::new();
+
+        let fallback_font_mgr = if fallback_fonts.is_empty() {
+            font_mgr
+        } else {
+            let mut provider = TypefaceFontProvider::new();
+
+            for path in fallback_fonts {
+                let data = std::fs::read(path).map_err(|source| ConfigError::Read {
+                    path: path.into(),
+                    source,
+                })?;
+                let typeface = font_mgr
+                    .new_from_data(&data, None)
+                    .ok_or_else(|| ConfigError::FallbackFont { path: path.clone() })?;
+
+                provider.register_typeface(typeface, None);
+            }
+
+            provider.into()
+        };
+
+        Ok(Self {
+            shaper: Shaper::new(fallback_font_mgr),
+        })
+    }
+
+    pub fn shape(&self, text: &str, font: &Font) -> Result<ShapedText, RenderError> {
+        let left_to_right = !starts_right_to_left(text);
+
+        let mut extents = Extents::default();
+        self.shaper
+            .shape(text, font, left_to_right, f32::INFINITY, &mut extents);
+
+        let (blob, _) = self
+            .shaper
+            .shape_text_blob(text, font, left_to_right, f32::INFINITY, (0.0, 0.0))
+            .ok_or_else(|| RenderError::Shaping(text.to_owned()))?;
+
+        Ok(ShapedText {
+            blob,
+            width: extents.width,
+            ascent: extents.ascent,
+        })
+    }
+}
+
+/// Whether the first strongly-directional character of `text` belongs to a
+/// right-to-left script (Hebrew, Arabic, and their neighbours)
+fn starts_right_to_left(text: &str) -> bool {
+    for c in text.chars() {
+        match c {
+            '\u{0590}'..='\u{08FF}'
+            | '\u{FB1D}'..='\u{FDFF}'
+            | '\u{FE70}'..='\u{FEFF}'
+            | '\u{10800}'..='\u{10FFF}'
+            | '\u{1E800}'..='\u{1EFFF}' => return true,
+            c if c.is_alphabetic() => return false,
+            _ => {}
+        }
+    }
+
+    false
+}
+
+/// Run handler that only records the advance and ascent of the shaped text,
+/// matching how skia positions the runs of a shaped text blob
+#[derive(Default)]
+struct Extents {
+    width: f32,
+    ascent: f32,
+    glyphs: Vec<GlyphId>,
+    positions: Vec<Point>,
+}
+
+impl RunHandler for Extents {
+    fn begin_line(&mut self) {}
+
+    fn run_info(&mut self, info: &RunInfo) {
+        self.width += info.advance.x;
+        self.ascent = self.ascent.min(info.font.metrics().1.ascent);
+    }
+
+    fn commit_run_info(&mut self) {}
+
+    fn run_buffer(&mut self, info: &RunInfo) -> Buffer<'_> {
+        self.glyphs.resize(info.glyph_count, 0);
+        self.positions.resize(info.glyph_count, Point::default());
+
+        Buffer::new(&mut self.glyphs, &mut self.positions, None)
+    }
+
+    fn commit_run_buffer(&mut self, _info: &RunInfo) {}
+
+    fn commit_line(&mut self) {}
+}
@@ -0,0 +1,615 @@
+use std::{fmt::Write, sync::Arc, time::Instant};
+
+use axum::{
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use itertools::Itertools;
+use serde::Deserialize;
+use tracing::warn;
+use utoipa::IntoParams;
+
+use crate::{
+    api_client::{Breaker, DataAccess, SUSTAINED_BUDGET_VIOLATIONS},
+    config::{ConfigFile, SectionConfig},
+    output,
+    trmnl::Displays,
+};
+
+#[derive(Clone)]
+pub struct StatusState {
+    pub data_access: Arc<DataAccess>,
+    pub started: Instant,
+    pub displays: Arc<Displays>,
+}
+
+/// Per-agency fetch health plus process uptime and memory, so that a missing
+/// section can be diagnosed without reading the debug logs
+#[utoipa::path(
+    get,
+    path = "/status",
+    responses((status = 200, description = "Health of each agency's fetches", content_type = "text/html", body = String))
+)]
+pub async fn status_page(State(state): State<StatusState>) -> impl IntoResponse {
+    let now = Utc::now();
+
+    let mut rows = String::new();
+    for (agency, status) in state.data_access.agency_status() {
+        let mut last_error = match &status.last_error {
+            Some((time, message)) => format!("{} ago: {}", ago(now, *time), escape(message)),
+            None => String::from("-"),
+        };
+        if let Some(path) = &status.schema_error {
+            let _ = write!(
+                last_error,
+                "<br>response did not match at <code>{}</code>",
+                escape(path)
+            );
+        }
+
+        let latency = status
+            .latency
+            .map(|latency| format!("{} ms", latency.as_millis()))
+            .unwrap_or_else(|| String::from("-"));
+
+        let last_fetch = status
+            .last_fetch
+            .map(|time| format!("{} ago", ago(now, time)))
+            .unwrap_or_else(|| String::from("never"));
+
+        let breaker = match status.breaker {
+            Breaker::Closed { failures: 0 } => String::from("ok"),
+            Breaker::Closed { failures } => format!("{failures} failures"),
+            Breaker::Open { until } => format!(
+                "paused for {}",
+                duration(until.saturating_duration_since(Instant::now()).as_secs() as i64)
+            ),
+            Breaker::HalfOpen => String::from("retrying"),
+        };
+
+        let cache_age = status
+            .cache_time
+            .map(|time| ago(now, time))
+            .unwrap_or_else(|| String::from("-"));
+
+        let mut journeys = status.journeys_retained.to_string();
+        if status.journeys_dropped > 0 {
+            let _ = write!(journeys, " ({} dropped)", status.journeys_dropped);
+        }
+
+        let visits = if status.last_fetch.is_some() {
+            format!("{} of {}", status.visits_at_stops, status.visits_returned)
+        } else {
+            String::from("-")
+        };
+
+        let skipped = status
+            .journeys_skipped
+            .iter()
+            .map(|(reason, count)| format!("{count} {}", reason.name()))
+            .join(", ");
+        let skipped = if skipped.is_empty() {
+            String::from("-")
+        } else {
+            skipped
+        };
+
+        let cache_size = status
+            .cache_bytes
+            .map(|bytes| format!("{:.1} KiB", bytes as f64 / 1024.0))
+            .unwrap_or_else(|| String::from("-"));
+
+        let budget = match &status.budget_streak {
+            Some((over, fetches)) if *fetches >= SUSTAINED_BUDGET_VIOLATIONS => format!(
+                "over {} for {fetches} fetches",
+                over.iter().map(|budget| budget.name()).join(" and ")
+            ),
+            _ => String::from("-"),
+        };
+
+        let _ = write!(
+            rows,
+            "<tr><td>{}</td><td>{breaker}</td><td>{last_fetch}</td><td>{latency}</td><td>{budget}</td><td>{cache_age}</td><td>{cache_size}</td><td>{visits}</td><td>{journeys}</td><td>{skipped}</td><td>{last_error}</td></tr>",
+            escape(&agency),
+        );
+    }
+
+    let mut displays = String::new();
+    for (id, report) in state.displays.reports() {
+        let battery = report
+            .battery_voltage
+            .map(|volts| format!("{volts:.2} V"))
+            .unwrap_or_else(|| String::from("-"));
+        let rssi = report
+            .rssi
+            .map(|rssi| format!("{rssi} dBm"))
+            .unwrap_or_else(|| String::from("-"));
+
+        let _ = write!(
+            displays,
+            "<tr><td>{}</td><td>{} ago</td><td>{battery}</td><td>{rssi}</td><td>{}</td></tr>",
+            escape(&id),
+            ago(now, report.last_seen),
+            escape(report.firmware.as_deref().unwrap_or("-")),
+        );
+    }
+    if !displays.is_empty() {
+        displays = format!(
+            "<h2>Displays</h2>\n<table>\n<tr><th>ID</th><th>Last polled</th><th>Battery</th><th>Signal</th><th>Firmware</th></tr>\n{displays}</table>"
+        );
+    }
+
+    let memory = resident_memory_kb()
+        .map(|kb| format!("{:.1} MiB", kb as f64 / 1024.0))
+        .unwrap_or_else(|| String::from("unknown"));
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>transit-kindle status</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+td, th {{ border: 1px solid #888; padding: 4px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Status</h1>
+<p>Uptime: {uptime}<br>Resident memory: {memory}</p>
+<table>
+<tr><th>Agency</th><th>Fetching</th><th>Last fetch</th><th>Latency</th><th>Over budget</th><th>Cache age</th><th>Cache size</th><th>Visits at stops</th><th>Journeys</th><th>Left off board</th><th>Last error</th></tr>
+{rows}
+</table>
+<p>These are also served as <a href="metrics">Prometheus metrics</a>.</p>
+{displays}
+</body>
+</html>
+"#,
+        uptime = duration(state.started.elapsed().as_secs() as i64),
+    );
+
+    ([(header::CACHE_CONTROL, "no-store")], Html(body))
+}
+
+/// What each agency's last fetch returned and how much of it reached the
+/// board, in Prometheus' text format, for alerting on a section that's gone
+/// empty
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses((status = 200, description = "Per-agency payload statistics", content_type = "text/plain", body = String))
+)]
+pub async fn metrics(State(data_access): State<Arc<DataAccess>>) -> impl IntoResponse {
+    let statuses = data_access.agency_status();
+
+    let mut body = String::new();
+
+    gauge(
+        &mut body,
+        "transit_kindle_stop_visits",
+        "Stop visits in the last successful fetch, at any stop or at the configured ones",
+        statuses.iter().flat_map(|(agency, status)| {
+            [
+                (
+                    format!("agency=\"{agency}\",stops=\"all\""),
+                    status.visits_returned,
+                ),
+                (
+                    format!("agency=\"{agency}\",stops=\"configured\""),
+                    status.visits_at_stops,
+                ),
+            ]
+        }),
+    );
+    gauge(
+        &mut body,
+        "transit_kindle_journeys",
+        "Journeys at the configured stops kept in the cache, or dropped as departed or over the cap",
+        statuses.iter().flat_map(|(agency, status)| {
+            [
+                (format!("agency=\"{agency}\",state=\"retained\""), status.journeys_retained),
+                (format!("agency=\"{agency}\",state=\"dropped\""), status.journeys_dropped),
+            ]
+        }),
+    );
+    gauge(
+        &mut body,
+        "transit_kindle_response_bytes",
+        "Bytes of the responses to the last successful fetch",
+        statuses
+            .iter()
+            .map(|(agency, status)| (format!("agency=\"{agency}\""), status.response_bytes)),
+    );
+    counter(
+        &mut body,
+        "transit_kindle_budget_violations_total",
+        "Fetches which went over the agency's response size or latency budget",
+        statuses.iter().flat_map(|(agency, status)| {
+            status.budget_violations.iter().map(move |(budget, count)| {
+                (
+                    format!("agency=\"{agency}\",budget=\"{}\"", budget.name()),
+                    *count,
+                )
+            })
+        }),
+    );
+    gauge(
+        &mut body,
+        "transit_kindle_journeys_skipped",
+        "Cached journeys left off the board when it was last drawn",
+        statuses.iter().flat_map(|(agency, status)| {
+            status.journeys_skipped.iter().map(move |(reason, count)| {
+                (
+                    format!("agency=\"{agency}\",reason=\"{}\"", reason.name()),
+                    *count,
+                )
+            })
+        }),
+    );
+    counter(
+        &mut body,
+        "transit_kindle_render_panics_total",
+        "Renders which panicked, and were answered with an error instead",
+        [(String::new(), output::render_panics())],
+    );
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/plain; version=0.0.4"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        body,
+    )
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccuracyParams {
+    /// Minutes before departure of the predictions to report on
+    lead: Option<i64>,
+}
+
+/// How early or late each line's vehicles left compared with the prediction
+/// shown `lead` minutes before, for choosing `departure_lead_minutes`
+#[utoipa::path(
+    get,
+    path = "/accuracy",
+    params(AccuracyParams),
+    responses(
+        (status = 200, description = "Lateness of each line's departures", content_type = "text/html", body = String),
+        (status = 404, description = "accuracy_db isn't set"),
+    )
+)]
+pub async fn accuracy_page(
+    State(data_access): State<Arc<DataAccess>>,
+    Query(params): Query<AccuracyParams>,
+) -> Response {
+    let Some(accuracy) = data_access.accuracy() else {
+        return (
+            StatusCode::NOT_FOUND,
+            "prediction accuracy is not being recorded, set accuracy_db to enable it",
+        )
+            .into_response();
+    };
+
+    let lead = params.lead.unwrap_or(10);
+    let report = match tokio::task::spawn_blocking(move || accuracy.report(lead)).await {
+        Ok(Ok(report)) => report,
+        Ok(Err(e)) => {
+            warn!(error = ?e, "failed to read prediction accuracy");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Err(e) => {
+            warn!(error = ?e, "prediction accuracy task failed");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut rows = String::new();
+    for line in report {
+        let _ = write!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:+.1}</td><td>{:.1}</td><td>{:.1}</td></tr>",
+            escape(&line.agency),
+            escape(&line.line),
+            escape(&line.destination),
+            line.samples,
+            line.mean_minutes,
+            line.early_p90_minutes,
+            line.earliest_minutes,
+        );
+    }
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>transit-kindle prediction accuracy</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+td, th {{ border: 1px solid #888; padding: 4px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Prediction accuracy</h1>
+<p>Predictions made {lead} minutes before departure. Minutes are how long after
+the predicted time vehicles left, so negative numbers mean they left early.
+Change the lead time with <code>?lead=</code>. Every prediction of a day's
+departures can be downloaded from <a href="export.csv">export.csv</a>, with
+<code>?date=2024-05-01</code> for another day.</p>
+<table>
+<tr><th>Agency</th><th>Line</th><th>Destination</th><th>Predictions</th><th>Average</th><th>90% left no more than this early</th><th>Earliest</th></tr>
+{rows}
+</table>
+</body>
+</html>
+"#,
+    );
+
+    ([(header::CACHE_CONTROL, "no-store")], Html(body)).into_response()
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParams {
+    /// Day whose departures are exported, in the board's time zone, today by
+    /// default
+    date: Option<NaiveDate>,
+}
+
+/// Every recorded prediction of the vehicles which left on a day, as CSV, for
+/// looking into a line's reliability in a spreadsheet
+#[utoipa::path(
+    get,
+    path = "/export.csv",
+    params(ExportParams),
+    responses(
+        (status = 200, description = "One row per recorded prediction", content_type = "text/csv", body = String),
+        (status = 404, description = "accuracy_db isn't set"),
+    )
+)]
+pub async fn export_csv(
+    State(data_access): State<Arc<DataAccess>>,
+    Query(params): Query<ExportParams>,
+) -> Response {
+    let Some(accuracy) = data_access.accuracy() else {
+        return (
+            StatusCode::NOT_FOUND,
+            "predictions are not being recorded, set accuracy_db to enable it",
+        )
+            .into_response();
+    };
+
+    let date = params.date.unwrap_or_else(|| accuracy.today());
+
+    let csv = tokio::task::spawn_blocking(move || -> eyre::Result<Vec<u8>> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for record in accuracy.departures_on(date)? {
+            writer.serialize(record)?;
+        }
+
+        Ok(writer.into_inner()?)
+    })
+    .await;
+
+    match csv {
+        Ok(Ok(csv)) => (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    String::from("text/csv; charset=utf-8"),
+                ),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"departures-{date}.csv\""),
+                ),
+                (header::CACHE_CONTROL, String::from("no-store")),
+            ],
+            csv,
+        )
+            .into_response(),
+        Ok(Err(e)) => {
+            warn!(error = ?e, "failed to export predictions");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+        Err(e) => {
+            warn!(error = ?e, "prediction export task failed");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DirectionsState {
+    pub data_access: Arc<DataAccess>,
+    pub config_file: ConfigFile,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DirectionsParams {
+    /// Agency to list, every configured agency by default
+    agency: Option<String>,
+}
+
+/// The `DirectionRef`s present at each configured stop, with the lines and
+/// destinations going that way, for choosing each section's `direction`
+#[utoipa::path(
+    get,
+    path = "/debug/directions",
+    params(DirectionsParams),
+    responses((status = 200, description = "Directions at each configured stop", content_type = "text/html", body = String))
+)]
+pub async fn directions_page(
+    State(state): State<DirectionsState>,
+    Query(params): Query<DirectionsParams>,
+) -> impl IntoResponse {
+    let layout = &state.config_file.layout;
+    let sections: Vec<(&str, &str)> = [
+        &layout.left.sections,
+        &layout.right.sections,
+        &layout.sections,
+    ]
+    .into_iter()
+    .flatten()
+    .filter_map(|section| match section {
+        SectionConfig::AgencySection(section) => {
+            Some((section.agency.as_str(), section.direction.as_str()))
+        }
+        SectionConfig::TextSection(_) => None,
+    })
+    .collect();
+
+    let mut agencies = String::new();
+    for stop_config in &state.config_file.stops {
+        let agency = &stop_config.agency;
+        if params
+            .agency
+            .as_ref()
+            .is_some_and(|wanted| wanted != agency)
+        {
+            continue;
+        }
+
+        let _ = writeln!(agencies, "<h2>{}</h2>", escape(agency));
+
+        let Some(stops) = state.data_access.directions(agency) else {
+            agencies.push_str("<p>Not fetched yet.</p>\n");
+            continue;
+        };
+
+        let mut rows = String::new();
+        for stop in &stop_config.stops {
+            let Some(directions) = stops.get(stop).filter(|directions| !directions.is_empty())
+            else {
+                let _ = write!(
+                    rows,
+                    "<tr><td>{}</td><td colspan=\"4\">no upcoming departures</td></tr>",
+                    escape(stop),
+                );
+                continue;
+            };
+
+            for (direction, heading) in directions {
+                let shown = if sections.contains(&(agency.as_str(), direction.as_str())) {
+                    "yes"
+                } else {
+                    "no"
+                };
+
+                let _ = write!(
+                    rows,
+                    "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{shown}</td></tr>",
+                    escape(stop),
+                    escape(direction),
+                    escape(&heading.lines.iter().join(", ")),
+                    escape(&heading.destinations.iter().join(", ")),
+                );
+            }
+        }
+
+        let _ = write!(
+            agencies,
+            "<table>\n<tr><th>Stop</th><th>Direction</th><th>Lines</th><th>Destinations</th><th>In a section</th></tr>\n{rows}</table>\n"
+        );
+    }
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>transit-kindle directions</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+td, th {{ border: 1px solid #888; padding: 4px 8px; text-align: left; }}
+</style>
+</head>
+<body>
+<h1>Directions</h1>
+<p>Each section shows the lines of one agency going in one direction. Put a direction below in a section's <code>direction</code> to show the lines going that way.</p>
+{agencies}
+</body>
+</html>
+"#
+    );
+
+    ([(header::CACHE_CONTROL, "no-store")], Html(body))
+}
+
+/// Write a Prometheus gauge with a sample for each set of labels
+fn gauge(
+    body: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, usize)>,
+) {
+    metric(body, name, "gauge", help, samples)
+}
+
+fn counter(
+    body: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, usize)>,
+) {
+    metric(body, name, "counter", help, samples)
+}
+
+fn metric(
+    body: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, usize)>,
+) {
+    let _ = writeln!(body, "# HELP {name} {help}");
+    let _ = writeln!(body, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(body, "{name}{{{labels}}} {value}");
+    }
+}
+
+fn ago(now: DateTime<Utc>, time: DateTime<Utc>) -> String {
+    duration((now - time).num_seconds())
+}
+
+fn duration(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Resident set size of this process, as reported by Linux
+fn resident_memory_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
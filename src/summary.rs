@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use eyre::Result;
+use itertools::Itertools;
+use minijinja::{context, Environment};
+use serde::Serialize;
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{
+    error::{Failure, RenderError},
+    handler::TransitHandler,
+    layout::{Agency, Layout, Line, Row},
+};
+
+/// Sentence a section is read out as without a `summary` of its own, e.g.
+/// "Next N toward Ocean Beach in 4 and 12 minutes, and J toward Balboa Park
+/// in 6 minutes."
+const DEFAULT_TEMPLATE: &str = "{% for line in lines %}\
+    {% if loop.first %}Next {% elif loop.last %}, and {% else %}, {% endif %}\
+    {{ line.id }} toward {{ line.destination }} in {{ line.minutes }}\
+    {% endfor %}.";
+
+/// The board as a sentence per section, for voice assistants to read out
+#[derive(Serialize, ToSchema)]
+pub struct Summary {
+    pub sections: Vec<SectionSummary>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SectionSummary {
+    /// Heading the section is under on the board, if any
+    pub heading: Option<String>,
+    /// The section's departures as a sentence
+    #[schema(example = "Next N toward Ocean Beach in 4 and 12 minutes.")]
+    pub text: String,
+}
+
+/// A line as the summary templates are given it
+#[derive(Serialize)]
+struct SpokenLine<'a> {
+    id: &'a str,
+    destination: &'a str,
+    /// Minutes until each departure, e.g. `4 and 12 minutes`
+    minutes: String,
+    /// The same minutes as numbers
+    times: Vec<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/summary.json",
+    params(crate::overrides::LayoutOverrides),
+    responses(
+        (status = 200, description = "A sentence describing each section's departures, for voice assistants", body = Summary),
+        (status = 500, description = "The configuration is invalid"),
+        (status = 503, description = "The transit API couldn't be reached"),
+    )
+)]
+pub(crate) async fn summary_json(State(handler): State<Arc<TransitHandler>>) -> Response {
+    let summary = match handler.layout().await {
+        Ok(layout) => summarize(&layout).map_err(eyre::Report::new),
+        Err(error) => Err(error),
+    };
+
+    match summary {
+        Ok(summary) => Json(summary).into_response(),
+        Err(error) => {
+            warn!(?error, "failed to summarize the board");
+
+            Failure::of(&error).response(&error)
+        }
+    }
+}
+
+/// Check that a section's summary template parses
+pub fn check_template(template: &str) -> Result<(), minijinja::Error> {
+    Environment::new().template_from_str(template).map(|_| ())
+}
+
+/// A sentence for each agency section on the board, in the order they're
+/// drawn, under the heading above them. A section's `summary` template is
+/// given its `lines`, each with its `id`, `destination`, `minutes` as spoken,
+/// e.g. `4 and 12 minutes`, and `times` as numbers, along with the section's
+/// `heading` and `stop`. Cancelled departures are left out.
+pub fn summarize(layout: &Layout) -> Result<Summary, RenderError> {
+    let env = Environment::new();
+    let mut heading = None;
+    let mut sections = Vec::new();
+
+    for row in layout.left.rows.iter().chain(&layout.right.rows) {
+        match row {
+            Row::Text(text) => heading = Some(text.text.clone()),
+            Row::Agency(agency) => sections.push(SectionSummary {
+                heading: heading.clone(),
+                text: sentence(&env, agency, heading.as_deref())?,
+            }),
+        }
+    }
+
+    Ok(Summary { sections })
+}
+
+fn sentence(
+    env: &Environment,
+    agency: &Agency,
+    heading: Option<&str>,
+) -> Result<String, RenderError> {
+    if let Some(notice) = &agency.notice {
+        return Ok(format!("{notice}."));
+    }
+
+    let lines = agency.lines.iter().filter_map(spoken).collect_vec();
+    if lines.is_empty() {
+        return Ok("No departures.".to_owned());
+    }
+
+    let template = agency.summary.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+    let sentence = env
+        .render_str(template, context! { lines, heading, stop => agency.stop })
+        .map_err(RenderError::Summary)?;
+
+    Ok(sentence.trim().to_owned())
+}
+
+/// A line's departures as they're read out, if any haven't been cancelled
+fn spoken(line: &Line) -> Option<SpokenLine<'_>> {
+    let times = line
+        .departures
+        .iter()
+        .filter(|departure| !departure.cancelled)
+        .map(|departure| departure.minutes)
+        .collect_vec();
+
+    let minutes = match times.as_slice() {
+        [] => return None,
+        [1] => "1 minute".to_owned(),
+        [only] => format!("{only} minutes"),
+        [first, last] => format!("{first} and {last} minutes"),
+        [rest @ .., last] => format!("{}, and {last} minutes", rest.iter().join(", ")),
+    };
+
+    Some(SpokenLine {
+        id: &line.id,
+        destination: &line.destination,
+        minutes,
+        times,
+    })
+}
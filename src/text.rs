@@ -0,0 +1,238 @@
+use chrono_tz::Tz;
+use itertools::{EitherOrBoth, Itertools};
+
+use crate::layout::{Agency, Layout, Line, Row};
+
+/// Width each column of the text board is padded to, in characters
+const COLUMN_WIDTH: usize = 56;
+
+/// Width line ids are padded to
+const ID_WIDTH: usize = 5;
+
+/// Fewest characters of a destination kept when its departures are long
+const MIN_DESTINATION_WIDTH: usize = 12;
+
+/// Drawn between the columns
+const GUTTER: &str = " | ";
+
+/// The board as plain text, with the right column beside the left. Each line
+/// gets a row of its id, destination, and departure times, e.g.
+/// `N     Ocean Beach            3, 12 min`, under its section's stop.
+pub fn board(layout: &Layout) -> String {
+    let [left, right] = columns(layout);
+
+    let mut text = String::new();
+    for rows in left.iter().zip_longest(&right) {
+        let row = match rows {
+            EitherOrBoth::Both(left, right) => format!("{left:COLUMN_WIDTH$}{GUTTER}{right}"),
+            EitherOrBoth::Left(left) => left.clone(),
+            EitherOrBoth::Right(right) => format!("{:COLUMN_WIDTH$}{GUTTER}{right}", ""),
+        };
+
+        text.push_str(row.trim_end());
+        text.push('\n');
+    }
+
+    for warning in warnings(layout) {
+        text.push('\n');
+        text.push_str(&warning);
+        text.push('\n');
+    }
+
+    text
+}
+
+/// The board as a compact summary for scripts and voice assistants: each
+/// heading, followed by a line for each departure line with its destination
+/// and minutes until it leaves, e.g. `N Ocean Beach: 3, 12 min`. Cancelled
+/// departures are left out.
+pub fn summary(layout: &Layout) -> String {
+    let mut text = String::new();
+
+    for row in layout.left.rows.iter().chain(&layout.right.rows) {
+        match row {
+            Row::Text(heading) => {
+                if !text.is_empty() {
+                    text.push('\n');
+                }
+                text.push_str(&heading.text);
+                text.push('\n');
+            }
+            Row::Agency(Agency {
+                notice: Some(notice),
+                ..
+            }) => {
+                text.push_str(notice);
+                text.push('\n');
+            }
+            Row::Agency(agency) => {
+                for line in &agency.lines {
+                    text.push_str(&format!(
+                        "{} {}: {}\n",
+                        line.id,
+                        line.destination,
+                        summary_minutes(line)
+                    ));
+                }
+            }
+        }
+    }
+
+    for warning in warnings(layout) {
+        text.push('\n');
+        text.push_str(&warning);
+        text.push('\n');
+    }
+
+    text
+}
+
+/// Minutes until each of a line's departures that hasn't been cancelled,
+/// e.g. `3, 12 min`
+fn summary_minutes(line: &Line) -> String {
+    let minutes = line
+        .departures
+        .iter()
+        .filter(|departure| !departure.cancelled)
+        .map(|departure| departure.minutes)
+        .join(", ");
+
+    if minutes.is_empty() {
+        "cancelled".to_owned()
+    } else {
+        format!("{minutes} min")
+    }
+}
+
+/// The lines of text in each column of the board
+pub fn columns(layout: &Layout) -> [Vec<String>; 2] {
+    column_rows(layout).map(|rows| column(&rows, layout.timezone))
+}
+
+/// Agencies the board warns about in its banner and footer, e.g.
+/// `Stale: SF`
+pub fn warnings(layout: &Layout) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    if !layout.stale_agencies.is_empty() {
+        warnings.push(format!(
+            "Stale: {}",
+            layout.stale_agencies.iter().join(", ")
+        ));
+    }
+    if !layout.failed_agencies.is_empty() {
+        warnings.push(format!(
+            "Failed: {}",
+            layout.failed_agencies.iter().join(", ")
+        ));
+    }
+
+    warnings
+}
+
+/// The rows of each column. A balanced layout is split where the taller
+/// column has the fewest lines of text, as the board splits it by height.
+fn column_rows(layout: &Layout) -> [Vec<&Row>; 2] {
+    if !layout.balance {
+        return [
+            layout.left.rows.iter().collect(),
+            layout.right.rows.iter().collect(),
+        ];
+    }
+
+    let rows = &layout.left.rows;
+    let heights = rows
+        .iter()
+        .map(|row| row_lines(row, layout.timezone).len())
+        .collect_vec();
+
+    let split = (0..=rows.len())
+        .min_by_key(|split| {
+            let left = heights[..*split].iter().sum::<usize>();
+            let right = heights[*split..].iter().sum::<usize>();
+            left.max(right)
+        })
+        .unwrap_or(0);
+
+    [
+        rows[..split].iter().collect(),
+        rows[split..].iter().collect(),
+    ]
+}
+
+/// A column's rows, with a blank line before each but those right under a
+/// heading
+fn column(rows: &[&Row], timezone: Tz) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for (i, row) in rows.iter().enumerate() {
+        if i > 0 && !matches!(rows[i - 1], Row::Text(_)) {
+            lines.push(String::new());
+        }
+        lines.extend(row_lines(row, timezone));
+    }
+
+    lines
+}
+
+fn row_lines(row: &Row, timezone: Tz) -> Vec<String> {
+    match row {
+        Row::Text(text) => vec![
+            text.text.clone(),
+            "=".repeat(text.text.chars().count().min(COLUMN_WIDTH)),
+        ],
+        Row::Agency(agency) => agency_lines(agency, timezone),
+    }
+}
+
+fn agency_lines(agency: &Agency, timezone: Tz) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    if let Some(stop) = &agency.stop {
+        lines.push(truncate(stop, COLUMN_WIDTH));
+    }
+
+    if let Some(notice) = &agency.notice {
+        lines.push(truncate(notice, COLUMN_WIDTH));
+        return lines;
+    }
+
+    if agency.lines.is_empty() {
+        lines.push("No departures".to_owned());
+    }
+
+    for line in &agency.lines {
+        let departures = line.departure_text(timezone);
+        let width = COLUMN_WIDTH
+            .saturating_sub(ID_WIDTH + 2 + departures.chars().count())
+            .max(MIN_DESTINATION_WIDTH);
+
+        lines.push(format!(
+            "{:ID_WIDTH$} {:width$} {departures}",
+            truncate(&line.id, ID_WIDTH),
+            truncate(&line.destination, width),
+        ));
+
+        if let Some(secondary) = &line.secondary_destination {
+            lines.push(format!(
+                "{:ID_WIDTH$} {}",
+                "",
+                truncate(secondary, COLUMN_WIDTH - ID_WIDTH - 1)
+            ));
+        }
+    }
+
+    lines
+}
+
+/// Cut text down to `width` characters, ending in an ellipsis if it's cut
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_owned();
+    }
+
+    text.chars()
+        .take(width.saturating_sub(1))
+        .chain(['…'])
+        .collect()
+}
@@ -0,0 +1,92 @@
+use serde::Deserialize;
+
+/// One predicted arrival from the TfL Unified API's
+/// `StopPoint/{ids}/Arrivals`, which answers with a flat list of them rather
+/// than SIRI
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Arrival {
+    /// NaPTAN code of the stop, which is what stops are configured by
+    pub naptan_id: String,
+    pub line_id: Option<String>,
+    pub line_name: Option<String>,
+    /// e.g. `Westbound - Platform 1`, which sections pick by as their
+    /// `direction`. Buses give `null`, or a stop letter.
+    pub platform_name: Option<String>,
+    /// `inbound` or `outbound`, left blank by some Underground lines
+    pub direction: Option<String>,
+    pub destination_name: Option<String>,
+    /// Where the vehicle is heading when `destination_name` isn't given, such
+    /// as `Check Front of Train`
+    pub towards: Option<String>,
+    pub expected_arrival: Option<String>,
+    pub vehicle_id: Option<String>,
+}
+
+impl Arrival {
+    /// The platform, which is what tells the two ways of an Underground
+    /// line apart, falling back to the direction for buses
+    pub(crate) fn direction(&self) -> Option<String> {
+        self.platform_name
+            .clone()
+            .filter(|platform| !platform.is_empty() && platform != "null")
+            .or_else(|| {
+                self.direction
+                    .clone()
+                    .filter(|direction| !direction.is_empty())
+            })
+    }
+
+    pub(crate) fn destination(&self) -> Option<String> {
+        self.destination_name
+            .clone()
+            .or_else(|| self.towards.clone())
+            .filter(|destination| !destination.is_empty())
+    }
+}
+
+/// URL of the arrivals at every stop in one request, as the Unified API
+/// takes a comma-separated list of stops
+pub(crate) fn arrivals_url(base: &str, stops: &[String], app_key: Option<&str>) -> String {
+    let mut url = format!(
+        "{}/StopPoint/{}/Arrivals",
+        base.trim_end_matches('/'),
+        stops.join(",")
+    );
+    if let Some(app_key) = app_key {
+        url.push_str(&format!("?app_key={app_key}"));
+    }
+
+    url
+}
+
+/// Colour of a line in TfL's own livery, by its id or name as the arrivals
+/// give it, for the shade of its bubble
+pub fn line_color(line: &str) -> Option<[u8; 3]> {
+    let color = match line.to_lowercase().replace(" & ", "-").as_str() {
+        "bakerloo" => [0xb3, 0x63, 0x05],
+        "central" => [0xe3, 0x20, 0x17],
+        "circle" => [0xff, 0xd3, 0x00],
+        "district" => [0x00, 0x78, 0x2a],
+        "elizabeth" | "elizabeth line" => [0x69, 0x50, 0xa1],
+        "hammersmith-city" => [0xf3, 0xa9, 0xbb],
+        "jubilee" => [0xa0, 0xa5, 0xa9],
+        "metropolitan" => [0x9b, 0x00, 0x56],
+        "northern" => [0x00, 0x00, 0x00],
+        "piccadilly" => [0x00, 0x36, 0x88],
+        "victoria" => [0x00, 0x98, 0xd4],
+        "waterloo-city" => [0x95, 0xcd, 0xba],
+        "dlr" => [0x00, 0xa4, 0xa7],
+        "london-overground" | "london overground" => [0xee, 0x7c, 0x0e],
+        "liberty" => [0x61, 0x68, 0x6b],
+        "lioness" => [0xf1, 0xb4, 0x1c],
+        "mildmay" => [0x43, 0x7e, 0xc1],
+        "suffragette" => [0x39, 0xb9, 0x7a],
+        "weaver" => [0x97, 0x2d, 0x5d],
+        "windrush" => [0xef, 0x40, 0x2d],
+        "tram" => [0x5f, 0xb7, 0x0d],
+        _ => return None,
+    };
+
+    Some(color)
+}
@@ -0,0 +1,475 @@
+use ab_glyph::{point, Font, FontRef, Glyph, PxScale, ScaleFont};
+use chrono::Utc;
+use chrono_tz::Tz;
+use tiny_skia::{
+    Color, FillRule, GradientStop, LineCap, LineJoin, LinearGradient, Paint, Path, PathBuilder,
+    Pixmap, Rect, SpreadMode, Stroke, Transform,
+};
+
+use crate::{
+    config::{FooterConfig, GrayRamp, Profile},
+    error::RenderError,
+    icons::{self, Icon},
+    layout::Layout,
+    layout_engine::{Bounds, FontKind, LayoutEngine, Measure, Metrics, Primitive, TextExtent},
+    output::OutputParams,
+    overrides::LayoutOverrides,
+    render::SharedRenderData,
+};
+
+/// Draws the primitives laid out by the [`LayoutEngine`] with tiny-skia and
+/// ab_glyph, which are pure Rust, for hosts that can't build skia. Text is
+/// drawn glyph by glyph from the bundled font, without shaping.
+pub struct TinyPainter<'a> {
+    metrics: Metrics,
+    footer: &'a FooterConfig,
+    gray_ramp: &'a GrayRamp,
+    timezone: Tz,
+    font: FontRef<'static>,
+    pixmap: Pixmap,
+}
+
+impl<'a> TinyPainter<'a> {
+    fn new(
+        shared: &'a SharedRenderData,
+        profile: Profile,
+        params: OutputParams,
+    ) -> Result<Self, RenderError> {
+        Self::with_metrics(
+            Metrics::for_profile(profile),
+            &shared.footer,
+            &shared.gray_ramp,
+            shared.timezone,
+            params,
+        )
+    }
+
+    fn with_metrics(
+        metrics: Metrics,
+        footer: &'a FooterConfig,
+        gray_ramp: &'a GrayRamp,
+        timezone: Tz,
+        params: OutputParams,
+    ) -> Result<Self, RenderError> {
+        let font = FontRef::try_from_slice(include_bytes!("../media/OpenSansEmoji.ttf")).unwrap();
+
+        let mut pixmap = Pixmap::new(params.width.max(1) as u32, params.height.max(1) as u32)
+            .ok_or(RenderError::Bitmap)?;
+        pixmap.fill(Color::WHITE);
+
+        Ok(Self {
+            metrics,
+            footer,
+            gray_ramp,
+            timezone,
+            font,
+            pixmap,
+        })
+    }
+
+    fn engine(&self) -> LayoutEngine<'_, Self> {
+        LayoutEngine::new(
+            &self.metrics,
+            self.footer,
+            self,
+            (self.pixmap.width() as f32, self.pixmap.height() as f32),
+            Utc::now(),
+        )
+        .gray_ramp(self.gray_ramp)
+        .timezone(self.timezone)
+    }
+
+    /// Scale which sets the font's em square at the size skia would use
+    fn scale(&self, font: FontKind) -> PxScale {
+        let size = self.metrics.font_size(font);
+        let units_per_em = self.font.units_per_em().unwrap_or(1000.0);
+
+        PxScale::from(size * self.font.height_unscaled() / units_per_em)
+    }
+
+    /// Glyphs of `text` positioned along a baseline at `(x, y)`
+    fn glyphs(&self, text: &str, font: FontKind, (x, y): (f32, f32)) -> (Vec<Glyph>, f32) {
+        let scale = self.scale(font);
+        let scaled = self.font.as_scaled(scale);
+
+        let mut glyphs = Vec::new();
+        let mut caret = x;
+        let mut previous = None;
+
+        for c in text.chars() {
+            let id = self.font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret += scaled.kern(previous, id);
+            }
+
+            glyphs.push(id.with_scale_and_position(scale, point(caret, y)));
+            caret += scaled.h_advance(id);
+            previous = Some(id);
+        }
+
+        (glyphs, caret - x)
+    }
+
+    fn paint(&mut self, primitives: &[Primitive]) {
+        for primitive in primitives {
+            match primitive {
+                Primitive::Rect { bounds, shade } => {
+                    if let Some(rect) = rect(*bounds) {
+                        self.pixmap.fill_rect(
+                            rect,
+                            &shade_paint(*shade, false),
+                            Transform::identity(),
+                            None,
+                        );
+                    }
+                }
+                Primitive::RoundRect {
+                    bounds,
+                    radius,
+                    shade,
+                } => {
+                    if let Some(path) = round_rect(*bounds, *radius) {
+                        self.fill(&path, *shade);
+                    }
+                }
+                Primitive::Outline {
+                    bounds,
+                    width,
+                    shade,
+                } => {
+                    if let Some(rect) = rect(*bounds) {
+                        let path = PathBuilder::from_rect(rect);
+                        self.stroke(&path, *shade, plain_stroke(*width));
+                    }
+                }
+                Primitive::Line {
+                    from,
+                    to,
+                    width,
+                    shade,
+                } => {
+                    if let Some(path) = polyline(&[*from, *to], false) {
+                        self.stroke(&path, *shade, plain_stroke(*width));
+                    }
+                }
+                Primitive::Text {
+                    text,
+                    font,
+                    x,
+                    y,
+                    shade,
+                } => self.paint_text(text, *font, (*x, *y), *shade, false),
+                Primitive::VerticalText {
+                    text,
+                    font,
+                    x,
+                    y,
+                    shade,
+                } => self.paint_text(text, *font, (*x, *y), *shade, true),
+                Primitive::Fade { bounds, shade } => self.paint_fade(*bounds, *shade),
+                Primitive::Icon {
+                    icon,
+                    x,
+                    y,
+                    size,
+                    shade,
+                } => self.paint_icon(*icon, (*x, *y), *size, *shade),
+                Primitive::Arrow {
+                    x,
+                    y,
+                    size,
+                    bearing,
+                    shade,
+                } => {
+                    let points = icons::arrow_points((*x, *y), *size, *bearing);
+                    if let Some(path) = polyline(&points, true) {
+                        self.fill(&path, *shade);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill(&mut self, path: &Path, shade: f32) {
+        self.pixmap.fill_path(
+            path,
+            &shade_paint(shade, true),
+            FillRule::Winding,
+            Transform::identity(),
+            None,
+        );
+    }
+
+    fn stroke(&mut self, path: &Path, shade: f32, stroke: Stroke) {
+        self.pixmap.stroke_path(
+            path,
+            &shade_paint(shade, true),
+            &stroke,
+            Transform::identity(),
+            None,
+        );
+    }
+
+    fn paint_icon(&mut self, icon: Icon, at: (f32, f32), size: f32, shade: f32) {
+        let stroke = Stroke {
+            width: Icon::stroke_width(size),
+            line_cap: LineCap::Round,
+            line_join: LineJoin::Round,
+            ..Stroke::default()
+        };
+
+        for part in icon.strokes(at, size) {
+            let path = match part {
+                icons::Stroke::Path { points, closed } => polyline(&points, closed),
+                icons::Stroke::RoundRect { bounds, radius } => round_rect(bounds, radius),
+                icons::Stroke::Circle { center, radius } => {
+                    PathBuilder::from_circle(center.0, center.1, radius)
+                }
+            };
+
+            if let Some(path) = path {
+                self.stroke(&path, shade, stroke.clone());
+            }
+        }
+    }
+
+    fn paint_fade(&mut self, bounds: Bounds, shade: f32) {
+        let shade = shade.clamp(0.0, 1.0);
+        let middle = bounds.top + (0.5 * bounds.height());
+
+        let shader = LinearGradient::new(
+            tiny_skia::Point::from_xy(bounds.right, middle),
+            tiny_skia::Point::from_xy(bounds.left, middle),
+            vec![
+                GradientStop::new(0.0, grey(shade, 1.0)),
+                GradientStop::new(1.0, grey(shade, 0.0)),
+            ],
+            SpreadMode::Repeat,
+            Transform::identity(),
+        );
+
+        if let (Some(shader), Some(rect)) = (shader, rect(bounds)) {
+            let paint = Paint {
+                shader,
+                ..Paint::default()
+            };
+            self.pixmap
+                .fill_rect(rect, &paint, Transform::identity(), None);
+        }
+    }
+
+    /// Blend each glyph's coverage into the pixmap. Bold text is drawn twice,
+    /// slightly offset, in place of skia's emboldening.
+    /// Draw text with the left end of its baseline at `(x, y)`, turned a
+    /// quarter turn counter-clockwise about that point if `vertical`
+    fn paint_text(
+        &mut self,
+        text: &str,
+        font: FontKind,
+        (x, y): (f32, f32),
+        shade: f32,
+        vertical: bool,
+    ) {
+        let offsets: &[f32] = if font == FontKind::Bold {
+            &[0.0, 0.5]
+        } else {
+            &[0.0]
+        };
+
+        let ink = shade.clamp(0.0, 1.0) * 255.0;
+        let width = self.pixmap.width() as i64;
+        let height = self.pixmap.height() as i64;
+
+        for offset in offsets {
+            let (glyphs, _) = self.glyphs(text, font, (x + offset, y));
+
+            for glyph in glyphs {
+                let Some(outlined) = self.font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outlined.px_bounds();
+                let pixels = self.pixmap.pixels_mut();
+
+                outlined.draw(|gx, gy, coverage| {
+                    let mut px = bounds.min.x as i64 + gx as i64;
+                    let mut py = bounds.min.y as i64 + gy as i64;
+                    if vertical {
+                        // turn about the start of the baseline, so that the
+                        // text runs up and its glyphs stand to the left
+                        (px, py) = (x as i64 + (py - y as i64), y as i64 - (px - x as i64));
+                    }
+                    if !(0..width).contains(&px) || !(0..height).contains(&py) {
+                        return;
+                    }
+
+                    let pixel = &mut pixels[(py * width + px) as usize];
+                    let coverage = coverage.clamp(0.0, 1.0);
+                    let level =
+                        (pixel.red() as f32 * (1.0 - coverage) + ink * coverage).round() as u8;
+
+                    if let Some(blended) =
+                        tiny_skia::PremultipliedColorU8::from_rgba(level, level, level, 255)
+                    {
+                        *pixel = blended;
+                    }
+                });
+            }
+        }
+    }
+
+    /// Encode as an 8-bit greyscale PNG, like the skia painter's output
+    fn encode_png(&self) -> Result<Vec<u8>, RenderError> {
+        let grey = self
+            .pixmap
+            .pixels()
+            .iter()
+            .map(|pixel| pixel.demultiply().red())
+            .collect::<Vec<_>>();
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, self.pixmap.width(), self.pixmap.height());
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(|_| RenderError::Encode)?;
+        writer
+            .write_image_data(&grey)
+            .map_err(|_| RenderError::Encode)?;
+        writer.finish().map_err(|_| RenderError::Encode)?;
+
+        Ok(data)
+    }
+}
+
+impl Measure for TinyPainter<'_> {
+    fn measure(&self, text: &str, font: FontKind) -> Result<TextExtent, RenderError> {
+        let (glyphs, width) = self.glyphs(text, font, (0.0, 0.0));
+
+        let ink_top = glyphs
+            .into_iter()
+            .filter_map(|glyph| self.font.outline_glyph(glyph))
+            .map(|outlined| outlined.px_bounds().min.y)
+            .fold(0.0, f32::min);
+
+        Ok(TextExtent {
+            width,
+            ascent: -self.font.as_scaled(self.scale(font)).ascent(),
+            ink_top,
+        })
+    }
+}
+
+fn grey(shade: f32, alpha: f32) -> Color {
+    Color::from_rgba(shade, shade, shade, alpha).unwrap_or(Color::BLACK)
+}
+
+fn shade_paint(shade: f32, anti_alias: bool) -> Paint<'static> {
+    let mut paint = Paint {
+        anti_alias,
+        ..Paint::default()
+    };
+    paint.set_color(grey(shade.clamp(0.0, 1.0), 1.0));
+
+    paint
+}
+
+fn plain_stroke(width: f32) -> Stroke {
+    Stroke {
+        width,
+        ..Stroke::default()
+    }
+}
+
+fn rect(bounds: Bounds) -> Option<Rect> {
+    Rect::from_ltrb(bounds.left, bounds.top, bounds.right, bounds.bottom)
+}
+
+fn polyline(points: &[(f32, f32)], closed: bool) -> Option<Path> {
+    let (first, rest) = points.split_first()?;
+
+    let mut path = PathBuilder::new();
+    path.move_to(first.0, first.1);
+    for (x, y) in rest {
+        path.line_to(*x, *y);
+    }
+    if closed {
+        path.close();
+    }
+
+    path.finish()
+}
+
+/// A rectangle with its corners rounded to quarter circles, approximated with
+/// cubic curves as skia does
+fn round_rect(bounds: Bounds, radius: f32) -> Option<Path> {
+    let Bounds {
+        left,
+        top,
+        right,
+        bottom,
+    } = bounds;
+    let r = radius.min(bounds.width() / 2.0).min(bounds.height() / 2.0);
+    // distance of the control points from the corners' ends
+    let k = r * (1.0 - 0.552_284_8);
+
+    let mut path = PathBuilder::new();
+    path.move_to(left + r, top);
+    path.line_to(right - r, top);
+    path.cubic_to(right - k, top, right, top + k, right, top + r);
+    path.line_to(right, bottom - r);
+    path.cubic_to(right, bottom - k, right - k, bottom, right - r, bottom);
+    path.line_to(left + r, bottom);
+    path.cubic_to(left + k, bottom, left, bottom - k, left, bottom - r);
+    path.line_to(left, top + r);
+    path.cubic_to(left, top + k, left + k, top, left + r, top);
+    path.close();
+
+    path.finish()
+}
+
+/// Draw the board as a PNG with the pure Rust painter
+pub fn render_png(
+    shared: &SharedRenderData,
+    layout: &Layout,
+    params: OutputParams,
+) -> Result<Vec<u8>, RenderError> {
+    let mut painter = TinyPainter::new(shared, layout.profile, params)?;
+    let primitives = painter
+        .engine()
+        .debug(LayoutOverrides::debug())
+        .theme(shared.theme)
+        .bubble_contrast(shared.bubble_contrast)
+        .board(layout)?;
+    painter.paint(&primitives);
+
+    painter.encode_png()
+}
+
+/// Draw the error screen shown in place of the board as a PNG with the pure
+/// Rust painter
+pub fn render_error_png(
+    shared: &SharedRenderData,
+    headline: &str,
+    error: &eyre::Report,
+    params: OutputParams,
+) -> Result<Vec<u8>, RenderError> {
+    let mut painter = TinyPainter::new(shared, shared.profile, params)?;
+    let primitives = painter.engine().error_screen(headline, error)?;
+    painter.paint(&primitives);
+
+    painter.encode_png()
+}
+
+/// Draw a labelled swatch of each grey a panel with `bit_depth` bits per pixel
+/// shows as a PNG with the pure Rust painter
+pub fn render_calibration_png(
+    shared: &SharedRenderData,
+    bit_depth: u8,
+    params: OutputParams,
+) -> Result<Vec<u8>, RenderError> {
+    let mut painter = TinyPainter::new(shared, shared.profile, params)?;
+    let primitives = painter.engine().calibration(bit_depth)?;
+    painter.paint(&primitives);
+
+    painter.encode_png()
+}
@@ -0,0 +1,101 @@
+use eyre::{Context, Result};
+use reqwest::Url;
+use serde::Deserialize;
+
+/// Body of the Transitous (MOTIS) `stoptimes` endpoint
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Response {
+    stop_times: Vec<StopTime>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StopTime {
+    place: Place,
+    headsign: Option<String>,
+    route_short_name: Option<String>,
+    trip_id: Option<String>,
+    #[serde(default)]
+    cancelled: bool,
+    #[serde(default)]
+    trip_cancelled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Place {
+    departure: Option<String>,
+    scheduled_departure: Option<String>,
+    track: Option<String>,
+}
+
+/// One of the stops matching a name, from the `geocode` endpoint
+#[derive(Deserialize)]
+pub(crate) struct Match {
+    #[serde(rename = "type")]
+    kind: String,
+    pub id: String,
+}
+
+/// The first stop matching a name, which is the one MOTIS ranks best
+pub(crate) fn best_stop(matches: Vec<Match>) -> Option<String> {
+    matches
+        .into_iter()
+        .find(|candidate| candidate.kind == "STOP")
+        .map(|stop| stop.id)
+}
+
+/// A departure from one stop, with its times as RFC 3339
+pub(crate) struct Departure {
+    /// The stop's name as configured, rather than the id it was resolved to
+    pub stop: String,
+    pub line: Option<String>,
+    pub headsign: Option<String>,
+    pub expected: Option<String>,
+    pub scheduled: Option<String>,
+    pub cancelled: bool,
+    pub track: Option<String>,
+    pub trip_id: Option<String>,
+}
+
+impl Response {
+    pub(crate) fn departures(self, stop: &str) -> Vec<Departure> {
+        self.stop_times
+            .into_iter()
+            .map(|stop_time| Departure {
+                stop: stop.to_owned(),
+                line: stop_time.route_short_name.filter(|line| !line.is_empty()),
+                headsign: stop_time.headsign.filter(|headsign| !headsign.is_empty()),
+                expected: stop_time
+                    .place
+                    .departure
+                    .or(stop_time.place.scheduled_departure.clone()),
+                scheduled: stop_time.place.scheduled_departure,
+                cancelled: stop_time.cancelled || stop_time.trip_cancelled,
+                track: stop_time.place.track.filter(|track| !track.is_empty()),
+                trip_id: stop_time.trip_id,
+            })
+            .collect()
+    }
+}
+
+/// URL of the stops whose name matches `name`
+pub(crate) fn geocode_url(base: &str, name: &str) -> Result<Url> {
+    endpoint(base, "geocode", [("text", name), ("type", "STOP")])
+}
+
+/// URL of the next departures from the stop with this id
+pub(crate) fn stoptimes_url(base: &str, stop_id: &str) -> Result<Url> {
+    endpoint(base, "stoptimes", [("stopId", stop_id), ("n", "30")])
+}
+
+fn endpoint<'a>(
+    base: &str,
+    endpoint: &str,
+    params: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<Url> {
+    let url = format!("{}/api/v1/{endpoint}", base.trim_end_matches('/'));
+
+    Url::parse_with_params(&url, params).wrap_err_with(|| format!("invalid source URL {base}"))
+}
@@ -0,0 +1,243 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    api_client::{DataAccess, REFRESH_INTERVAL},
+    config::TrmnlConfig,
+};
+
+/// Shortest time a panel is told to sleep for, so that one which polls just
+/// before a refresh doesn't wake again straight away
+const MIN_REFRESH_SECS: i64 = 60;
+
+/// Extra time given to a refresh to finish before panels wake to fetch it
+const REFRESH_SLACK_SECS: i64 = 15;
+
+/// Most panels whose reports are kept, so that a client making up `ID`
+/// headers can't grow them without end. The panel seen longest ago makes way
+/// for a new one.
+const MAX_DISPLAYS: usize = 32;
+
+/// What a panel last said about itself when it polled
+#[derive(Clone, Debug)]
+pub struct DisplayReport {
+    pub last_seen: DateTime<Utc>,
+    pub battery_voltage: Option<f32>,
+    pub rssi: Option<i32>,
+    pub firmware: Option<String>,
+}
+
+/// Reports from every panel that has polled, keyed by its `ID` header, which
+/// TRMNL's firmware sets to the MAC address
+#[derive(Default)]
+pub struct Displays {
+    reports: Mutex<BTreeMap<String, DisplayReport>>,
+}
+
+impl Displays {
+    pub fn reports(&self) -> Vec<(String, DisplayReport)> {
+        self.reports
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, report)| (id.clone(), report.clone()))
+            .collect()
+    }
+
+    fn record(&self, id: String, headers: &HeaderMap) {
+        let report = DisplayReport {
+            last_seen: Utc::now(),
+            battery_voltage: header(headers, "battery-voltage").and_then(|x| x.parse().ok()),
+            rssi: header(headers, "rssi").and_then(|x| x.parse().ok()),
+            firmware: header(headers, "fw-version").map(str::to_owned),
+        };
+
+        let mut reports = self.reports.lock().unwrap();
+
+        if reports.len() >= MAX_DISPLAYS && !reports.contains_key(&id) {
+            let oldest = reports
+                .iter()
+                .min_by_key(|(_, report)| report.last_seen)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                reports.remove(&oldest);
+            }
+        }
+
+        reports.insert(id, report);
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct TrmnlState {
+    config: TrmnlConfig,
+    data_access: Arc<DataAccess>,
+    displays: Arc<Displays>,
+    public_url: String,
+}
+
+impl TrmnlState {
+    fn image_url(&self) -> String {
+        format!("{}{}", self.public_url, self.config.screen)
+    }
+
+    /// Changes whenever the board has new departures, so that panels only
+    /// download the image when it will look different
+    fn filename(&self) -> String {
+        match self.data_access.last_refreshed() {
+            Some(time) => format!("stops-{}", time.timestamp()),
+            None => String::from("stops"),
+        }
+    }
+
+    /// Seconds until shortly after the next refresh
+    fn refresh_rate(&self) -> i64 {
+        let interval = REFRESH_INTERVAL.as_secs() as i64;
+
+        let remaining = match self.data_access.last_refreshed() {
+            Some(time) => interval - (Utc::now() - time).num_seconds(),
+            None => MIN_REFRESH_SECS,
+        };
+
+        (remaining + REFRESH_SLACK_SECS).clamp(MIN_REFRESH_SECS, interval + REFRESH_SLACK_SECS)
+    }
+
+    fn authorized(&self, headers: &HeaderMap) -> bool {
+        match &self.config.access_token {
+            Some(token) => header(headers, "access-token") == Some(token.as_str()),
+            None => true,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct DisplayResponse {
+    status: u16,
+    image_url: String,
+    filename: String,
+    refresh_rate: i64,
+    reset_firmware: bool,
+    update_firmware: bool,
+    firmware_url: Option<String>,
+    special_function: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct SetupResponse {
+    status: u16,
+    api_key: String,
+    friendly_id: String,
+    image_url: String,
+    filename: String,
+}
+
+/// Routes speaking the polling protocol of TRMNL's ESP32 firmware, which
+/// asks `/api/display` what to show and how long to sleep for
+pub fn router(
+    config: TrmnlConfig,
+    data_access: Arc<DataAccess>,
+    displays: Arc<Displays>,
+    public_url: String,
+) -> Router {
+    Router::new()
+        .route("/api/setup", get(setup))
+        .route("/api/display", get(display))
+        .with_state(TrmnlState {
+            config,
+            data_access,
+            displays,
+            public_url,
+        })
+}
+
+/// Sent once by a new panel, which keeps the key it's given to send as its
+/// `Access-Token`. With an `access_token` configured, the panel must already
+/// send it, having had it entered by hand, so that the key isn't handed to
+/// anyone who asks.
+#[utoipa::path(
+    get,
+    path = "/api/setup",
+    params(
+        ("ID" = String, Header, description = "The panel's MAC address"),
+        ("Access-Token" = Option<String>, Header, description = "The access_token, if one is configured"),
+    ),
+    responses(
+        (status = 200, body = SetupResponse),
+        (status = 400, description = "The ID header is missing"),
+        (status = 401, description = "The Access-Token is wrong"),
+    )
+)]
+pub(crate) async fn setup(State(state): State<TrmnlState>, headers: HeaderMap) -> Response {
+    if !state.authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid Access-Token").into_response();
+    }
+
+    let Some(id) = header(&headers, "id") else {
+        return (StatusCode::BAD_REQUEST, "missing ID header").into_response();
+    };
+
+    Json(SetupResponse {
+        status: 200,
+        api_key: state.config.access_token.clone().unwrap_or_default(),
+        friendly_id: id.to_owned(),
+        image_url: state.image_url(),
+        filename: state.filename(),
+    })
+    .into_response()
+}
+
+/// Polled by each panel when it wakes, for the image to show and how long to
+/// sleep before asking again
+#[utoipa::path(
+    get,
+    path = "/api/display",
+    params(
+        ("ID" = Option<String>, Header, description = "The panel's MAC address"),
+        ("Access-Token" = Option<String>, Header, description = "The access_token, if one is configured"),
+        ("Battery-Voltage" = Option<f32>, Header, description = "Battery voltage, shown on /status"),
+        ("RSSI" = Option<i32>, Header, description = "WiFi signal strength in dBm"),
+        ("FW-Version" = Option<String>, Header, description = "Firmware version"),
+    ),
+    responses(
+        (status = 200, body = DisplayResponse),
+        (status = 401, description = "The Access-Token is wrong"),
+    )
+)]
+pub(crate) async fn display(State(state): State<TrmnlState>, headers: HeaderMap) -> Response {
+    if !state.authorized(&headers) {
+        return (StatusCode::UNAUTHORIZED, "invalid Access-Token").into_response();
+    }
+
+    if let Some(id) = header(&headers, "id") {
+        state.displays.record(id.to_owned(), &headers);
+    }
+
+    Json(DisplayResponse {
+        status: 0,
+        image_url: state.image_url(),
+        filename: state.filename(),
+        refresh_rate: state.refresh_rate(),
+        reset_firmware: false,
+        update_firmware: false,
+        firmware_url: None,
+        special_function: "sleep",
+    })
+    .into_response()
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
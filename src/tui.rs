@@ -0,0 +1,107 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use eyre::{Context, Result};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout as Split},
+    style::{Modifier, Style},
+    widgets::{Block, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+
+use crate::{api_client::DataAccess, config::ConfigFile, layout::data_to_layout, text};
+
+/// How often the board is laid out again, so that departure times count down
+/// and newly fetched departures show up
+const REDRAW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Show the board in the terminal, laid out as text from the departures being
+/// fetched in the background, until `q` or Esc is pressed
+pub async fn run(data_access: Arc<DataAccess>, config_file: ConfigFile) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let mut terminal = ratatui::init();
+        let result = show(&mut terminal, &data_access, &config_file);
+        ratatui::restore();
+
+        result
+    })
+    .await?
+}
+
+fn show(
+    terminal: &mut DefaultTerminal,
+    data_access: &DataAccess,
+    config_file: &ConfigFile,
+) -> Result<()> {
+    loop {
+        terminal.draw(|frame| draw(frame, data_access, config_file))?;
+
+        if !event::poll(REDRAW_INTERVAL)? {
+            continue;
+        }
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press
+                && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc)
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Draw a header saying how fresh the departures are, the board's columns
+/// side by side, and a footer naming the agencies the board warns about
+fn draw(frame: &mut Frame, data_access: &DataAccess, config_file: &ConfigFile) {
+    let [header, board, footer] = Split::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let refreshed = match data_access.last_refreshed() {
+        Some(refreshed) => format!("refreshed {}s ago", (Utc::now() - refreshed).num_seconds()),
+        None if data_access.last_attempt().is_none() => "waiting for the first fetch".to_owned(),
+        None => "no fetch has succeeded yet".to_owned(),
+    };
+    frame.render_widget(
+        Paragraph::new(format!("transit-kindle, {refreshed}"))
+            .style(Style::new().add_modifier(Modifier::BOLD)),
+        header,
+    );
+
+    let layout = match data_access
+        .stop_data(config_file)
+        .wrap_err("load stop data")
+    {
+        Ok(stop_data) => data_to_layout(stop_data, config_file),
+        Err(error) => {
+            frame.render_widget(
+                Paragraph::new(format!("{error:#}"))
+                    .wrap(Wrap { trim: false })
+                    .block(Block::bordered().title("Can't lay out the board")),
+                board,
+            );
+            frame.render_widget(Paragraph::new("q to quit"), footer);
+            return;
+        }
+    };
+
+    let [left, right] = text::columns(&layout);
+    let [left_area, right_area] =
+        Split::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(board);
+    frame.render_widget(
+        Paragraph::new(left.join("\n")).block(Block::bordered()),
+        left_area,
+    );
+    frame.render_widget(
+        Paragraph::new(right.join("\n")).block(Block::bordered()),
+        right_area,
+    );
+
+    let mut status = text::warnings(&layout);
+    status.push("q to quit".to_owned());
+    frame.render_widget(Paragraph::new(status.join(" \u{b7} ")), footer);
+}